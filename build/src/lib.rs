@@ -40,13 +40,48 @@ use std::fmt;
 #[allow(missing_copy_implementations)]
 #[derive(Clone, Debug)]
 pub struct ServiceGenerator {
-    _private: (),
+    std_futures: bool,
+    blocking: bool,
 }
 
 impl ServiceGenerator {
     /// Create a new `ServiceGenerator` instance with the default options set.
     pub fn new() -> ServiceGenerator {
-        ServiceGenerator { _private: () }
+        ServiceGenerator {
+            std_futures: false,
+            blocking: false,
+        }
+    }
+
+    /// Generate code based on `std::future::Future` instead of `futures` 0.1 futures.
+    ///
+    /// In this mode, the methods of generated service traits return `impl Future`, so they can be
+    /// implemented using `async fn`.  Generated clients and servers use the
+    /// `prost_simple_rpc::std_future::Handler` trait instead of `prost_simple_rpc::handler::Handler`.
+    ///
+    /// The generated code requires Rust 2018 or later, and streaming methods are not supported.
+    pub fn std_futures(mut self, std_futures: bool) -> ServiceGenerator {
+        self.std_futures = std_futures;
+        self
+    }
+
+    /// Also generate blocking clients and servers.
+    ///
+    /// For a service called `Foo`, this generates a `FooBlockingClient` whose methods wait for the
+    /// result of each call and return it directly.  For services without streaming methods, it
+    /// also generates a `FooBlocking` trait with plain methods returning `Result`, and a
+    /// `FooBlockingServer` that implements `Foo` by running those methods on a thread pool.
+    ///
+    /// This can't be combined with `std_futures`.
+    pub fn blocking(mut self, blocking: bool) -> ServiceGenerator {
+        self.blocking = blocking;
+        self
+    }
+}
+
+impl Default for ServiceGenerator {
+    fn default() -> ServiceGenerator {
+        ServiceGenerator::new()
     }
 }
 
@@ -55,9 +90,17 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
         use heck::CamelCase;
         use std::fmt::Write;
 
+        assert!(
+            !(self.std_futures && self.blocking),
+            "Blocking code generation is not supported with std futures"
+        );
+
         let descriptor_name = format!("{}Descriptor", service.name);
         let server_name = format!("{}Server", service.name);
         let client_name = format!("{}Client", service.name);
+        let blocking_name = format!("{}Blocking", service.name);
+        let blocking_client_name = format!("{}BlockingClient", service.name);
+        let blocking_server_name = format!("{}BlockingServer", service.name);
         let method_descriptor_name = format!("{}MethodDescriptor", service.name);
 
         let mut trait_types = String::new();
@@ -69,41 +112,90 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
         let mut client_own_methods = String::new();
         let mut match_name_methods = String::new();
         let mut match_proto_name_methods = String::new();
+        let mut match_from_name_methods = String::new();
+        let mut match_from_proto_name_methods = String::new();
         let mut match_input_type_methods = String::new();
         let mut match_input_proto_type_methods = String::new();
         let mut match_output_type_methods = String::new();
         let mut match_output_proto_type_methods = String::new();
         let mut match_handle_methods = String::new();
 
+        let mut match_client_streaming_methods = String::new();
+        let mut match_server_streaming_methods = String::new();
+        let mut match_handle_client_streaming_methods = String::new();
+        let mut match_handle_server_streaming_methods = String::new();
+        let mut match_handle_bidi_streaming_methods = String::new();
+        let mut has_client_streaming = false;
+        let mut has_server_streaming = false;
+        let mut has_bidi_streaming = false;
+
+        let mut blocking_trait_methods = String::new();
+        let mut blocking_client_methods = String::new();
+        let mut blocking_server_types = String::new();
+        let mut blocking_server_methods = String::new();
+
         for method in service.methods {
             assert!(
-                !method.client_streaming,
-                "Client streaming not yet supported for method {}",
-                method.proto_name
-            );
-            assert!(
-                !method.server_streaming,
-                "Server streaming not yet supported for method {}",
+                !self.std_futures || !(method.client_streaming || method.server_streaming),
+                "Streaming is not supported with std futures for method {}",
                 method.proto_name
             );
 
-            writeln!(
-                trait_types,
-                "    /// A future resulting from calling `{name}`.
-    type {camel_case_name}Future: ::futures::Future<Item = {output_type}, Error = Self::Error> + Send;",
-                name = method.name,
-                camel_case_name = method.name.to_camel_case(),
-                output_type = method.output_type
-            ).unwrap();
+            let is_bidi_streaming = method.client_streaming && method.server_streaming;
+            has_client_streaming |= method.client_streaming && !is_bidi_streaming;
+            has_server_streaming |= method.server_streaming && !is_bidi_streaming;
+            has_bidi_streaming |= is_bidi_streaming;
 
-            ServiceGenerator::write_comments(&mut trait_methods, 4, &method.comments).unwrap();
-            writeln!(
-                trait_methods,
-                r#"    fn {name}(&self, input: {input_type}) -> Self::{camel_case_name}Future;"#,
-                name = method.name,
-                camel_case_name = method.name.to_camel_case(),
-                input_type = method.input_type
-            ).unwrap();
+            // Server-streaming methods return a `Stream` of outputs instead of a `Future`.
+            let (return_kind, client_return_type) =
+                match (method.client_streaming, method.server_streaming) {
+                    (false, false) => ("Future", "ClientFuture"),
+                    (false, true) => ("Stream", "ClientStream"),
+                    (true, false) => ("Future", "ClientStreamingFuture"),
+                    (true, true) => ("Stream", "ClientBidiStream"),
+                };
+
+            // Client-streaming methods accept a `Stream` of inputs instead of a single input.
+            let input_type = if method.client_streaming {
+                format!(
+                    "::prost_simple_rpc::handler::InputStream<{}>",
+                    method.input_type
+                )
+            } else {
+                method.input_type.clone()
+            };
+
+            if self.std_futures {
+                ServiceGenerator::write_comments(&mut trait_methods, 4, &method.comments).unwrap();
+                writeln!(
+                    trait_methods,
+                    r#"    fn {name}(&self, input: {input_type}) -> impl ::std::future::Future<Output = Result<{output_type}, Self::Error>> + Send;"#,
+                    name = method.name,
+                    input_type = input_type,
+                    output_type = method.output_type
+                ).unwrap();
+            } else {
+                writeln!(
+                    trait_types,
+                    "    /// A {return_kind_lower} resulting from calling `{name}`.
+    type {camel_case_name}{return_kind}: ::futures::{return_kind}<Item = {output_type}, Error = Self::Error> + Send;",
+                    name = method.name,
+                    camel_case_name = method.name.to_camel_case(),
+                    return_kind = return_kind,
+                    return_kind_lower = return_kind.to_lowercase(),
+                    output_type = method.output_type
+                ).unwrap();
+
+                ServiceGenerator::write_comments(&mut trait_methods, 4, &method.comments).unwrap();
+                writeln!(
+                    trait_methods,
+                    r#"    fn {name}(&self, input: {input_type}) -> Self::{camel_case_name}{return_kind};"#,
+                    name = method.name,
+                    camel_case_name = method.name.to_camel_case(),
+                    return_kind = return_kind,
+                    input_type = input_type
+                ).unwrap();
+            }
 
             ServiceGenerator::write_comments(&mut enum_methods, 4, &method.comments).unwrap();
             writeln!(enum_methods, "    {name},", name = method.proto_name).unwrap();
@@ -114,37 +206,161 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
                 name = method.proto_name
             ).unwrap();
 
-            writeln!(
-                client_types,
-                "    type {camel_case_name}Future = ::prost_simple_rpc::__rt::ClientFuture<H, {input_type}, {output_type}>;",
-                camel_case_name = method.name.to_camel_case(),
-                input_type = method.input_type,
-                output_type = method.output_type,
-            ).unwrap();
-
-            writeln!(
-                client_methods,
-                r#"    fn {name}(&self, input: {input_type}) -> Self::{camel_case_name}Future {{
+            if self.std_futures {
+                writeln!(
+                    client_methods,
+                    r#"    fn {name}(&self, input: {input_type}) -> impl ::std::future::Future<Output = Result<{output_type}, Self::Error>> + Send {{
         {client_name}::{name}_inner(self.0.clone(), input)
     }}"#,
-                name = method.name,
-                camel_case_name = method.name.to_camel_case(),
-                input_type = method.input_type,
-                client_name = format!("{}Client", service.name)
-            ).unwrap();
+                    name = method.name,
+                    input_type = input_type,
+                    output_type = method.output_type,
+                    client_name = client_name
+                ).unwrap();
 
-            writeln!(
-                client_own_methods,
-                r#"    fn {name}_inner(handler: H, input: {input_type}) -> <Self as {trait_name}>::{camel_case_name}Future {{
-        ::prost_simple_rpc::__rt::ClientFuture::new(handler, input, {method_descriptor_name}::{proto_name})
+                writeln!(
+                    client_own_methods,
+                    r#"    fn {name}_inner(handler: H, input: {input_type}) -> ::prost_simple_rpc::__rt::StdClientFuture<H, {input_type}, {output_type}> {{
+        ::prost_simple_rpc::__rt::StdClientFuture::new(handler, input, {method_descriptor_name}::{proto_name})
     }}"#,
-                trait_name = service.name,
-                name = method.name,
-                camel_case_name = method.name.to_camel_case(),
-                method_descriptor_name = method_descriptor_name,
-                proto_name = method.proto_name,
-                input_type = method.input_type,
-            ).unwrap();
+                    name = method.name,
+                    method_descriptor_name = method_descriptor_name,
+                    proto_name = method.proto_name,
+                    input_type = input_type,
+                    output_type = method.output_type,
+                ).unwrap();
+            } else {
+                writeln!(
+                    client_types,
+                    "    type {camel_case_name}{return_kind} = ::prost_simple_rpc::__rt::{client_return_type}<H, {input_type}, {output_type}>;",
+                    camel_case_name = method.name.to_camel_case(),
+                    return_kind = return_kind,
+                    client_return_type = client_return_type,
+                    input_type = method.input_type,
+                    output_type = method.output_type,
+                ).unwrap();
+
+                writeln!(
+                    client_methods,
+                    r#"    fn {name}(&self, input: {input_type}) -> Self::{camel_case_name}{return_kind} {{
+            {client_name}::{name}_inner(self.0.clone(), input)
+        }}"#,
+                    name = method.name,
+                    camel_case_name = method.name.to_camel_case(),
+                    return_kind = return_kind,
+                    input_type = input_type,
+                    client_name = client_name
+                ).unwrap();
+
+                writeln!(
+                    client_own_methods,
+                    r#"    fn {name}_inner(handler: H, input: {input_type}) -> <Self as {trait_name}>::{camel_case_name}{return_kind} {{
+            ::prost_simple_rpc::__rt::{client_return_type}::new(handler, input, {method_descriptor_name}::{proto_name})
+        }}"#,
+                    trait_name = service.name,
+                    name = method.name,
+                    camel_case_name = method.name.to_camel_case(),
+                    return_kind = return_kind,
+                    client_return_type = client_return_type,
+                    method_descriptor_name = method_descriptor_name,
+                    proto_name = method.proto_name,
+                    input_type = input_type,
+                ).unwrap();
+            }
+
+            if self.blocking {
+                ServiceGenerator::write_comments(&mut blocking_client_methods, 4, &method.comments)
+                    .unwrap();
+                match (method.client_streaming, method.server_streaming) {
+                    (false, false) => writeln!(
+                        blocking_client_methods,
+                        r#"    pub fn {name}(&self, input: {input_type}) -> ::prost_simple_rpc::error::Result<{output_type}, H::Error> {{
+        use futures::Future;
+
+        {trait_name}::{name}(&self.0, input).wait()
+    }}"#,
+                        trait_name = service.name,
+                        name = method.name,
+                        input_type = method.input_type,
+                        output_type = method.output_type
+                    ).unwrap(),
+                    (false, true) => writeln!(
+                        blocking_client_methods,
+                        r#"    pub fn {name}(&self, input: {input_type}) -> ::futures::stream::Wait<<{client_name}<H> as {trait_name}>::{camel_case_name}Stream> {{
+        use futures::Stream;
+
+        {trait_name}::{name}(&self.0, input).wait()
+    }}"#,
+                        trait_name = service.name,
+                        client_name = client_name,
+                        name = method.name,
+                        camel_case_name = method.name.to_camel_case(),
+                        input_type = method.input_type
+                    ).unwrap(),
+                    (true, false) => writeln!(
+                        blocking_client_methods,
+                        r#"    pub fn {name}<I>(&self, input: I) -> ::prost_simple_rpc::error::Result<{output_type}, H::Error>
+        where I: IntoIterator<Item = {input_type}>, I::IntoIter: Send + 'static
+    {{
+        use futures::Future;
+
+        {trait_name}::{name}(&self.0, Box::new(::futures::stream::iter_ok(input))).wait()
+    }}"#,
+                        trait_name = service.name,
+                        name = method.name,
+                        input_type = method.input_type,
+                        output_type = method.output_type
+                    ).unwrap(),
+                    (true, true) => writeln!(
+                        blocking_client_methods,
+                        r#"    pub fn {name}<I>(&self, input: I) -> ::futures::stream::Wait<<{client_name}<H> as {trait_name}>::{camel_case_name}Stream>
+        where I: IntoIterator<Item = {input_type}>, I::IntoIter: Send + 'static
+    {{
+        use futures::Stream;
+
+        {trait_name}::{name}(&self.0, Box::new(::futures::stream::iter_ok(input))).wait()
+    }}"#,
+                        trait_name = service.name,
+                        client_name = client_name,
+                        name = method.name,
+                        camel_case_name = method.name.to_camel_case(),
+                        input_type = method.input_type
+                    ).unwrap(),
+                }
+            }
+
+            // Blocking servers are only generated for services without streaming methods.
+            if self.blocking && !method.client_streaming && !method.server_streaming {
+                ServiceGenerator::write_comments(&mut blocking_trait_methods, 4, &method.comments)
+                    .unwrap();
+                writeln!(
+                    blocking_trait_methods,
+                    "    fn {name}(&self, input: {input_type}) -> Result<{output_type}, Self::Error>;",
+                    name = method.name,
+                    input_type = method.input_type,
+                    output_type = method.output_type
+                ).unwrap();
+
+                writeln!(
+                    blocking_server_types,
+                    "    type {camel_case_name}Future = ::prost_simple_rpc::blocking::CpuFuture<{output_type}, Self::Error>;",
+                    camel_case_name = method.name.to_camel_case(),
+                    output_type = method.output_type
+                ).unwrap();
+
+                writeln!(
+                    blocking_server_methods,
+                    r#"    fn {name}(&self, input: {input_type}) -> Self::{camel_case_name}Future {{
+        let service = self.service.clone();
+        // The service runs on another thread, so the context of the call has to move with it.
+        let context = ::prost_simple_rpc::context::Context::current();
+        self.pool.spawn_fn(move || context.scope(|| service.{name}(input)))
+    }}"#,
+                    name = method.name,
+                    camel_case_name = method.name.to_camel_case(),
+                    input_type = method.input_type
+                ).unwrap();
+            }
 
             let case = format!(
                 "            {service_name}MethodDescriptor::{proto_name} => ",
@@ -154,6 +370,16 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
 
             writeln!(match_name_methods, "{}{:?},", case, method.name).unwrap();
             writeln!(match_proto_name_methods, "{}{:?},", case, method.proto_name).unwrap();
+            writeln!(
+                match_from_name_methods,
+                "            {:?} => Some({}::{}),",
+                method.name, method_descriptor_name, method.proto_name
+            ).unwrap();
+            writeln!(
+                match_from_proto_name_methods,
+                "            {:?} => Some({}::{}),",
+                method.proto_name, method_descriptor_name, method.proto_name
+            ).unwrap();
             writeln!(
                 match_input_type_methods,
                 "{}::std::any::TypeId::of::<{}>(),",
@@ -174,19 +400,152 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
                 "{}{:?},",
                 case, method.output_proto_type
             ).unwrap();
-            write!(
-                match_handle_methods,
-                r#"{}
+            writeln!(
+                match_client_streaming_methods,
+                "{}{:?},",
+                case, method.client_streaming
+            ).unwrap();
+            writeln!(
+                match_server_streaming_methods,
+                "{}{:?},",
+                case, method.server_streaming
+            ).unwrap();
+
+            let unsupported_call = format!(
+                "::prost_simple_rpc::error::Error::unsupported_call({:?})",
+                method.proto_name
+            );
+
+            let unsupported_future = format!(
+                "{}Box::new(::futures::future::err({})),",
+                case, unsupported_call
+            );
+            let unsupported_stream = format!(
+                "{}Box::new(::futures::stream::once(Err({}))),",
+                case, unsupported_call
+            );
+
+            match (method.client_streaming, method.server_streaming) {
+                (false, false) if self.std_futures => {
+                    write!(
+                        match_handle_methods,
+                        r#"{}
+                Box::pin(async move {{
+                    let i = ::prost_simple_rpc::__rt::decode(input)?;
+                    let o = service.{name}(i).await.map_err(::prost_simple_rpc::error::Error::execution)?;
+                    ::prost_simple_rpc::__rt::encode(o)
+                }}),
+"#,
+                        case,
+                        name = method.name
+                    ).unwrap();
+                }
+                (false, false) => {
+                    write!(
+                        match_handle_methods,
+                        r#"{}
                 Box::new(
                     ::futures::future::result(::prost_simple_rpc::__rt::decode(input))
                         .and_then(move |i| {{
-                            service.{name}(i).map_err(|e| ::prost_simple_rpc::error::Error::execution(e))
+                            service.{name}(i).map_err(::prost_simple_rpc::error::Error::execution)
                         }})
                         .and_then(::prost_simple_rpc::__rt::encode)),
 "#,
-                case,
-                name = method.name
-            ).unwrap();
+                        case,
+                        name = method.name
+                    ).unwrap();
+                    // Unary methods can be called in a server-streaming fashion, yielding exactly
+                    // one output.
+                    writeln!(
+                        match_handle_server_streaming_methods,
+                        "{}Box::new({}::call_inner(service, method, input).into_stream()),",
+                        case, server_name
+                    ).unwrap();
+                    writeln!(match_handle_client_streaming_methods, "{}", unsupported_future).unwrap();
+                    writeln!(match_handle_bidi_streaming_methods, "{}", unsupported_stream).unwrap();
+                }
+                (false, true) => {
+                    writeln!(match_handle_methods, "{}", unsupported_future).unwrap();
+                    write!(
+                        match_handle_server_streaming_methods,
+                        r#"{}
+                Box::new(
+                    ::futures::future::result(::prost_simple_rpc::__rt::decode(input))
+                        .map(move |i| {{
+                            service.{name}(i).map_err(::prost_simple_rpc::error::Error::execution)
+                        }})
+                        .flatten_stream()
+                        .and_then(::prost_simple_rpc::__rt::encode)),
+"#,
+                        case,
+                        name = method.name
+                    ).unwrap();
+                    writeln!(match_handle_client_streaming_methods, "{}", unsupported_future).unwrap();
+                    writeln!(match_handle_bidi_streaming_methods, "{}", unsupported_stream).unwrap();
+                }
+                (true, false) => {
+                    writeln!(match_handle_methods, "{}", unsupported_future).unwrap();
+                    writeln!(match_handle_server_streaming_methods, "{}", unsupported_stream).unwrap();
+                    write!(
+                        match_handle_client_streaming_methods,
+                        r#"{}
+                Box::new(
+                    service.{name}(Box::new(::prost_simple_rpc::__rt::DecodeStream::new(input)))
+                        .map_err(::prost_simple_rpc::error::Error::execution)
+                        .and_then(::prost_simple_rpc::__rt::encode)),
+"#,
+                        case,
+                        name = method.name
+                    ).unwrap();
+                    writeln!(match_handle_bidi_streaming_methods, "{}", unsupported_stream).unwrap();
+                }
+                (true, true) => {
+                    writeln!(match_handle_methods, "{}", unsupported_future).unwrap();
+                    writeln!(match_handle_server_streaming_methods, "{}", unsupported_stream).unwrap();
+                    writeln!(match_handle_client_streaming_methods, "{}", unsupported_future).unwrap();
+                    write!(
+                        match_handle_bidi_streaming_methods,
+                        r#"{}
+                Box::new(
+                    service.{name}(Box::new(::prost_simple_rpc::__rt::DecodeStream::new(input)))
+                        .map_err(::prost_simple_rpc::error::Error::execution)
+                        .and_then(::prost_simple_rpc::__rt::encode)),
+"#,
+                        case,
+                        name = method.name
+                    ).unwrap();
+                }
+            }
+        }
+
+        let (handler_trait, server_bounds, call_future_type, call_inner_imports, box_new) =
+            if self.std_futures {
+                (
+                    "::prost_simple_rpc::std_future::Handler",
+                    format!("{} + Clone + Send + Sync + 'static", service.name),
+                    "::std::pin::Pin<Box<dyn Send + ::std::future::Future<Output = Result<::bytes::Bytes, Self::Error>>>>",
+                    "",
+                    "Box::pin",
+                )
+            } else {
+                (
+                    "::prost_simple_rpc::handler::Handler",
+                    format!("{} + Clone + Send + 'static", service.name),
+                    "Box<dyn Send + ::futures::Future<Item = ::bytes::Bytes, Error = Self::Error>>",
+                    "        use futures::Future;\n\n",
+                    "Box::new",
+                )
+            };
+
+        let mut client_handler_bounds = format!("{}<Descriptor = {}>", handler_trait, descriptor_name);
+        if has_client_streaming {
+            client_handler_bounds.push_str(" + ::prost_simple_rpc::handler::ClientStreamingHandler");
+        }
+        if has_server_streaming {
+            client_handler_bounds.push_str(" + ::prost_simple_rpc::handler::ServerStreamingHandler");
+        }
+        if has_bidi_streaming {
+            client_handler_bounds.push_str(" + ::prost_simple_rpc::handler::BidiStreamingHandler");
         }
 
         ServiceGenerator::write_comments(&mut buf, 0, &service.comments).unwrap();
@@ -204,37 +563,36 @@ pub struct {descriptor_name};
 /// This implements the `Server` trait by handling requests and dispatch them to methods on the
 /// supplied `{name}`.
 #[derive(Clone, Debug)]
-pub struct {server_name}<A>(A) where A: {name} + Clone + Send + 'static;
+pub struct {server_name}<A>(A) where A: {server_bounds};
 /// A client for a `{name}`.
 ///
 /// This implements the `{name}` trait by dispatching all method calls to the supplied `Handler`.
 #[derive(Clone, Debug)]
-pub struct {client_name}<H>(H) where H: ::prost_simple_rpc::handler::Handler;
+pub struct {client_name}<H>(H) where H: {handler_trait};
 /// A method available on a `{name}`.
 ///
 /// This can be used as a key when routing requests for servers/clients of a `{name}`.
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub enum {method_descriptor_name} {{
 {enum_methods}}}
-impl<A> {server_name}<A> where A: {name} + Clone + Send + 'static {{
+impl<A> {server_name}<A> where A: {server_bounds} {{
     /// Creates a new server instance that dispatches all calls to the supplied service.
     pub fn new(service: A) -> {server_name}<A> {{
         {server_name}(service)
     }}
 
+    #[allow(unused_imports, unused_variables)]
     fn call_inner(
         service: A,
         method: {method_descriptor_name},
         input: ::bytes::Bytes)
-        -> <Self as ::prost_simple_rpc::handler::Handler>::CallFuture
+        -> <Self as {handler_trait}>::CallFuture
     {{
-        use futures::Future;
-
-        match method {{
+{call_inner_imports}        match method {{
 {match_handle_methods}        }}
     }}
 }}
-impl<H> {client_name}<H> where H: ::prost_simple_rpc::handler::Handler<Descriptor = {descriptor_name}> {{
+impl<H> {client_name}<H> where H: {handler_trait}<Descriptor = {descriptor_name}> {{
     /// Creates a new client instance that delegates all method calls to the supplied handler.
     pub fn new(handler: H) -> {client_name}<H> {{
         {client_name}(handler)
@@ -250,23 +608,27 @@ impl ::prost_simple_rpc::descriptor::ServiceDescriptor for {descriptor_name} {{
 {list_enum_methods}        ]
     }}
 }}
-impl<A> ::prost_simple_rpc::handler::Handler for {server_name}<A> where A: {name} + Clone + Send + 'static {{
+impl<A> {handler_trait} for {server_name}<A> where A: {server_bounds} {{
     type Error = ::prost_simple_rpc::error::Error<<A as {name}>::Error>;
     type Descriptor = {descriptor_name};
-    type CallFuture = Box<::futures::Future<Item = ::bytes::Bytes, Error = Self::Error> + Send>;
+    type CallFuture = {call_future_type};
 
     fn call(
         &self,
         method: {method_descriptor_name},
-        input: ::bytes::Bytes)
+        input: ::bytes::Bytes,
+        context: ::prost_simple_rpc::context::Context)
         -> Self::CallFuture
     {{
-        {server_name}::call_inner(self.0.clone(), method, input)
+        let service = self.0.clone();
+        {box_new}(::prost_simple_rpc::__rt::incoming(context, move || {{
+            {server_name}::call_inner(service, method, input)
+        }}))
     }}
 }}
-impl<H> {client_name}<H> where H: ::prost_simple_rpc::handler::Handler<Descriptor = {descriptor_name}> {{
+impl<H> {client_name}<H> where H: {client_handler_bounds} {{
 {client_own_methods}}}
-impl<H> {name} for {client_name}<H> where H: ::prost_simple_rpc::handler::Handler<Descriptor = {descriptor_name}> {{
+impl<H> {name} for {client_name}<H> where H: {client_handler_bounds} {{
     type Error = ::prost_simple_rpc::error::Error<H::Error>;
 {client_types}
 {client_methods}}}
@@ -279,6 +641,16 @@ impl ::prost_simple_rpc::descriptor::MethodDescriptor for {method_descriptor_nam
         match *self {{
 {match_proto_name_methods}        }}
     }}
+    fn from_name(name: &str) -> Option<Self> {{
+        match name {{
+{match_from_name_methods}            _ => None,
+        }}
+    }}
+    fn from_proto_name(proto_name: &str) -> Option<Self> {{
+        match proto_name {{
+{match_from_proto_name_methods}            _ => None,
+        }}
+    }}
     fn input_type(&self) -> ::std::any::TypeId {{
         match *self {{
 {match_input_type_methods}        }}
@@ -295,6 +667,14 @@ impl ::prost_simple_rpc::descriptor::MethodDescriptor for {method_descriptor_nam
         match *self {{
 {match_output_proto_type_methods}        }}
     }}
+    fn client_streaming(&self) -> bool {{
+        match *self {{
+{match_client_streaming_methods}        }}
+    }}
+    fn server_streaming(&self) -> bool {{
+        match *self {{
+{match_server_streaming_methods}        }}
+    }}
 }}
 "#,
             name = service.name,
@@ -311,14 +691,216 @@ impl ::prost_simple_rpc::descriptor::MethodDescriptor for {method_descriptor_nam
             client_own_methods = client_own_methods,
             client_types = client_types,
             client_methods = client_methods,
+            client_handler_bounds = client_handler_bounds,
+            handler_trait = handler_trait,
+            server_bounds = server_bounds,
+            call_future_type = call_future_type,
+            call_inner_imports = call_inner_imports,
+            box_new = box_new,
             match_name_methods = match_name_methods,
             match_proto_name_methods = match_proto_name_methods,
+            match_from_name_methods = match_from_name_methods,
+            match_from_proto_name_methods = match_from_proto_name_methods,
             match_input_type_methods = match_input_type_methods,
             match_input_proto_type_methods = match_input_proto_type_methods,
             match_output_type_methods = match_output_type_methods,
             match_output_proto_type_methods = match_output_proto_type_methods,
+            match_client_streaming_methods = match_client_streaming_methods,
+            match_server_streaming_methods = match_server_streaming_methods,
             match_handle_methods = match_handle_methods
         ).unwrap();
+
+        if self.blocking {
+            write!(
+                buf,
+                r#"/// A blocking client for a `{name}`.
+///
+/// This waits for the result of each call to the wrapped `{client_name}` before returning it.
+#[derive(Clone, Debug)]
+pub struct {blocking_client_name}<H>({client_name}<H>) where H: ::prost_simple_rpc::handler::Handler;
+impl<H> {blocking_client_name}<H> where H: {client_handler_bounds} {{
+    /// Creates a new blocking client instance that delegates all method calls to the supplied
+    /// handler.
+    pub fn new(handler: H) -> {blocking_client_name}<H> {{
+        {blocking_client_name}({client_name}::new(handler))
+    }}
+
+{blocking_client_methods}}}
+"#,
+                name = service.name,
+                client_name = client_name,
+                blocking_client_name = blocking_client_name,
+                client_handler_bounds = client_handler_bounds,
+                blocking_client_methods = blocking_client_methods
+            ).unwrap();
+        }
+
+        if self.blocking && !(has_client_streaming || has_server_streaming || has_bidi_streaming) {
+            write!(
+                buf,
+                r#"/// A blocking variant of a `{name}`, whose methods return their results directly.
+pub trait {blocking_name} {{
+    type Error: ::failure::Fail;
+{blocking_trait_methods}}}
+/// A blocking server for a `{name}`.
+///
+/// This implements the `{name}` trait by running the methods of the supplied `{blocking_name}` on
+/// a thread pool.  Wrap it in a `{server_name}` to handle raw requests.
+#[derive(Clone, Debug)]
+pub struct {blocking_server_name}<A> where A: {blocking_name} + Clone + Send + 'static {{
+    service: A,
+    pool: ::prost_simple_rpc::blocking::CpuPool,
+}}
+impl<A> {blocking_server_name}<A> where A: {blocking_name} + Clone + Send + 'static {{
+    /// Creates a new blocking server that runs the supplied service on a thread pool with one
+    /// thread per CPU.
+    pub fn new(service: A) -> {blocking_server_name}<A> {{
+        {blocking_server_name}::with_pool(service, ::prost_simple_rpc::blocking::CpuPool::new_num_cpus())
+    }}
+
+    /// Creates a new blocking server that runs the supplied service on the supplied thread pool.
+    pub fn with_pool(service: A, pool: ::prost_simple_rpc::blocking::CpuPool) -> {blocking_server_name}<A> {{
+        {blocking_server_name} {{ service, pool }}
+    }}
+}}
+impl<A> {name} for {blocking_server_name}<A> where A: {blocking_name} + Clone + Send + 'static {{
+    type Error = <A as {blocking_name}>::Error;
+{blocking_server_types}
+{blocking_server_methods}}}
+"#,
+                name = service.name,
+                server_name = server_name,
+                blocking_name = blocking_name,
+                blocking_server_name = blocking_server_name,
+                blocking_trait_methods = blocking_trait_methods,
+                blocking_server_types = blocking_server_types,
+                blocking_server_methods = blocking_server_methods
+            ).unwrap();
+        }
+
+        if has_server_streaming {
+            write!(
+                buf,
+                r#"impl<A> {server_name}<A> where A: {name} + Clone + Send + 'static {{
+    #[allow(unused_imports, unused_variables)]
+    fn call_server_streaming_inner(
+        service: A,
+        method: {method_descriptor_name},
+        input: ::bytes::Bytes)
+        -> <Self as ::prost_simple_rpc::handler::ServerStreamingHandler>::CallStream
+    {{
+        use futures::Future;
+        use futures::Stream;
+
+        match method {{
+{match_handle_server_streaming_methods}        }}
+    }}
+}}
+impl<A> ::prost_simple_rpc::handler::ServerStreamingHandler for {server_name}<A> where A: {name} + Clone + Send + 'static {{
+    type CallStream = Box<dyn Send + ::futures::Stream<Item = ::bytes::Bytes, Error = Self::Error>>;
+
+    fn call_server_streaming(
+        &self,
+        method: {method_descriptor_name},
+        input: ::bytes::Bytes,
+        context: ::prost_simple_rpc::context::Context)
+        -> Self::CallStream
+    {{
+        let service = self.0.clone();
+        Box::new(::prost_simple_rpc::__rt::incoming(context, move || {{
+            {server_name}::call_server_streaming_inner(service, method, input)
+        }}))
+    }}
+}}
+"#,
+                name = service.name,
+                server_name = server_name,
+                method_descriptor_name = method_descriptor_name,
+                match_handle_server_streaming_methods = match_handle_server_streaming_methods
+            ).unwrap();
+        }
+
+        if has_client_streaming {
+            write!(
+                buf,
+                r#"impl<A> {server_name}<A> where A: {name} + Clone + Send + 'static {{
+    #[allow(unused_imports, unused_variables)]
+    fn call_client_streaming_inner(
+        service: A,
+        method: {method_descriptor_name},
+        input: ::prost_simple_rpc::handler::InputStream<::bytes::Bytes>)
+        -> <Self as ::prost_simple_rpc::handler::ClientStreamingHandler>::StreamingCallFuture
+    {{
+        use futures::Future;
+
+        match method {{
+{match_handle_client_streaming_methods}        }}
+    }}
+}}
+impl<A> ::prost_simple_rpc::handler::ClientStreamingHandler for {server_name}<A> where A: {name} + Clone + Send + 'static {{
+    type StreamingCallFuture = Box<dyn Send + ::futures::Future<Item = ::bytes::Bytes, Error = Self::Error>>;
+
+    fn call_client_streaming(
+        &self,
+        method: {method_descriptor_name},
+        input: ::prost_simple_rpc::handler::InputStream<::bytes::Bytes>,
+        context: ::prost_simple_rpc::context::Context)
+        -> Self::StreamingCallFuture
+    {{
+        let service = self.0.clone();
+        Box::new(::prost_simple_rpc::__rt::incoming(context, move || {{
+            {server_name}::call_client_streaming_inner(service, method, input)
+        }}))
+    }}
+}}
+"#,
+                name = service.name,
+                server_name = server_name,
+                method_descriptor_name = method_descriptor_name,
+                match_handle_client_streaming_methods = match_handle_client_streaming_methods
+            ).unwrap();
+        }
+
+        if has_bidi_streaming {
+            write!(
+                buf,
+                r#"impl<A> {server_name}<A> where A: {name} + Clone + Send + 'static {{
+    #[allow(unused_imports, unused_variables)]
+    fn call_bidi_streaming_inner(
+        service: A,
+        method: {method_descriptor_name},
+        input: ::prost_simple_rpc::handler::InputStream<::bytes::Bytes>)
+        -> <Self as ::prost_simple_rpc::handler::BidiStreamingHandler>::StreamingCallStream
+    {{
+        use futures::Stream;
+
+        match method {{
+{match_handle_bidi_streaming_methods}        }}
+    }}
+}}
+impl<A> ::prost_simple_rpc::handler::BidiStreamingHandler for {server_name}<A> where A: {name} + Clone + Send + 'static {{
+    type StreamingCallStream = Box<dyn Send + ::futures::Stream<Item = ::bytes::Bytes, Error = Self::Error>>;
+
+    fn call_bidi_streaming(
+        &self,
+        method: {method_descriptor_name},
+        input: ::prost_simple_rpc::handler::InputStream<::bytes::Bytes>,
+        context: ::prost_simple_rpc::context::Context)
+        -> Self::StreamingCallStream
+    {{
+        let service = self.0.clone();
+        Box::new(::prost_simple_rpc::__rt::incoming(context, move || {{
+            {server_name}::call_bidi_streaming_inner(service, method, input)
+        }}))
+    }}
+}}
+"#,
+                name = service.name,
+                server_name = server_name,
+                method_descriptor_name = method_descriptor_name,
+                match_handle_bidi_streaming_methods = match_handle_bidi_streaming_methods
+            ).unwrap();
+        }
     }
 }
 
@@ -333,6 +915,9 @@ impl ServiceGenerator {
     {
         for comment in &comments.leading {
             for line in comment.lines().filter(|s| !s.is_empty()) {
+                // Comments that are written with `///` in the proto file still start with the
+                // extra slash, which must not turn them into `////` comments.
+                let line = line.strip_prefix('/').unwrap_or(line);
                 writeln!(write, "{}///{}", " ".repeat(indent), line)?;
             }
         }
@@ -0,0 +1,97 @@
+//! Compares the latency and throughput of the transports that connect processes on the same host.
+//!
+//! Run with `cargo bench -p prost-simple-rpc-example`.
+use std::time;
+
+use futures::Future;
+use futures::Stream;
+use prost_simple_rpc::transport;
+
+const CALLS: usize = 10_000;
+const SIZES: &[usize] = &[16, 4096, 65536];
+
+/// Echoes every call, without decoding it.
+#[derive(Clone, Debug)]
+struct Echo;
+
+impl transport::Dispatch for Echo {
+    fn dispatch(
+        &self,
+        _service: &str,
+        _method: &str,
+        input: bytes::Bytes,
+        _context: prost_simple_rpc::context::Context,
+    ) -> transport::DispatchFuture {
+        Box::new(futures::future::ok(input))
+    }
+}
+
+fn main() {
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let socket = std::env::temp_dir()
+        .join(format!("prost-simple-rpc-bench-{}.sock", std::process::id()));
+    let shm_socket = std::env::temp_dir()
+        .join(format!("prost-simple-rpc-bench-shm-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&socket);
+    let _ = std::fs::remove_file(&shm_socket);
+
+    let listener = tokio::net::TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    runtime.spawn(transport::tcp::serve(listener, Echo).map_err(|e| panic!("tcp: {}", e)));
+    let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+    runtime.spawn(transport::unix::serve(listener, Echo).map_err(|e| panic!("unix: {}", e)));
+    let listener = tokio::net::UnixListener::bind(&shm_socket).unwrap();
+    runtime.spawn(transport::shm::serve(listener, Echo).map_err(|e| panic!("shm: {}", e)));
+
+    let connections = vec![
+        ("tcp", runtime.block_on(transport::tcp::connect(&addr)).unwrap()),
+        ("unix", runtime.block_on(transport::unix::connect(&socket)).unwrap()),
+        ("shm", runtime.block_on(transport::shm::connect(&shm_socket)).unwrap()),
+    ];
+
+    for &size in SIZES {
+        for (name, connection) in &connections {
+            let input = bytes::Bytes::from(vec![0; size]);
+            let sequential = measure(&mut runtime, connection, &input, 1);
+            let concurrent = measure(&mut runtime, connection, &input, 64);
+            println!(
+                "{:>6} bytes {:>5}: {:>8.2} µs/call sequential, {:>9.0} calls/s with 64 in flight",
+                size,
+                name,
+                sequential.as_secs_f64() * 1e6 / CALLS as f64,
+                CALLS as f64 / concurrent.as_secs_f64(),
+            );
+        }
+    }
+
+    drop(connections);
+    runtime.shutdown_now().wait().unwrap();
+    std::fs::remove_file(&socket).unwrap();
+    std::fs::remove_file(&shm_socket).unwrap();
+}
+
+/// Makes `CALLS` calls over the connection, with at most `in_flight` calls in flight at once.
+fn measure(
+    runtime: &mut tokio::runtime::Runtime,
+    connection: &transport::Connection,
+    input: &bytes::Bytes,
+    in_flight: usize,
+) -> time::Duration {
+    let calls = (0..CALLS).map({
+        let connection = connection.clone();
+        let input = input.clone();
+        move |_| {
+            let context = prost_simple_rpc::context::Context::new();
+            connection.call("echo.Echo", "Echo", input.clone(), context)
+        }
+    });
+    let start = time::Instant::now();
+    runtime
+        .block_on(
+            futures::stream::iter_ok::<_, transport::Error>(calls)
+                .buffer_unordered(in_flight)
+                .for_each(|_| Ok(())),
+        )
+        .unwrap();
+    start.elapsed()
+}
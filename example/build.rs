@@ -3,13 +3,37 @@ extern crate prost_simple_rpc_build;
 
 fn main() {
     prost_build::Config::new()
-        .service_generator(Box::new(prost_simple_rpc_build::ServiceGenerator::new()))
+        .service_generator(Box::new(
+            prost_simple_rpc_build::ServiceGenerator::new().blocking(true),
+        ))
+        // Lets the Twirp transport serve echo calls with JSON bodies.
+        .type_attribute(".echo", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".echo", "#[serde(default, rename_all = \"camelCase\")]")
+        .field_attribute(
+            ".echo",
+            "#[serde(with = \"prost_simple_rpc::transport::twirp::proto3::bytes\")]",
+        )
         .compile_protos(
             &[
+                "src/schema/counter/service.proto",
                 "src/schema/echo/service.proto",
                 "src/schema/greeting/service.proto",
             ],
             &["src/schema"],
         )
         .unwrap();
+
+    prost_build::Config::new()
+        .service_generator(Box::new(
+            prost_simple_rpc_build::ServiceGenerator::new().std_futures(true),
+        ))
+        // Lets the Twirp transport serve calculator calls with JSON bodies.
+        .type_attribute(".calculator", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".calculator", "#[serde(default, rename_all = \"camelCase\")]")
+        .field_attribute(
+            ".calculator",
+            "#[serde(with = \"prost_simple_rpc::transport::twirp::proto3::int64\")]",
+        )
+        .compile_protos(&["src/schema/calculator/service.proto"], &["src/schema"])
+        .unwrap();
 }
@@ -8,6 +8,8 @@
 #![deny(unstable_features)]
 #![deny(unused_import_braces)]
 #![deny(unused_qualifications)]
+// `failure_derive` generates its impls inside an anonymous constant.
+#![allow(non_local_definitions)]
 #![cfg_attr(feature = "dev", allow(unstable_features))]
 #![cfg_attr(feature = "dev", feature(plugin))]
 #![cfg_attr(feature = "dev", plugin(clippy))]
@@ -17,10 +19,23 @@ extern crate failure;
 #[macro_use]
 extern crate failure_derive;
 extern crate futures;
+#[cfg(test)]
+extern crate base64;
+#[cfg(test)]
+extern crate h2;
+#[cfg(test)]
+extern crate http;
 extern crate prost;
 #[macro_use]
 extern crate prost_derive;
 extern crate prost_simple_rpc;
+#[cfg(test)]
+extern crate quinn;
+#[cfg(test)]
+extern crate rcgen;
+extern crate serde;
+#[cfg(test)]
+extern crate sha1;
 extern crate tokio;
 
 mod schema;
@@ -28,14 +43,27 @@ mod schema;
 fn main() {
     run_echo_roundtrip();
     run_greeting_roundtrip();
+    run_count_roundtrip();
+    run_sum_roundtrip();
+    run_running_sum_roundtrip();
+    run_calculator_roundtrip();
+    run_blocking_echo_roundtrip();
 }
 
 fn run_echo_roundtrip() {
     use futures::Future;
     use schema::echo::Echo;
 
+    // Run the service on its own thread, and talk to it over a channel.
+    let (connection, listener) = prost_simple_rpc::transport::channel::channel(16);
     let server = schema::echo::EchoServer::new(EchoService { fail: false });
-    let client = schema::echo::EchoClient::new(server);
+    let server = std::thread::spawn(move || {
+        prost_simple_rpc::transport::channel::serve(listener, server).wait()
+    });
+
+    let client = schema::echo::EchoClient::new(
+        prost_simple_rpc::transport::channel::Client::new(connection),
+    );
     let data = vec![1, 2, 3];
     let future = client
         .echo(schema::echo::EchoRequest { data })
@@ -45,7 +73,11 @@ fn run_echo_roundtrip() {
         .map_err(|e| {
             eprintln!("Error: {:?}", e);
         });
-    tokio::run(future)
+    tokio::run(future);
+
+    // The server stops once the client has been dropped.
+    drop(client);
+    server.join().unwrap().unwrap();
 }
 
 fn run_greeting_roundtrip() {
@@ -70,6 +102,119 @@ fn run_greeting_roundtrip() {
     tokio::run(future)
 }
 
+fn run_count_roundtrip() {
+    use futures::Future;
+    use futures::Stream;
+    use schema::counter::Counter;
+
+    let server = schema::counter::CounterServer::new(CounterService);
+    let client = schema::counter::CounterClient::new(server);
+
+    let future = client
+        .count(schema::counter::CountRequest { limit: 3 })
+        .for_each(|r| {
+            eprintln!("Response: {:?}", r);
+            Ok(())
+        })
+        .map_err(|e| {
+            eprintln!("Error: {:?}", e);
+        });
+    tokio::run(future)
+}
+
+fn run_sum_roundtrip() {
+    use futures::Future;
+    use schema::counter::Counter;
+
+    let server = schema::counter::CounterServer::new(CounterService);
+    let client = schema::counter::CounterClient::new(server);
+    let numbers = (1..4)
+        .map(|number| schema::counter::SumRequest { number })
+        .collect::<Vec<_>>();
+
+    let future = client
+        .sum(Box::new(futures::stream::iter_ok(numbers)))
+        .map(|r| {
+            eprintln!("Response: {:?}", r);
+        })
+        .map_err(|e| {
+            eprintln!("Error: {:?}", e);
+        });
+    tokio::run(future)
+}
+
+fn run_running_sum_roundtrip() {
+    use futures::Future;
+    use futures::Stream;
+    use schema::counter::Counter;
+
+    let server = schema::counter::CounterServer::new(CounterService);
+    let client = schema::counter::CounterClient::new(server);
+    let numbers = (1..4)
+        .map(|number| schema::counter::SumRequest { number })
+        .collect::<Vec<_>>();
+
+    let future = client
+        .running_sum(Box::new(futures::stream::iter_ok(numbers)))
+        .for_each(|r| {
+            eprintln!("Response: {:?}", r);
+            Ok(())
+        })
+        .map_err(|e| {
+            eprintln!("Error: {:?}", e);
+        });
+    tokio::run(future)
+}
+
+fn run_calculator_roundtrip() {
+    use schema::calculator::Calculator;
+
+    let server = schema::calculator::CalculatorServer::new(CalculatorService);
+    let client = schema::calculator::CalculatorClient::new(server);
+
+    match block_on(client.add(schema::calculator::AddRequest { a: 1, b: 2 })) {
+        Ok(r) => eprintln!("Response: {:?}", r),
+        Err(e) => eprintln!("Error: {:?}", e),
+    }
+}
+
+fn run_blocking_echo_roundtrip() {
+    let server = schema::echo::EchoServer::new(schema::echo::EchoBlockingServer::new(
+        BlockingEchoService,
+    ));
+    let client = schema::echo::EchoBlockingClient::new(server);
+    let data = vec![1, 2, 3];
+
+    match client.echo(schema::echo::EchoRequest { data }) {
+        Ok(r) => eprintln!("Response: {:?}", r),
+        Err(e) => eprintln!("Error: {:?}", e),
+    }
+}
+
+/// Runs a `std::future::Future` to completion on the current thread.
+fn block_on<F>(future: F) -> F::Output
+where
+    F: std::future::Future,
+{
+    struct ThreadWaker(std::thread::Thread);
+
+    impl std::task::Wake for ThreadWaker {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let mut future = Box::pin(future);
+    let waker = std::task::Waker::from(std::sync::Arc::new(ThreadWaker(std::thread::current())));
+    let mut context = std::task::Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            std::task::Poll::Ready(output) => return output,
+            std::task::Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
 #[derive(Debug, Eq, Fail, PartialEq)]
 #[fail(display = "Error!")]
 struct Error;
@@ -84,6 +229,12 @@ impl schema::echo::Echo for EchoService {
     type EchoFuture = futures::future::FutureResult<schema::echo::EchoResponse, Self::Error>;
 
     fn echo(&self, input: schema::echo::EchoRequest) -> Self::EchoFuture {
+        // Tag the response with the ID of the request, if the client sent one.
+        let context = prost_simple_rpc::context::Context::current();
+        if let Some(request_id) = context.metadata().get("request-id") {
+            context.set_trailer("request-id", request_id);
+        }
+
         if self.fail {
             futures::future::err(Error)
         } else {
@@ -116,7 +267,7 @@ impl schema::greeting::Greeting for GreetingService {
     }
 
     fn say_goodbye(&self, input: schema::greeting::SayGoodbyeRequest) -> Self::SayGoodbyeFuture {
-        if self.fail_hello {
+        if self.fail_goodbye {
             futures::future::err(Error)
         } else {
             futures::future::ok(schema::greeting::SayGoodbyeResponse {
@@ -126,10 +277,331 @@ impl schema::greeting::Greeting for GreetingService {
     }
 }
 
+#[derive(Clone, Debug)]
+struct CounterService;
+
+impl schema::counter::Counter for CounterService {
+    type Error = Error;
+    type CountStream =
+        futures::stream::IterOk<std::vec::IntoIter<schema::counter::CountResponse>, Self::Error>;
+    type SumFuture =
+        Box<dyn futures::Future<Item = schema::counter::SumResponse, Error = Self::Error> + Send>;
+    type RunningSumStream =
+        Box<dyn futures::Stream<Item = schema::counter::SumResponse, Error = Self::Error> + Send>;
+
+    fn count(&self, input: schema::counter::CountRequest) -> Self::CountStream {
+        futures::stream::iter_ok(
+            (0..input.limit)
+                .map(|number| schema::counter::CountResponse { number })
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn sum(
+        &self,
+        input: prost_simple_rpc::handler::InputStream<schema::counter::SumRequest>,
+    ) -> Self::SumFuture {
+        use futures::Future;
+        use futures::Stream;
+
+        Box::new(
+            input
+                .fold(0, |sum, request| Ok::<_, failure::Error>(sum + request.number))
+                .map(|sum| schema::counter::SumResponse { sum })
+                .map_err(|_| Error),
+        )
+    }
+
+    fn running_sum(
+        &self,
+        input: prost_simple_rpc::handler::InputStream<schema::counter::SumRequest>,
+    ) -> Self::RunningSumStream {
+        use futures::Stream;
+
+        let mut sum = 0;
+        Box::new(
+            input
+                .map(move |request| {
+                    sum += request.number;
+                    schema::counter::SumResponse { sum }
+                })
+                .map_err(|_| Error),
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+struct BlockingEchoService;
+
+impl schema::echo::EchoBlocking for BlockingEchoService {
+    type Error = Error;
+
+    fn echo(
+        &self,
+        input: schema::echo::EchoRequest,
+    ) -> Result<schema::echo::EchoResponse, Self::Error> {
+        if input.data.is_empty() {
+            Err(Error)
+        } else {
+            Ok(schema::echo::EchoResponse { data: input.data })
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CalculatorService;
+
+impl schema::calculator::Calculator for CalculatorService {
+    type Error = Error;
+
+    async fn add(
+        &self,
+        input: schema::calculator::AddRequest,
+    ) -> Result<schema::calculator::AddResponse, Self::Error> {
+        Ok(schema::calculator::AddResponse {
+            sum: input.a + input.b,
+        })
+    }
+
+    async fn divide(
+        &self,
+        input: schema::calculator::DivideRequest,
+    ) -> Result<schema::calculator::DivideResponse, Self::Error> {
+        if input.divisor == 0 {
+            Err(Error)
+        } else {
+            Ok(schema::calculator::DivideResponse {
+                quotient: input.dividend / input.divisor,
+            })
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use std::sync;
+    use std::time;
+
+    /// An echo service that never responds, but remembers the context of each call.
+    #[derive(Clone, Debug, Default)]
+    struct PendingEchoService {
+        contexts: sync::Arc<sync::Mutex<Vec<prost_simple_rpc::context::Context>>>,
+    }
+
+    impl schema::echo::Echo for PendingEchoService {
+        type Error = Error;
+        type EchoFuture = futures::future::Empty<schema::echo::EchoResponse, Self::Error>;
+
+        fn echo(&self, _input: schema::echo::EchoRequest) -> Self::EchoFuture {
+            self.contexts
+                .lock()
+                .unwrap()
+                .push(prost_simple_rpc::context::Context::current());
+            futures::future::empty()
+        }
+    }
+
+    type PendingEchoClient = schema::echo::EchoClient<schema::echo::EchoServer<PendingEchoService>>;
+
+    /// An echo service that forwards each call to another echo service.
+    #[derive(Clone, Debug)]
+    struct RelayEchoService(PendingEchoClient);
+
+    impl schema::echo::Echo for RelayEchoService {
+        type Error = <PendingEchoClient as schema::echo::Echo>::Error;
+        type EchoFuture = <PendingEchoClient as schema::echo::Echo>::EchoFuture;
+
+        fn echo(&self, input: schema::echo::EchoRequest) -> Self::EchoFuture {
+            self.0.echo(input)
+        }
+    }
+
+    /// An echo service that only knows how to fail with a status.
+    #[derive(Clone, Debug)]
+    struct StatusEchoService;
+
+    impl schema::echo::Echo for StatusEchoService {
+        type Error = prost_simple_rpc::status::Status;
+        type EchoFuture = futures::future::FutureResult<schema::echo::EchoResponse, Self::Error>;
+
+        fn echo(&self, _input: schema::echo::EchoRequest) -> Self::EchoFuture {
+            futures::future::err(prost_simple_rpc::status::Status::new(
+                prost_simple_rpc::status::Code::NotFound,
+                "nothing to echo",
+            ))
+        }
+    }
+
+    /// An echo service that fails with a status whose message is `len` bytes long.
+    #[derive(Clone, Debug)]
+    struct LargeStatusEchoService {
+        len: usize,
+    }
+
+    impl schema::echo::Echo for LargeStatusEchoService {
+        type Error = prost_simple_rpc::status::Status;
+        type EchoFuture = futures::future::FutureResult<schema::echo::EchoResponse, Self::Error>;
+
+        fn echo(&self, _input: schema::echo::EchoRequest) -> Self::EchoFuture {
+            futures::future::err(prost_simple_rpc::status::Status::new(
+                prost_simple_rpc::status::Code::NotFound,
+                "x".repeat(self.len),
+            ))
+        }
+    }
+
+    /// An echo service that responds with `len` bytes when it is asked to echo nothing.
+    #[derive(Clone, Debug)]
+    struct LargeEchoService {
+        len: usize,
+    }
+
+    impl schema::echo::Echo for LargeEchoService {
+        type Error = Error;
+        type EchoFuture = futures::future::FutureResult<schema::echo::EchoResponse, Self::Error>;
+
+        fn echo(&self, input: schema::echo::EchoRequest) -> Self::EchoFuture {
+            let data = if input.data.is_empty() {
+                vec![0; self.len]
+            } else {
+                input.data
+            };
+            futures::future::ok(schema::echo::EchoResponse { data })
+        }
+    }
+
+    /// An echo service that responds with the credentials of its caller, if they are known.
+    #[derive(Clone, Debug)]
+    struct PeerEchoService;
+
+    impl schema::echo::Echo for PeerEchoService {
+        type Error = Error;
+        type EchoFuture = futures::future::FutureResult<schema::echo::EchoResponse, Self::Error>;
+
+        fn echo(&self, _input: schema::echo::EchoRequest) -> Self::EchoFuture {
+            let peer = match prost_simple_rpc::context::Context::current().peer_credentials() {
+                Some(credentials) => format!("{}:{:?}", credentials.uid, credentials.pid),
+                None => "unknown".to_owned(),
+            };
+            futures::future::ok(schema::echo::EchoResponse {
+                data: peer.into_bytes(),
+            })
+        }
+    }
+
+    /// An echo service that counts the calls that reach it.
+    #[derive(Clone, Debug, Default)]
+    struct CountingEchoService {
+        calls: sync::Arc<sync::atomic::AtomicUsize>,
+    }
+
+    impl schema::echo::Echo for CountingEchoService {
+        type Error = Error;
+        type EchoFuture = futures::future::FutureResult<schema::echo::EchoResponse, Self::Error>;
+
+        fn echo(&self, input: schema::echo::EchoRequest) -> Self::EchoFuture {
+            self.calls.fetch_add(1, sync::atomic::Ordering::SeqCst);
+            futures::future::ok(schema::echo::EchoResponse { data: input.data })
+        }
+    }
+
+    /// An echo service that fails its first calls with a status, and counts all calls.
+    #[derive(Clone, Debug)]
+    struct FlakyEchoService {
+        failures: usize,
+        code: prost_simple_rpc::status::Code,
+        calls: sync::Arc<sync::atomic::AtomicUsize>,
+    }
+
+    impl FlakyEchoService {
+        fn new(failures: usize, code: prost_simple_rpc::status::Code) -> FlakyEchoService {
+            FlakyEchoService {
+                failures,
+                code,
+                calls: sync::Arc::default(),
+            }
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    impl schema::echo::Echo for FlakyEchoService {
+        type Error = prost_simple_rpc::status::Status;
+        type EchoFuture = futures::future::FutureResult<schema::echo::EchoResponse, Self::Error>;
+
+        fn echo(&self, input: schema::echo::EchoRequest) -> Self::EchoFuture {
+            if self.calls.fetch_add(1, sync::atomic::Ordering::SeqCst) < self.failures {
+                futures::future::err(prost_simple_rpc::status::Status::new(self.code, "flaky"))
+            } else {
+                futures::future::ok(schema::echo::EchoResponse { data: input.data })
+            }
+        }
+    }
+
+    /// A layer that records the calls that pass through its handlers.
+    #[derive(Clone, Debug)]
+    struct RecordLayer {
+        name: &'static str,
+        log: sync::Arc<sync::Mutex<Vec<String>>>,
+    }
+
+    #[derive(Clone, Debug)]
+    struct Recorded<H> {
+        handler: H,
+        layer: RecordLayer,
+    }
+
+    impl<H> prost_simple_rpc::middleware::Layer<H> for RecordLayer
+    where
+        H: prost_simple_rpc::handler::Handler,
+        H::CallFuture: 'static,
+    {
+        type Handler = Recorded<H>;
+
+        fn layer(&self, handler: H) -> Recorded<H> {
+            Recorded {
+                handler,
+                layer: self.clone(),
+            }
+        }
+    }
+
+    impl<H> prost_simple_rpc::handler::Handler for Recorded<H>
+    where
+        H: prost_simple_rpc::handler::Handler,
+        H::CallFuture: 'static,
+    {
+        type Error = H::Error;
+        type Descriptor = H::Descriptor;
+        type CallFuture = Box<dyn futures::Future<Item = bytes::Bytes, Error = H::Error> + Send>;
+
+        fn call(
+            &self,
+            method: <H::Descriptor as prost_simple_rpc::descriptor::ServiceDescriptor>::Method,
+            input: bytes::Bytes,
+            context: prost_simple_rpc::context::Context,
+        ) -> Self::CallFuture {
+            use futures::Future;
+            use prost_simple_rpc::descriptor::MethodDescriptor;
+
+            let layer = self.layer.clone();
+            let entry = format!("{} {}", layer.name, method.proto_name());
+            layer.log.lock().unwrap().push(entry);
+            Box::new(self.handler.call(method, input, context).then(move |result| {
+                layer.log.lock().unwrap().push(format!("{} done", layer.name));
+                result
+            }))
+        }
+    }
+
+    struct NoopNotify;
+
+    impl futures::executor::Notify for NoopNotify {
+        fn notify(&self, _id: usize) {}
+    }
 
     #[test]
     fn echo_success() {
@@ -197,4 +669,2790 @@ mod test {
             ))
         );
     }
+
+    #[test]
+    fn count_success() {
+        use futures::Future;
+        use futures::Stream;
+        use schema::counter::Counter;
+
+        let server = schema::counter::CounterServer::new(CounterService);
+        let client = schema::counter::CounterClient::new(server);
+
+        let response = sync::Arc::new(sync::Mutex::new(None));
+        let response_clone = response.clone();
+        let error = sync::Arc::new(sync::Mutex::new(None));
+        let error_clone = error.clone();
+        let future = client
+            .count(schema::counter::CountRequest { limit: 3 })
+            .collect()
+            .map(move |r| {
+                *response_clone.lock().unwrap() = Some(r);
+            })
+            .map_err(move |e| {
+                *error_clone.lock().unwrap() = Some(e);
+            });
+
+        tokio::run(future);
+
+        assert_eq!(
+            *response.lock().unwrap(),
+            Some(vec![
+                schema::counter::CountResponse { number: 0 },
+                schema::counter::CountResponse { number: 1 },
+                schema::counter::CountResponse { number: 2 },
+            ])
+        );
+        assert_eq!(*error.lock().unwrap(), None);
+    }
+
+    #[test]
+    fn count_as_unary_call() {
+        use futures::Future;
+        use prost_simple_rpc::handler::Handler;
+
+        let server = schema::counter::CounterServer::new(CounterService);
+        let result = server
+            .call(
+                schema::counter::CounterMethodDescriptor::Count,
+                bytes::Bytes::new(),
+                prost_simple_rpc::context::Context::new(),
+            )
+            .wait();
+
+        assert_eq!(
+            result,
+            Err(prost_simple_rpc::error::Error::unsupported_call("Count"))
+        );
+    }
+
+    #[test]
+    fn sum_success() {
+        use futures::Future;
+        use schema::counter::Counter;
+
+        let server = schema::counter::CounterServer::new(CounterService);
+        let client = schema::counter::CounterClient::new(server);
+        let numbers = (1..4)
+            .map(|number| schema::counter::SumRequest { number })
+            .collect::<Vec<_>>();
+
+        let response = sync::Arc::new(sync::Mutex::new(None));
+        let response_clone = response.clone();
+        let error = sync::Arc::new(sync::Mutex::new(None));
+        let error_clone = error.clone();
+        let future = client
+            .sum(Box::new(futures::stream::iter_ok(numbers)))
+            .map(move |r| {
+                *response_clone.lock().unwrap() = Some(r);
+            })
+            .map_err(move |e| {
+                *error_clone.lock().unwrap() = Some(e);
+            });
+
+        tokio::run(future);
+
+        assert_eq!(
+            *response.lock().unwrap(),
+            Some(schema::counter::SumResponse { sum: 6 })
+        );
+        assert_eq!(*error.lock().unwrap(), None);
+    }
+
+    #[test]
+    fn running_sum_success() {
+        use futures::Future;
+        use futures::Stream;
+        use schema::counter::Counter;
+
+        let server = schema::counter::CounterServer::new(CounterService);
+        let client = schema::counter::CounterClient::new(server);
+        let numbers = (1..4)
+            .map(|number| schema::counter::SumRequest { number })
+            .collect::<Vec<_>>();
+
+        let response = sync::Arc::new(sync::Mutex::new(None));
+        let response_clone = response.clone();
+        let error = sync::Arc::new(sync::Mutex::new(None));
+        let error_clone = error.clone();
+        let future = client
+            .running_sum(Box::new(futures::stream::iter_ok(numbers)))
+            .collect()
+            .map(move |r| {
+                *response_clone.lock().unwrap() = Some(r);
+            })
+            .map_err(move |e| {
+                *error_clone.lock().unwrap() = Some(e);
+            });
+
+        tokio::run(future);
+
+        assert_eq!(
+            *response.lock().unwrap(),
+            Some(vec![
+                schema::counter::SumResponse { sum: 1 },
+                schema::counter::SumResponse { sum: 3 },
+                schema::counter::SumResponse { sum: 6 },
+            ])
+        );
+        assert_eq!(*error.lock().unwrap(), None);
+    }
+
+    #[test]
+    fn add_success() {
+        use schema::calculator::Calculator;
+
+        let server = schema::calculator::CalculatorServer::new(CalculatorService);
+        let client = schema::calculator::CalculatorClient::new(server);
+
+        assert_eq!(
+            block_on(client.add(schema::calculator::AddRequest { a: 1, b: 2 })),
+            Ok(schema::calculator::AddResponse { sum: 3 })
+        );
+    }
+
+    #[test]
+    fn divide_fail() {
+        use schema::calculator::Calculator;
+
+        let server = schema::calculator::CalculatorServer::new(CalculatorService);
+        let client = schema::calculator::CalculatorClient::new(server);
+
+        // We expect two layers of execution errors; one from the server and one from the client.
+        assert_eq!(
+            block_on(client.divide(schema::calculator::DivideRequest {
+                dividend: 1,
+                divisor: 0,
+            })),
+            Err(prost_simple_rpc::error::Error::execution(
+                prost_simple_rpc::error::Error::execution(Error)
+            ))
+        );
+    }
+
+    #[test]
+    fn blocking_echo_success() {
+        let server = schema::echo::EchoServer::new(schema::echo::EchoBlockingServer::new(
+            BlockingEchoService,
+        ));
+        let client = schema::echo::EchoBlockingClient::new(server);
+
+        assert_eq!(
+            client.echo(schema::echo::EchoRequest {
+                data: vec![1, 2, 3],
+            }),
+            Ok(schema::echo::EchoResponse {
+                data: vec![1, 2, 3],
+            })
+        );
+    }
+
+    #[test]
+    fn blocking_echo_fail() {
+        let server = schema::echo::EchoServer::new(schema::echo::EchoBlockingServer::new(
+            BlockingEchoService,
+        ));
+        let client = schema::echo::EchoBlockingClient::new(server);
+
+        assert_eq!(
+            client.echo(schema::echo::EchoRequest { data: vec![] }),
+            Err(prost_simple_rpc::error::Error::execution(
+                prost_simple_rpc::error::Error::execution(Error)
+            ))
+        );
+    }
+
+    #[test]
+    fn blocking_count_success() {
+        let server = schema::counter::CounterServer::new(CounterService);
+        let client = schema::counter::CounterBlockingClient::new(server);
+
+        assert_eq!(
+            client
+                .count(schema::counter::CountRequest { limit: 2 })
+                .collect::<Result<Vec<_>, _>>(),
+            Ok(vec![
+                schema::counter::CountResponse { number: 0 },
+                schema::counter::CountResponse { number: 1 },
+            ])
+        );
+        assert_eq!(
+            client.sum((1..4).map(|number| schema::counter::SumRequest { number })),
+            Ok(schema::counter::SumResponse { sum: 6 })
+        );
+    }
+
+    #[test]
+    fn metadata_trailers() {
+        use futures::Future;
+        use schema::echo::Echo;
+
+        let server = schema::echo::EchoServer::new(EchoService { fail: false });
+        let client = schema::echo::EchoClient::new(server);
+
+        let mut metadata = prost_simple_rpc::metadata::Metadata::new();
+        metadata.insert("Request-ID", "1234");
+        let context = prost_simple_rpc::context::Context::with_metadata(metadata);
+
+        let response = context
+            .scope(|| {
+                client.echo(schema::echo::EchoRequest {
+                    data: vec![1, 2, 3],
+                })
+            })
+            .wait()
+            .unwrap();
+
+        assert_eq!(response.data, vec![1, 2, 3]);
+        assert_eq!(context.trailers().get("request-id"), Some("1234"));
+
+        let response = client
+            .echo(schema::echo::EchoRequest {
+                data: vec![1, 2, 3],
+            })
+            .wait()
+            .unwrap();
+
+        assert_eq!(response.data, vec![1, 2, 3]);
+        assert!(prost_simple_rpc::context::Context::current()
+            .trailers()
+            .is_empty());
+    }
+
+    #[test]
+    fn deadline_exceeded() {
+        use futures::Future;
+        use schema::counter::Counter;
+
+        let server = schema::counter::CounterServer::new(CounterService);
+        let client = schema::counter::CounterClient::new(server);
+        let context = prost_simple_rpc::context::Context::new()
+            .with_timeout(time::Duration::from_millis(50));
+        let start = time::Instant::now();
+
+        let result = context
+            .scope(|| client.sum(Box::new(futures::future::empty().into_stream())))
+            .wait();
+
+        assert_eq!(
+            result,
+            Err(prost_simple_rpc::error::Error::deadline_exceeded())
+        );
+        assert!(start.elapsed() >= time::Duration::from_millis(50));
+        assert!(context.is_cancelled());
+    }
+
+    #[test]
+    fn deadline_propagation() {
+        use schema::echo::Echo;
+
+        let service = PendingEchoService::default();
+        let downstream = schema::echo::EchoServer::new(service.clone());
+        let relay = RelayEchoService(schema::echo::EchoClient::new(downstream));
+        let client = schema::echo::EchoClient::new(schema::echo::EchoServer::new(relay));
+
+        let deadline = time::Instant::now() + time::Duration::from_secs(60);
+        let mut metadata = prost_simple_rpc::metadata::Metadata::new();
+        metadata.insert("authorization", "secret");
+        let context =
+            prost_simple_rpc::context::Context::with_metadata(metadata).with_deadline(deadline);
+
+        let future = context.scope(|| client.echo(schema::echo::EchoRequest { data: vec![] }));
+        let mut spawn = futures::executor::spawn(future);
+        let notify = sync::Arc::new(NoopNotify);
+        assert!(spawn.poll_future_notify(&notify, 0).unwrap().is_not_ready());
+
+        let downstream_context = service.contexts.lock().unwrap()[0].clone();
+        assert_eq!(downstream_context.deadline(), Some(deadline));
+        assert!(downstream_context.metadata().is_empty());
+        assert!(!downstream_context.is_cancelled());
+
+        // Cancelling the outermost call cancels the downstream call too.
+        context.cancel();
+        assert!(downstream_context.is_cancelled());
+        assert_eq!(
+            spawn.poll_future_notify(&notify, 0),
+            Err(prost_simple_rpc::error::Error::cancelled())
+        );
+    }
+
+    #[test]
+    fn cancellation_on_drop() {
+        use schema::echo::Echo;
+
+        let service = PendingEchoService::default();
+        let client = schema::echo::EchoClient::new(schema::echo::EchoServer::new(service.clone()));
+
+        let future = client.echo(schema::echo::EchoRequest { data: vec![] });
+        let mut spawn = futures::executor::spawn(future);
+        let notify = sync::Arc::new(NoopNotify);
+        assert!(spawn.poll_future_notify(&notify, 0).unwrap().is_not_ready());
+
+        let server_context = service.contexts.lock().unwrap()[0].clone();
+        assert!(!server_context.is_cancelled());
+
+        drop(spawn);
+        assert!(server_context.is_cancelled());
+    }
+
+    #[test]
+    fn status_round_trip() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+
+        let server = schema::echo::EchoServer::new(StatusEchoService);
+        let client = schema::echo::EchoClient::new(server);
+
+        let error = client
+            .echo(schema::echo::EchoRequest { data: vec![] })
+            .wait()
+            .unwrap_err();
+        let status = error.to_status();
+
+        assert_eq!(status.code(), prost_simple_rpc::status::Code::NotFound);
+        assert_eq!(status.message(), "nothing to echo");
+        assert_eq!(
+            prost_simple_rpc::status::Status::decode(status.encode()),
+            Ok(status)
+        );
+        assert_eq!(
+            prost_simple_rpc::status::Code::from_i32(42),
+            prost_simple_rpc::status::Code::Unknown
+        );
+    }
+
+    #[test]
+    fn status_through_router() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+
+        let mut router = prost_simple_rpc::router::Router::new();
+        router
+            .add(schema::echo::EchoServer::new(StatusEchoService))
+            .add(schema::greeting::GreetingServer::new(GreetingService {
+                fail_hello: false,
+                fail_goodbye: true,
+            }));
+
+        let call = |service, method| {
+            router
+                .call(
+                    service,
+                    method,
+                    bytes::Bytes::new(),
+                    prost_simple_rpc::context::Context::new(),
+                )
+                .wait()
+                .unwrap_err()
+                .to_status()
+                .code()
+        };
+
+        assert_eq!(
+            call("echo.Echo", "Echo"),
+            prost_simple_rpc::status::Code::NotFound
+        );
+        assert_eq!(
+            call("echo.Echo", "Shout"),
+            prost_simple_rpc::status::Code::Unimplemented
+        );
+        assert_eq!(
+            call("greeting.Greeting", "SayGoodbye"),
+            prost_simple_rpc::status::Code::Unknown
+        );
+
+        // The error of the service itself is part of the cause chain.
+        match router
+            .call(
+                "greeting.Greeting",
+                "SayGoodbye",
+                bytes::Bytes::new(),
+                prost_simple_rpc::context::Context::new(),
+            )
+            .wait()
+        {
+            Err(prost_simple_rpc::error::Error::Execution { error }) => {
+                let cause = failure::Fail::cause(&error).unwrap();
+                assert_eq!(
+                    cause.downcast_ref::<prost_simple_rpc::error::Error<Error>>(),
+                    Some(&prost_simple_rpc::error::Error::execution(Error))
+                );
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    /// A socket that transports can serve calls on.
+    trait LocalSocket: Sized {
+        fn bind(addr: &std::net::SocketAddr) -> std::io::Result<Self>;
+
+        fn local_addr(&self) -> std::io::Result<std::net::SocketAddr>;
+    }
+
+    impl LocalSocket for tokio::net::TcpListener {
+        fn bind(addr: &std::net::SocketAddr) -> std::io::Result<Self> {
+            tokio::net::TcpListener::bind(addr)
+        }
+
+        fn local_addr(&self) -> std::io::Result<std::net::SocketAddr> {
+            tokio::net::TcpListener::local_addr(self)
+        }
+    }
+
+    impl LocalSocket for tokio::net::UdpSocket {
+        fn bind(addr: &std::net::SocketAddr) -> std::io::Result<Self> {
+            tokio::net::UdpSocket::bind(addr)
+        }
+
+        fn local_addr(&self) -> std::io::Result<std::net::SocketAddr> {
+            tokio::net::UdpSocket::local_addr(self)
+        }
+    }
+
+    /// Serves calls with the `serve` function of a transport, on a free port of the local host.
+    fn serve_on<S, D, F, T>(
+        runtime: &mut tokio::runtime::Runtime,
+        serve: F,
+        dispatch: D,
+    ) -> std::net::SocketAddr
+    where
+        S: LocalSocket,
+        D: prost_simple_rpc::transport::Dispatch,
+        F: FnOnce(S, D) -> T,
+        T: futures::Future<Item = (), Error = std::io::Error> + Send + 'static,
+    {
+        let socket = S::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = socket.local_addr().unwrap();
+        runtime.spawn(serve(socket, dispatch).map_err(|e| panic!("server failed: {}", e)));
+        addr
+    }
+
+    #[test]
+    fn tcp_multiplexing() {
+        use futures::Future;
+        use schema::echo::Echo;
+        use schema::greeting::Greeting;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut router = prost_simple_rpc::router::Router::new();
+        router
+            .add(schema::echo::EchoServer::new(EchoService { fail: false }))
+            .add(schema::greeting::GreetingServer::new(GreetingService {
+                fail_hello: false,
+                fail_goodbye: false,
+            }));
+        let addr = serve_on(&mut runtime, prost_simple_rpc::transport::tcp::serve, router);
+
+        let future = prost_simple_rpc::transport::tcp::connect(&addr)
+            .map_err(|e| panic!("connect failed: {}", e))
+            .and_then(|connection| {
+                let echo = schema::echo::EchoClient::new(
+                    prost_simple_rpc::transport::Client::new(connection.clone()),
+                );
+                let greeting = schema::greeting::GreetingClient::new(
+                    prost_simple_rpc::transport::Client::new(connection),
+                );
+
+                let echoes = (0..10u8).map(|i| {
+                    let mut metadata = prost_simple_rpc::metadata::Metadata::new();
+                    metadata.insert("request-id", i.to_string());
+                    let context = prost_simple_rpc::context::Context::with_metadata(metadata);
+                    let trailers = context.clone();
+                    context
+                        .scope(|| echo.echo(schema::echo::EchoRequest { data: vec![i] }))
+                        .map(move |response| (response.data, trailers.trailers()))
+                });
+                let greetings = (0..10).map(|i| {
+                    greeting
+                        .say_hello(schema::greeting::SayHelloRequest {
+                            name: i.to_string(),
+                        })
+                        .map(|response| response.greeting)
+                });
+
+                futures::future::join_all(echoes.collect::<Vec<_>>())
+                    .join(futures::future::join_all(greetings.collect::<Vec<_>>()))
+            });
+
+        let (echoes, greetings) = runtime.block_on(future).unwrap();
+
+        for (i, (data, trailers)) in echoes.into_iter().enumerate() {
+            assert_eq!(data, vec![i as u8]);
+            assert_eq!(trailers.get("request-id"), Some(i.to_string().as_str()));
+        }
+        for (i, greeting) in greetings.into_iter().enumerate() {
+            assert_eq!(greeting, format!("Hello, {}!", i));
+        }
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn tcp_errors() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+        use schema::greeting::Greeting;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let pending = PendingEchoService::default();
+        let pending_addr = serve_on(
+            &mut runtime,
+            prost_simple_rpc::transport::tcp::serve,
+            schema::echo::EchoServer::new(pending.clone()),
+        );
+        let greeting_addr = serve_on(
+            &mut runtime,
+            prost_simple_rpc::transport::tcp::serve,
+            schema::greeting::GreetingServer::new(GreetingService {
+                fail_hello: false,
+                fail_goodbye: true,
+            }),
+        );
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::tcp::connect(&greeting_addr))
+            .unwrap();
+        let greeting = schema::greeting::GreetingClient::new(
+            prost_simple_rpc::transport::Client::new(connection.clone()),
+        );
+        let error = runtime
+            .block_on(greeting.say_goodbye(schema::greeting::SayGoodbyeRequest {
+                name: "dflemstr".to_owned(),
+            }))
+            .unwrap_err();
+        assert_eq!(
+            error.to_status().code(),
+            prost_simple_rpc::status::Code::Unknown
+        );
+
+        // The greeting server doesn't know about the echo service.
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(
+            connection,
+        ));
+        let error = runtime
+            .block_on(echo.echo(schema::echo::EchoRequest { data: vec![] }))
+            .unwrap_err();
+        assert_eq!(
+            error.to_status().code(),
+            prost_simple_rpc::status::Code::Unimplemented
+        );
+
+        // The deadline is enforced by the client, and sent along to the server.
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::tcp::connect(&pending_addr))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(
+            connection,
+        ));
+        let context = prost_simple_rpc::context::Context::new()
+            .with_timeout(time::Duration::from_millis(50));
+        let result =
+            runtime.block_on(context.scope(|| echo.echo(schema::echo::EchoRequest { data: vec![] })));
+        assert_eq!(
+            result.unwrap_err().to_status().code(),
+            prost_simple_rpc::status::Code::DeadlineExceeded
+        );
+
+        let server_context = pending.contexts.lock().unwrap()[0].clone();
+        assert!(server_context.deadline().is_some());
+        runtime.block_on(server_context.cancelled()).unwrap();
+        assert!(server_context.is_cancelled());
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn tcp_large_messages() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = schema::echo::EchoServer::new(LargeEchoService {
+            len: prost_simple_rpc::transport::tcp::MAX_MESSAGE_LEN,
+        });
+        let addr = serve_on(&mut runtime, prost_simple_rpc::transport::tcp::serve, server);
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::tcp::connect(&addr))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(
+            connection.clone(),
+        ));
+
+        // Messages that are too large only fail their own call, not the others on the connection.
+        let data = vec![0; prost_simple_rpc::transport::tcp::MAX_MESSAGE_LEN];
+        let future = echo
+            .echo(schema::echo::EchoRequest { data })
+            .then(Ok::<_, ()>)
+            .join3(
+                echo.echo(schema::echo::EchoRequest { data: vec![] })
+                    .then(Ok),
+                echo.echo(schema::echo::EchoRequest { data: vec![1, 2, 3] })
+                    .then(Ok),
+            );
+        let (request, response, small) = runtime.block_on(future).unwrap();
+        match request {
+            Err(prost_simple_rpc::error::Error::Execution {
+                error: prost_simple_rpc::transport::Error::TooLarge { len, max },
+            }) => {
+                assert!(len > max);
+                assert_eq!(max, prost_simple_rpc::transport::tcp::MAX_MESSAGE_LEN);
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(
+            response.unwrap_err().to_status().code(),
+            prost_simple_rpc::status::Code::ResourceExhausted
+        );
+        assert_eq!(small.unwrap().data, vec![1, 2, 3]);
+        assert!(!connection.is_closed());
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn grpc_round_trip() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+        use schema::greeting::Greeting;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut router = prost_simple_rpc::router::Router::new();
+        router
+            .add(schema::echo::EchoServer::new(EchoService { fail: false }))
+            .add(schema::greeting::GreetingServer::new(GreetingService {
+                fail_hello: false,
+                fail_goodbye: true,
+            }));
+        let addr = serve_on(&mut runtime, prost_simple_rpc::transport::grpc::serve, router);
+        let status_addr = serve_on(
+            &mut runtime,
+            prost_simple_rpc::transport::grpc::serve,
+            schema::echo::EchoServer::new(StatusEchoService),
+        );
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::grpc::connect(&addr))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::grpc::Client::new(
+            connection.clone(),
+        ));
+        let greeting = schema::greeting::GreetingClient::new(
+            prost_simple_rpc::transport::grpc::Client::new(connection),
+        );
+
+        let mut metadata = prost_simple_rpc::metadata::Metadata::new();
+        metadata.insert("request-id", "42");
+        let context = prost_simple_rpc::context::Context::with_metadata(metadata);
+        let response = runtime
+            .block_on(context.scope(|| echo.echo(schema::echo::EchoRequest { data: vec![1, 2] })))
+            .unwrap();
+        assert_eq!(response.data, vec![1, 2]);
+        assert_eq!(context.trailers().get("request-id"), Some("42"));
+
+        // Requests that are too large are never sent.
+        let data = vec![0; prost_simple_rpc::transport::grpc::MAX_MESSAGE_LEN];
+        match runtime.block_on(echo.echo(schema::echo::EchoRequest { data })) {
+            Err(prost_simple_rpc::error::Error::Execution {
+                error: prost_simple_rpc::transport::Error::TooLarge { len, max },
+            }) => {
+                assert!(len > max);
+                assert_eq!(max, prost_simple_rpc::transport::grpc::MAX_MESSAGE_LEN);
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        let response = runtime
+            .block_on(greeting.say_hello(schema::greeting::SayHelloRequest {
+                name: "dflemstr".to_owned(),
+            }))
+            .unwrap();
+        assert_eq!(response.greeting, "Hello, dflemstr!");
+
+        let error = runtime
+            .block_on(greeting.say_goodbye(schema::greeting::SayGoodbyeRequest {
+                name: "dflemstr".to_owned(),
+            }))
+            .unwrap_err();
+        assert_eq!(
+            error.to_status().code(),
+            prost_simple_rpc::status::Code::Unknown
+        );
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::grpc::connect(&status_addr))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::grpc::Client::new(
+            connection,
+        ));
+        let error = runtime
+            .block_on(echo.echo(schema::echo::EchoRequest { data: vec![] }))
+            .unwrap_err();
+        assert_eq!(
+            error.to_status(),
+            prost_simple_rpc::status::Status::new(
+                prost_simple_rpc::status::Code::NotFound,
+                "nothing to echo",
+            )
+        );
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn grpc_deadline() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let pending = PendingEchoService::default();
+        let addr = serve_on(
+            &mut runtime,
+            prost_simple_rpc::transport::grpc::serve,
+            schema::echo::EchoServer::new(pending.clone()),
+        );
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::grpc::connect(&addr))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::grpc::Client::new(
+            connection,
+        ));
+        let context = prost_simple_rpc::context::Context::new()
+            .with_timeout(time::Duration::from_millis(50));
+        let future = context.scope(|| echo.echo(schema::echo::EchoRequest { data: vec![] }));
+        assert_eq!(
+            runtime.block_on(future).unwrap_err().to_status().code(),
+            prost_simple_rpc::status::Code::DeadlineExceeded
+        );
+
+        // The deadline was sent along in `grpc-timeout`.
+        let server_context = pending.contexts.lock().unwrap()[0].clone();
+        assert!(server_context.deadline().is_some());
+        runtime.block_on(server_context.cancelled()).unwrap();
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn grpc_wire_format() {
+        use futures::Future;
+        use futures::Stream;
+        use prost::Message;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let addr = serve_on(
+            &mut runtime,
+            prost_simple_rpc::transport::grpc::serve,
+            schema::echo::EchoServer::new(EchoService { fail: false }),
+        );
+
+        // Talk to the server like any other gRPC client would.
+        let mut message = Vec::new();
+        schema::echo::EchoRequest { data: vec![7] }
+            .encode(&mut message)
+            .unwrap();
+        let mut body = vec![0, 0, 0, 0, message.len() as u8];
+        body.extend(message);
+
+        let request = move || {
+            http::Request::builder()
+                .method("POST")
+                .uri(format!("http://{}/echo.Echo/Echo", addr))
+                .header("content-type", "application/grpc")
+                .header("te", "trailers")
+                .header("request-id", "7")
+                .body(())
+                .unwrap()
+        };
+        let future = tokio::net::TcpStream::connect(&addr)
+            .map_err(h2::Error::from)
+            .and_then(h2::client::handshake)
+            .and_then(move |(mut client, connection)| {
+                tokio::spawn(connection.map_err(|_| ()));
+                let (response, mut stream) = client.send_request(request(), false).unwrap();
+                stream.send_data(body.into(), true).unwrap();
+                response
+            })
+            .and_then(|response| {
+                let (parts, mut body) = response.into_parts();
+                let mut data = Vec::new();
+                futures::future::poll_fn(move || {
+                    while let Some(chunk) = futures::try_ready!(body.poll()) {
+                        data.extend_from_slice(&chunk);
+                    }
+                    let trailers = futures::try_ready!(body.poll_trailers());
+                    Ok(futures::Async::Ready((std::mem::take(&mut data), trailers)))
+                })
+                .map(move |(data, trailers)| (parts, data, trailers))
+            });
+        let (parts, data, trailers) = runtime.block_on(future).unwrap();
+        let trailers = trailers.unwrap();
+
+        assert_eq!(parts.status, http::StatusCode::OK);
+        assert_eq!(parts.headers["content-type"], "application/grpc");
+        assert_eq!(&data[..5], &[0, 0, 0, 0, data.len() as u8 - 5]);
+        let response = schema::echo::EchoResponse::decode(&data[5..]).unwrap();
+        assert_eq!(response.data, vec![7]);
+        assert_eq!(trailers["grpc-status"], "0");
+        assert_eq!(trailers["request-id"], "7");
+
+        // Messages that are too large are rejected as soon as their length prefix arrives.
+        let future = tokio::net::TcpStream::connect(&addr)
+            .map_err(h2::Error::from)
+            .and_then(h2::client::handshake)
+            .and_then(move |(mut client, connection)| {
+                tokio::spawn(connection.map_err(|_| ()));
+                let (response, mut stream) = client.send_request(request(), false).unwrap();
+                stream
+                    .send_data(vec![0, 0x7f, 0xff, 0xff, 0xff].into(), false)
+                    .unwrap();
+                response.map(move |response| (response, stream))
+            });
+        let (response, _stream) = runtime.block_on(future).unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(response.headers()["grpc-status"], "8");
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn twirp_round_trip() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+        use schema::greeting::Greeting;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut router = prost_simple_rpc::router::Router::new();
+        router
+            .add(schema::echo::EchoServer::new(EchoService { fail: false }))
+            .add(schema::greeting::GreetingServer::new(GreetingService {
+                fail_hello: false,
+                fail_goodbye: true,
+            }));
+        let addr = serve_on(&mut runtime, prost_simple_rpc::transport::twirp::serve, router);
+        let status_addr = serve_on(
+            &mut runtime,
+            prost_simple_rpc::transport::twirp::serve,
+            schema::echo::EchoServer::new(StatusEchoService),
+        );
+
+        let connection =
+            prost_simple_rpc::transport::twirp::Connection::new(format!("http://{}/twirp", addr));
+        let echo = schema::echo::EchoClient::new(
+            prost_simple_rpc::transport::twirp::Client::new(connection.clone()),
+        );
+        let greeting = schema::greeting::GreetingClient::new(
+            prost_simple_rpc::transport::twirp::Client::new(connection),
+        );
+
+        let mut metadata = prost_simple_rpc::metadata::Metadata::new();
+        metadata.insert("request-id", "42");
+        let context = prost_simple_rpc::context::Context::with_metadata(metadata);
+        let future = context.scope(|| echo.echo(schema::echo::EchoRequest { data: vec![1, 2] }));
+        assert_eq!(runtime.block_on(future).unwrap().data, vec![1, 2]);
+        assert_eq!(context.trailers().get("request-id"), Some("42"));
+
+        let error = runtime
+            .block_on(greeting.say_goodbye(schema::greeting::SayGoodbyeRequest {
+                name: "dflemstr".to_owned(),
+            }))
+            .unwrap_err();
+        assert_eq!(
+            error.to_status().code(),
+            prost_simple_rpc::status::Code::Unknown
+        );
+
+        let connection = prost_simple_rpc::transport::twirp::Connection::new(format!(
+            "http://{}/twirp",
+            status_addr
+        ));
+        let echo = schema::echo::EchoClient::new(
+            prost_simple_rpc::transport::twirp::Client::new(connection),
+        );
+        let error = runtime
+            .block_on(echo.echo(schema::echo::EchoRequest { data: vec![] }))
+            .unwrap_err();
+        assert_eq!(
+            error.to_status(),
+            prost_simple_rpc::status::Status::new(
+                prost_simple_rpc::status::Code::NotFound,
+                "nothing to echo",
+            )
+        );
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn twirp_wire_format() {
+        use futures::Future;
+        use prost::Message;
+        use std::io::Read;
+        use std::io::Write;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let addr = serve_on(
+            &mut runtime,
+            prost_simple_rpc::transport::twirp::serve,
+            schema::echo::EchoServer::new(EchoService { fail: false }),
+        );
+        let mut json = prost_simple_rpc::transport::twirp::SerdeJson::new();
+        json.add::<
+            schema::echo::EchoDescriptor,
+            schema::echo::EchoRequest,
+            schema::echo::EchoResponse,
+        >(schema::echo::EchoMethodDescriptor::Echo);
+        json.add::<
+            schema::calculator::CalculatorDescriptor,
+            schema::calculator::AddRequest,
+            schema::calculator::AddResponse,
+        >(schema::calculator::CalculatorMethodDescriptor::Add);
+
+        // 64-bit integers are strings in the proto3 JSON mapping, but numbers are accepted too.
+        {
+            use prost_simple_rpc::transport::twirp::JsonMapping;
+
+            let input = json
+                .input_to_protobuf(
+                    "calculator.Calculator",
+                    "Add",
+                    b"{\"a\":\"-9007199254740993\",\"b\":2}",
+                )
+                .unwrap();
+            assert_eq!(
+                schema::calculator::AddRequest::decode(input).unwrap(),
+                schema::calculator::AddRequest {
+                    a: -9_007_199_254_740_993,
+                    b: 2,
+                }
+            );
+            let mut output = Vec::new();
+            schema::calculator::AddResponse { sum: -9_007_199_254_740_991 }
+                .encode(&mut output)
+                .unwrap();
+            let output = json
+                .output_to_json("calculator.Calculator", "Add", output.into())
+                .unwrap();
+            assert_eq!(&output[..], &b"{\"sum\":\"-9007199254740991\"}"[..]);
+        }
+
+        let json_addr = serve_on(
+            &mut runtime,
+            |listener, server| {
+                prost_simple_rpc::transport::twirp::serve_with_json(listener, server, json)
+            },
+            schema::echo::EchoServer::new(EchoService { fail: false }),
+        );
+
+        // Talk to the servers like `curl` would.
+        let post_to = |addr, path: &str, content_type: &str, body: &[u8]| {
+            let mut socket = std::net::TcpStream::connect(addr).unwrap();
+            write!(
+                socket,
+                "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+                 Request-Id: 7\r\nConnection: close\r\n\r\n",
+                path,
+                addr,
+                content_type,
+                body.len()
+            )
+            .unwrap();
+            socket.write_all(body).unwrap();
+            let mut response = Vec::new();
+            socket.read_to_end(&mut response).unwrap();
+            let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            let body = response.split_off(split + 4);
+            (String::from_utf8(response).unwrap().to_lowercase(), body)
+        };
+        let post = |path: &str, content_type: &str, body: &[u8]| {
+            post_to(addr, path, content_type, body)
+        };
+
+        let mut request = Vec::new();
+        schema::echo::EchoRequest { data: vec![7] }
+            .encode(&mut request)
+            .unwrap();
+        let (head, body) = post("/twirp/echo.Echo/Echo", "application/protobuf", &request);
+        assert!(head.starts_with("http/1.1 200 ok\r\n"));
+        assert!(head.contains("\r\ncontent-type: application/protobuf\r\n"));
+        assert!(head.contains("\r\nrequest-id: 7\r\n"));
+        assert_eq!(schema::echo::EchoResponse::decode(body).unwrap().data, vec![7]);
+
+        let (head, body) = post("/twirp/echo.Echo/Missing", "application/protobuf", &request);
+        assert!(head.starts_with("http/1.1 404 not found\r\n"));
+        assert!(String::from_utf8(body).unwrap().contains("\"code\":\"bad_route\""));
+        let (head, _) = post("/twirp/echo.Missing/Echo", "application/protobuf", &request);
+        assert!(head.starts_with("http/1.1 404 not found\r\n"));
+
+        let (head, body) = post("/twirp/echo.Echo/Echo", "application/json", b"{}");
+        assert!(head.starts_with("http/1.1 404 not found\r\n"));
+        let body = String::from_utf8(body).unwrap();
+        assert!(body.contains("\"code\":\"bad_route\""));
+        assert!(body.contains("JSON bodies are not served"));
+
+        // Bytes are base64 and 64-bit integers are strings in the proto3 JSON mapping.
+        let json_request = b"{\"data\":\"Bw==\"}";
+        let (head, body) =
+            post_to(json_addr, "/twirp/echo.Echo/Echo", "application/json", json_request);
+        assert!(head.starts_with("http/1.1 200 ok\r\n"));
+        assert!(head.contains("\r\ncontent-type: application/json\r\n"));
+        assert_eq!(body, json_request);
+
+        let (_, body) =
+            post_to(json_addr, "/twirp/echo.Echo/Echo", "application/json", b"{\"data\":\"_w\"}");
+        assert_eq!(body, b"{\"data\":\"/w==\"}");
+
+        let (head, body) =
+            post_to(json_addr, "/twirp/echo.Echo/Echo", "application/json", b"{\"data\":[7]}");
+        assert!(head.starts_with("http/1.1 400 bad request\r\n"));
+        assert!(String::from_utf8(body).unwrap().contains("\"code\":\"malformed\""));
+
+        let (head, body) = post_to(json_addr, "/twirp/echo.Echo/Echo", "application/json", b"[");
+        assert!(head.starts_with("http/1.1 400 bad request\r\n"));
+        assert!(String::from_utf8(body).unwrap().contains("\"code\":\"malformed\""));
+
+        let (head, body) =
+            post_to(json_addr, "/twirp/echo.Echo/Echo", "application/protobuf", &request);
+        assert!(head.starts_with("http/1.1 200 ok\r\n"));
+        assert_eq!(schema::echo::EchoResponse::decode(body).unwrap().data, vec![7]);
+
+        let too_large = vec![0; prost_simple_rpc::transport::twirp::MAX_MESSAGE_LEN + 1];
+        let (head, body) = post("/twirp/echo.Echo/Echo", "application/protobuf", &too_large);
+        assert!(head.starts_with("http/1.1 429 too many requests\r\n"));
+        assert!(String::from_utf8(body).unwrap().contains("\"code\":\"resource_exhausted\""));
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn unix_peer_credentials() {
+        use futures::Future;
+        use schema::echo::Echo;
+        use std::os::unix::fs::MetadataExt;
+
+        let path = std::env::temp_dir()
+            .join(format!("prost-simple-rpc-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // The same server listens on both TCP and a Unix socket.
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = schema::echo::EchoServer::new(PeerEchoService);
+        let addr = serve_on(&mut runtime, prost_simple_rpc::transport::tcp::serve, server.clone());
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        runtime.spawn(
+            prost_simple_rpc::transport::unix::serve(listener, server)
+                .map_err(|e| panic!("server failed: {}", e)),
+        );
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::unix::connect(&path))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(
+            connection,
+        ));
+        let response = runtime
+            .block_on(echo.echo(schema::echo::EchoRequest { data: vec![] }))
+            .unwrap();
+        // The socket was created by this process, so it is owned by the same user.
+        let uid = std::fs::metadata(&path).unwrap().uid();
+        let pid = if cfg!(target_os = "linux") {
+            Some(std::process::id())
+        } else {
+            None
+        };
+        assert_eq!(
+            String::from_utf8(response.data).unwrap(),
+            format!("{}:{:?}", uid, pid)
+        );
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::tcp::connect(&addr))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(
+            connection,
+        ));
+        let response = runtime
+            .block_on(echo.echo(schema::echo::EchoRequest { data: vec![] }))
+            .unwrap();
+        assert_eq!(String::from_utf8(response.data).unwrap(), "unknown");
+
+        runtime.shutdown_now().wait().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// The envelope that the stream and WebSocket transports wrap every call in.
+    #[derive(Clone, PartialEq, Message)]
+    struct Envelope {
+        #[prost(uint64, tag = "1")]
+        id: u64,
+        #[prost(int32, tag = "2")]
+        kind: i32,
+        #[prost(string, tag = "3")]
+        service: String,
+        #[prost(string, tag = "4")]
+        method: String,
+        #[prost(bytes, tag = "7")]
+        body: Vec<u8>,
+    }
+
+    /// Writes a short WebSocket frame, starting with the specified FIN bit and opcode.
+    fn write_websocket_frame<W>(writer: &mut W, head: u8, payload: &[u8], masked: bool)
+    where
+        W: std::io::Write,
+    {
+        assert!(payload.len() < 126);
+        let mut frame = vec![head, payload.len() as u8];
+        if masked {
+            let mask = [0x12, 0x34, 0x56, 0x78];
+            frame[1] |= 0x80;
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        } else {
+            frame.extend_from_slice(payload);
+        }
+        writer.write_all(&frame).unwrap();
+    }
+
+    /// Reads a short WebSocket frame, returning its FIN bit and opcode along with its payload.
+    fn read_websocket_frame<R>(reader: &mut R) -> (u8, Vec<u8>)
+    where
+        R: std::io::Read,
+    {
+        let mut head = [0; 2];
+        reader.read_exact(&mut head).unwrap();
+        let len = usize::from(head[1] & 0x7f);
+        assert!(len < 126);
+        let mut mask = [0; 4];
+        if head[1] & 0x80 != 0 {
+            reader.read_exact(&mut mask).unwrap();
+        }
+        let mut payload = vec![0; len];
+        reader.read_exact(&mut payload).unwrap();
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        (head[0], payload)
+    }
+
+    /// Reads the head of an HTTP request or response.
+    fn read_http_head<R>(reader: &mut R) -> String
+    where
+        R: std::io::BufRead,
+    {
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            assert!(reader.read_line(&mut head).unwrap() > 0);
+        }
+        head
+    }
+
+    #[test]
+    fn websocket_round_trip() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+        use schema::greeting::Greeting;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut router = prost_simple_rpc::router::Router::new();
+        router
+            .add(schema::echo::EchoServer::new(EchoService { fail: false }))
+            .add(schema::greeting::GreetingServer::new(GreetingService {
+                fail_hello: false,
+                fail_goodbye: true,
+            }));
+        let addr = serve_on(&mut runtime, prost_simple_rpc::transport::websocket::serve, router);
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::websocket::connect(&addr, "/rpc"))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(
+            connection.clone(),
+        ));
+        let greeting = schema::greeting::GreetingClient::new(
+            prost_simple_rpc::transport::Client::new(connection),
+        );
+
+        let echoes = (0..10u8).map(|i| {
+            let mut metadata = prost_simple_rpc::metadata::Metadata::new();
+            metadata.insert("request-id", i.to_string());
+            let context = prost_simple_rpc::context::Context::with_metadata(metadata);
+            let trailers = context.clone();
+            context
+                .scope(|| echo.echo(schema::echo::EchoRequest { data: vec![i; 1000] }))
+                .map(move |response| (response.data, trailers.trailers()))
+        });
+        let echoes = runtime
+            .block_on(futures::future::join_all(echoes.collect::<Vec<_>>()))
+            .unwrap();
+        for (i, (data, trailers)) in echoes.into_iter().enumerate() {
+            assert_eq!(data, vec![i as u8; 1000]);
+            assert_eq!(trailers.get("request-id"), Some(i.to_string().as_str()));
+        }
+
+        let error = runtime
+            .block_on(greeting.say_goodbye(schema::greeting::SayGoodbyeRequest {
+                name: "dflemstr".to_owned(),
+            }))
+            .unwrap_err();
+        assert_eq!(
+            error.to_status().code(),
+            prost_simple_rpc::status::Code::Unknown
+        );
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn websocket_large_messages() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = schema::echo::EchoServer::new(LargeEchoService {
+            len: prost_simple_rpc::transport::websocket::MAX_MESSAGE_LEN,
+        });
+        let addr = serve_on(&mut runtime, prost_simple_rpc::transport::websocket::serve, server);
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::websocket::connect(&addr, "/rpc"))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(
+            connection.clone(),
+        ));
+
+        // Messages that are too large only fail their own call, not the WebSocket.
+        let data = vec![0; prost_simple_rpc::transport::websocket::MAX_MESSAGE_LEN];
+        match runtime.block_on(echo.echo(schema::echo::EchoRequest { data })) {
+            Err(prost_simple_rpc::error::Error::Execution {
+                error: prost_simple_rpc::transport::Error::TooLarge { len, max },
+            }) => {
+                assert!(len > max);
+                assert_eq!(max, prost_simple_rpc::transport::websocket::MAX_MESSAGE_LEN);
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        let error = runtime
+            .block_on(echo.echo(schema::echo::EchoRequest { data: vec![] }))
+            .unwrap_err();
+        assert_eq!(
+            error.to_status().code(),
+            prost_simple_rpc::status::Code::ResourceExhausted
+        );
+        let response = runtime
+            .block_on(echo.echo(schema::echo::EchoRequest { data: vec![1, 2, 3] }))
+            .unwrap();
+        assert_eq!(response.data, vec![1, 2, 3]);
+        assert!(!connection.is_closed());
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn websocket_wire_format() {
+        use futures::Future;
+        use prost::Message;
+        use std::io::Read;
+        use std::io::Write;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let addr = serve_on(
+            &mut runtime,
+            prost_simple_rpc::transport::websocket::serve,
+            schema::echo::EchoServer::new(EchoService { fail: false }),
+        );
+
+        // Open a WebSocket with the handshake from RFC 6455, like a browser would.
+        let mut socket = std::net::TcpStream::connect(addr).unwrap();
+        let mut reader = std::io::BufReader::new(socket.try_clone().unwrap());
+        write!(
+            socket,
+            "GET /rpc HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\n\
+             Connection: keep-alive, Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Version: 13\r\nSec-WebSocket-Protocol: chat, prost-simple-rpc\r\n\r\n",
+            addr
+        )
+        .unwrap();
+        let head = read_http_head(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(head.contains("\r\nSec-WebSocket-Protocol: prost-simple-rpc\r\n"));
+
+        write_websocket_frame(&mut socket, 0x89, b"ping", true);
+        assert_eq!(read_websocket_frame(&mut reader), (0x8a, b"ping".to_vec()));
+
+        // The envelope of a call may be split over several fragments.
+        let mut body = Vec::new();
+        schema::echo::EchoRequest { data: vec![7] }
+            .encode(&mut body)
+            .unwrap();
+        let mut request = Vec::new();
+        Envelope {
+            id: 3,
+            kind: 0,
+            service: "echo.Echo".to_owned(),
+            method: "Echo".to_owned(),
+            body,
+        }
+        .encode(&mut request)
+        .unwrap();
+        let (first, second) = request.split_at(5);
+        write_websocket_frame(&mut socket, 0x02, first, true);
+        write_websocket_frame(&mut socket, 0x80, second, true);
+
+        let (head, payload) = read_websocket_frame(&mut reader);
+        assert_eq!(head, 0x82);
+        let response = Envelope::decode(payload).unwrap();
+        assert_eq!((response.id, response.kind), (3, 1));
+        let response = schema::echo::EchoResponse::decode(response.body).unwrap();
+        assert_eq!(response.data, vec![7]);
+
+        // Text messages are not supported, and close the WebSocket.
+        write_websocket_frame(&mut socket, 0x81, b"{}", true);
+        assert_eq!(
+            read_websocket_frame(&mut reader),
+            (0x88, 1003u16.to_be_bytes().to_vec())
+        );
+
+        // Anything but a WebSocket handshake is rejected.
+        let mut socket = std::net::TcpStream::connect(addr).unwrap();
+        write!(socket, "GET /rpc HTTP/1.1\r\nHost: {}\r\n\r\n", addr).unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn websocket_close() {
+        use futures::Future;
+        use schema::echo::Echo;
+        use std::io::Write;
+
+        // A server that closes the WebSocket as soon as the first call arrives.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut reader = std::io::BufReader::new(socket.try_clone().unwrap());
+            let head = read_http_head(&mut reader);
+            let key = head
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.eq_ignore_ascii_case("sec-websocket-key"))
+                .map(|(_, key)| key.trim())
+                .unwrap();
+            let mut sha1 = sha1::Sha1::new();
+            sha1.update(key.as_bytes());
+            sha1.update(b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11");
+            write!(
+                socket,
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                base64::encode(&sha1.digest().bytes())
+            )
+            .unwrap();
+
+            assert_eq!(read_websocket_frame(&mut reader).0, 0x82);
+            write_websocket_frame(&mut socket, 0x88, &1001u16.to_be_bytes(), false);
+            read_websocket_frame(&mut reader)
+        });
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::websocket::connect(&addr, "/rpc"))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(
+            connection.clone(),
+        ));
+        let error = runtime
+            .block_on(echo.echo(schema::echo::EchoRequest { data: vec![] }))
+            .unwrap_err();
+        match error {
+            prost_simple_rpc::error::Error::Execution {
+                error: prost_simple_rpc::transport::Error::Closed,
+            } => {}
+            error => panic!("unexpected error: {}", error),
+        }
+        assert!(connection.is_closed());
+
+        // The client echoes the close.
+        assert_eq!(
+            server.join().unwrap(),
+            (0x88, 1001u16.to_be_bytes().to_vec())
+        );
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn shm_round_trip() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+        use schema::greeting::Greeting;
+
+        let path = std::env::temp_dir()
+            .join(format!("prost-simple-rpc-shm-{}.sock", std::process::id()));
+        let small_path = std::env::temp_dir()
+            .join(format!("prost-simple-rpc-shm-small-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&small_path);
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut router = prost_simple_rpc::router::Router::new();
+        router
+            .add(schema::echo::EchoServer::new(EchoService { fail: false }))
+            .add(schema::greeting::GreetingServer::new(GreetingService {
+                fail_hello: false,
+                fail_goodbye: true,
+            }));
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        runtime.spawn(
+            prost_simple_rpc::transport::shm::serve(listener, router)
+                .map_err(|e| panic!("server failed: {}", e)),
+        );
+        let listener = tokio::net::UnixListener::bind(&small_path).unwrap();
+        runtime.spawn(
+            prost_simple_rpc::transport::shm::serve_with_capacity(
+                listener,
+                256,
+                schema::echo::EchoServer::new(LargeEchoService { len: 1000 }),
+            )
+            .map_err(|e| panic!("server failed: {}", e)),
+        );
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::shm::connect(&path))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(
+            connection.clone(),
+        ));
+        let greeting = schema::greeting::GreetingClient::new(
+            prost_simple_rpc::transport::Client::new(connection),
+        );
+
+        // Together, these calls don't fit into the ring buffers at once, which wrap around.
+        let echoes = (0..20u8).map(|i| {
+            let mut metadata = prost_simple_rpc::metadata::Metadata::new();
+            metadata.insert("request-id", i.to_string());
+            let context = prost_simple_rpc::context::Context::with_metadata(metadata);
+            let trailers = context.clone();
+            context
+                .scope(|| echo.echo(schema::echo::EchoRequest { data: vec![i; 100_000] }))
+                .map(move |response| (response.data, trailers.trailers()))
+        });
+        let echoes = runtime
+            .block_on(futures::future::join_all(echoes.collect::<Vec<_>>()))
+            .unwrap();
+        for (i, (data, trailers)) in echoes.into_iter().enumerate() {
+            assert_eq!(data, vec![i as u8; 100_000]);
+            assert_eq!(trailers.get("request-id"), Some(i.to_string().as_str()));
+        }
+
+        let error = runtime
+            .block_on(greeting.say_goodbye(schema::greeting::SayGoodbyeRequest {
+                name: "dflemstr".to_owned(),
+            }))
+            .unwrap_err();
+        assert_eq!(
+            error.to_status().code(),
+            prost_simple_rpc::status::Code::Unknown
+        );
+
+        // Frames that are larger than the ring buffers only fail their own call.
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::shm::connect(&small_path))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(
+            connection.clone(),
+        ));
+        match runtime.block_on(echo.echo(schema::echo::EchoRequest { data: vec![1; 1000] })) {
+            Err(prost_simple_rpc::error::Error::Execution {
+                error: prost_simple_rpc::transport::Error::TooLarge { len, max },
+            }) => {
+                assert!(len > max);
+                assert_eq!(max, 252);
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        let error = runtime
+            .block_on(echo.echo(schema::echo::EchoRequest { data: vec![] }))
+            .unwrap_err();
+        assert_eq!(
+            error.to_status().code(),
+            prost_simple_rpc::status::Code::ResourceExhausted
+        );
+        let response = runtime
+            .block_on(echo.echo(schema::echo::EchoRequest { data: vec![1; 100] }))
+            .unwrap();
+        assert_eq!(response.data, vec![1; 100]);
+        assert!(!connection.is_closed());
+
+        // Ring buffers that are too large to allocate fail the connection, not the server.
+        let huge_path = std::env::temp_dir()
+            .join(format!("prost-simple-rpc-shm-huge-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&huge_path);
+        let listener = tokio::net::UnixListener::bind(&huge_path).unwrap();
+        runtime.spawn(
+            prost_simple_rpc::transport::shm::serve_with_capacity(
+                listener,
+                usize::MAX,
+                schema::echo::EchoServer::new(EchoService { fail: false }),
+            )
+            .map_err(|e| panic!("server failed: {}", e)),
+        );
+        for _ in 0..2 {
+            runtime
+                .block_on(prost_simple_rpc::transport::shm::connect(&huge_path))
+                .unwrap_err();
+        }
+
+        runtime.shutdown_now().wait().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&small_path).unwrap();
+        std::fs::remove_file(&huge_path).unwrap();
+    }
+
+    #[test]
+    fn udp_round_trip() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+        use schema::greeting::Greeting;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut router = prost_simple_rpc::router::Router::new();
+        router
+            .add(schema::echo::EchoServer::new(EchoService { fail: false }))
+            .add(schema::greeting::GreetingServer::new(GreetingService {
+                fail_hello: false,
+                fail_goodbye: true,
+            }));
+        let addr = serve_on(&mut runtime, prost_simple_rpc::transport::udp::serve, router);
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::udp::connect(&addr))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::udp::Client::new(
+            connection.clone(),
+        ));
+        let greeting = schema::greeting::GreetingClient::new(
+            prost_simple_rpc::transport::udp::Client::new(connection),
+        );
+
+        let echoes = (0..10u8).map(|i| {
+            let mut metadata = prost_simple_rpc::metadata::Metadata::new();
+            metadata.insert("request-id", i.to_string());
+            let context = prost_simple_rpc::context::Context::with_metadata(metadata);
+            let trailers = context.clone();
+            context
+                .scope(|| echo.echo(schema::echo::EchoRequest { data: vec![i; 1000] }))
+                .map(move |response| (response.data, trailers.trailers()))
+        });
+        let echoes = runtime
+            .block_on(futures::future::join_all(echoes.collect::<Vec<_>>()))
+            .unwrap();
+        for (i, (data, trailers)) in echoes.into_iter().enumerate() {
+            assert_eq!(data, vec![i as u8; 1000]);
+            assert_eq!(trailers.get("request-id"), Some(i.to_string().as_str()));
+        }
+
+        let error = runtime
+            .block_on(greeting.say_goodbye(schema::greeting::SayGoodbyeRequest {
+                name: "dflemstr".to_owned(),
+            }))
+            .unwrap_err();
+        assert_eq!(
+            error.to_status().code(),
+            prost_simple_rpc::status::Code::Unknown
+        );
+
+        // Requests that don't fit into a datagram are never sent.
+        let data = vec![0; prost_simple_rpc::transport::udp::MAX_DATAGRAM_LEN];
+        match runtime.block_on(echo.echo(schema::echo::EchoRequest { data })) {
+            Err(prost_simple_rpc::error::Error::Execution {
+                error: prost_simple_rpc::transport::Error::TooLarge { len, max },
+            }) => {
+                assert!(len > max);
+                assert_eq!(max, prost_simple_rpc::transport::udp::MAX_DATAGRAM_LEN);
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn udp_large_error() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = schema::echo::EchoServer::new(LargeStatusEchoService {
+            len: prost_simple_rpc::transport::udp::MAX_DATAGRAM_LEN,
+        });
+        let addr = serve_on(&mut runtime, prost_simple_rpc::transport::udp::serve, server);
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::udp::connect(&addr))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::udp::Client::new(
+            connection,
+        ));
+
+        // Errors that don't fit into a datagram still arrive, with a shortened message.
+        let status = runtime
+            .block_on(echo.echo(schema::echo::EchoRequest { data: vec![] }))
+            .unwrap_err()
+            .to_status();
+        assert_eq!(status.code(), prost_simple_rpc::status::Code::NotFound);
+        assert!(!status.message().is_empty());
+        assert!(status.message().len() < prost_simple_rpc::transport::udp::MAX_DATAGRAM_LEN);
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn udp_retransmission() {
+        use futures::Future;
+        use schema::echo::Echo;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let service = CountingEchoService::default();
+        let server = serve_on(
+            &mut runtime,
+            prost_simple_rpc::transport::udp::serve,
+            schema::echo::EchoServer::new(service.clone()),
+        );
+
+        // Relays datagrams between the client and the server, but loses the first response.
+        let relay = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        relay
+            .set_read_timeout(Some(time::Duration::from_secs(5)))
+            .unwrap();
+        let relay_thread = std::thread::spawn(move || {
+            let mut client = None;
+            let mut requests = Vec::new();
+            let mut responses = 0;
+            let mut buf = vec![0; 65536];
+            while let Ok((len, from)) = relay.recv_from(&mut buf) {
+                if from == server {
+                    responses += 1;
+                    if responses > 1 {
+                        relay.send_to(&buf[..len], client.unwrap()).unwrap();
+                        break;
+                    }
+                } else {
+                    client = Some(from);
+                    requests.push(buf[..len].to_vec());
+                    relay.send_to(&buf[..len], server).unwrap();
+                }
+            }
+            requests
+        });
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::udp::connect(&relay_addr))
+            .unwrap()
+            .with_retransmits(5, time::Duration::from_millis(50));
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::udp::Client::new(
+            connection,
+        ));
+        let response = runtime
+            .block_on(echo.echo(schema::echo::EchoRequest { data: vec![7; 10] }))
+            .unwrap();
+        assert_eq!(response.data, vec![7; 10]);
+
+        // The retransmitted request is identical, and the server answers it without handling the
+        // call again.
+        let requests = relay_thread.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0], requests[1]);
+        assert_eq!(service.calls.load(sync::atomic::Ordering::SeqCst), 1);
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn udp_unanswered() {
+        use futures::Future;
+        use schema::echo::Echo;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        silent
+            .set_read_timeout(Some(time::Duration::from_secs(5)))
+            .unwrap();
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::udp::connect(
+                &silent.local_addr().unwrap(),
+            ))
+            .unwrap()
+            .with_retransmits(2, time::Duration::from_millis(20));
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::udp::Client::new(
+            connection.clone(),
+        ));
+        match runtime.block_on(echo.echo(schema::echo::EchoRequest { data: vec![1] })) {
+            Err(prost_simple_rpc::error::Error::Execution {
+                error: prost_simple_rpc::transport::Error::Unanswered,
+            }) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(!connection.is_closed());
+
+        // The call was sent once, and retransmitted twice.
+        let mut buf = vec![0; 65536];
+        let mut requests = Vec::new();
+        for _ in 0..3 {
+            let len = silent.recv(&mut buf).unwrap();
+            requests.push(buf[..len].to_vec());
+        }
+        assert!(requests.iter().all(|request| *request == requests[0]));
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    /// Creates a server and client configuration with a self-signed certificate for "localhost".
+    fn quic_configs() -> (quinn::ServerConfig, quinn::ClientConfig) {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let cert = certified.cert.der().clone();
+        let key = quinn::rustls::pki_types::PrivatePkcs8KeyDer::from(
+            certified.key_pair.serialize_der(),
+        );
+        let server = quinn::ServerConfig::with_single_cert(vec![cert.clone()], key.into()).unwrap();
+
+        let mut roots = quinn::rustls::RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let client = quinn::ClientConfig::with_root_certificates(sync::Arc::new(roots)).unwrap();
+        (server, client)
+    }
+
+    fn serve_quic<D>(
+        runtime: &mut tokio::runtime::Runtime,
+        dispatch: D,
+    ) -> (std::net::SocketAddr, quinn::ClientConfig)
+    where
+        D: prost_simple_rpc::transport::Dispatch,
+    {
+        use futures::Future;
+
+        let (server, client) = quic_configs();
+        let listener = prost_simple_rpc::transport::quic::Listener::bind(
+            &"127.0.0.1:0".parse().unwrap(),
+            server,
+        )
+        .unwrap();
+        let addr = listener.local_addr().unwrap();
+        runtime.spawn(
+            prost_simple_rpc::transport::quic::serve(listener, dispatch)
+                .map_err(|e| panic!("server failed: {}", e)),
+        );
+        (addr, client)
+    }
+
+    #[test]
+    fn quic_round_trip() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+        use schema::greeting::Greeting;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut router = prost_simple_rpc::router::Router::new();
+        router
+            .add(schema::echo::EchoServer::new(EchoService { fail: false }))
+            .add(schema::greeting::GreetingServer::new(GreetingService {
+                fail_hello: false,
+                fail_goodbye: true,
+            }));
+        let (addr, config) = serve_quic(&mut runtime, router);
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::quic::connect(
+                &addr,
+                "localhost",
+                config,
+            ))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::quic::Client::new(
+            connection.clone(),
+        ));
+        let greeting = schema::greeting::GreetingClient::new(
+            prost_simple_rpc::transport::quic::Client::new(connection.clone()),
+        );
+
+        // Every call has its own stream, so large calls are all in flight at the same time.
+        let echoes = (0..20u8).map(|i| {
+            let mut metadata = prost_simple_rpc::metadata::Metadata::new();
+            metadata.insert("request-id", i.to_string());
+            let context = prost_simple_rpc::context::Context::with_metadata(metadata);
+            let trailers = context.clone();
+            context
+                .scope(|| echo.echo(schema::echo::EchoRequest { data: vec![i; 100_000] }))
+                .map(move |response| (response.data, trailers.trailers()))
+        });
+        let echoes = runtime
+            .block_on(futures::future::join_all(echoes.collect::<Vec<_>>()))
+            .unwrap();
+        for (i, (data, trailers)) in echoes.into_iter().enumerate() {
+            assert_eq!(data, vec![i as u8; 100_000]);
+            assert_eq!(trailers.get("request-id"), Some(i.to_string().as_str()));
+        }
+
+        let error = runtime
+            .block_on(greeting.say_goodbye(schema::greeting::SayGoodbyeRequest {
+                name: "dflemstr".to_owned(),
+            }))
+            .unwrap_err();
+        assert_eq!(
+            error.to_status().code(),
+            prost_simple_rpc::status::Code::Unknown
+        );
+
+        let data = vec![0; prost_simple_rpc::transport::quic::MAX_MESSAGE_LEN];
+        match runtime.block_on(echo.echo(schema::echo::EchoRequest { data })) {
+            Err(prost_simple_rpc::error::Error::Execution {
+                error: prost_simple_rpc::transport::Error::TooLarge { len, max },
+            }) => {
+                assert!(len > max);
+                assert_eq!(max, prost_simple_rpc::transport::quic::MAX_MESSAGE_LEN);
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(!connection.is_closed());
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn quic_large_error() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = schema::echo::EchoServer::new(LargeStatusEchoService {
+            len: prost_simple_rpc::transport::quic::MAX_MESSAGE_LEN,
+        });
+        let (addr, config) = serve_quic(&mut runtime, server);
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::quic::connect(
+                &addr,
+                "localhost",
+                config,
+            ))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::quic::Client::new(
+            connection,
+        ));
+
+        // Errors that are too large to be sent still arrive, with a shortened message.
+        let status = runtime
+            .block_on(echo.echo(schema::echo::EchoRequest { data: vec![] }))
+            .unwrap_err()
+            .to_status();
+        assert_eq!(status.code(), prost_simple_rpc::status::Code::NotFound);
+        assert!(!status.message().is_empty());
+        assert!(status.message().len() < prost_simple_rpc::transport::quic::MAX_MESSAGE_LEN);
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn quic_cancellation() {
+        use futures::Future;
+        use schema::echo::Echo;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let service = PendingEchoService::default();
+        let (addr, config) = serve_quic(
+            &mut runtime,
+            schema::echo::EchoServer::new(service.clone()),
+        );
+
+        let connection = runtime
+            .block_on(prost_simple_rpc::transport::quic::connect(
+                &addr,
+                "localhost",
+                config,
+            ))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::quic::Client::new(
+            connection.clone(),
+        ));
+
+        let future = echo.echo(schema::echo::EchoRequest { data: vec![1] });
+        let mut spawn = futures::executor::spawn(future);
+        let notify = sync::Arc::new(NoopNotify);
+        assert!(spawn.poll_future_notify(&notify, 0).unwrap().is_not_ready());
+
+        let deadline = time::Instant::now() + time::Duration::from_secs(5);
+        while service.contexts.lock().unwrap().is_empty() {
+            assert!(time::Instant::now() < deadline, "call never arrived");
+            std::thread::sleep(time::Duration::from_millis(10));
+        }
+        let server_context = service.contexts.lock().unwrap()[0].clone();
+        assert!(!server_context.is_cancelled());
+
+        // Dropping the call stops its stream, which cancels it on the server.
+        drop(spawn);
+        while !server_context.is_cancelled() {
+            assert!(time::Instant::now() < deadline, "call was never cancelled");
+            std::thread::sleep(time::Duration::from_millis(10));
+        }
+
+        // The connection itself is still usable.
+        assert!(!connection.is_closed());
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn stdio_pipes() {
+        use futures::Future;
+        use schema::echo::Echo;
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let (client, server) = tokio::net::UnixStream::pair().unwrap();
+        let (server_reader, server_writer) = tokio::io::AsyncRead::split(server);
+        let (stopped_sender, stopped) = futures::sync::oneshot::channel();
+        runtime.spawn(
+            prost_simple_rpc::transport::stdio::serve(
+                server_reader,
+                server_writer,
+                schema::echo::EchoServer::new(EchoService { fail: false }),
+            )
+            .then(move |result| stopped_sender.send(result.is_ok()))
+            .map_err(|_| ()),
+        );
+
+        let (client_reader, client_writer) = tokio::io::AsyncRead::split(client);
+        let connection = runtime
+            .block_on(futures::future::lazy(move || {
+                Ok::<_, ()>(prost_simple_rpc::transport::stdio::connect(
+                    client_reader,
+                    client_writer,
+                ))
+            }))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(
+            connection,
+        ));
+        let response = runtime
+            .block_on(echo.echo(schema::echo::EchoRequest { data: vec![1, 2] }))
+            .unwrap();
+        assert_eq!(response.data, vec![1, 2]);
+
+        // Dropping the last connection closes the pipe, which stops the server.
+        drop(echo);
+        assert!(runtime.block_on(stopped).unwrap());
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn stdio_spawn() {
+        use futures::Future;
+        use prost::Message;
+        use schema::echo::Echo;
+
+        // A plugin that answers the first call with a canned response, and then waits for its
+        // input to be closed.
+        let mut body = Vec::new();
+        schema::echo::EchoResponse { data: vec![7] }
+            .encode(&mut body)
+            .unwrap();
+        let mut response = Vec::new();
+        Envelope {
+            id: 0,
+            kind: 1,
+            body,
+            ..Envelope::default()
+        }
+        .encode(&mut response)
+        .unwrap();
+        let mut frame = (response.len() as u32).to_be_bytes().to_vec();
+        frame.extend(response);
+        let printf: String = frame.iter().map(|byte| format!("\\{:03o}", byte)).collect();
+        let script = format!("head -c 1 >/dev/null; printf '{}'; cat >/dev/null; exit 3", printf);
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let child = runtime
+            .block_on(futures::future::lazy(move || {
+                prost_simple_rpc::transport::stdio::spawn(
+                    std::process::Command::new("sh").arg("-c").arg(script),
+                )
+            }))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(
+            child.connection().clone(),
+        ));
+        let response = runtime
+            .block_on(echo.echo(schema::echo::EchoRequest { data: vec![7] }))
+            .unwrap();
+        assert_eq!(response.data, vec![7]);
+
+        // Once all connections are dropped, the plugin sees its input close and exits.
+        drop(echo);
+        let status = runtime.block_on(child.wait()).unwrap();
+        assert_eq!(status.code(), Some(3));
+
+        // A plugin that exits while a call is in flight fails the call.
+        let child = runtime
+            .block_on(futures::future::lazy(|| {
+                prost_simple_rpc::transport::stdio::spawn(
+                    std::process::Command::new("sh")
+                        .arg("-c")
+                        .arg("head -c 1 >/dev/null"),
+                )
+            }))
+            .unwrap();
+        let echo = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(
+            child.connection().clone(),
+        ));
+        match runtime.block_on(echo.echo(schema::echo::EchoRequest { data: vec![7] })) {
+            Err(prost_simple_rpc::error::Error::Execution {
+                error: prost_simple_rpc::transport::Error::Closed,
+            }) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(child.connection().is_closed());
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    fn serve_channel<D>(
+        capacity: usize,
+        dispatch: D,
+    ) -> (
+        prost_simple_rpc::transport::channel::Connection,
+        std::thread::JoinHandle<Result<(), ()>>,
+    )
+    where
+        D: prost_simple_rpc::transport::Dispatch,
+    {
+        use futures::Future;
+
+        let (connection, listener) = prost_simple_rpc::transport::channel::channel(capacity);
+        let server = std::thread::spawn(move || {
+            prost_simple_rpc::transport::channel::serve(listener, dispatch).wait()
+        });
+        (connection, server)
+    }
+
+    #[test]
+    fn channel_round_trip() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+        use schema::greeting::Greeting;
+
+        let mut router = prost_simple_rpc::router::Router::new();
+        router
+            .add(schema::echo::EchoServer::new(EchoService { fail: false }))
+            .add(schema::greeting::GreetingServer::new(GreetingService {
+                fail_hello: false,
+                fail_goodbye: true,
+            }));
+        let (connection, server) = serve_channel(4, router);
+
+        let echo = schema::echo::EchoClient::new(
+            prost_simple_rpc::transport::channel::Client::new(connection.clone()),
+        );
+        let greeting = schema::greeting::GreetingClient::new(
+            prost_simple_rpc::transport::channel::Client::new(connection),
+        );
+
+        let mut metadata = prost_simple_rpc::metadata::Metadata::new();
+        metadata.insert("request-id", "42");
+        let context = prost_simple_rpc::context::Context::with_metadata(metadata);
+        let echoes = (0..10u8).map(|i| {
+            context
+                .scope(|| echo.echo(schema::echo::EchoRequest { data: vec![i] }))
+                .map(|response| response.data)
+        });
+        let echoes = futures::future::join_all(echoes.collect::<Vec<_>>())
+            .wait()
+            .unwrap();
+        assert_eq!(echoes, (0..10u8).map(|i| vec![i]).collect::<Vec<_>>());
+        assert_eq!(context.trailers().get("request-id"), Some("42"));
+
+        let error = greeting
+            .say_goodbye(schema::greeting::SayGoodbyeRequest {
+                name: "dflemstr".to_owned(),
+            })
+            .wait()
+            .unwrap_err();
+        assert_eq!(
+            error.to_status().code(),
+            prost_simple_rpc::status::Code::Unknown
+        );
+
+        // The server stops once all of its clients are gone.
+        drop(echo);
+        drop(greeting);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn channel_backpressure() {
+        use schema::echo::Echo;
+
+        let service = PendingEchoService::default();
+        let (connection, server) =
+            serve_channel(2, schema::echo::EchoServer::new(service.clone()));
+        let echo = schema::echo::EchoClient::new(
+            prost_simple_rpc::transport::channel::Client::new(connection),
+        );
+
+        let notify = sync::Arc::new(NoopNotify);
+        let mut calls = (0..3)
+            .map(|_| echo.echo(schema::echo::EchoRequest { data: vec![] }))
+            .map(futures::executor::spawn)
+            .collect::<Vec<_>>();
+        let poll_until = |calls: &mut [futures::executor::Spawn<_>], count: usize| {
+            let deadline = time::Instant::now() + time::Duration::from_secs(5);
+            while service.contexts.lock().unwrap().len() < count {
+                assert!(time::Instant::now() < deadline, "calls didn't reach the server");
+                for call in calls.iter_mut() {
+                    assert!(call.poll_future_notify(&notify, 0).unwrap().is_not_ready());
+                }
+                std::thread::sleep(time::Duration::from_millis(1));
+            }
+        };
+
+        // Only two calls fit in the channel, so the third one has to wait.
+        poll_until(&mut calls, 2);
+        std::thread::sleep(time::Duration::from_millis(50));
+        poll_until(&mut calls, 2);
+        assert_eq!(service.contexts.lock().unwrap().len(), 2);
+
+        // Cancelling one of the calls makes room for the third one.
+        calls.remove(0);
+        poll_until(&mut calls, 3);
+        assert!(service.contexts.lock().unwrap()[0].is_cancelled());
+
+        drop(calls);
+        drop(echo);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn channel_faults() {
+        use futures::Future;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+
+        let service = CountingEchoService::default();
+        let (connection, server) =
+            serve_channel(4, schema::echo::EchoServer::new(service.clone()));
+
+        // Each call misbehaves in a different way, in order.
+        let faults = [
+            prost_simple_rpc::transport::channel::Fault::Delay(time::Duration::from_millis(50)),
+            prost_simple_rpc::transport::channel::Fault::DropRequest,
+            prost_simple_rpc::transport::channel::Fault::DropResponse,
+        ];
+        let next = sync::atomic::AtomicUsize::new(0);
+        let connection = connection.with_faults(move |service, method| {
+            assert_eq!((service, method), ("echo.Echo", "Echo"));
+            faults[next.fetch_add(1, sync::atomic::Ordering::SeqCst)]
+        });
+        let echo = schema::echo::EchoClient::new(
+            prost_simple_rpc::transport::channel::Client::new(connection),
+        );
+
+        let start = time::Instant::now();
+        let response = echo
+            .echo(schema::echo::EchoRequest { data: vec![1] })
+            .wait()
+            .unwrap();
+        assert_eq!(response.data, vec![1]);
+        assert!(start.elapsed() >= time::Duration::from_millis(50));
+        assert_eq!(service.calls.load(sync::atomic::Ordering::SeqCst), 1);
+
+        // Lost calls only fail once their deadline has passed.
+        for calls in 1..3 {
+            let context = prost_simple_rpc::context::Context::new()
+                .with_timeout(time::Duration::from_millis(50));
+            let error = context
+                .scope(|| echo.echo(schema::echo::EchoRequest { data: vec![] }))
+                .wait()
+                .unwrap_err();
+            assert_eq!(
+                error.to_status().code(),
+                prost_simple_rpc::status::Code::DeadlineExceeded
+            );
+            assert_eq!(service.calls.load(sync::atomic::Ordering::SeqCst), calls);
+        }
+
+        drop(echo);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn router_dispatch() {
+        use futures::Future;
+        use prost::Message;
+
+        let mut router = prost_simple_rpc::router::Router::new();
+        router
+            .add(schema::echo::EchoServer::new(EchoService { fail: false }))
+            .add(schema::greeting::GreetingServer::new(GreetingService {
+                fail_hello: false,
+                fail_goodbye: false,
+            }));
+
+        let request = schema::greeting::SayHelloRequest {
+            name: "dflemstr".to_owned(),
+        };
+        let mut input = Vec::new();
+        request.encode(&mut input).unwrap();
+        let output = router
+            .call(
+                "greeting.Greeting",
+                "SayHello",
+                input.into(),
+                prost_simple_rpc::context::Context::new(),
+            )
+            .wait()
+            .unwrap();
+
+        assert_eq!(
+            schema::greeting::SayHelloResponse::decode(output).unwrap(),
+            schema::greeting::SayHelloResponse {
+                greeting: "Hello, dflemstr!".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn router_unknown() {
+        use futures::Future;
+
+        let mut router = prost_simple_rpc::router::Router::new();
+        router.add(schema::echo::EchoServer::new(EchoService { fail: false }));
+
+        match router
+            .call(
+                "greeting.Greeting",
+                "SayHello",
+                bytes::Bytes::new(),
+                prost_simple_rpc::context::Context::new(),
+            )
+            .wait()
+        {
+            Err(prost_simple_rpc::error::Error::UnknownService { service }) => {
+                assert_eq!(service, "greeting.Greeting")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        match router
+            .call(
+                "echo.Echo",
+                "Shout",
+                bytes::Bytes::new(),
+                prost_simple_rpc::context::Context::new(),
+            )
+            .wait()
+        {
+            Err(prost_simple_rpc::error::Error::UnknownMethod { service, method }) => {
+                assert_eq!(service, "echo.Echo");
+                assert_eq!(method, "Shout");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn method_lookup() {
+        use prost_simple_rpc::descriptor::MethodDescriptor;
+        use prost_simple_rpc::descriptor::ServiceDescriptor;
+        use schema::greeting::GreetingDescriptor;
+        use schema::greeting::GreetingMethodDescriptor;
+
+        assert_eq!(
+            GreetingMethodDescriptor::from_name("say_goodbye"),
+            Some(GreetingMethodDescriptor::SayGoodbye)
+        );
+        assert_eq!(
+            GreetingMethodDescriptor::from_proto_name("SayHello"),
+            Some(GreetingMethodDescriptor::SayHello)
+        );
+        assert_eq!(GreetingMethodDescriptor::from_proto_name("say_hello"), None);
+
+        assert_eq!(
+            GreetingDescriptor::path(&GreetingMethodDescriptor::SayHello),
+            "/greeting.Greeting/SayHello"
+        );
+        assert_eq!(
+            GreetingDescriptor::method_from_path("/greeting.Greeting/SayGoodbye"),
+            Some(GreetingMethodDescriptor::SayGoodbye)
+        );
+        assert_eq!(
+            GreetingDescriptor::method_from_path("/echo.Echo/SayGoodbye"),
+            None
+        );
+        assert_eq!(
+            GreetingDescriptor::method_from_path("greeting.Greeting/SayGoodbye"),
+            None
+        );
+    }
+
+    #[test]
+    fn middleware_layers() {
+        use futures::Future;
+        use prost_simple_rpc::middleware::HandlerExt;
+        use prost_simple_rpc::middleware::Layer;
+        use schema::echo::Echo;
+
+        let log = sync::Arc::new(sync::Mutex::new(Vec::new()));
+        let layer = |name| RecordLayer {
+            name,
+            log: log.clone(),
+        };
+
+        // Layers work on both sides of a transport, and the last one added is the outermost.
+        let server =
+            schema::echo::EchoServer::new(EchoService { fail: false }).layer(layer("server"));
+        let (connection, server) = serve_channel(4, server);
+        let echo = schema::echo::EchoClient::new(
+            prost_simple_rpc::transport::channel::Client::new(connection)
+                .layer(layer("inner"))
+                .layer(layer("outer")),
+        );
+        let response = echo
+            .echo(schema::echo::EchoRequest { data: vec![1, 2, 3] })
+            .wait()
+            .unwrap();
+        assert_eq!(response.data, vec![1, 2, 3]);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "outer Echo",
+                "inner Echo",
+                "server Echo",
+                "server done",
+                "inner done",
+                "outer done",
+            ]
+        );
+        drop(echo);
+        server.join().unwrap().unwrap();
+
+        // A stack of layers applies them in the same order.
+        log.lock().unwrap().clear();
+        let stack = prost_simple_rpc::middleware::Stack::new(layer("inner"), layer("outer"))
+            .push(prost_simple_rpc::middleware::Identity);
+        let echo = schema::echo::EchoClient::new(
+            stack.layer(schema::echo::EchoServer::new(EchoService { fail: false })),
+        );
+        echo.echo(schema::echo::EchoRequest { data: vec![] })
+            .wait()
+            .unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["outer Echo", "inner Echo", "inner done", "outer done"]
+        );
+    }
+
+    #[test]
+    fn middleware_timeout() {
+        use futures::Future;
+        use prost_simple_rpc::middleware::timeout::Error;
+        use prost_simple_rpc::middleware::timeout::Timeout;
+        use prost_simple_rpc::middleware::HandlerExt;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+        use schema::echo::EchoMethodDescriptor;
+
+        let service = PendingEchoService::default();
+        let (connection, server) =
+            serve_channel(4, schema::echo::EchoServer::new(service.clone()));
+        let timeout = Timeout::new(time::Duration::from_secs(60))
+            .with_method(EchoMethodDescriptor::Echo, time::Duration::from_millis(50));
+        assert_eq!(
+            timeout.timeout(&EchoMethodDescriptor::Echo),
+            time::Duration::from_millis(50)
+        );
+        let echo = schema::echo::EchoClient::new(
+            prost_simple_rpc::transport::channel::Client::new(connection).layer(timeout),
+        );
+
+        // The server notices the deadline as well, but the caller still gets the timeout error.
+        let start = time::Instant::now();
+        let error = echo
+            .echo(schema::echo::EchoRequest { data: vec![] })
+            .wait()
+            .unwrap_err();
+        assert!(start.elapsed() >= time::Duration::from_millis(50));
+        assert!(start.elapsed() < time::Duration::from_secs(5));
+        match error {
+            prost_simple_rpc::error::Error::Execution {
+                error: Error::Elapsed { timeout },
+            } => assert_eq!(timeout, time::Duration::from_millis(50)),
+            error => panic!("unexpected error: {:?}", error),
+        }
+        let server_context = service.contexts.lock().unwrap()[0].clone();
+        assert!(server_context.deadline().is_some());
+        drop(echo);
+        server.join().unwrap().unwrap();
+
+        // On the server side, timeouts are reported to clients as `DeadlineExceeded`.
+        let (connection, server) = serve_channel(
+            4,
+            schema::echo::EchoServer::new(service.clone())
+                .layer(Timeout::new(time::Duration::from_millis(10))),
+        );
+        let echo = schema::echo::EchoClient::new(
+            prost_simple_rpc::transport::channel::Client::new(connection),
+        );
+        let error = echo
+            .echo(schema::echo::EchoRequest { data: vec![] })
+            .wait()
+            .unwrap_err();
+        assert_eq!(
+            error.to_status().code(),
+            prost_simple_rpc::status::Code::DeadlineExceeded
+        );
+        drop(echo);
+        server.join().unwrap().unwrap();
+
+        // Calls that fail on their own keep their error.
+        let echo = schema::echo::EchoClient::new(
+            schema::echo::EchoServer::new(EchoService { fail: true })
+                .layer(Timeout::new(time::Duration::from_secs(60))),
+        );
+        match echo
+            .echo(schema::echo::EchoRequest { data: vec![] })
+            .wait()
+        {
+            Err(prost_simple_rpc::error::Error::Execution {
+                error: Error::Inner { .. },
+            }) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn middleware_retry() {
+        use futures::Future;
+        use prost_simple_rpc::middleware::retry::Retry;
+        use prost_simple_rpc::middleware::HandlerExt;
+        use prost_simple_rpc::status::Code;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+        use schema::echo::EchoMethodDescriptor;
+
+        let retry = || {
+            Retry::new(3).with_backoff(
+                time::Duration::from_millis(1),
+                time::Duration::from_millis(10),
+            )
+        };
+        let echo = |service: &FlakyEchoService, retry: Retry<EchoMethodDescriptor>| {
+            let server = schema::echo::EchoServer::new(service.clone());
+            let echo = schema::echo::EchoClient::new(server.layer(retry));
+            echo.echo(schema::echo::EchoRequest { data: vec![1] })
+                .wait()
+        };
+
+        // Idempotent methods are retried until they succeed.
+        let service = FlakyEchoService::new(2, Code::Unavailable);
+        let response = echo(&service, retry().with_method(EchoMethodDescriptor::Echo)).unwrap();
+        assert_eq!(response.data, vec![1]);
+        assert_eq!(service.calls(), 3);
+
+        // Other methods aren't retried at all.
+        let service = FlakyEchoService::new(2, Code::Unavailable);
+        let error = echo(&service, retry()).unwrap_err();
+        assert_eq!(error.to_status().code(), Code::Unavailable);
+        assert_eq!(service.calls(), 1);
+
+        // Once the retries run out, the last error is returned.
+        let service = FlakyEchoService::new(10, Code::Unavailable);
+        let error = echo(&service, retry().with_all_methods()).unwrap_err();
+        assert_eq!(error.to_status().code(), Code::Unavailable);
+        assert_eq!(service.calls(), 4);
+
+        // Only errors that the classifier accepts are retried.
+        let service = FlakyEchoService::new(2, Code::NotFound);
+        let error = echo(&service, retry().with_all_methods()).unwrap_err();
+        assert_eq!(error.to_status().code(), Code::NotFound);
+        assert_eq!(service.calls(), 1);
+
+        let service = FlakyEchoService::new(2, Code::NotFound);
+        let retry = retry()
+            .with_all_methods()
+            .with_classifier(|status| status.code() == Code::NotFound);
+        echo(&service, retry).unwrap();
+        assert_eq!(service.calls(), 3);
+
+        // Calls aren't retried when their deadline would pass before the next attempt.
+        let service = FlakyEchoService::new(2, Code::Unavailable);
+        let retry = Retry::new(3)
+            .with_all_methods()
+            .with_backoff(time::Duration::from_secs(60), time::Duration::from_secs(60));
+        let context =
+            prost_simple_rpc::context::Context::new().with_timeout(time::Duration::from_secs(5));
+        let start = time::Instant::now();
+        let error = context.scope(|| echo(&service, retry)).unwrap_err();
+        assert_eq!(error.to_status().code(), Code::Unavailable);
+        assert!(start.elapsed() < time::Duration::from_secs(5));
+        assert_eq!(service.calls(), 1);
+    }
+
+    #[test]
+    fn middleware_circuit_breaker() {
+        use futures::Future;
+        use prost_simple_rpc::middleware::circuit_breaker::CircuitBreaker;
+        use prost_simple_rpc::middleware::circuit_breaker::Error;
+        use prost_simple_rpc::middleware::circuit_breaker::State;
+        use prost_simple_rpc::middleware::retry::Retry;
+        use prost_simple_rpc::middleware::HandlerExt;
+        use prost_simple_rpc::status::Code;
+        use schema::echo::Echo;
+        use schema::echo::EchoMethodDescriptor;
+        use schema::greeting::Greeting;
+        use schema::greeting::GreetingMethodDescriptor;
+
+        let service = FlakyEchoService::new(3, Code::Unavailable);
+        let breaker = CircuitBreaker::new()
+            .with_window(4, 2)
+            .with_open_duration(time::Duration::from_millis(50));
+        let echo = schema::echo::EchoClient::new(
+            schema::echo::EchoServer::new(service.clone()).layer(breaker.clone()),
+        );
+        let call = || {
+            echo.echo(schema::echo::EchoRequest { data: vec![1] })
+                .wait()
+        };
+
+        // The circuit breaker opens once half of the calls failed, and then fails calls right away.
+        call().unwrap_err();
+        assert_eq!(breaker.state(&EchoMethodDescriptor::Echo), State::Closed);
+        call().unwrap_err();
+        assert_eq!(breaker.state(&EchoMethodDescriptor::Echo), State::Open);
+        match call() {
+            Err(prost_simple_rpc::error::Error::Execution {
+                error: Error::Open { method: "Echo" },
+            }) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(service.calls(), 2);
+
+        // A failed probe opens it again...
+        std::thread::sleep(time::Duration::from_millis(60));
+        assert_eq!(breaker.state(&EchoMethodDescriptor::Echo), State::HalfOpen);
+        call().unwrap_err();
+        assert_eq!(breaker.state(&EchoMethodDescriptor::Echo), State::Open);
+        assert_eq!(service.calls(), 3);
+
+        // ...and a successful one closes it.
+        std::thread::sleep(time::Duration::from_millis(60));
+        call().unwrap();
+        assert_eq!(breaker.state(&EchoMethodDescriptor::Echo), State::Closed);
+        call().unwrap();
+        assert_eq!(service.calls(), 5);
+
+        // Calls that it rejects aren't retried with a backoff, whether it's layered inside or
+        // outside of a retry.
+        let service = FlakyEchoService::new(100, Code::Unavailable);
+        let breaker = CircuitBreaker::new()
+            .with_window(2, 2)
+            .with_open_duration(time::Duration::from_secs(60));
+        let server = schema::echo::EchoServer::new(service.clone());
+        let echo = schema::echo::EchoClient::new(server.clone().layer(breaker.clone()));
+        for _ in 0..2 {
+            echo.echo(schema::echo::EchoRequest { data: vec![1] })
+                .wait()
+                .unwrap_err();
+        }
+        assert_eq!(breaker.state(&EchoMethodDescriptor::Echo), State::Open);
+        let retry = Retry::new(2)
+            .with_all_methods()
+            .with_backoff(time::Duration::from_secs(60), time::Duration::from_secs(60));
+        let echo = schema::echo::EchoClient::new(
+            server
+                .clone()
+                .layer(breaker.clone())
+                .layer(retry.clone()),
+        );
+        let start = time::Instant::now();
+        match echo
+            .echo(schema::echo::EchoRequest { data: vec![1] })
+            .wait()
+        {
+            Err(prost_simple_rpc::error::Error::Execution {
+                error: Error::Open { method: "Echo" },
+            }) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        let echo = schema::echo::EchoClient::new(server.layer(retry).layer(breaker));
+        match echo
+            .echo(schema::echo::EchoRequest { data: vec![1] })
+            .wait()
+        {
+            Err(prost_simple_rpc::error::Error::Execution {
+                error: Error::Open { method: "Echo" },
+            }) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(start.elapsed() < time::Duration::from_secs(5));
+        assert_eq!(service.calls(), 2);
+
+        // Each method has its own circuit breaker, unless they are per service.  Which errors count
+        // as failures is up to the classifier.
+        for &per_service in &[false, true] {
+            let mut breaker = CircuitBreaker::new()
+                .with_window(2, 2)
+                .with_classifier(|status| status.code() == Code::Unknown);
+            if per_service {
+                breaker = breaker.per_service();
+            }
+            let greeting = schema::greeting::GreetingClient::new(
+                schema::greeting::GreetingServer::new(GreetingService {
+                    fail_hello: true,
+                    fail_goodbye: false,
+                })
+                .layer(breaker.clone()),
+            );
+            for _ in 0..2 {
+                greeting
+                    .say_hello(schema::greeting::SayHelloRequest {
+                        name: "dflemstr".to_owned(),
+                    })
+                    .wait()
+                    .unwrap_err();
+            }
+            assert_eq!(breaker.state(&GreetingMethodDescriptor::SayHello), State::Open);
+            let goodbye = greeting
+                .say_goodbye(schema::greeting::SayGoodbyeRequest {
+                    name: "dflemstr".to_owned(),
+                })
+                .wait();
+            assert_eq!(goodbye.is_err(), per_service);
+            assert_eq!(
+                breaker.state(&GreetingMethodDescriptor::SayGoodbye),
+                if per_service { State::Open } else { State::Closed }
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "failure rate must be greater than 0 and at most 1")]
+    fn middleware_circuit_breaker_failure_rate() {
+        use prost_simple_rpc::middleware::circuit_breaker::CircuitBreaker;
+
+        // A failure rate of 0 would open the circuit breaker without any failures.
+        CircuitBreaker::<schema::echo::EchoMethodDescriptor>::new().with_failure_rate(0.0);
+    }
+
+    #[test]
+    fn middleware_concurrency_limit() {
+        use futures::Future;
+        use prost_simple_rpc::middleware::concurrency::ConcurrencyLimit;
+        use prost_simple_rpc::middleware::concurrency::Error;
+        use prost_simple_rpc::middleware::HandlerExt;
+        use prost_simple_rpc::status::ToStatus;
+        use schema::echo::Echo;
+        use schema::echo::EchoMethodDescriptor;
+
+        let service = PendingEchoService::default();
+        let limit = ConcurrencyLimit::new(2).with_queue(1);
+        let echo = schema::echo::EchoClient::new(
+            schema::echo::EchoServer::new(service.clone()).layer(limit.clone()),
+        );
+        let notify = sync::Arc::new(NoopNotify);
+        let mut calls = (0..3)
+            .map(|_| echo.echo(schema::echo::EchoRequest { data: vec![] }))
+            .map(futures::executor::spawn)
+            .collect::<Vec<_>>();
+        for call in &mut calls {
+            assert!(call.poll_future_notify(&notify, 0).unwrap().is_not_ready());
+        }
+
+        // Calls beyond the limit wait in the queue, and once that is full, they are rejected.
+        assert_eq!(limit.in_flight(), 2);
+        assert_eq!(limit.queued(), 1);
+        assert_eq!(service.contexts.lock().unwrap().len(), 2);
+        match echo
+            .echo(schema::echo::EchoRequest { data: vec![] })
+            .wait()
+        {
+            Err(prost_simple_rpc::error::Error::Execution {
+                error: Error::Rejected,
+            }) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        // Cancelling one of the calls lets the queued one in.
+        calls.remove(0);
+        assert_eq!(limit.in_flight(), 2);
+        assert_eq!(limit.queued(), 0);
+        assert!(calls[1]
+            .poll_future_notify(&notify, 0)
+            .unwrap()
+            .is_not_ready());
+        assert_eq!(service.contexts.lock().unwrap().len(), 3);
+        drop(calls);
+        assert_eq!(limit.in_flight(), 0);
+
+        // Calls that wait in the queue for too long are shed.  On the server side, both rejected
+        // and shed calls are reported to clients as `ResourceExhausted`.
+        let service = PendingEchoService::default();
+        let limit = ConcurrencyLimit::new(10)
+            .with_method(EchoMethodDescriptor::Echo, 1)
+            .with_queue(4)
+            .with_max_queueing_delay(time::Duration::from_millis(50));
+        let (connection, server) = serve_channel(
+            4,
+            schema::echo::EchoServer::new(service.clone()).layer(limit.clone()),
+        );
+        let echo = schema::echo::EchoClient::new(
+            prost_simple_rpc::transport::channel::Client::new(connection),
+        );
+        let mut pending =
+            futures::executor::spawn(echo.echo(schema::echo::EchoRequest { data: vec![] }));
+        let deadline = time::Instant::now() + time::Duration::from_secs(5);
+        while service.contexts.lock().unwrap().is_empty() {
+            assert!(time::Instant::now() < deadline, "the call didn't reach the server");
+            assert!(pending
+                .poll_future_notify(&notify, 0)
+                .unwrap()
+                .is_not_ready());
+            std::thread::sleep(time::Duration::from_millis(1));
+        }
+
+        let start = time::Instant::now();
+        let error = echo
+            .echo(schema::echo::EchoRequest { data: vec![] })
+            .wait()
+            .unwrap_err();
+        assert!(start.elapsed() >= time::Duration::from_millis(50));
+        assert_eq!(
+            error.to_status().code(),
+            prost_simple_rpc::status::Code::ResourceExhausted
+        );
+        assert_eq!(limit.queued(), 0);
+        assert_eq!(service.contexts.lock().unwrap().len(), 1);
+
+        drop(pending);
+        drop(echo);
+        server.join().unwrap().unwrap();
+        assert_eq!(limit.in_flight(), 0);
+    }
 }
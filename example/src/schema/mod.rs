@@ -1,2 +1,7 @@
+// Not all of the generated code is used by this example.
+#![allow(dead_code)]
+
+pub mod calculator;
+pub mod counter;
 pub mod echo;
 pub mod greeting;
@@ -1,15 +1,21 @@
 //! Utility functions used by generated code; this is *not* part of the crate's public API!
+use std::fmt;
+use std::future;
 use std::marker;
 use std::mem;
+use std::pin;
+use std::task;
 
 use bytes;
 use failure;
 use futures;
 use prost;
 
+use context;
 use descriptor;
 use error;
 use handler;
+use std_future;
 
 /// A future returned by a client call.
 #[derive(Debug)]
@@ -22,9 +28,10 @@ where
         I,
         H,
         <H::Descriptor as descriptor::ServiceDescriptor>::Method,
+        context::Context,
     ),
     /// The message was sent over RPC but the call future is not yet done.
-    Call(H::CallFuture),
+    Call(H::CallFuture, Watch),
     /// We have returned the response to the caller.
     Done(marker::PhantomData<O>),
 }
@@ -40,7 +47,7 @@ where
         input: I,
         method: <H::Descriptor as descriptor::ServiceDescriptor>::Method,
     ) -> Self {
-        ClientFuture::Encode(input, handler, method)
+        ClientFuture::Encode(input, handler, method, outgoing_context())
     }
 }
 
@@ -56,26 +63,593 @@ where
     fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
         loop {
             match mem::replace(self, ClientFuture::Done(marker::PhantomData)) {
-                ClientFuture::Encode(input, handler, method) => {
+                ClientFuture::Encode(input, handler, method, context) => {
                     let input_bytes = encode(input)?;
-                    *self = ClientFuture::Call(handler.call(method, input_bytes));
+                    let watch = Watch::new(context.clone());
+                    *self = ClientFuture::Call(handler.call(method, input_bytes, context), watch);
                 }
-                ClientFuture::Call(mut future) => match future.poll() {
-                    Ok(futures::Async::Ready(bytes)) => {
-                        return Ok(futures::Async::Ready(decode::<O, _>(bytes)?));
+                ClientFuture::Call(mut future, mut watch) => {
+                    watch.poll()?;
+                    match future.poll() {
+                        Ok(futures::Async::Ready(bytes)) => {
+                            watch.finish();
+                            return Ok(futures::Async::Ready(decode::<O, _>(bytes)?));
+                        }
+                        Ok(futures::Async::NotReady) => {
+                            *self = ClientFuture::Call(future, watch);
+                            return Ok(futures::Async::NotReady);
+                        }
+                        Err(err) => return Err(error::Error::execution(err)),
                     }
-                    Ok(futures::Async::NotReady) => {
-                        *self = ClientFuture::Call(future);
-                        return Ok(futures::Async::NotReady);
-                    }
-                    Err(err) => return Err(error::Error::execution(err)),
-                },
+                }
                 ClientFuture::Done(_) => panic!("cannot poll a client future twice"),
             }
         }
     }
 }
 
+/// A `std::future::Future` returned by a client call.
+#[derive(Debug)]
+pub enum StdClientFuture<H, I, O>
+where
+    H: std_future::Handler,
+{
+    /// The message has not yet been encoded.
+    Encode(
+        I,
+        H,
+        <H::Descriptor as descriptor::ServiceDescriptor>::Method,
+        context::Context,
+    ),
+    /// The message was sent over RPC but the call future is not yet done.
+    Call(pin::Pin<Box<H::CallFuture>>, Watch),
+    /// We have returned the response to the caller.
+    Done(marker::PhantomData<O>),
+}
+
+impl<H, I, O> StdClientFuture<H, I, O>
+where
+    H: std_future::Handler,
+    I: prost::Message,
+    O: prost::Message + Default,
+{
+    pub fn new(
+        handler: H,
+        input: I,
+        method: <H::Descriptor as descriptor::ServiceDescriptor>::Method,
+    ) -> Self {
+        StdClientFuture::Encode(input, handler, method, outgoing_context())
+    }
+}
+
+// The call future is pinned on the heap, and no other field is ever pinned.
+impl<H, I, O> Unpin for StdClientFuture<H, I, O> where H: std_future::Handler {}
+
+impl<H, I, O> future::Future for StdClientFuture<H, I, O>
+where
+    H: std_future::Handler,
+    I: prost::Message,
+    O: prost::Message + Default,
+{
+    type Output = error::Result<O, H::Error>;
+
+    fn poll(self: pin::Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Self::Output> {
+        let this = pin::Pin::get_mut(self);
+        loop {
+            match mem::replace(this, StdClientFuture::Done(marker::PhantomData)) {
+                StdClientFuture::Encode(input, handler, method, context) => {
+                    let input_bytes = encode(input)?;
+                    let watch = Watch::new(context.clone());
+                    *this = StdClientFuture::Call(
+                        Box::pin(handler.call(method, input_bytes, context)),
+                        watch,
+                    );
+                }
+                StdClientFuture::Call(mut future, mut watch) => {
+                    watch.poll_std(cx)?;
+                    match future.as_mut().poll(cx) {
+                        task::Poll::Ready(Ok(bytes)) => {
+                            watch.finish();
+                            return task::Poll::Ready(decode::<O, _>(bytes));
+                        }
+                        task::Poll::Ready(Err(err)) => {
+                            return task::Poll::Ready(Err(error::Error::execution(err)));
+                        }
+                        task::Poll::Pending => {
+                            *this = StdClientFuture::Call(future, watch);
+                            return task::Poll::Pending;
+                        }
+                    }
+                }
+                StdClientFuture::Done(_) => panic!("cannot poll a client future twice"),
+            }
+        }
+    }
+}
+
+/// A stream returned by a server-streaming client call.
+#[derive(Debug)]
+pub enum ClientStream<H, I, O>
+where
+    H: handler::ServerStreamingHandler,
+{
+    /// The message has not yet been encoded.
+    Encode(
+        I,
+        H,
+        <H::Descriptor as descriptor::ServiceDescriptor>::Method,
+        context::Context,
+    ),
+    /// The message was sent over RPC and we are receiving responses from the call stream.
+    Call(H::CallStream, Watch),
+    /// The call stream has been exhausted.
+    Done(marker::PhantomData<O>),
+}
+
+impl<H, I, O> ClientStream<H, I, O>
+where
+    H: handler::ServerStreamingHandler,
+    I: prost::Message,
+    O: prost::Message + Default,
+{
+    pub fn new(
+        handler: H,
+        input: I,
+        method: <H::Descriptor as descriptor::ServiceDescriptor>::Method,
+    ) -> Self {
+        ClientStream::Encode(input, handler, method, outgoing_context())
+    }
+}
+
+impl<H, I, O> futures::Stream for ClientStream<H, I, O>
+where
+    H: handler::ServerStreamingHandler,
+    I: prost::Message,
+    O: prost::Message + Default,
+{
+    type Item = O;
+    type Error = error::Error<H::Error>;
+
+    fn poll(&mut self) -> futures::Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            match mem::replace(self, ClientStream::Done(marker::PhantomData)) {
+                ClientStream::Encode(input, handler, method, context) => {
+                    let input_bytes = encode(input)?;
+                    let watch = Watch::new(context.clone());
+                    *self = ClientStream::Call(
+                        handler.call_server_streaming(method, input_bytes, context),
+                        watch,
+                    );
+                }
+                ClientStream::Call(mut stream, mut watch) => {
+                    watch.poll()?;
+                    return match stream.poll() {
+                        Ok(futures::Async::Ready(Some(bytes))) => {
+                            *self = ClientStream::Call(stream, watch);
+                            Ok(futures::Async::Ready(Some(decode::<O, _>(bytes)?)))
+                        }
+                        Ok(futures::Async::Ready(None)) => {
+                            watch.finish();
+                            Ok(futures::Async::Ready(None))
+                        }
+                        Ok(futures::Async::NotReady) => {
+                            *self = ClientStream::Call(stream, watch);
+                            Ok(futures::Async::NotReady)
+                        }
+                        Err(err) => Err(error::Error::execution(err)),
+                    };
+                }
+                ClientStream::Done(_) => return Ok(futures::Async::Ready(None)),
+            }
+        }
+    }
+}
+
+/// A future returned by a client-streaming client call.
+#[derive(Debug)]
+pub enum ClientStreamingFuture<H, I, O>
+where
+    H: handler::ClientStreamingHandler,
+{
+    /// The call has not yet been started.
+    Start(
+        EncodeStream<I>,
+        H,
+        <H::Descriptor as descriptor::ServiceDescriptor>::Method,
+        context::Context,
+    ),
+    /// The input stream was handed over to RPC but the call future is not yet done.
+    Call(H::StreamingCallFuture, Watch),
+    /// We have returned the response to the caller.
+    Done(marker::PhantomData<O>),
+}
+
+impl<H, I, O> ClientStreamingFuture<H, I, O>
+where
+    H: handler::ClientStreamingHandler,
+    I: prost::Message + 'static,
+    O: prost::Message + Default,
+{
+    pub fn new(
+        handler: H,
+        input: handler::InputStream<I>,
+        method: <H::Descriptor as descriptor::ServiceDescriptor>::Method,
+    ) -> Self {
+        ClientStreamingFuture::Start(
+            EncodeStream::new(input),
+            handler,
+            method,
+            outgoing_context(),
+        )
+    }
+}
+
+impl<H, I, O> futures::Future for ClientStreamingFuture<H, I, O>
+where
+    H: handler::ClientStreamingHandler,
+    I: prost::Message + 'static,
+    O: prost::Message + Default,
+{
+    type Item = O;
+    type Error = error::Error<H::Error>;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        loop {
+            match mem::replace(self, ClientStreamingFuture::Done(marker::PhantomData)) {
+                ClientStreamingFuture::Start(input, handler, method, context) => {
+                    let watch = Watch::new(context.clone());
+                    *self = ClientStreamingFuture::Call(
+                        handler.call_client_streaming(method, Box::new(input), context),
+                        watch,
+                    );
+                }
+                ClientStreamingFuture::Call(mut future, mut watch) => {
+                    watch.poll()?;
+                    match future.poll() {
+                        Ok(futures::Async::Ready(bytes)) => {
+                            watch.finish();
+                            return Ok(futures::Async::Ready(decode::<O, _>(bytes)?));
+                        }
+                        Ok(futures::Async::NotReady) => {
+                            *self = ClientStreamingFuture::Call(future, watch);
+                            return Ok(futures::Async::NotReady);
+                        }
+                        Err(err) => return Err(error::Error::execution(err)),
+                    }
+                }
+                ClientStreamingFuture::Done(_) => {
+                    panic!("cannot poll a client streaming future twice")
+                }
+            }
+        }
+    }
+}
+
+/// A stream returned by a bidirectional streaming client call.
+#[derive(Debug)]
+pub enum ClientBidiStream<H, I, O>
+where
+    H: handler::BidiStreamingHandler,
+{
+    /// The call has not yet been started.
+    Start(
+        EncodeStream<I>,
+        H,
+        <H::Descriptor as descriptor::ServiceDescriptor>::Method,
+        context::Context,
+    ),
+    /// The input stream was handed over to RPC and we are receiving responses from the call
+    /// stream.
+    Call(H::StreamingCallStream, Watch),
+    /// The call stream has been exhausted.
+    Done(marker::PhantomData<O>),
+}
+
+impl<H, I, O> ClientBidiStream<H, I, O>
+where
+    H: handler::BidiStreamingHandler,
+    I: prost::Message + 'static,
+    O: prost::Message + Default,
+{
+    pub fn new(
+        handler: H,
+        input: handler::InputStream<I>,
+        method: <H::Descriptor as descriptor::ServiceDescriptor>::Method,
+    ) -> Self {
+        ClientBidiStream::Start(
+            EncodeStream::new(input),
+            handler,
+            method,
+            outgoing_context(),
+        )
+    }
+}
+
+impl<H, I, O> futures::Stream for ClientBidiStream<H, I, O>
+where
+    H: handler::BidiStreamingHandler,
+    I: prost::Message + 'static,
+    O: prost::Message + Default,
+{
+    type Item = O;
+    type Error = error::Error<H::Error>;
+
+    fn poll(&mut self) -> futures::Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            match mem::replace(self, ClientBidiStream::Done(marker::PhantomData)) {
+                ClientBidiStream::Start(input, handler, method, context) => {
+                    let watch = Watch::new(context.clone());
+                    *self = ClientBidiStream::Call(
+                        handler.call_bidi_streaming(method, Box::new(input), context),
+                        watch,
+                    );
+                }
+                ClientBidiStream::Call(mut stream, mut watch) => {
+                    watch.poll()?;
+                    return match stream.poll() {
+                        Ok(futures::Async::Ready(Some(bytes))) => {
+                            *self = ClientBidiStream::Call(stream, watch);
+                            Ok(futures::Async::Ready(Some(decode::<O, _>(bytes)?)))
+                        }
+                        Ok(futures::Async::Ready(None)) => {
+                            watch.finish();
+                            Ok(futures::Async::Ready(None))
+                        }
+                        Ok(futures::Async::NotReady) => {
+                            *self = ClientBidiStream::Call(stream, watch);
+                            Ok(futures::Async::NotReady)
+                        }
+                        Err(err) => Err(error::Error::execution(err)),
+                    };
+                }
+                ClientBidiStream::Done(_) => return Ok(futures::Async::Ready(None)),
+            }
+        }
+    }
+}
+
+/// Watches the context of a call for cancellation, and cancels the call if it is dropped before
+/// it is done.
+#[derive(Debug)]
+pub struct Watch {
+    context: context::Context,
+    cancelled: context::Cancelled,
+    done: bool,
+}
+
+impl Watch {
+    pub fn new(context: context::Context) -> Self {
+        let cancelled = context.cancelled();
+        Watch {
+            context,
+            cancelled,
+            done: false,
+        }
+    }
+
+    /// Fails if the call was cancelled, registering the current `futures` task otherwise.
+    pub fn poll<E>(&mut self) -> error::Result<(), E>
+    where
+        E: failure::Fail,
+    {
+        match futures::Future::poll(&mut self.cancelled) {
+            Ok(futures::Async::Ready(())) => Err(self.error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails if the call was cancelled, registering the current `std::future` waker otherwise.
+    pub fn poll_std<E>(&mut self, cx: &mut task::Context) -> error::Result<(), E>
+    where
+        E: failure::Fail,
+    {
+        match future::Future::poll(pin::Pin::new(&mut self.cancelled), cx) {
+            task::Poll::Ready(()) => Err(self.error()),
+            task::Poll::Pending => Ok(()),
+        }
+    }
+
+    /// Marks the call as done, so that it isn't cancelled when dropped.
+    pub fn finish(mut self) {
+        self.done = true;
+    }
+
+    fn error<E>(&self) -> error::Error<E>
+    where
+        E: failure::Fail,
+    {
+        if self.context.is_deadline_exceeded() {
+            error::Error::deadline_exceeded()
+        } else {
+            error::Error::cancelled()
+        }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        if !self.done {
+            self.context.cancel();
+        }
+    }
+}
+
+/// The future or stream handling an incoming call, which fails early if the call is cancelled.
+#[derive(Debug)]
+pub struct Incoming<F> {
+    inner: context::Bound<F>,
+    watch: Option<Watch>,
+}
+
+impl<F, E> futures::Future for Incoming<F>
+where
+    F: futures::Future<Error = error::Error<E>>,
+    E: failure::Fail,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let mut watch = self
+            .watch
+            .take()
+            .expect("cannot poll an incoming call twice");
+        watch.poll()?;
+        let result = self.inner.poll();
+        match result {
+            Ok(futures::Async::NotReady) => self.watch = Some(watch),
+            _ => watch.finish(),
+        }
+        result
+    }
+}
+
+impl<F, E> futures::Stream for Incoming<F>
+where
+    F: futures::Stream<Error = error::Error<E>>,
+    E: failure::Fail,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> futures::Poll<Option<Self::Item>, Self::Error> {
+        let mut watch = match self.watch.take() {
+            Some(watch) => watch,
+            None => return Ok(futures::Async::Ready(None)),
+        };
+        watch.poll()?;
+        let result = self.inner.poll();
+        match result {
+            Ok(futures::Async::NotReady) | Ok(futures::Async::Ready(Some(_))) => {
+                self.watch = Some(watch)
+            }
+            _ => watch.finish(),
+        }
+        result
+    }
+}
+
+impl<F, T, E> future::Future for Incoming<F>
+where
+    F: future::Future<Output = Result<T, error::Error<E>>> + Unpin,
+    E: failure::Fail,
+{
+    type Output = F::Output;
+
+    fn poll(self: pin::Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Self::Output> {
+        let this = pin::Pin::get_mut(self);
+        let mut watch = this
+            .watch
+            .take()
+            .expect("cannot poll an incoming call twice");
+        watch.poll_std(cx)?;
+        let result = pin::Pin::new(&mut this.inner).poll(cx);
+        match result {
+            task::Poll::Pending => this.watch = Some(watch),
+            task::Poll::Ready(_) => watch.finish(),
+        }
+        result
+    }
+}
+
+/// A stream that lazily encodes each message of an input stream into a byte buffer.
+pub struct EncodeStream<M> {
+    inner: handler::InputStream<M>,
+}
+
+impl<M> EncodeStream<M>
+where
+    M: prost::Message,
+{
+    pub fn new(inner: handler::InputStream<M>) -> Self {
+        EncodeStream { inner }
+    }
+}
+
+impl<M> fmt::Debug for EncodeStream<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EncodeStream").finish()
+    }
+}
+
+impl<M> futures::Stream for EncodeStream<M>
+where
+    M: prost::Message,
+{
+    type Item = bytes::Bytes;
+    type Error = failure::Error;
+
+    fn poll(&mut self) -> futures::Poll<Option<Self::Item>, Self::Error> {
+        match self.inner.poll()? {
+            futures::Async::Ready(Some(message)) => {
+                Ok(futures::Async::Ready(Some(encode_raw(message)?)))
+            }
+            futures::Async::Ready(None) => Ok(futures::Async::Ready(None)),
+            futures::Async::NotReady => Ok(futures::Async::NotReady),
+        }
+    }
+}
+
+/// A stream that lazily decodes each byte buffer of an input stream into a message.
+pub struct DecodeStream<M> {
+    inner: handler::InputStream<bytes::Bytes>,
+    phantom: marker::PhantomData<fn() -> M>,
+}
+
+impl<M> DecodeStream<M>
+where
+    M: prost::Message + Default,
+{
+    pub fn new(inner: handler::InputStream<bytes::Bytes>) -> Self {
+        DecodeStream {
+            inner,
+            phantom: marker::PhantomData,
+        }
+    }
+}
+
+impl<M> fmt::Debug for DecodeStream<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DecodeStream").finish()
+    }
+}
+
+impl<M> futures::Stream for DecodeStream<M>
+where
+    M: prost::Message + Default,
+{
+    type Item = M;
+    type Error = failure::Error;
+
+    fn poll(&mut self) -> futures::Poll<Option<Self::Item>, Self::Error> {
+        match self.inner.poll()? {
+            futures::Async::Ready(Some(bytes)) => {
+                Ok(futures::Async::Ready(Some(prost::Message::decode(bytes)?)))
+            }
+            futures::Async::Ready(None) => Ok(futures::Async::Ready(None)),
+            futures::Async::NotReady => Ok(futures::Async::NotReady),
+        }
+    }
+}
+
+/// The context to send along with a client call that is made right now.
+pub fn outgoing_context() -> context::Context {
+    context::Context::current().outgoing()
+}
+
+/// Starts handling an incoming call, binding its context to the future or stream that handles it.
+pub fn incoming<F, R>(context: context::Context, f: F) -> Incoming<R>
+where
+    F: FnOnce() -> R,
+{
+    let context = context.into_incoming();
+    let inner = context.scope(f);
+    Incoming {
+        watch: Some(Watch::new(context.clone())),
+        inner: context.bind(inner),
+    }
+}
+
 /// Efficiently decode a particular message type from a byte buffer.
 pub fn decode<M, E>(buf: bytes::Bytes) -> error::Result<M, E>
 where
@@ -91,6 +665,13 @@ pub fn encode<M, E>(message: M) -> error::Result<bytes::Bytes, E>
 where
     M: prost::Message,
     E: failure::Fail,
+{
+    Ok(encode_raw(message)?)
+}
+
+fn encode_raw<M>(message: M) -> Result<bytes::Bytes, prost::EncodeError>
+where
+    M: prost::Message,
 {
     let len = prost::Message::encoded_len(&message);
     let mut buf = ::bytes::BytesMut::with_capacity(len);
@@ -0,0 +1,8 @@
+//! Types used by blocking servers generated with `ServiceGenerator::blocking`.
+//!
+//! A blocking server runs the methods of a service on a thread pool, so that they don't block the
+//! thread that drives the server.  By default, each server gets a pool with one thread per CPU,
+//! but a `CpuPool` can also be created up front and shared between several servers.
+pub use futures_cpupool::Builder;
+pub use futures_cpupool::CpuFuture;
+pub use futures_cpupool::CpuPool;
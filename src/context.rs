@@ -0,0 +1,447 @@
+//! Per-call context, carrying metadata, deadlines and cancellation from clients to servers and
+//! trailers back again.
+//!
+//! Every call made through a `Handler` carries a `Context`.  Generated clients use the context that
+//! is current when the call is made, and generated servers make the context of a call current
+//! while the service implementation handles it, so services can read the request metadata via
+//! `Context::current()` without it showing up in their method signatures.
+//!
+//! ```rust,ignore
+//! let mut metadata = Metadata::new();
+//! metadata.insert("authorization", "Bearer abc123");
+//! let context = Context::with_metadata(metadata).with_timeout(Duration::from_secs(5));
+//!
+//! let future = context.scope(|| client.echo(request));
+//! // ...once the future has completed:
+//! let trailers = context.trailers();
+//! ```
+//!
+//! Calls that a service makes while handling a call inherit its deadline, and are cancelled when
+//! it is cancelled, so that deadlines carry over across a whole graph of calls.
+use std::cell;
+use std::collections;
+use std::future;
+use std::mem;
+use std::pin;
+use std::sync;
+use std::sync::atomic;
+use std::task;
+use std::time;
+
+use futures;
+
+use metadata;
+use timer;
+
+thread_local! {
+    static CURRENT: cell::RefCell<Option<Context>> = const { cell::RefCell::new(None) };
+}
+
+/// The context of a single call.
+///
+/// Contexts are cheap to clone, and clones share the same trailers and cancellation state.
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+    metadata: sync::Arc<metadata::Metadata>,
+    trailers: sync::Arc<sync::Mutex<metadata::Metadata>>,
+    deadline: Option<time::Instant>,
+    cancellation: sync::Arc<Cancellation>,
+    peer_credentials: Option<PeerCredentials>,
+    incoming: bool,
+}
+
+/// The credentials of the process on the other end of a local connection.
+///
+/// Transports over local sockets, such as the `unix` transport, attach these to the context of
+/// incoming calls, so that services can tell which user is calling them.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct PeerCredentials {
+    /// The user ID of the peer process.
+    pub uid: u32,
+    /// The group ID of the peer process.
+    pub gid: u32,
+    /// The process ID of the peer process, on platforms that report it.
+    pub pid: Option<u32>,
+}
+
+/// A future or stream that makes a `Context` current whenever it is polled.
+#[derive(Debug)]
+pub struct Bound<F> {
+    context: Context,
+    inner: F,
+}
+
+/// A future that completes when a `Context` is cancelled or its deadline passes.
+///
+/// This works both as a `futures::Future` and as a `std::future::Future`.
+#[derive(Debug)]
+pub struct Cancelled {
+    cancellation: sync::Arc<Cancellation>,
+    delay: Option<timer::Delay>,
+    /// The ID under which the waiter is registered with the cancellation, and the waiter itself.
+    registration: Option<(u64, timer::Waiter)>,
+}
+
+#[derive(Debug, Default)]
+struct Cancellation {
+    cancelled: atomic::AtomicBool,
+    waiters: sync::Mutex<Waiters>,
+    children: sync::Mutex<Vec<sync::Weak<Cancellation>>>,
+}
+
+#[derive(Debug, Default)]
+struct Waiters {
+    entries: collections::BTreeMap<u64, timer::Waiter>,
+    next_id: u64,
+}
+
+struct Reset(Option<Context>);
+
+impl Context {
+    /// Creates a new context without any metadata or deadline.
+    pub fn new() -> Context {
+        Context::default()
+    }
+
+    /// Creates a new context that sends the specified metadata with each call.
+    pub fn with_metadata(metadata: metadata::Metadata) -> Context {
+        Context {
+            metadata: sync::Arc::new(metadata),
+            ..Context::default()
+        }
+    }
+
+    /// Returns this context with the specified deadline.
+    ///
+    /// This never extends an existing deadline; the earliest deadline always wins.
+    pub fn with_deadline(mut self, deadline: time::Instant) -> Context {
+        self.deadline = Some(match self.deadline {
+            Some(existing) if existing < deadline => existing,
+            _ => deadline,
+        });
+        self
+    }
+
+    /// Returns this context with a deadline at the specified duration from now.
+    ///
+    /// This is what transports should use to restore a deadline sent by `Context::timeout`.
+    pub fn with_timeout(self, timeout: time::Duration) -> Context {
+        self.with_deadline(time::Instant::now() + timeout)
+    }
+
+    /// Returns this context with the credentials of the peer that made the call.
+    ///
+    /// This is meant to be used by transports for incoming calls.
+    pub fn with_peer_credentials(mut self, peer_credentials: PeerCredentials) -> Context {
+        self.peer_credentials = Some(peer_credentials);
+        self
+    }
+
+    /// Returns the context that is current on this thread, or a new empty context if there is none.
+    ///
+    /// Inside of a service implementation, this is the context of the call being handled.
+    pub fn current() -> Context {
+        CURRENT.with(|current| current.borrow().clone().unwrap_or_default())
+    }
+
+    /// The metadata sent along with the request.
+    pub fn metadata(&self) -> &metadata::Metadata {
+        &self.metadata
+    }
+
+    /// The credentials of the peer that made the call, if the transport knows them.
+    pub fn peer_credentials(&self) -> Option<PeerCredentials> {
+        self.peer_credentials
+    }
+
+    /// The trailers that have been set so far for the response.
+    pub fn trailers(&self) -> metadata::Metadata {
+        self.lock_trailers().clone()
+    }
+
+    /// Sets a trailer that will be sent back along with the response.
+    pub fn set_trailer<K, V>(&self, key: K, value: V)
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.lock_trailers().insert(key, value);
+    }
+
+    /// Sets several trailers at once, for example after receiving them from a transport.
+    pub fn set_trailers(&self, trailers: metadata::Metadata) {
+        self.lock_trailers().extend(trailers);
+    }
+
+    /// The deadline of the call, if any.
+    pub fn deadline(&self) -> Option<time::Instant> {
+        self.deadline
+    }
+
+    /// The time remaining until the deadline of the call, if any.
+    ///
+    /// Since instants can't be sent to other machines, this is what transports should send along
+    /// with a request.
+    pub fn timeout(&self) -> Option<time::Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(time::Instant::now()))
+    }
+
+    /// Whether the deadline of the call has passed.
+    pub fn is_deadline_exceeded(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| deadline <= time::Instant::now())
+    }
+
+    /// Whether the call has been cancelled, or its deadline has passed.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled() || self.is_deadline_exceeded()
+    }
+
+    /// Cancels the call, and all of the calls made on its behalf.
+    ///
+    /// Generated clients do this when a call is dropped before it completes, and transports should
+    /// do this when they notice that the client of an incoming call went away.
+    pub fn cancel(&self) {
+        self.cancellation.cancel()
+    }
+
+    /// Returns a future that completes when the call is cancelled or its deadline passes.
+    pub fn cancelled(&self) -> Cancelled {
+        Cancelled {
+            cancellation: self.cancellation.clone(),
+            delay: self.deadline.map(timer::Delay::new),
+            registration: None,
+        }
+    }
+
+    /// Runs the specified function with this context as the current context.
+    pub fn scope<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let _reset = Reset(CURRENT.with(|current| current.replace(Some(self.clone()))));
+        f()
+    }
+
+    /// Wraps a future or stream so that this context is current whenever it is polled.
+    pub fn bind<F>(self, inner: F) -> Bound<F> {
+        Bound {
+            context: self,
+            inner,
+        }
+    }
+
+    pub(crate) fn into_incoming(mut self) -> Context {
+        self.incoming = true;
+        self
+    }
+
+    /// The context to use for a single call made while this context is current.
+    ///
+    /// Each call gets its own cancellation state, so that cancelling it doesn't affect its parent,
+    /// but it keeps the deadline of the parent.  Metadata and trailers of incoming calls aren't
+    /// forwarded to downstream calls, so that metadata isn't leaked to other services and
+    /// downstream trailers don't end up in the response.
+    pub(crate) fn outgoing(self) -> Context {
+        let cancellation = self.cancellation.child();
+        if self.incoming {
+            Context {
+                deadline: self.deadline,
+                cancellation,
+                ..Context::default()
+            }
+        } else {
+            Context {
+                cancellation,
+                ..self
+            }
+        }
+    }
+
+    fn lock_trailers(&self) -> sync::MutexGuard<'_, metadata::Metadata> {
+        lock(&self.trailers)
+    }
+}
+
+impl Cancelled {
+    fn poll_waiter(&mut self, waiter: timer::Waiter) -> bool {
+        if self.cancellation.is_cancelled() {
+            return true;
+        }
+
+        let registered = match self.registration {
+            Some((_, ref registered)) => registered.same_as(&waiter),
+            None => false,
+        };
+        if !registered {
+            if let Some((id, _)) = self.registration.take() {
+                self.cancellation.deregister(id);
+            }
+            let id = self.cancellation.register(waiter.clone());
+            self.registration = Some((id, waiter.clone()));
+        }
+
+        let deadline_exceeded = match self.delay {
+            Some(ref mut delay) => match waiter {
+                timer::Waiter::Task(_) => {
+                    futures::Future::poll(delay) == Ok(futures::Async::Ready(()))
+                }
+                timer::Waiter::Waker(ref waker) => {
+                    let mut cx = task::Context::from_waker(waker);
+                    future::Future::poll(pin::Pin::new(delay), &mut cx).is_ready()
+                }
+            },
+            None => false,
+        };
+
+        // Check again in case the call was cancelled while registering.
+        deadline_exceeded || self.cancellation.is_cancelled()
+    }
+}
+
+impl futures::Future for Cancelled {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> futures::Poll<(), ()> {
+        if self.poll_waiter(timer::Waiter::current()) {
+            Ok(futures::Async::Ready(()))
+        } else {
+            Ok(futures::Async::NotReady)
+        }
+    }
+}
+
+impl future::Future for Cancelled {
+    type Output = ();
+
+    fn poll(self: pin::Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<()> {
+        if self
+            .get_mut()
+            .poll_waiter(timer::Waiter::Waker(cx.waker().clone()))
+        {
+            task::Poll::Ready(())
+        } else {
+            task::Poll::Pending
+        }
+    }
+}
+
+impl Drop for Cancelled {
+    fn drop(&mut self) {
+        // Long-lived contexts would otherwise collect a waiter for every call that was made.
+        if let Some((id, _)) = self.registration.take() {
+            self.cancellation.deregister(id);
+        }
+    }
+}
+
+impl Cancellation {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(atomic::Ordering::SeqCst)
+    }
+
+    fn cancel(&self) {
+        if self.cancelled.swap(true, atomic::Ordering::SeqCst) {
+            return;
+        }
+        let waiters = mem::take(&mut lock(&self.waiters).entries);
+        for waiter in waiters.values() {
+            waiter.notify();
+        }
+        for child in lock(&self.children).drain(..) {
+            if let Some(child) = child.upgrade() {
+                child.cancel();
+            }
+        }
+    }
+
+    fn register(&self, waiter: timer::Waiter) -> u64 {
+        let mut waiters = lock(&self.waiters);
+        let id = waiters.next_id;
+        waiters.next_id += 1;
+        waiters.entries.insert(id, waiter);
+        id
+    }
+
+    fn deregister(&self, id: u64) {
+        lock(&self.waiters).entries.remove(&id);
+    }
+
+    fn child(self: &sync::Arc<Self>) -> sync::Arc<Cancellation> {
+        let child = sync::Arc::new(Cancellation::default());
+        {
+            let mut children = lock(&self.children);
+            children.retain(|child| child.strong_count() > 0);
+            children.push(sync::Arc::downgrade(&child));
+        }
+        // Check after registering, so that a concurrent cancellation can't be missed.
+        if self.is_cancelled() {
+            child.cancel();
+        }
+        child
+    }
+}
+
+impl<F> Bound<F> {
+    /// The context that is bound to the inner future or stream.
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Returns the inner future or stream.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+impl<F> futures::Future for Bound<F>
+where
+    F: futures::Future,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let inner = &mut self.inner;
+        self.context.scope(|| inner.poll())
+    }
+}
+
+impl<F> futures::Stream for Bound<F>
+where
+    F: futures::Stream,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> futures::Poll<Option<Self::Item>, Self::Error> {
+        let inner = &mut self.inner;
+        self.context.scope(|| inner.poll())
+    }
+}
+
+impl<F> future::Future for Bound<F>
+where
+    F: future::Future + Unpin,
+{
+    type Output = F::Output;
+
+    fn poll(self: pin::Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Self::Output> {
+        let this = self.get_mut();
+        let inner = pin::Pin::new(&mut this.inner);
+        this.context.scope(|| inner.poll(cx))
+    }
+}
+
+impl Drop for Reset {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+fn lock<T>(mutex: &sync::Mutex<T>) -> sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(sync::PoisonError::into_inner)
+}
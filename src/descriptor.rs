@@ -23,6 +23,34 @@ pub trait ServiceDescriptor: Clone + fmt::Debug + Send + Sync {
 
     /// All of the available methods on the service.
     fn methods() -> &'static [Self::Method];
+
+    /// The fully qualified protobuf name of the service, e.g. `package.Service`.
+    fn full_name() -> String {
+        if Self::package().is_empty() {
+            Self::proto_name().to_owned()
+        } else {
+            format!("{}.{}", Self::package(), Self::proto_name())
+        }
+    }
+
+    /// The fully qualified path of a method on the service, e.g. `/package.Service/Method`.
+    fn path(method: &Self::Method) -> String {
+        format!("/{}/{}", Self::full_name(), method.proto_name())
+    }
+
+    /// Looks up a method on the service by its fully qualified path, e.g.
+    /// `/package.Service/Method`.
+    ///
+    /// Returns `None` if the path does not refer to a method on this service.
+    fn method_from_path(path: &str) -> Option<Self::Method> {
+        let mut parts = path.splitn(3, '/');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(""), Some(service), Some(method)) if service == Self::full_name() => {
+                Self::Method::from_proto_name(method)
+            }
+            _ => None,
+        }
+    }
 }
 
 /// A descriptor for a method available on an RPC service.
@@ -33,6 +61,12 @@ pub trait MethodDescriptor: Clone + Copy + fmt::Debug + Send + Sync {
     /// The raw protobuf name of the service.
     fn proto_name(&self) -> &'static str;
 
+    /// Looks up a method by the name used in Rust code.
+    fn from_name(name: &str) -> Option<Self>;
+
+    /// Looks up a method by its raw protobuf name.
+    fn from_proto_name(proto_name: &str) -> Option<Self>;
+
     /// The Rust `TypeId` for the input that this method accepts.
     fn input_type(&self) -> any::TypeId;
 
@@ -44,4 +78,14 @@ pub trait MethodDescriptor: Clone + Copy + fmt::Debug + Send + Sync {
 
     /// The raw protobuf name for the output type that this method produces.
     fn output_proto_type(&self) -> &'static str;
+
+    /// Whether this method accepts a stream of inputs instead of a single input.
+    fn client_streaming(&self) -> bool {
+        false
+    }
+
+    /// Whether this method produces a stream of outputs instead of a single output.
+    fn server_streaming(&self) -> bool {
+        false
+    }
 }
@@ -35,6 +35,33 @@ where
         #[cause]
         error: prost::EncodeError,
     },
+    /// A method was called using a calling convention that it does not support, for example a
+    /// server-streaming method being called as a unary method.
+    #[fail(display = "Unsupported call to method {}", method)]
+    UnsupportedCall {
+        /// The raw protobuf name of the method that was called.
+        method: &'static str,
+    },
+    /// A call was made to a service that is not known.
+    #[fail(display = "Unknown service {}", service)]
+    UnknownService {
+        /// The name of the service that was called.
+        service: String,
+    },
+    /// A call was made to a method that is not known on a particular service.
+    #[fail(display = "Unknown method {} on service {}", method, service)]
+    UnknownMethod {
+        /// The name of the service that was called.
+        service: String,
+        /// The name of the method that was called.
+        method: String,
+    },
+    /// The call was cancelled before it completed.
+    #[fail(display = "The call was cancelled")]
+    Cancelled,
+    /// The deadline of the call passed before it completed.
+    #[fail(display = "The deadline of the call was exceeded")]
+    DeadlineExceeded,
 }
 
 impl<E> Error<E>
@@ -45,6 +72,36 @@ where
     pub fn execution(error: E) -> Self {
         Error::Execution { error }
     }
+
+    /// Constructs a new unsupported call error.
+    pub fn unsupported_call(method: &'static str) -> Self {
+        Error::UnsupportedCall { method }
+    }
+
+    /// Constructs a new unknown service error.
+    pub fn unknown_service(service: &str) -> Self {
+        Error::UnknownService {
+            service: service.to_owned(),
+        }
+    }
+
+    /// Constructs a new unknown method error.
+    pub fn unknown_method(service: &str, method: &str) -> Self {
+        Error::UnknownMethod {
+            service: service.to_owned(),
+            method: method.to_owned(),
+        }
+    }
+
+    /// Constructs a new cancelled error.
+    pub fn cancelled() -> Self {
+        Error::Cancelled
+    }
+
+    /// Constructs a new deadline exceeded error.
+    pub fn deadline_exceeded() -> Self {
+        Error::DeadlineExceeded
+    }
 }
 
 impl<E> From<prost::DecodeError> for Error<E>
@@ -3,8 +3,16 @@ use bytes;
 use failure;
 use futures;
 
+use context;
 use descriptor;
 
+/// A stream of inputs for a client-streaming call.
+///
+/// Input streams can fail for reasons outside of the control of the receiving end, for example
+/// because of a broken transport or a message that can't be decoded, so their errors are
+/// type-erased.
+pub type InputStream<T> = Box<dyn futures::Stream<Item = T, Error = failure::Error> + Send>;
+
 /// An implementation of a specific RPC handler.
 ///
 /// This can be an actual implementation of a service, or something that will send a request over
@@ -17,10 +25,59 @@ pub trait Handler: Clone + Send + 'static {
     /// The future that results from a call to the `call` method of this trait.
     type CallFuture: futures::Future<Item = bytes::Bytes, Error = Self::Error> + Send;
 
-    /// Perform a raw call to the specified service and method.
+    /// Perform a raw call to the specified service and method, in the specified call context.
     fn call(
         &self,
         method: <Self::Descriptor as descriptor::ServiceDescriptor>::Method,
         input: bytes::Bytes,
+        context: context::Context,
     ) -> Self::CallFuture;
 }
+
+/// An implementation of a specific RPC handler that also supports server-streaming calls.
+///
+/// Server-streaming calls take a single input but produce a stream of outputs.
+pub trait ServerStreamingHandler: Handler {
+    /// The stream that results from a call to the `call_server_streaming` method of this trait.
+    type CallStream: futures::Stream<Item = bytes::Bytes, Error = Self::Error> + Send;
+
+    /// Perform a raw server-streaming call to the specified service and method.
+    fn call_server_streaming(
+        &self,
+        method: <Self::Descriptor as descriptor::ServiceDescriptor>::Method,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> Self::CallStream;
+}
+
+/// An implementation of a specific RPC handler that also supports client-streaming calls.
+///
+/// Client-streaming calls take a stream of inputs but produce a single output.
+pub trait ClientStreamingHandler: Handler {
+    /// The future that results from a call to the `call_client_streaming` method of this trait.
+    type StreamingCallFuture: futures::Future<Item = bytes::Bytes, Error = Self::Error> + Send;
+
+    /// Perform a raw client-streaming call to the specified service and method.
+    fn call_client_streaming(
+        &self,
+        method: <Self::Descriptor as descriptor::ServiceDescriptor>::Method,
+        input: InputStream<bytes::Bytes>,
+        context: context::Context,
+    ) -> Self::StreamingCallFuture;
+}
+
+/// An implementation of a specific RPC handler that also supports bidirectional streaming calls.
+///
+/// Bidirectional streaming calls take a stream of inputs and produce a stream of outputs.
+pub trait BidiStreamingHandler: Handler {
+    /// The stream that results from a call to the `call_bidi_streaming` method of this trait.
+    type StreamingCallStream: futures::Stream<Item = bytes::Bytes, Error = Self::Error> + Send;
+
+    /// Perform a raw bidirectional streaming call to the specified service and method.
+    fn call_bidi_streaming(
+        &self,
+        method: <Self::Descriptor as descriptor::ServiceDescriptor>::Method,
+        input: InputStream<bytes::Bytes>,
+        context: context::Context,
+    ) -> Self::StreamingCallStream;
+}
@@ -1,8 +1,8 @@
 //! A simple RPC library to be used together with `prost` for defining type-safe RPC services.
 //!
 //! This library lets you generate traits for implementing a generic RPC mechanism using Protobuf as
-//! the schema language.  You have to supply your own underlying transport mechanism, for example
-//! WebSockets, UNIX pipes, HTTP, etc.
+//! the schema language.  You can supply your own underlying transport mechanism, for example
+//! WebSockets, UNIX pipes, HTTP, etc., or use one of the transports that come with this library.
 //!
 //! You probably want to use this library together with `prost-simple-rpc-build` to generate the
 //! code for all of the traits defined in this crate.
@@ -99,9 +99,11 @@
 //!         &mut self,
 //!         method: <Self::Descriptor as descriptor::ServiceDescriptor>::Method,
 //!         input: bytes::Bytes,
+//!         context: prost_simple_rpc::context::Context,
 //!     ) -> Self::CallFuture {
-//!         // You can use information from the descriptors to include in the request:
-//!         self.websocket.call(Self::Descriptor::name(), method.name(), input)
+//!         // You can use information from the descriptors to include in the request, together with
+//!         // the metadata of the call:
+//!         self.websocket.call(Self::Descriptor::name(), method.name(), input, context.metadata())
 //!     }
 //! }
 //! ```
@@ -154,11 +156,394 @@
 //!         // information; here's a hard-coded example:
 //!         let method = schema::echo::EchoMethodDescriptor::Echo;
 //!
-//!         server.call(method, request.data);
+//!         // The context carries the request metadata to the service.
+//!         let context = prost_simple_rpc::context::Context::with_metadata(request.metadata);
+//!
+//!         server.call(method, request.data, context);
 //!     });
 //! }
 //! ```
 //!
+//! If you want to serve several services from the same transport, you can register their servers
+//! with a `router::Router`, which dispatches raw calls based on the names of the service and method:
+//!
+//! ```rust,ignore
+//! fn main() {
+//!     let mut router = prost_simple_rpc::router::Router::new();
+//!     router
+//!         .add(schema::echo::EchoServer::new(EchoService))
+//!         .add(schema::greeting::GreetingServer::new(GreetingService));
+//!
+//!     websocket::spawn_server(move |request| {
+//!         // The service name is the fully qualified protobuf name, e.g. "echo.Echo", and the method
+//!         // name is the raw protobuf name, e.g. "Echo".
+//!         let context = prost_simple_rpc::context::Context::with_metadata(request.metadata);
+//!         router.call(&request.service, &request.method, request.data, context);
+//!     });
+//! }
+//! ```
+//!
+//! ## Streaming
+//!
+//! Methods that return a `stream` of responses are supported too.  For such a method, the generated
+//! service trait has an associated `Stream` type instead of a `Future` type, for example:
+//!
+//! ```proto
+//! service Counter {
+//!   rpc Count (CountRequest) returns (stream CountResponse);
+//! }
+//! ```
+//!
+//! ```rust,ignore
+//! impl schema::counter::Counter for CounterService {
+//!     type Error = Error;
+//!     type CountStream = Box<futures::Stream<Item = schema::counter::CountResponse, Error = Error> + Send>;
+//!
+//!     fn count(&self, input: schema::counter::CountRequest) -> Self::CountStream {
+//!         // ...
+//!     }
+//! }
+//! ```
+//!
+//! The transport for a client of such a service must implement `ServerStreamingHandler` in addition
+//! to `Handler`, and the generated server implements `ServerStreamingHandler` so that it can produce a
+//! stream of raw responses.
+//!
+//! Similarly, methods that accept a `stream` of requests take a `handler::InputStream` of input
+//! messages, and their clients and servers use the `ClientStreamingHandler` trait.  Methods that both
+//! accept and return a `stream` use the `BidiStreamingHandler` trait.
+//!
+//! ## `std::future` and `async fn`
+//!
+//! The generated code uses `futures` 0.1 by default.  If you'd rather implement your services using
+//! `async fn`, enable `std::future::Future` based code generation in your `build.rs`:
+//!
+//! ```rust,ignore
+//! prost_build::Config::new()
+//!     .service_generator(Box::new(
+//!         prost_simple_rpc_build::ServiceGenerator::new().std_futures(true),
+//!     ))
+//!     .compile_protos(&["src/schema/echo/service.proto"], &["src/schema"])
+//!     .unwrap();
+//! ```
+//!
+//! The service trait can then be implemented with plain `async fn`s:
+//!
+//! ```rust,ignore
+//! impl schema::echo::Echo for EchoService {
+//!     type Error = Error;
+//!
+//!     async fn echo(
+//!         &self,
+//!         input: schema::echo::EchoRequest,
+//!     ) -> Result<schema::echo::EchoResponse, Self::Error> {
+//!         Ok(schema::echo::EchoResponse { data: input.data })
+//!     }
+//! }
+//! ```
+//!
+//! Transports for code generated in this mode implement `std_future::Handler` instead of
+//! `handler::Handler`.  Streaming methods are not supported in this mode yet.
+//!
+//! ## Blocking clients and servers
+//!
+//! For programs without an executor, `ServiceGenerator::new().blocking(true)` also generates blocking
+//! wrappers.  A `EchoBlockingClient` waits for the result of each call and returns it directly:
+//!
+//! ```rust,ignore
+//! let client = schema::echo::EchoBlockingClient::new(websocket);
+//! let response = client.echo(schema::echo::EchoRequest { /* ... */ })?;
+//! ```
+//!
+//! For services without streaming methods, an `EchoBlocking` trait with plain methods is generated as
+//! well.  The `EchoBlockingServer` runs such an implementation on a thread pool and can be wrapped in
+//! the regular `EchoServer`:
+//!
+//! ```rust,ignore
+//! impl schema::echo::EchoBlocking for EchoService {
+//!     type Error = Error;
+//!
+//!     fn echo(&self, input: schema::echo::EchoRequest) -> Result<schema::echo::EchoResponse, Error> {
+//!         Ok(schema::echo::EchoResponse { data: input.data })
+//!     }
+//! }
+//!
+//! let server = schema::echo::EchoServer::new(schema::echo::EchoBlockingServer::new(EchoService));
+//! ```
+//!
+//! ## Metadata
+//!
+//! Every call carries a `context::Context` with `metadata::Metadata`, such as authentication tokens or
+//! request IDs, from the client to the server, and trailers back from the server to the client.
+//! Generated clients send the context that is current when a call is made:
+//!
+//! ```rust,ignore
+//! let mut metadata = prost_simple_rpc::metadata::Metadata::new();
+//! metadata.insert("authorization", "Bearer abc123");
+//! let context = prost_simple_rpc::context::Context::with_metadata(metadata);
+//!
+//! let future = context.scope(|| client.echo(schema::echo::EchoRequest { /* ... */ }));
+//! // ... once the future is done, the trailers sent by the server are available:
+//! let trailers = context.trailers();
+//! ```
+//!
+//! Generated servers make the context of a call current while the service handles it, so the service
+//! can read the metadata and set trailers:
+//!
+//! ```rust,ignore
+//! fn echo(&self, input: schema::echo::EchoRequest) -> Self::EchoFuture {
+//!     let context = prost_simple_rpc::context::Context::current();
+//!     let token = context.metadata().get("authorization");
+//!     context.set_trailer("served-by", "echo-1");
+//!     // ...
+//! }
+//! ```
+//!
+//! The context of an incoming call is not forwarded to calls that the service makes itself.
+//!
+//! ## Deadlines and cancellation
+//!
+//! A context can also carry a deadline.  Generated clients fail with `Error::DeadlineExceeded` once it
+//! passes, and cancel the call when they are dropped before it is done.  Transports send
+//! `Context::timeout()` with each request and restore it on the server with `Context::with_timeout`:
+//!
+//! ```rust,ignore
+//! let context = prost_simple_rpc::context::Context::new().with_timeout(Duration::from_secs(5));
+//! let future = context.scope(|| client.echo(schema::echo::EchoRequest { /* ... */ }));
+//! ```
+//!
+//! Services can check `Context::current().is_cancelled()`, or wait for `Context::current().cancelled()`,
+//! to stop working on calls that nobody is waiting for anymore.  Calls that a service makes while
+//! handling a call inherit its deadline, and are cancelled together with it.
+//!
+//! ## Status codes
+//!
+//! To tell callers why a call failed, services can fail with a `status::Status`, which combines a
+//! gRPC-style `status::Code` with a message, or with an error that has a `Status` among its causes:
+//!
+//! ```rust,ignore
+//! impl schema::echo::Echo for EchoService {
+//!     type Error = prost_simple_rpc::status::Status;
+//!     // ...
+//!
+//!     fn echo(&self, input: schema::echo::EchoRequest) -> Self::EchoFuture {
+//!         futures::future::err(Status::new(Code::NotFound, "nothing to echo"))
+//!     }
+//! }
+//! ```
+//!
+//! Any `error::Error` can be described as a `Status` with `ToStatus::to_status`; errors that didn't
+//! originate from a `Status` get a fitting code such as `Code::DeadlineExceeded` or
+//! `Code::Unimplemented`, or `Code::Unknown` otherwise.  Transports send the result of
+//! `Status::encode` back for failed calls, and reconstruct it with `Status::decode`.
+//!
+//! ## Transports
+//!
+//! Instead of writing your own transport, you can use one of the transports in the `transport` module,
+//! each enabled by a cargo feature of the same name.  The `tcp` transport multiplexes concurrent calls
+//! over a single connection:
+//!
+//! ```rust,ignore
+//! let future = prost_simple_rpc::transport::tcp::connect(&addr).and_then(|connection| {
+//!     let client = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(connection));
+//!     client.echo(schema::echo::EchoRequest { /* ... */ })
+//! });
+//! ```
+//!
+//! Servers accept calls for any `transport::Dispatch`, which includes generated servers and routers:
+//!
+//! ```rust,ignore
+//! let listener = tokio::net::TcpListener::bind(&addr)?;
+//! let server = schema::echo::EchoServer::new(EchoService);
+//! tokio::run(prost_simple_rpc::transport::tcp::serve(listener, server).map_err(|_| ()));
+//! ```
+//!
+//! The `unix` transport uses the same protocol over Unix domain sockets, and tells services which
+//! process is calling them.  Since all stream transports share their framing, the same server can listen
+//! on both at once:
+//!
+//! ```rust,ignore
+//! let unix = tokio::net::UnixListener::bind("/run/echo.sock")?;
+//! tokio::spawn(prost_simple_rpc::transport::unix::serve(unix, server.clone()).map_err(|_| ()));
+//!
+//! // ...and in the service:
+//! let credentials = prost_simple_rpc::context::Context::current().peer_credentials();
+//! let uid = credentials.map(|credentials| credentials.uid);
+//! ```
+//!
+//! The `channel` transport stays within the process, but handles calls on a separate server task,
+//! for example on its own thread, with a bounded number of calls in flight.  In tests, it can inject
+//! delays and lost messages:
+//!
+//! ```rust,ignore
+//! let (connection, listener) = prost_simple_rpc::transport::channel::channel(16);
+//! thread::spawn(move || prost_simple_rpc::transport::channel::serve(listener, server).wait());
+//!
+//! let connection = connection.with_faults(|_service, _method| Fault::Delay(Duration::from_millis(10)));
+//! let client = schema::echo::EchoClient::new(prost_simple_rpc::transport::channel::Client::new(connection));
+//! ```
+//!
+//! To interoperate with gRPC implementations in other languages, the `grpc` transport speaks the gRPC
+//! protocol over HTTP/2, and works the same way as the `tcp` transport:
+//!
+//! ```rust,ignore
+//! let listener = tokio::net::TcpListener::bind(&addr)?;
+//! tokio::spawn(prost_simple_rpc::transport::grpc::serve(listener, server).map_err(|_| ()));
+//!
+//! let future = prost_simple_rpc::transport::grpc::connect(&addr).and_then(|connection| {
+//!     let client = schema::echo::EchoClient::new(prost_simple_rpc::transport::grpc::Client::new(connection));
+//!     client.echo(schema::echo::EchoRequest { /* ... */ })
+//! });
+//! ```
+//!
+//! The `twirp` transport speaks the Twirp protocol over HTTP/1.1 instead, so that services can be
+//! called by Twirp clients, or with `curl`.  JSON bodies are only served with `serve_with_json`, which
+//! takes a `JsonMapping` such as `SerdeJson` to convert messages:
+//!
+//! ```rust,ignore
+//! tokio::spawn(prost_simple_rpc::transport::twirp::serve(listener, server).map_err(|_| ()));
+//!
+//! let connection = prost_simple_rpc::transport::twirp::Connection::new("http://localhost:8080/twirp");
+//! let client = schema::echo::EchoClient::new(prost_simple_rpc::transport::twirp::Client::new(connection));
+//! ```
+//!
+//! The `websocket` transport carries the same frames as the `tcp` transport in binary WebSocket messages,
+//! for browsers and networks that only let HTTP through.  When either side closes the WebSocket, the calls
+//! that are still in flight fail with `transport::Error::Closed`:
+//!
+//! ```rust,ignore
+//! tokio::spawn(prost_simple_rpc::transport::websocket::serve(listener, server).map_err(|_| ()));
+//!
+//! let future = prost_simple_rpc::transport::websocket::connect(&addr, "/rpc").and_then(|connection| {
+//!     let client = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(connection));
+//!     client.echo(schema::echo::EchoRequest { /* ... */ })
+//! });
+//! ```
+//!
+//! The `stdio` transport runs plugins as child processes, making calls over their stdin and stdout.  The
+//! plugin serves calls with `serve_stdio` until the host closes its input, and can log to stderr, which is
+//! passed through to the host:
+//!
+//! ```rust,ignore
+//! let plugin = prost_simple_rpc::transport::stdio::spawn(&mut process::Command::new("echo-plugin"))?;
+//! let client = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(plugin.connection().clone()));
+//!
+//! // ...and in the plugin:
+//! tokio::run(prost_simple_rpc::transport::stdio::serve_stdio(server).map_err(|_| ()));
+//! ```
+//!
+//! The `shm` transport connects processes on the same Linux host over shared-memory ring buffers, using a
+//! Unix domain socket only to set up connections and to notice when the other process exits.  Frames must fit
+//! into the ring buffers, whose capacity the server chooses:
+//!
+//! ```rust,ignore
+//! let listener = tokio::net::UnixListener::bind("/run/echo.sock")?;
+//! tokio::spawn(prost_simple_rpc::transport::shm::serve_with_capacity(listener, 4 * 1024 * 1024, server).map_err(|_| ()));
+//!
+//! let future = prost_simple_rpc::transport::shm::connect("/run/echo.sock").and_then(|connection| {
+//!     let client = schema::echo::EchoClient::new(prost_simple_rpc::transport::Client::new(connection));
+//!     client.echo(schema::echo::EchoRequest { /* ... */ })
+//! });
+//! ```
+//!
+//! The `udp` transport sends every call as a single datagram, for small calls that can tolerate loss.
+//! Clients retransmit unanswered calls within a configurable budget, and servers answer retransmitted calls
+//! without handling them again:
+//!
+//! ```rust,ignore
+//! let socket = tokio::net::UdpSocket::bind(&addr)?;
+//! tokio::spawn(prost_simple_rpc::transport::udp::serve(socket, server).map_err(|_| ()));
+//!
+//! let future = prost_simple_rpc::transport::udp::connect(&addr).and_then(|connection| {
+//!     let connection = connection.with_retransmits(3, Duration::from_millis(50));
+//!     let client = schema::echo::EchoClient::new(prost_simple_rpc::transport::udp::Client::new(connection));
+//!     client.echo(schema::echo::EchoRequest { /* ... */ })
+//! });
+//! ```
+//!
+//! The `quic` transport opens a QUIC stream for every call, so that a lost packet only holds up the call
+//! it belongs to.  QUIC always uses TLS, configured with the `ServerConfig` and `ClientConfig` of `quinn`:
+//!
+//! ```rust,ignore
+//! let listener = prost_simple_rpc::transport::quic::Listener::bind(&addr, server_config)?;
+//! tokio::spawn(prost_simple_rpc::transport::quic::serve(listener, server).map_err(|_| ()));
+//!
+//! let future = prost_simple_rpc::transport::quic::connect(&addr, "localhost", client_config).and_then(|connection| {
+//!     let client = schema::echo::EchoClient::new(prost_simple_rpc::transport::quic::Client::new(connection));
+//!     client.echo(schema::echo::EchoRequest { /* ... */ })
+//! });
+//! ```
+//!
+//! ## Middleware
+//!
+//! Cross-cutting behavior such as timeouts or retries can be added to any `Handler` by wrapping it in
+//! `middleware::Layer`s, which produce handlers for the same service.  With `HandlerExt::layer`, layers
+//! can be stacked on the transport of a client, or on a generated server before it is served:
+//!
+//! ```rust,ignore
+//! use prost_simple_rpc::middleware::HandlerExt;
+//!
+//! let handler = prost_simple_rpc::transport::Client::new(connection)
+//!     .layer(Timeout::new(Duration::from_secs(1)))
+//!     .layer(Retry::new(3).with_all_methods());
+//! let client = schema::echo::EchoClient::new(handler);
+//! ```
+//!
+//! The layer that is added last is the outermost one, so in this example, every attempt has its own
+//! timeout.  A `middleware::Stack` builds a stack of layers up front, so that it can be applied to
+//! several handlers.
+//!
+//! `middleware::timeout::Timeout` fails calls that are still running after a timeout with
+//! `timeout::Error::Elapsed`, instead of letting them hang forever.  Methods can have their own
+//! timeouts:
+//!
+//! ```rust,ignore
+//! let handler = handler.layer(
+//!     Timeout::new(Duration::from_secs(1))
+//!         .with_method(schema::echo::EchoMethodDescriptor::Echo, Duration::from_millis(100)),
+//! );
+//! ```
+//!
+//! `middleware::retry::Retry` retries calls that failed with a transient error, waiting for an
+//! exponential backoff with jitter between attempts.  Only idempotent methods should be retried, so
+//! methods have to opt in, and a classifier decides which errors are worth retrying based on their
+//! `Status`:
+//!
+//! ```rust,ignore
+//! let handler = handler.layer(
+//!     Retry::new(3)
+//!         .with_method(schema::echo::EchoMethodDescriptor::Echo)
+//!         .with_classifier(|status| status.code() == Code::Unavailable),
+//! );
+//! ```
+//!
+//! `middleware::circuit_breaker::CircuitBreaker` stops calling a server that keeps failing.  Once too
+//! many recent calls failed, calls fail right away with `circuit_breaker::Error::Open`, until a probing
+//! call succeeds after a while.  Clones of a circuit breaker share their state, which can be monitored:
+//!
+//! ```rust,ignore
+//! let breaker = CircuitBreaker::new().with_open_duration(Duration::from_secs(10));
+//! let client = schema::echo::EchoClient::new(handler.layer(breaker.clone()));
+//!
+//! if breaker.state(&schema::echo::EchoMethodDescriptor::Echo) == State::Open {
+//!     // ...
+//! }
+//! ```
+//!
+//! `middleware::concurrency::ConcurrencyLimit` protects servers from traffic spikes by limiting how
+//! many calls are handled at the same time, overall and per method.  Calls beyond the limit wait in a
+//! bounded queue, and are rejected with `Code::ResourceExhausted` once it is full, or once they have
+//! been waiting for longer than the maximum queueing delay:
+//!
+//! ```rust,ignore
+//! let limit = ConcurrencyLimit::new(64)
+//!     .with_method(schema::echo::EchoMethodDescriptor::Echo, 16)
+//!     .with_queue(128)
+//!     .with_max_queueing_delay(Duration::from_millis(100));
+//! let server = schema::echo::EchoServer::new(EchoService).layer(limit);
+//! ```
+//!
+//!
 #![deny(missing_docs)]
 #![deny(missing_debug_implementations)]
 #![deny(missing_copy_implementations)]
@@ -168,19 +553,77 @@
 #![deny(unstable_features)]
 #![deny(unused_import_braces)]
 #![deny(unused_qualifications)]
+// `failure_derive` generates its impls inside an anonymous constant.
+#![allow(non_local_definitions)]
 #![cfg_attr(feature = "dev", allow(unstable_features))]
 #![cfg_attr(feature = "dev", feature(plugin))]
 #![cfg_attr(feature = "dev", plugin(clippy))]
 
+#[cfg(any(feature = "twirp", feature = "websocket"))]
+extern crate base64;
 extern crate bytes;
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
 extern crate futures;
+extern crate futures_cpupool;
+extern crate getrandom;
+#[cfg(feature = "grpc")]
+extern crate h2;
+#[cfg(any(feature = "grpc", feature = "twirp"))]
+extern crate http;
+#[cfg(feature = "websocket")]
+extern crate httparse;
+#[cfg(feature = "twirp")]
+extern crate hyper;
 extern crate prost;
+#[macro_use]
+extern crate prost_derive;
+#[cfg(feature = "quic")]
+extern crate quinn;
+#[cfg(any(
+    all(target_os = "linux", feature = "shm"),
+    all(
+        unix,
+        feature = "unix",
+        any(target_os = "linux", target_os = "android")
+    )
+))]
+extern crate libc;
+#[cfg(feature = "twirp")]
+extern crate serde;
+#[cfg(feature = "twirp")]
+extern crate serde_json;
+#[cfg(feature = "websocket")]
+extern crate sha1;
+#[cfg(any(
+    feature = "grpc",
+    feature = "quic",
+    feature = "stdio",
+    feature = "tcp",
+    feature = "twirp",
+    feature = "udp",
+    feature = "websocket",
+    all(target_os = "linux", feature = "shm"),
+    all(unix, feature = "unix")
+))]
+extern crate tokio;
+#[cfg(feature = "stdio")]
+extern crate tokio_process;
+#[cfg(feature = "quic")]
+extern crate tokio1;
 
 #[doc(hidden)]
 pub mod __rt;
+pub mod blocking;
+pub mod context;
 pub mod descriptor;
 pub mod error;
 pub mod handler;
+pub mod metadata;
+pub mod middleware;
+pub mod router;
+pub mod status;
+pub mod std_future;
+pub mod timer;
+pub mod transport;
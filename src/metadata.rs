@@ -0,0 +1,105 @@
+//! Metadata that is sent along with RPC requests and responses, for example authentication tokens,
+//! request IDs or tracing information.
+use std::collections;
+use std::iter;
+
+/// A set of metadata entries, mapping keys to values.
+///
+/// Keys are case-insensitive and are always stored in lowercase, like HTTP header names.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Metadata {
+    entries: collections::BTreeMap<String, String>,
+}
+
+impl Metadata {
+    /// Creates a new, empty set of metadata.
+    pub fn new() -> Metadata {
+        Metadata {
+            entries: collections::BTreeMap::new(),
+        }
+    }
+
+    /// Returns the value associated with the specified key, if any.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .get(&key.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    /// Associates a value with the specified key, returning the previous value, if any.
+    pub fn insert<K, V>(&mut self, key: K, value: V) -> Option<String>
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.entries
+            .insert(key.into().to_ascii_lowercase(), value.into())
+    }
+
+    /// Removes the value associated with the specified key, returning it, if any.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.entries.remove(&key.to_ascii_lowercase())
+    }
+
+    /// Adds all of the entries of another set of metadata to this one, replacing existing values.
+    pub fn extend(&mut self, other: Metadata) {
+        self.entries.extend(other.entries)
+    }
+
+    /// Iterates over all of the entries in key order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            inner: self.entries.iter(),
+        }
+    }
+
+    /// The number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// An iterator over the entries of a `Metadata` instance.
+#[derive(Debug)]
+pub struct Iter<'a> {
+    inner: collections::btree_map::Iter<'a, String, String>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+impl<'a> IntoIterator for &'a Metadata {
+    type Item = (&'a str, &'a str);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl<K, V> iter::FromIterator<(K, V)> for Metadata
+where
+    K: Into<String>,
+    V: Into<String>,
+{
+    fn from_iter<I>(iter: I) -> Metadata
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut metadata = Metadata::new();
+        for (key, value) in iter {
+            metadata.insert(key, value);
+        }
+        metadata
+    }
+}
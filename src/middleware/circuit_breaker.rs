@@ -0,0 +1,533 @@
+//! A middleware that stops calling a handler that keeps failing.
+//!
+//! A circuit breaker keeps track of the outcomes of recent calls.  While it is closed, calls go
+//! through as usual, until too many of them fail.  It then opens, and calls fail right away with
+//! `Error::Open` instead of adding to the load of a struggling server.  After a while, it becomes
+//! half-open and lets a few probing calls through: if they succeed, it closes again, and otherwise
+//! it opens for another while.
+//!
+//! ```rust,ignore
+//! let breaker = CircuitBreaker::new()
+//!     .with_failure_rate(0.5)
+//!     .with_open_duration(Duration::from_secs(10));
+//! let client = schema::echo::EchoClient::new(handler.layer(breaker.clone()));
+//!
+//! // ...and for monitoring:
+//! let state = breaker.state(&schema::echo::EchoMethodDescriptor::Echo);
+//! ```
+//!
+//! Every method has its own circuit breaker, unless `per_service` is used.  Clones of a
+//! `CircuitBreaker` share their state, as do all handlers that were wrapped by it.
+//!
+//! Only errors that indicate a problem with the server count as failures.  By default, these are
+//! errors with `Code::Unavailable`, `Code::DeadlineExceeded`, `Code::ResourceExhausted` and
+//! `Code::Internal`, but a different classifier can be supplied.  Calls that are dropped before
+//! they complete aren't counted at all.
+//!
+//! `Error::Open` is described by `Code::ResourceExhausted`, which `Retry` doesn't retry by default,
+//! so calls fail fast no matter on which side of a retry a circuit breaker is layered.  Layered
+//! outside of a retry, i.e. added after it, the circuit breaker counts every call once, no matter
+//! how often it was attempted:
+//!
+//! ```rust,ignore
+//! let handler = handler.layer(Retry::new(3).with_all_methods()).layer(breaker.clone());
+//! ```
+use std::collections;
+use std::fmt;
+use std::marker;
+use std::mem;
+use std::sync;
+use std::time;
+
+use bytes;
+use failure;
+use futures;
+
+use context;
+use descriptor;
+use handler;
+use status;
+
+use super::Layer;
+
+/// The failure rate at which circuit breakers open, unless specified otherwise.
+pub const DEFAULT_FAILURE_RATE: f64 = 0.5;
+
+/// How many recent calls circuit breakers keep track of, unless specified otherwise.
+pub const DEFAULT_WINDOW: usize = 20;
+
+/// How many calls circuit breakers need to have seen before they open, unless specified otherwise.
+pub const DEFAULT_MINIMUM_CALLS: usize = 10;
+
+/// How long circuit breakers stay open before they let probing calls through, unless specified
+/// otherwise.
+pub const DEFAULT_OPEN_DURATION: time::Duration = time::Duration::from_secs(30);
+
+/// A layer that fails calls right away while too many recent calls have failed.
+pub struct CircuitBreaker<M> {
+    config: Config,
+    breakers: sync::Arc<sync::Mutex<collections::BTreeMap<&'static str, Breaker>>>,
+    method: marker::PhantomData<M>,
+}
+
+/// A handler that stops calling the handler that it wraps while too many calls have failed.
+#[derive(Clone, Debug)]
+pub struct CircuitBreakerHandler<H> {
+    handler: H,
+    shared: sync::Arc<Shared>,
+}
+
+/// The future that results from a call to `CircuitBreakerHandler::call`.
+pub struct CircuitBreakerFuture<F> {
+    call: Call<F>,
+}
+
+/// The state of a circuit breaker.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum State {
+    /// Calls go through as usual.
+    Closed,
+    /// Calls fail right away.
+    Open,
+    /// A limited number of probing calls go through, to find out whether to close again.
+    HalfOpen,
+}
+
+/// An error that occurred during a call through a circuit breaker.
+#[derive(Debug, Fail)]
+pub enum Error<E>
+where
+    E: failure::Fail,
+{
+    /// The circuit breaker was open, so the call was not made.
+    #[fail(display = "The circuit breaker for method {} is open", method)]
+    Open {
+        /// The raw protobuf name of the method that was called.
+        method: &'static str,
+    },
+    /// The wrapped handler failed.
+    #[fail(display = "{}", error)]
+    Inner {
+        /// The underlying error.
+        #[cause]
+        error: E,
+    },
+}
+
+#[derive(Clone)]
+struct Config {
+    failure_rate: f64,
+    window: usize,
+    minimum_calls: usize,
+    open_duration: time::Duration,
+    probes: usize,
+    per_service: bool,
+    classifier: sync::Arc<dyn Fn(&status::Status) -> bool + Send + Sync>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    config: Config,
+    breakers: sync::Arc<sync::Mutex<collections::BTreeMap<&'static str, Breaker>>>,
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    state: BreakerState,
+    /// The outcomes of recent calls while closed, `true` for failures.
+    outcomes: collections::VecDeque<bool>,
+    failures: usize,
+}
+
+#[derive(Debug, Default)]
+enum BreakerState {
+    #[default]
+    Closed,
+    Open {
+        until: time::Instant,
+    },
+    HalfOpen {
+        in_flight: usize,
+        successes: usize,
+    },
+}
+
+enum Call<F> {
+    Open(&'static str),
+    Calling(F, Permit),
+    Done,
+}
+
+/// Permission to make a call, which records its outcome.
+#[derive(Debug)]
+struct Permit {
+    shared: sync::Arc<Shared>,
+    key: &'static str,
+    probe: bool,
+}
+
+impl<M> CircuitBreaker<M>
+where
+    M: descriptor::MethodDescriptor,
+{
+    /// Creates a layer with circuit breakers that open once half of the recent calls failed.
+    pub fn new() -> CircuitBreaker<M> {
+        CircuitBreaker {
+            config: Config {
+                failure_rate: DEFAULT_FAILURE_RATE,
+                window: DEFAULT_WINDOW,
+                minimum_calls: DEFAULT_MINIMUM_CALLS,
+                open_duration: DEFAULT_OPEN_DURATION,
+                probes: 1,
+                per_service: false,
+                classifier: sync::Arc::new(|status| {
+                    matches!(
+                        status.code(),
+                        status::Code::Unavailable
+                            | status::Code::DeadlineExceeded
+                            | status::Code::ResourceExhausted
+                            | status::Code::Internal
+                    )
+                }),
+            },
+            breakers: sync::Arc::default(),
+            method: marker::PhantomData,
+        }
+    }
+
+    /// Opens circuit breakers once this fraction of the recent calls failed, between 0 and 1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure_rate` is not greater than 0 and at most 1.
+    pub fn with_failure_rate(mut self, failure_rate: f64) -> CircuitBreaker<M> {
+        assert!(
+            failure_rate > 0.0 && failure_rate <= 1.0,
+            "failure rate must be greater than 0 and at most 1"
+        );
+        self.config.failure_rate = failure_rate;
+        self
+    }
+
+    /// Keeps track of the last `window` calls, but only opens circuit breakers once at least
+    /// `minimum_calls` of them have been made.
+    pub fn with_window(mut self, window: usize, minimum_calls: usize) -> CircuitBreaker<M> {
+        self.config.window = window.max(1);
+        self.config.minimum_calls = minimum_calls.clamp(1, self.config.window);
+        self
+    }
+
+    /// Keeps circuit breakers open for the specified duration, before they let probing calls
+    /// through.
+    pub fn with_open_duration(mut self, open_duration: time::Duration) -> CircuitBreaker<M> {
+        self.config.open_duration = open_duration;
+        self
+    }
+
+    /// Lets the specified number of probing calls through while half-open, all of which have to
+    /// succeed for the circuit breaker to close again.
+    pub fn with_probes(mut self, probes: usize) -> CircuitBreaker<M> {
+        self.config.probes = probes.max(1);
+        self
+    }
+
+    /// Uses a single circuit breaker for all methods of the service, instead of one per method.
+    pub fn per_service(mut self) -> CircuitBreaker<M> {
+        self.config.per_service = true;
+        self
+    }
+
+    /// Decides which errors count as failures, based on the status that describes them.
+    pub fn with_classifier<F>(mut self, classifier: F) -> CircuitBreaker<M>
+    where
+        F: Fn(&status::Status) -> bool + Send + Sync + 'static,
+    {
+        self.config.classifier = sync::Arc::new(classifier);
+        self
+    }
+
+    /// The current state of the circuit breaker for the specified method.
+    pub fn state(&self, method: &M) -> State {
+        let key = self.config.key(method.proto_name());
+        match lock(&self.breakers).get(key) {
+            Some(breaker) => match breaker.state {
+                BreakerState::Closed => State::Closed,
+                BreakerState::Open { until } if time::Instant::now() < until => State::Open,
+                BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => State::HalfOpen,
+            },
+            None => State::Closed,
+        }
+    }
+}
+
+impl<M> Default for CircuitBreaker<M>
+where
+    M: descriptor::MethodDescriptor,
+{
+    fn default() -> CircuitBreaker<M> {
+        CircuitBreaker::new()
+    }
+}
+
+impl<M> Clone for CircuitBreaker<M> {
+    fn clone(&self) -> CircuitBreaker<M> {
+        CircuitBreaker {
+            config: self.config.clone(),
+            breakers: self.breakers.clone(),
+            method: marker::PhantomData,
+        }
+    }
+}
+
+impl<M> fmt::Debug for CircuitBreaker<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl<H, M> Layer<H> for CircuitBreaker<M>
+where
+    H: handler::Handler,
+    H::Descriptor: descriptor::ServiceDescriptor<Method = M>,
+    H::Error: status::ToStatus,
+    M: descriptor::MethodDescriptor + fmt::Debug,
+{
+    type Handler = CircuitBreakerHandler<H>;
+
+    fn layer(&self, handler: H) -> CircuitBreakerHandler<H> {
+        CircuitBreakerHandler {
+            handler,
+            shared: sync::Arc::new(Shared {
+                config: self.config.clone(),
+                breakers: self.breakers.clone(),
+            }),
+        }
+    }
+}
+
+impl<H> handler::Handler for CircuitBreakerHandler<H>
+where
+    H: handler::Handler,
+    H::Error: status::ToStatus,
+{
+    type Error = Error<H::Error>;
+    type Descriptor = H::Descriptor;
+    type CallFuture = CircuitBreakerFuture<H::CallFuture>;
+
+    fn call(
+        &self,
+        method: <H::Descriptor as descriptor::ServiceDescriptor>::Method,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> Self::CallFuture {
+        use descriptor::MethodDescriptor;
+
+        let name = method.proto_name();
+        let call = match self.shared.acquire(self.shared.config.key(name)) {
+            Some(permit) => Call::Calling(self.handler.call(method, input, context), permit),
+            None => Call::Open(name),
+        };
+        CircuitBreakerFuture { call }
+    }
+}
+
+impl<F> futures::Future for CircuitBreakerFuture<F>
+where
+    F: futures::Future<Item = bytes::Bytes>,
+    F::Error: failure::Fail + status::ToStatus,
+{
+    type Item = bytes::Bytes;
+    type Error = Error<F::Error>;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        use status::ToStatus;
+
+        let result = match self.call {
+            Call::Open(method) => {
+                self.call = Call::Done;
+                return Err(Error::Open { method });
+            }
+            Call::Calling(ref mut inner, _) => inner.poll(),
+            Call::Done => panic!("cannot poll a call after it has completed"),
+        };
+        if let Ok(futures::Async::NotReady) = result {
+            return Ok(futures::Async::NotReady);
+        }
+
+        if let Call::Calling(_, permit) = mem::replace(&mut self.call, Call::Done) {
+            let failed = match result {
+                Err(ref error) => (permit.shared.config.classifier)(&error.to_status()),
+                Ok(_) => false,
+            };
+            permit.record(failed);
+        }
+        result.map_err(|error| Error::Inner { error })
+    }
+}
+
+impl<F> fmt::Debug for CircuitBreakerFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.call {
+            Call::Open(_) => "open",
+            Call::Calling(..) => "calling",
+            Call::Done => "done",
+        };
+        f.debug_struct("CircuitBreakerFuture")
+            .field("state", &state)
+            .finish()
+    }
+}
+
+impl Config {
+    fn key(&self, method: &'static str) -> &'static str {
+        if self.per_service {
+            ""
+        } else {
+            method
+        }
+    }
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("failure_rate", &self.failure_rate)
+            .field("window", &self.window)
+            .field("minimum_calls", &self.minimum_calls)
+            .field("open_duration", &self.open_duration)
+            .field("probes", &self.probes)
+            .field("per_service", &self.per_service)
+            .finish()
+    }
+}
+
+impl Shared {
+    /// Decides whether a call may be made, returning a permit that records its outcome if so.
+    fn acquire(self: &sync::Arc<Self>, key: &'static str) -> Option<Permit> {
+        let mut breakers = lock(&self.breakers);
+        let breaker = breakers.entry(key).or_default();
+        if let BreakerState::Open { until } = breaker.state {
+            if time::Instant::now() < until {
+                return None;
+            }
+            breaker.state = BreakerState::HalfOpen {
+                in_flight: 0,
+                successes: 0,
+            };
+        }
+
+        let probe = match breaker.state {
+            BreakerState::HalfOpen {
+                ref mut in_flight,
+                successes,
+            } => {
+                if *in_flight + successes >= self.config.probes {
+                    return None;
+                }
+                *in_flight += 1;
+                true
+            }
+            _ => false,
+        };
+        Some(Permit {
+            shared: self.clone(),
+            key,
+            probe,
+        })
+    }
+}
+
+impl Permit {
+    fn record(mut self, failed: bool) {
+        let config = &self.shared.config;
+        let mut breakers = lock(&self.shared.breakers);
+        let breaker = breakers.entry(self.key).or_default();
+        match breaker.state {
+            BreakerState::Closed if !self.probe => {
+                breaker.outcomes.push_back(failed);
+                if failed {
+                    breaker.failures += 1;
+                }
+                if breaker.outcomes.len() > config.window
+                    && breaker.outcomes.pop_front() == Some(true)
+                {
+                    breaker.failures -= 1;
+                }
+                let calls = breaker.outcomes.len();
+                if calls >= config.minimum_calls
+                    && breaker.failures as f64 >= config.failure_rate * calls as f64
+                {
+                    breaker.open(config.open_duration);
+                }
+            }
+            BreakerState::HalfOpen {
+                ref mut in_flight,
+                ref mut successes,
+            } if self.probe => {
+                *in_flight = in_flight.saturating_sub(1);
+                if failed {
+                    breaker.open(config.open_duration);
+                } else {
+                    *successes += 1;
+                    if *successes >= config.probes {
+                        *breaker = Breaker::default();
+                    }
+                }
+            }
+            // The state changed while the call was in flight, so its outcome is outdated.
+            _ => {}
+        }
+        // The probe has been accounted for, so it mustn't be released again when dropped.
+        self.probe = false;
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        // The call was dropped before it completed, so let another probe through instead.
+        if self.probe {
+            let mut breakers = lock(&self.shared.breakers);
+            if let Some(Breaker {
+                state:
+                    BreakerState::HalfOpen {
+                        ref mut in_flight, ..
+                    },
+                ..
+            }) = breakers.get_mut(self.key)
+            {
+                *in_flight = in_flight.saturating_sub(1);
+            }
+        }
+    }
+}
+
+impl Breaker {
+    fn open(&mut self, duration: time::Duration) {
+        *self = Breaker {
+            state: BreakerState::Open {
+                until: time::Instant::now() + duration,
+            },
+            ..Breaker::default()
+        };
+    }
+}
+
+impl<E> status::ToStatus for Error<E>
+where
+    E: failure::Fail + status::ToStatus,
+{
+    fn to_status(&self) -> status::Status {
+        match *self {
+            Error::Open { .. } => {
+                status::Status::new(status::Code::ResourceExhausted, self.to_string())
+            }
+            Error::Inner { ref error } => error.to_status(),
+        }
+    }
+}
+
+fn lock<T>(mutex: &sync::Mutex<T>) -> sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(sync::PoisonError::into_inner)
+}
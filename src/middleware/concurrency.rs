@@ -0,0 +1,486 @@
+//! A middleware that limits how many calls are handled at the same time.
+//!
+//! This protects servers from spikes in traffic: calls beyond the limit wait in a queue until other
+//! calls complete, and once the queue is full, they fail right away with `Error::Rejected`.  Limits
+//! can be set for all calls together, and for specific methods:
+//!
+//! ```rust,ignore
+//! let limit = ConcurrencyLimit::new(64)
+//!     .with_method(schema::echo::EchoMethodDescriptor::Echo, 16)
+//!     .with_queue(128);
+//! let server = schema::echo::EchoServer::new(EchoService).layer(limit.clone());
+//!
+//! // ...and for monitoring:
+//! let load = (limit.in_flight(), limit.queued());
+//! ```
+//!
+//! With a maximum queueing delay, calls that have been waiting in the queue for too long are shed
+//! with `Error::Shed`, since their callers have probably given up on them by the time they would be
+//! handled.  Both errors are described by `Code::ResourceExhausted`.
+//!
+//! Clones of a `ConcurrencyLimit` share their limits, as do all handlers that were wrapped by it.
+use std::collections;
+use std::fmt;
+use std::marker;
+use std::mem;
+use std::sync;
+use std::time;
+
+use bytes;
+use failure;
+use futures;
+
+use context;
+use descriptor;
+use handler;
+use status;
+use timer;
+
+use super::Layer;
+
+/// A layer that limits how many calls are handled at the same time.
+pub struct ConcurrencyLimit<M> {
+    shared: sync::Arc<Shared>,
+    method: marker::PhantomData<M>,
+}
+
+/// A handler that limits how many calls to the handler that it wraps are made at the same time.
+#[derive(Clone, Debug)]
+pub struct ConcurrencyLimitHandler<H> {
+    handler: H,
+    shared: sync::Arc<Shared>,
+}
+
+/// The future that results from a call to `ConcurrencyLimitHandler::call`.
+pub struct ConcurrencyLimitFuture<H>
+where
+    H: handler::Handler,
+{
+    handler: H,
+    shared: sync::Arc<Shared>,
+    state: State<H>,
+}
+
+/// An error that occurred during a call with a concurrency limit.
+#[derive(Debug, Fail)]
+pub enum Error<E>
+where
+    E: failure::Fail,
+{
+    /// Too many calls were already in flight or waiting, so the call was not made.
+    #[fail(display = "Too many calls are in flight")]
+    Rejected,
+    /// The call waited for longer than the maximum queueing delay, so it was not made.
+    #[fail(display = "The call was shed after waiting for {:?}", waited)]
+    Shed {
+        /// How long the call waited.
+        waited: time::Duration,
+    },
+    /// The wrapped handler failed.
+    #[fail(display = "{}", error)]
+    Inner {
+        /// The underlying error.
+        #[cause]
+        error: E,
+    },
+}
+
+#[derive(Debug)]
+struct Shared {
+    config: Config,
+    limiter: sync::Mutex<Limiter>,
+}
+
+#[derive(Clone, Debug)]
+struct Config {
+    max: usize,
+    methods: collections::BTreeMap<&'static str, usize>,
+    max_queued: usize,
+    max_queueing_delay: Option<time::Duration>,
+}
+
+#[derive(Debug, Default)]
+struct Limiter {
+    in_flight: usize,
+    methods: collections::BTreeMap<&'static str, usize>,
+    queue: collections::VecDeque<Queued>,
+    /// The IDs of queued calls that have been admitted, but haven't noticed yet.
+    admitted: collections::BTreeSet<u64>,
+    next_id: u64,
+}
+
+#[derive(Debug)]
+struct Queued {
+    id: u64,
+    method: &'static str,
+    waiter: Option<timer::Waiter>,
+}
+
+enum State<H>
+where
+    H: handler::Handler,
+{
+    Rejected,
+    Queued {
+        id: u64,
+        method: <H::Descriptor as descriptor::ServiceDescriptor>::Method,
+        input: bytes::Bytes,
+        context: context::Context,
+        since: time::Instant,
+        shed: Option<timer::Delay>,
+    },
+    Calling {
+        call: H::CallFuture,
+        /// Keeps the call counted as in flight until it is dropped.
+        _permit: Permit,
+    },
+    Done,
+}
+
+/// Counts a call as in flight, and makes room for queued calls once it is dropped.
+#[derive(Debug)]
+struct Permit {
+    shared: sync::Arc<Shared>,
+    method: &'static str,
+}
+
+impl<M> ConcurrencyLimit<M>
+where
+    M: descriptor::MethodDescriptor,
+{
+    /// Creates a layer that allows at most `max` calls in flight, rejecting calls beyond that.
+    pub fn new(max: usize) -> ConcurrencyLimit<M> {
+        ConcurrencyLimit::with_config(Config {
+            max,
+            methods: collections::BTreeMap::new(),
+            max_queued: 0,
+            max_queueing_delay: None,
+        })
+    }
+
+    /// Allows at most `max` calls to the specified method in flight, within the overall limit.
+    pub fn with_method(self, method: M, max: usize) -> ConcurrencyLimit<M> {
+        let mut config = self.shared.config.clone();
+        config.methods.insert(method.proto_name(), max);
+        ConcurrencyLimit::with_config(config)
+    }
+
+    /// Lets up to `max_queued` calls wait for others to complete, instead of rejecting them.
+    pub fn with_queue(self, max_queued: usize) -> ConcurrencyLimit<M> {
+        let mut config = self.shared.config.clone();
+        config.max_queued = max_queued;
+        ConcurrencyLimit::with_config(config)
+    }
+
+    /// Sheds calls that have been waiting in the queue for longer than the specified delay.
+    pub fn with_max_queueing_delay(self, delay: time::Duration) -> ConcurrencyLimit<M> {
+        let mut config = self.shared.config.clone();
+        config.max_queueing_delay = Some(delay);
+        ConcurrencyLimit::with_config(config)
+    }
+
+    /// The number of calls that are currently in flight.
+    pub fn in_flight(&self) -> usize {
+        self.shared.lock().in_flight
+    }
+
+    /// The number of calls that are currently waiting in the queue.
+    pub fn queued(&self) -> usize {
+        self.shared.lock().queue.len()
+    }
+
+    fn with_config(config: Config) -> ConcurrencyLimit<M> {
+        ConcurrencyLimit {
+            shared: sync::Arc::new(Shared {
+                config,
+                limiter: sync::Mutex::default(),
+            }),
+            method: marker::PhantomData,
+        }
+    }
+}
+
+impl<M> Clone for ConcurrencyLimit<M> {
+    fn clone(&self) -> ConcurrencyLimit<M> {
+        ConcurrencyLimit {
+            shared: self.shared.clone(),
+            method: marker::PhantomData,
+        }
+    }
+}
+
+impl<M> fmt::Debug for ConcurrencyLimit<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConcurrencyLimit")
+            .field("config", &self.shared.config)
+            .finish()
+    }
+}
+
+impl<H, M> Layer<H> for ConcurrencyLimit<M>
+where
+    H: handler::Handler,
+    H::Descriptor: descriptor::ServiceDescriptor<Method = M>,
+    M: descriptor::MethodDescriptor + fmt::Debug,
+{
+    type Handler = ConcurrencyLimitHandler<H>;
+
+    fn layer(&self, handler: H) -> ConcurrencyLimitHandler<H> {
+        ConcurrencyLimitHandler {
+            handler,
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<H> handler::Handler for ConcurrencyLimitHandler<H>
+where
+    H: handler::Handler,
+{
+    type Error = Error<H::Error>;
+    type Descriptor = H::Descriptor;
+    type CallFuture = ConcurrencyLimitFuture<H>;
+
+    fn call(
+        &self,
+        method: <H::Descriptor as descriptor::ServiceDescriptor>::Method,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> Self::CallFuture {
+        use descriptor::MethodDescriptor;
+
+        let name = method.proto_name();
+        let config = &self.shared.config;
+        let mut limiter = self.shared.lock();
+        let state = if limiter.admits(config, name) {
+            limiter.enter(name);
+            drop(limiter);
+            let permit = Permit {
+                shared: self.shared.clone(),
+                method: name,
+            };
+            State::Calling {
+                call: self.handler.call(method, input, context),
+                _permit: permit,
+            }
+        } else if limiter.queue.len() < config.max_queued {
+            let id = limiter.next_id;
+            limiter.next_id += 1;
+            limiter.queue.push_back(Queued {
+                id,
+                method: name,
+                waiter: None,
+            });
+            let since = time::Instant::now();
+            State::Queued {
+                id,
+                method,
+                input,
+                context,
+                since,
+                shed: config
+                    .max_queueing_delay
+                    .map(|delay| timer::Delay::new(since + delay)),
+            }
+        } else {
+            State::Rejected
+        };
+
+        ConcurrencyLimitFuture {
+            handler: self.handler.clone(),
+            shared: self.shared.clone(),
+            state,
+        }
+    }
+}
+
+impl<H> futures::Future for ConcurrencyLimitFuture<H>
+where
+    H: handler::Handler,
+{
+    type Item = bytes::Bytes;
+    type Error = Error<H::Error>;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        use descriptor::MethodDescriptor;
+
+        loop {
+            let next = match self.state {
+                State::Rejected => State::Done,
+                State::Queued {
+                    id,
+                    ref mut shed,
+                    since,
+                    ..
+                } => {
+                    let mut limiter = self.shared.lock();
+                    if limiter.admitted.remove(&id) {
+                        State::Done
+                    } else {
+                        let shed = match *shed {
+                            Some(ref mut delay) => {
+                                futures::Future::poll(delay) == Ok(futures::Async::Ready(()))
+                            }
+                            None => false,
+                        };
+                        if shed {
+                            limiter.queue.retain(|queued| queued.id != id);
+                            self.state = State::Done;
+                            return Err(Error::Shed {
+                                waited: since.elapsed(),
+                            });
+                        }
+                        if let Some(queued) = limiter.queue.iter_mut().find(|q| q.id == id) {
+                            queued.waiter = Some(timer::Waiter::current());
+                        }
+                        return Ok(futures::Async::NotReady);
+                    }
+                }
+                State::Calling { ref mut call, .. } => {
+                    let result = call.poll();
+                    if let Ok(futures::Async::NotReady) = result {
+                        return Ok(futures::Async::NotReady);
+                    }
+                    // Let the next call in as soon as possible.
+                    self.state = State::Done;
+                    return result.map_err(|error| Error::Inner { error });
+                }
+                State::Done => panic!("cannot poll a call after it has completed"),
+            };
+
+            match mem::replace(&mut self.state, next) {
+                State::Rejected => return Err(Error::Rejected),
+                State::Queued {
+                    method,
+                    input,
+                    context,
+                    ..
+                } => {
+                    let permit = Permit {
+                        shared: self.shared.clone(),
+                        method: method.proto_name(),
+                    };
+                    let call = self.handler.call(method, input, context);
+                    self.state = State::Calling {
+                        call,
+                        _permit: permit,
+                    };
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+impl<H> Drop for ConcurrencyLimitFuture<H>
+where
+    H: handler::Handler,
+{
+    fn drop(&mut self) {
+        if let State::Queued { id, ref method, .. } = self.state {
+            use descriptor::MethodDescriptor;
+
+            let mut limiter = self.shared.lock();
+            if limiter.admitted.remove(&id) {
+                // The call was admitted before it noticed, so it has to make room for another one.
+                drop(limiter);
+                drop(Permit {
+                    shared: self.shared.clone(),
+                    method: method.proto_name(),
+                });
+            } else {
+                limiter.queue.retain(|queued| queued.id != id);
+            }
+        }
+    }
+}
+
+impl<H> fmt::Debug for ConcurrencyLimitFuture<H>
+where
+    H: handler::Handler,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Rejected => "rejected",
+            State::Queued { .. } => "queued",
+            State::Calling { .. } => "calling",
+            State::Done => "done",
+        };
+        f.debug_struct("ConcurrencyLimitFuture")
+            .field("state", &state)
+            .finish()
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> sync::MutexGuard<'_, Limiter> {
+        self.limiter
+            .lock()
+            .unwrap_or_else(sync::PoisonError::into_inner)
+    }
+}
+
+impl Limiter {
+    /// Whether a call to the specified method fits within the limits.
+    fn admits(&self, config: &Config, method: &'static str) -> bool {
+        self.in_flight < config.max
+            && match config.methods.get(method) {
+                Some(&max) => self.methods.get(method).cloned().unwrap_or(0) < max,
+                None => true,
+            }
+    }
+
+    fn enter(&mut self, method: &'static str) {
+        self.in_flight += 1;
+        *self.methods.entry(method).or_insert(0) += 1;
+    }
+
+    fn exit(&mut self, method: &'static str) {
+        self.in_flight -= 1;
+        if let collections::btree_map::Entry::Occupied(mut entry) = self.methods.entry(method) {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+    }
+
+    /// Admits as many queued calls as fit within the limits, in the order in which they arrived.
+    fn admit(&mut self, config: &Config) {
+        let mut index = 0;
+        while index < self.queue.len() && self.in_flight < config.max {
+            if self.admits(config, self.queue[index].method) {
+                let queued = self.queue.remove(index).expect("index is within the queue");
+                self.enter(queued.method);
+                self.admitted.insert(queued.id);
+                if let Some(waiter) = queued.waiter {
+                    waiter.notify();
+                }
+            } else {
+                index += 1;
+            }
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut limiter = self.shared.lock();
+        limiter.exit(self.method);
+        limiter.admit(&self.shared.config);
+    }
+}
+
+impl<E> status::ToStatus for Error<E>
+where
+    E: failure::Fail + status::ToStatus,
+{
+    fn to_status(&self) -> status::Status {
+        match *self {
+            Error::Rejected | Error::Shed { .. } => {
+                status::Status::new(status::Code::ResourceExhausted, self.to_string())
+            }
+            Error::Inner { ref error } => error.to_status(),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Middleware that adds behavior to any `Handler`, such as timeouts or retries.
+//!
+//! A `Layer` wraps a handler in another handler for the same service.  With `HandlerExt::layer`,
+//! layers can be stacked on top of each other without touching generated code:
+//!
+//! ```rust,ignore
+//! use prost_simple_rpc::middleware::HandlerExt;
+//!
+//! let handler = transport::Client::new(connection)
+//!     .layer(Timeout::new(Duration::from_secs(1)))
+//!     .layer(Retry::new(3).with_all_methods());
+//! let client = schema::echo::EchoClient::new(handler);
+//! ```
+//!
+//! The layer that is added last is the outermost one, so in this example, every attempt made by
+//! `Retry` has its own timeout.
+//!
+//! Since layers only rely on the `Handler` trait, they work on both sides of a transport: on the
+//! client side, they wrap the transport before it is passed to a generated client, and on the
+//! server side, they wrap a generated server before it is served.  Layers only apply to unary
+//! calls.
+use handler;
+
+pub mod circuit_breaker;
+pub mod concurrency;
+pub mod retry;
+pub mod timeout;
+
+/// Wraps handlers in another handler for the same service, to add behavior to their calls.
+pub trait Layer<H>
+where
+    H: handler::Handler,
+{
+    /// The handler that results from wrapping a handler of type `H`.
+    type Handler: handler::Handler<Descriptor = H::Descriptor>;
+
+    /// Wraps the specified handler.
+    fn layer(&self, handler: H) -> Self::Handler;
+}
+
+/// Extension methods for wrapping handlers with `Layer`s.
+pub trait HandlerExt: handler::Handler + Sized {
+    /// Wraps this handler with the specified layer.
+    fn layer<L>(self, layer: L) -> L::Handler
+    where
+        L: Layer<Self>,
+    {
+        layer.layer(self)
+    }
+}
+
+/// A layer that leaves handlers as they are.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Identity;
+
+/// A layer that applies an inner layer first, and then an outer layer around the result.
+///
+/// This can be used to build a stack of layers up front, and to apply it to several handlers.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stack<I, O> {
+    inner: I,
+    outer: O,
+}
+
+impl<H> HandlerExt for H where H: handler::Handler {}
+
+impl<H> Layer<H> for Identity
+where
+    H: handler::Handler,
+{
+    type Handler = H;
+
+    fn layer(&self, handler: H) -> H {
+        handler
+    }
+}
+
+impl<I, O> Stack<I, O> {
+    /// Creates a stack that wraps handlers with `inner`, and then with `outer`.
+    pub fn new(inner: I, outer: O) -> Stack<I, O> {
+        Stack { inner, outer }
+    }
+
+    /// Adds another layer on top of this stack.
+    pub fn push<L>(self, layer: L) -> Stack<Stack<I, O>, L> {
+        Stack::new(self, layer)
+    }
+}
+
+impl<H, I, O> Layer<H> for Stack<I, O>
+where
+    H: handler::Handler,
+    I: Layer<H>,
+    O: Layer<I::Handler>,
+{
+    type Handler = O::Handler;
+
+    fn layer(&self, handler: H) -> Self::Handler {
+        self.outer.layer(self.inner.layer(handler))
+    }
+}
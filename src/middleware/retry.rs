@@ -0,0 +1,296 @@
+//! A middleware that retries calls that failed for transient reasons.
+//!
+//! Retrying is only safe for idempotent methods, so methods have to opt in to it:
+//!
+//! ```rust,ignore
+//! let handler = handler.layer(
+//!     Retry::new(3)
+//!         .with_method(schema::echo::EchoMethodDescriptor::Echo)
+//!         .with_backoff(Duration::from_millis(10), Duration::from_secs(1)),
+//! );
+//! ```
+//!
+//! Between attempts, calls wait for an exponentially growing backoff with random jitter, so that
+//! many clients don't retry in lockstep.  Whether an error is worth retrying is decided by a
+//! classifier, which gets the `Status` that describes the error.  By default, only errors with
+//! `Code::Unavailable` are retried, which is what transports report when they lose their
+//! connection.
+//!
+//! Calls are not retried once their context has been cancelled, or when their deadline would pass
+//! before the next attempt.  The last error is returned as is once no attempts are left.
+use std::collections;
+use std::fmt;
+use std::marker;
+use std::sync;
+use std::time;
+
+use bytes;
+use futures;
+use getrandom;
+
+use context;
+use descriptor;
+use handler;
+use status;
+use timer;
+
+use super::Layer;
+
+/// How long calls wait before their first retry, unless specified otherwise.
+pub const DEFAULT_INITIAL_BACKOFF: time::Duration = time::Duration::from_millis(50);
+
+/// The longest that calls wait between retries, unless specified otherwise.
+pub const DEFAULT_MAX_BACKOFF: time::Duration = time::Duration::from_secs(5);
+
+/// A layer that retries calls that failed with a retryable error.
+#[derive(Clone)]
+pub struct Retry<M> {
+    max_retries: u32,
+    initial_backoff: time::Duration,
+    max_backoff: time::Duration,
+    methods: Methods,
+    classifier: sync::Arc<dyn Fn(&status::Status) -> bool + Send + Sync>,
+    method: marker::PhantomData<M>,
+}
+
+/// A handler that retries failed calls to the handler that it wraps.
+#[derive(Clone, Debug)]
+pub struct RetryHandler<H>
+where
+    H: handler::Handler,
+{
+    handler: H,
+    retry: sync::Arc<Retry<<H::Descriptor as descriptor::ServiceDescriptor>::Method>>,
+}
+
+/// The future that results from a call to `RetryHandler::call`.
+pub struct RetryFuture<H>
+where
+    H: handler::Handler,
+{
+    handler: RetryHandler<H>,
+    method: <H::Descriptor as descriptor::ServiceDescriptor>::Method,
+    input: bytes::Bytes,
+    context: context::Context,
+    retries: u32,
+    state: State<H::CallFuture>,
+}
+
+#[derive(Clone, Debug)]
+enum Methods {
+    All,
+    Some(collections::BTreeSet<&'static str>),
+}
+
+enum State<F> {
+    Calling(F),
+    Waiting(timer::Delay),
+}
+
+impl<M> Retry<M>
+where
+    M: descriptor::MethodDescriptor,
+{
+    /// Creates a layer that retries failed calls up to `max_retries` times.
+    ///
+    /// No methods are retried until they opt in with `with_method` or `with_all_methods`.
+    pub fn new(max_retries: u32) -> Retry<M> {
+        Retry {
+            max_retries,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            methods: Methods::Some(collections::BTreeSet::new()),
+            classifier: sync::Arc::new(|status| status.code() == status::Code::Unavailable),
+            method: marker::PhantomData,
+        }
+    }
+
+    /// Retries calls to the specified method, which must be idempotent.
+    pub fn with_method(mut self, method: M) -> Retry<M> {
+        if let Methods::Some(ref mut methods) = self.methods {
+            methods.insert(method.proto_name());
+        }
+        self
+    }
+
+    /// Retries calls to all methods, which must all be idempotent.
+    pub fn with_all_methods(mut self) -> Retry<M> {
+        self.methods = Methods::All;
+        self
+    }
+
+    /// Waits for `initial` before the first retry, doubling the backoff for every further retry
+    /// up to `max`.
+    ///
+    /// The actual backoff is chosen at random between half of this and all of it.
+    pub fn with_backoff(mut self, initial: time::Duration, max: time::Duration) -> Retry<M> {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Decides which errors are retried, based on the status that describes them.
+    pub fn with_classifier<F>(mut self, classifier: F) -> Retry<M>
+    where
+        F: Fn(&status::Status) -> bool + Send + Sync + 'static,
+    {
+        self.classifier = sync::Arc::new(classifier);
+        self
+    }
+
+    /// Whether calls to the specified method are retried.
+    pub fn is_retried(&self, method: &M) -> bool {
+        match self.methods {
+            Methods::All => true,
+            Methods::Some(ref methods) => methods.contains(method.proto_name()),
+        }
+    }
+
+    /// Whether an error that is described by the specified status is retried.
+    pub fn is_retryable(&self, status: &status::Status) -> bool {
+        (self.classifier)(status)
+    }
+
+    /// The backoff before the specified retry, counting from zero, without jitter.
+    fn backoff(&self, retry: u32) -> time::Duration {
+        let factor = 1u32.checked_shl(retry).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
+
+impl<M> fmt::Debug for Retry<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Retry")
+            .field("max_retries", &self.max_retries)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("methods", &self.methods)
+            .finish()
+    }
+}
+
+impl<H, M> Layer<H> for Retry<M>
+where
+    H: handler::Handler,
+    H::Descriptor: descriptor::ServiceDescriptor<Method = M>,
+    H::Error: status::ToStatus,
+    M: descriptor::MethodDescriptor + fmt::Debug,
+{
+    type Handler = RetryHandler<H>;
+
+    fn layer(&self, handler: H) -> RetryHandler<H> {
+        RetryHandler {
+            handler,
+            retry: sync::Arc::new(self.clone()),
+        }
+    }
+}
+
+impl<H> handler::Handler for RetryHandler<H>
+where
+    H: handler::Handler,
+    H::Error: status::ToStatus,
+{
+    type Error = H::Error;
+    type Descriptor = H::Descriptor;
+    type CallFuture = RetryFuture<H>;
+
+    fn call(
+        &self,
+        method: <H::Descriptor as descriptor::ServiceDescriptor>::Method,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> Self::CallFuture {
+        let call = self.handler.call(method, input.clone(), context.clone());
+        RetryFuture {
+            handler: self.clone(),
+            method,
+            input,
+            context,
+            retries: 0,
+            state: State::Calling(call),
+        }
+    }
+}
+
+impl<H> futures::Future for RetryFuture<H>
+where
+    H: handler::Handler,
+    H::Error: status::ToStatus,
+{
+    type Item = bytes::Bytes;
+    type Error = H::Error;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        use status::ToStatus;
+
+        loop {
+            let next = match self.state {
+                State::Calling(ref mut call) => match call.poll() {
+                    Err(error) => {
+                        let retry = &self.handler.retry;
+                        if self.retries >= retry.max_retries
+                            || !retry.is_retried(&self.method)
+                            || !retry.is_retryable(&error.to_status())
+                            || self.context.is_cancelled()
+                        {
+                            return Err(error);
+                        }
+                        let backoff = jitter(retry.backoff(self.retries));
+                        let when = time::Instant::now() + backoff;
+                        if self
+                            .context
+                            .deadline()
+                            .is_some_and(|deadline| deadline <= when)
+                        {
+                            return Err(error);
+                        }
+                        self.retries += 1;
+                        State::Waiting(timer::Delay::new(when))
+                    }
+                    result => return result,
+                },
+                State::Waiting(ref mut delay) => match futures::Future::poll(delay) {
+                    Ok(futures::Async::Ready(())) => State::Calling(self.handler.handler.call(
+                        self.method,
+                        self.input.clone(),
+                        self.context.clone(),
+                    )),
+                    _ => return Ok(futures::Async::NotReady),
+                },
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl<H> fmt::Debug for RetryFuture<H>
+where
+    H: handler::Handler,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Calling(_) => "calling",
+            State::Waiting(_) => "waiting",
+        };
+        f.debug_struct("RetryFuture")
+            .field("method", &self.method)
+            .field("retries", &self.retries)
+            .field("state", &state)
+            .finish()
+    }
+}
+
+/// Picks a random backoff between half of the specified backoff and all of it.
+fn jitter(backoff: time::Duration) -> time::Duration {
+    let mut random = [0; 8];
+    if getrandom::getrandom(&mut random).is_err() {
+        // Waiting for the whole backoff is always safe, just not spread out.
+        return backoff;
+    }
+    let random = u64::from_le_bytes(random);
+    let half = backoff / 2;
+    half + half.mul_f64(random as f64 / u64::MAX as f64)
+}
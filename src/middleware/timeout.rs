@@ -0,0 +1,201 @@
+//! A middleware that fails calls that take too long.
+//!
+//! Every call gets a timeout, which can be overridden for specific methods:
+//!
+//! ```rust,ignore
+//! let handler = handler.layer(
+//!     Timeout::new(Duration::from_secs(1))
+//!         .with_method(schema::echo::EchoMethodDescriptor::Echo, Duration::from_millis(100)),
+//! );
+//! ```
+//!
+//! Calls that are still running once their timeout elapses fail with `Error::Elapsed`, and are
+//! dropped, which cancels them.  The timeout is also added to the deadline of the call context, so
+//! that transports send it along to the server, which can then stop working on the call in time.
+use std::collections;
+use std::fmt;
+use std::marker;
+use std::sync;
+use std::time;
+
+use bytes;
+use failure;
+use futures;
+
+use context;
+use descriptor;
+use handler;
+use status;
+use timer;
+
+use super::Layer;
+
+/// A layer that fails calls once a timeout elapses.
+#[derive(Clone, Debug)]
+pub struct Timeout<M> {
+    default: time::Duration,
+    methods: collections::BTreeMap<&'static str, time::Duration>,
+    method: marker::PhantomData<M>,
+}
+
+/// A handler that fails calls to the handler that it wraps once a timeout elapses.
+#[derive(Clone, Debug)]
+pub struct TimeoutHandler<H>
+where
+    H: handler::Handler,
+{
+    handler: H,
+    timeout: sync::Arc<Timeout<<H::Descriptor as descriptor::ServiceDescriptor>::Method>>,
+}
+
+/// The future that results from a call to `TimeoutHandler::call`.
+pub struct TimeoutFuture<F> {
+    inner: F,
+    delay: timer::Delay,
+    timeout: time::Duration,
+}
+
+/// An error that occurred during a call with a timeout.
+#[derive(Debug, Fail)]
+pub enum Error<E>
+where
+    E: failure::Fail,
+{
+    /// The timeout elapsed before the call completed.
+    #[fail(display = "The call timed out after {:?}", timeout)]
+    Elapsed {
+        /// The timeout of the call.
+        timeout: time::Duration,
+    },
+    /// The wrapped handler failed.
+    #[fail(display = "{}", error)]
+    Inner {
+        /// The underlying error.
+        #[cause]
+        error: E,
+    },
+}
+
+impl<M> Timeout<M>
+where
+    M: descriptor::MethodDescriptor,
+{
+    /// Creates a layer that gives every call the specified timeout.
+    pub fn new(timeout: time::Duration) -> Timeout<M> {
+        Timeout {
+            default: timeout,
+            methods: collections::BTreeMap::new(),
+            method: marker::PhantomData,
+        }
+    }
+
+    /// Gives calls to the specified method a different timeout.
+    pub fn with_method(mut self, method: M, timeout: time::Duration) -> Timeout<M> {
+        self.methods.insert(method.proto_name(), timeout);
+        self
+    }
+
+    /// The timeout of calls to the specified method.
+    pub fn timeout(&self, method: &M) -> time::Duration {
+        self.methods
+            .get(method.proto_name())
+            .cloned()
+            .unwrap_or(self.default)
+    }
+}
+
+impl<H, M> Layer<H> for Timeout<M>
+where
+    H: handler::Handler,
+    H::Descriptor: descriptor::ServiceDescriptor<Method = M>,
+    M: descriptor::MethodDescriptor + fmt::Debug,
+{
+    type Handler = TimeoutHandler<H>;
+
+    fn layer(&self, handler: H) -> TimeoutHandler<H> {
+        TimeoutHandler {
+            handler,
+            timeout: sync::Arc::new(self.clone()),
+        }
+    }
+}
+
+impl<H> handler::Handler for TimeoutHandler<H>
+where
+    H: handler::Handler,
+{
+    type Error = Error<H::Error>;
+    type Descriptor = H::Descriptor;
+    type CallFuture = TimeoutFuture<H::CallFuture>;
+
+    fn call(
+        &self,
+        method: <H::Descriptor as descriptor::ServiceDescriptor>::Method,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> Self::CallFuture {
+        let timeout = self.timeout.timeout(&method);
+        let deadline = time::Instant::now() + timeout;
+        TimeoutFuture {
+            inner: self
+                .handler
+                .call(method, input, context.with_deadline(deadline)),
+            delay: timer::Delay::new(deadline),
+            timeout,
+        }
+    }
+}
+
+impl<F> futures::Future for TimeoutFuture<F>
+where
+    F: futures::Future<Item = bytes::Bytes>,
+    F::Error: failure::Fail,
+{
+    type Item = bytes::Bytes;
+    type Error = Error<F::Error>;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        match self.inner.poll() {
+            Ok(futures::Async::Ready(output)) => return Ok(futures::Async::Ready(output)),
+            Ok(futures::Async::NotReady) => {}
+            // The deadline of the context is the same as that of the timeout, so the wrapped
+            // handler might notice that it passed first.
+            Err(_) if self.delay.is_elapsed() => {
+                return Err(Error::Elapsed {
+                    timeout: self.timeout,
+                })
+            }
+            Err(error) => return Err(Error::Inner { error }),
+        }
+
+        match futures::Future::poll(&mut self.delay) {
+            Ok(futures::Async::Ready(())) => Err(Error::Elapsed {
+                timeout: self.timeout,
+            }),
+            _ => Ok(futures::Async::NotReady),
+        }
+    }
+}
+
+impl<F> fmt::Debug for TimeoutFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TimeoutFuture")
+            .field("timeout", &self.timeout)
+            .field("deadline", &self.delay.when())
+            .finish()
+    }
+}
+
+impl<E> status::ToStatus for Error<E>
+where
+    E: failure::Fail + status::ToStatus,
+{
+    fn to_status(&self) -> status::Status {
+        match *self {
+            Error::Elapsed { .. } => {
+                status::Status::new(status::Code::DeadlineExceeded, self.to_string())
+            }
+            Error::Inner { ref error } => error.to_status(),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! A router that dispatches raw calls to one of many handlers based on service and method names.
+//!
+//! This is useful for transports that carry the names of the service and method being called
+//! together with each request, and that want to serve several services at once.
+use std::collections;
+use std::fmt;
+
+use bytes;
+use failure;
+use futures;
+
+use context;
+use descriptor;
+use error;
+use handler;
+use status;
+
+/// The future that results from a call to `Router::call`.
+pub type CallFuture =
+    Box<dyn futures::Future<Item = bytes::Bytes, Error = error::Error<HandlerError>> + Send>;
+
+/// A router that dispatches raw calls to registered handlers.
+///
+/// Handlers are registered by the fully qualified protobuf name of their service, for example
+/// `echo.Echo` for the `Echo` service in the `echo` package, and methods are looked up by their raw
+/// protobuf name.
+#[derive(Clone, Debug, Default)]
+pub struct Router {
+    routes: collections::BTreeMap<String, Box<dyn Route>>,
+}
+
+/// A type-erased error produced by a handler that was registered with a `Router`.
+///
+/// The status of the original error is kept, so that it can still be sent over a transport.
+#[derive(Debug)]
+pub struct HandlerError {
+    error: failure::Error,
+    status: status::Status,
+}
+
+trait Route: Send {
+    fn call(
+        &self,
+        method: &str,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> Option<CallFuture>;
+
+    fn has_method(&self, method: &str) -> bool;
+
+    fn box_clone(&self) -> Box<dyn Route>;
+}
+
+impl Router {
+    /// Creates a new router without any registered handlers.
+    pub fn new() -> Router {
+        Router {
+            routes: collections::BTreeMap::new(),
+        }
+    }
+
+    /// Registers a handler for the service described by its `Descriptor`.
+    ///
+    /// If a handler was already registered for the same service, it is replaced.
+    pub fn add<H>(&mut self, handler: H) -> &mut Router
+    where
+        H: handler::Handler,
+        H::Error: status::ToStatus,
+    {
+        let service = <H::Descriptor as descriptor::ServiceDescriptor>::full_name();
+        self.routes.insert(service, Box::new(handler));
+        self
+    }
+
+    /// Whether a handler is registered for the specified service, and that service has the
+    /// specified method.
+    pub fn has_method(&self, service: &str, method: &str) -> bool {
+        self.routes
+            .get(service)
+            .is_some_and(|route| route.has_method(method))
+    }
+
+    /// Perform a raw call to the specified service and method, in the specified call context.
+    ///
+    /// The resulting future fails with `Error::UnknownService` or `Error::UnknownMethod` if there is
+    /// no such service or method registered with this router.
+    pub fn call(
+        &self,
+        service: &str,
+        method: &str,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> CallFuture {
+        match self.routes.get(service) {
+            Some(route) => route.call(method, input, context).unwrap_or_else(|| {
+                Box::new(futures::future::err(error::Error::unknown_method(
+                    service, method,
+                )))
+            }),
+            None => Box::new(futures::future::err(error::Error::unknown_service(service))),
+        }
+    }
+}
+
+impl HandlerError {
+    fn new<E>(error: E) -> HandlerError
+    where
+        E: failure::Fail + status::ToStatus,
+    {
+        let status = error.to_status();
+        HandlerError {
+            error: failure::Error::from(error),
+            status,
+        }
+    }
+
+    /// Returns the underlying error.
+    pub fn into_inner(self) -> failure::Error {
+        self.error
+    }
+
+    /// Attempts to downcast the underlying error to a specific type.
+    pub fn downcast_ref<F>(&self) -> Option<&F>
+    where
+        F: failure::Fail,
+    {
+        self.error.downcast_ref()
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl failure::Fail for HandlerError {
+    fn cause(&self) -> Option<&dyn failure::Fail> {
+        Some(self.error.as_fail())
+    }
+}
+
+impl status::ToStatus for HandlerError {
+    fn to_status(&self) -> status::Status {
+        self.status.clone()
+    }
+}
+
+impl fmt::Debug for dyn Route {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Route").finish()
+    }
+}
+
+impl Clone for Box<dyn Route> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+impl<H> Route for H
+where
+    H: handler::Handler,
+    H::Error: status::ToStatus,
+{
+    fn call(
+        &self,
+        method: &str,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> Option<CallFuture> {
+        use futures::Future;
+
+        let method = descriptor::MethodDescriptor::from_proto_name(method)?;
+        Some(Box::new(
+            handler::Handler::call(self, method, input, context)
+                .map_err(|e| error::Error::execution(HandlerError::new(e))),
+        ))
+    }
+
+    fn has_method(&self, method: &str) -> bool {
+        use descriptor::MethodDescriptor;
+
+        <H::Descriptor as descriptor::ServiceDescriptor>::Method::from_proto_name(method).is_some()
+    }
+
+    fn box_clone(&self) -> Box<dyn Route> {
+        Box::new(self.clone())
+    }
+}
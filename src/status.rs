@@ -0,0 +1,231 @@
+//! Canonical status codes for describing why a call failed, modelled after gRPC.
+//!
+//! Service implementations can fail with a `Status` (or with an error that has a `Status` among its
+//! causes) to tell callers exactly what went wrong.  Transports send the `Status` of a failed call
+//! using `Status::encode`, and reconstruct it on the client side using `Status::decode`.
+use std::fmt;
+
+use bytes;
+use failure;
+use prost;
+
+use error;
+use router;
+
+/// A canonical status code.
+///
+/// The numeric values are the same as for gRPC, and are part of the wire encoding of a `Status`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Code {
+    /// Not an error; returned on success.
+    Ok = 0,
+    /// The call was cancelled, typically by the caller.
+    Cancelled = 1,
+    /// An unknown error, for example an error without any more specific status.
+    Unknown = 2,
+    /// The caller specified an invalid argument.
+    InvalidArgument = 3,
+    /// The deadline of the call passed before it could complete.
+    DeadlineExceeded = 4,
+    /// Some requested entity was not found.
+    NotFound = 5,
+    /// An entity that the caller tried to create already exists.
+    AlreadyExists = 6,
+    /// The caller does not have permission to execute the call.
+    PermissionDenied = 7,
+    /// Some resource has been exhausted, for example a quota or the capacity of a server.
+    ResourceExhausted = 8,
+    /// The system is not in a state required for the call's execution.
+    FailedPrecondition = 9,
+    /// The call was aborted, typically due to a concurrency issue.
+    Aborted = 10,
+    /// The call was attempted past the valid range.
+    OutOfRange = 11,
+    /// The call is not implemented or not supported by the server.
+    Unimplemented = 12,
+    /// An internal invariant was broken.
+    Internal = 13,
+    /// The service is currently unavailable; retrying later might help.
+    Unavailable = 14,
+    /// Unrecoverable data loss or corruption.
+    DataLoss = 15,
+    /// The caller does not have valid authentication credentials.
+    Unauthenticated = 16,
+}
+
+/// A status code together with a human-readable message, describing why a call failed.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Status {
+    code: Code,
+    message: String,
+}
+
+/// Errors that can be described by a `Status`.
+///
+/// Transports use this to send errors produced by a server back to the client.
+pub trait ToStatus {
+    /// Describes this error as a `Status`.
+    fn to_status(&self) -> Status;
+}
+
+/// The wire representation of a `Status`, compatible with the `code` and `message` fields of
+/// `google.rpc.Status`.
+#[derive(Clone, PartialEq, Message)]
+struct StatusProto {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+}
+
+impl Code {
+    /// Looks up a code by its numeric value, falling back to `Code::Unknown` for unknown values.
+    pub fn from_i32(value: i32) -> Code {
+        match value {
+            0 => Code::Ok,
+            1 => Code::Cancelled,
+            2 => Code::Unknown,
+            3 => Code::InvalidArgument,
+            4 => Code::DeadlineExceeded,
+            5 => Code::NotFound,
+            6 => Code::AlreadyExists,
+            7 => Code::PermissionDenied,
+            8 => Code::ResourceExhausted,
+            9 => Code::FailedPrecondition,
+            10 => Code::Aborted,
+            11 => Code::OutOfRange,
+            12 => Code::Unimplemented,
+            13 => Code::Internal,
+            14 => Code::Unavailable,
+            15 => Code::DataLoss,
+            16 => Code::Unauthenticated,
+            _ => Code::Unknown,
+        }
+    }
+
+    /// The numeric value of this code.
+    pub fn as_i32(self) -> i32 {
+        self as i32
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl Status {
+    /// Creates a new status with the specified code and message.
+    pub fn new<M>(code: Code, message: M) -> Status
+    where
+        M: Into<String>,
+    {
+        Status {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Creates a status for an arbitrary error.
+    ///
+    /// If the error or any of its causes is a `Status`, that status is returned.  Otherwise, the
+    /// status has code `Code::Unknown` and the error's message.
+    pub fn from_fail(fail: &dyn failure::Fail) -> Status {
+        for cause in fail.iter_chain() {
+            if let Some(status) = cause.downcast_ref::<Status>() {
+                return status.clone();
+            }
+            if let Some(error) = cause.downcast_ref::<router::HandlerError>() {
+                return error.to_status();
+            }
+        }
+        Status::new(Code::Unknown, fail.to_string())
+    }
+
+    /// The status code.
+    pub fn code(&self) -> Code {
+        self.code
+    }
+
+    /// The human-readable message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns this status with its message cut down to at most `max_len` bytes, for transports
+    /// that have to fit it into a bounded amount of space.
+    #[cfg(any(
+        feature = "quic",
+        feature = "stdio",
+        feature = "tcp",
+        feature = "udp",
+        feature = "websocket",
+        all(target_os = "linux", feature = "shm"),
+        all(unix, feature = "unix")
+    ))]
+    pub(crate) fn truncated(&self, max_len: usize) -> Status {
+        let mut len = self.message.len().min(max_len);
+        while !self.message.is_char_boundary(len) {
+            len -= 1;
+        }
+        Status::new(self.code, &self.message[..len])
+    }
+
+    /// Encodes this status for sending it over a transport.
+    pub fn encode(&self) -> bytes::Bytes {
+        let proto = StatusProto {
+            code: self.code.as_i32(),
+            message: self.message.clone(),
+        };
+        let mut buf = bytes::BytesMut::with_capacity(prost::Message::encoded_len(&proto));
+        prost::Message::encode(&proto, &mut buf).expect("buffer has enough capacity");
+        buf.freeze()
+    }
+
+    /// Decodes a status that was encoded with `Status::encode`.
+    pub fn decode(buf: bytes::Bytes) -> Result<Status, prost::DecodeError> {
+        let proto: StatusProto = prost::Message::decode(buf)?;
+        Ok(Status {
+            code: Code::from_i32(proto.code),
+            message: proto.message,
+        })
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "{}", self.code)
+        } else {
+            write!(f, "{}: {}", self.code, self.message)
+        }
+    }
+}
+
+impl failure::Fail for Status {}
+
+impl ToStatus for Status {
+    fn to_status(&self) -> Status {
+        self.clone()
+    }
+}
+
+impl<E> ToStatus for error::Error<E>
+where
+    E: failure::Fail,
+{
+    fn to_status(&self) -> Status {
+        let code = match *self {
+            error::Error::Execution { ref error } => return Status::from_fail(error),
+            error::Error::Decode { .. } => Code::InvalidArgument,
+            error::Error::Encode { .. } => Code::Internal,
+            error::Error::UnsupportedCall { .. }
+            | error::Error::UnknownService { .. }
+            | error::Error::UnknownMethod { .. } => Code::Unimplemented,
+            error::Error::Cancelled => Code::Cancelled,
+            error::Error::DeadlineExceeded => Code::DeadlineExceeded,
+        };
+        Status::new(code, self.to_string())
+    }
+}
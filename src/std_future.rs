@@ -0,0 +1,32 @@
+//! Traits for defining generic RPC handlers based on `std::future::Future`.
+//!
+//! These are used by code generated with `ServiceGenerator::std_futures` enabled, and are the
+//! `std::future` counterparts of the traits in the `handler` module.
+use std::future;
+
+use bytes;
+use failure;
+
+use context;
+use descriptor;
+
+/// An implementation of a specific RPC handler that produces `std::future::Future`s.
+///
+/// This can be an actual implementation of a service, or something that will send a request over
+/// a network to fulfill a request.
+pub trait Handler: Clone + Send + Sync + 'static {
+    /// The type of errors that this handler might generate, beyond the default RPC error type.
+    type Error: failure::Fail;
+    /// The service descriptor for the service whose requests this handler can handle.
+    type Descriptor: descriptor::ServiceDescriptor;
+    /// The future that results from a call to the `call` method of this trait.
+    type CallFuture: future::Future<Output = Result<bytes::Bytes, Self::Error>> + Send + 'static;
+
+    /// Perform a raw call to the specified service and method, in the specified call context.
+    fn call(
+        &self,
+        method: <Self::Descriptor as descriptor::ServiceDescriptor>::Method,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> Self::CallFuture;
+}
@@ -0,0 +1,217 @@
+//! A minimal timer that works with any executor, or none at all.
+//!
+//! Deadlines have to be enforced even for calls that are driven with `Future::wait`, so instead of
+//! relying on the timer of a particular runtime, all delays are tracked by a single background
+//! thread that wakes up tasks once their delays have elapsed.
+use std::collections;
+use std::future;
+use std::pin;
+use std::sync;
+use std::task;
+use std::thread;
+use std::time;
+
+use futures;
+
+/// A future that completes at a specific point in time.
+///
+/// This works both as a `futures::Future` and as a `std::future::Future`.
+#[derive(Debug)]
+pub struct Delay {
+    when: time::Instant,
+    /// The ID of the timer entry for this delay, and the waiter that it notifies.
+    registration: Option<(u64, Waiter)>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Waiter {
+    Task(futures::task::Task),
+    Waker(task::Waker),
+}
+
+#[derive(Debug, Default)]
+struct Timer {
+    state: sync::Mutex<State>,
+    condvar: sync::Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: collections::BTreeMap<(time::Instant, u64), Waiter>,
+    next_id: u64,
+    started: bool,
+}
+
+static TIMER: sync::OnceLock<sync::Arc<Timer>> = sync::OnceLock::new();
+
+impl Delay {
+    /// Creates a delay that completes at the specified instant.
+    pub fn new(when: time::Instant) -> Delay {
+        Delay {
+            when,
+            registration: None,
+        }
+    }
+
+    /// Creates a delay that completes once the specified duration has passed.
+    pub fn after(duration: time::Duration) -> Delay {
+        Delay::new(time::Instant::now() + duration)
+    }
+
+    /// The instant at which this delay completes.
+    pub fn when(&self) -> time::Instant {
+        self.when
+    }
+
+    /// Whether this delay has completed.
+    pub fn is_elapsed(&self) -> bool {
+        time::Instant::now() >= self.when
+    }
+
+    fn poll_waiter(&mut self, waiter: Waiter) -> bool {
+        if self.is_elapsed() {
+            return true;
+        }
+        // Only register with the timer again if a different task is polling this delay now.
+        let registered = match self.registration {
+            Some((_, ref registered)) => registered.same_as(&waiter),
+            None => false,
+        };
+        if !registered {
+            let timer = timer();
+            if let Some((id, _)) = self.registration.take() {
+                timer.deregister(self.when, id);
+            }
+            let id = timer.register(self.when, waiter.clone());
+            self.registration = Some((id, waiter));
+        }
+        false
+    }
+}
+
+impl Drop for Delay {
+    fn drop(&mut self) {
+        // Don't keep the waiter around until the delay would have elapsed.
+        if let Some((id, _)) = self.registration.take() {
+            timer().deregister(self.when, id);
+        }
+    }
+}
+
+impl futures::Future for Delay {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> futures::Poll<(), ()> {
+        if self.poll_waiter(Waiter::current()) {
+            Ok(futures::Async::Ready(()))
+        } else {
+            Ok(futures::Async::NotReady)
+        }
+    }
+}
+
+impl future::Future for Delay {
+    type Output = ();
+
+    fn poll(self: pin::Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<()> {
+        if self
+            .get_mut()
+            .poll_waiter(Waiter::Waker(cx.waker().clone()))
+        {
+            task::Poll::Ready(())
+        } else {
+            task::Poll::Pending
+        }
+    }
+}
+
+impl Waiter {
+    /// The waiter for the `futures` task that is currently running.
+    pub(crate) fn current() -> Waiter {
+        Waiter::Task(futures::task::current())
+    }
+
+    pub(crate) fn notify(&self) {
+        match *self {
+            Waiter::Task(ref task) => task.notify(),
+            Waiter::Waker(ref waker) => waker.wake_by_ref(),
+        }
+    }
+
+    pub(crate) fn same_as(&self, other: &Waiter) -> bool {
+        match (self, other) {
+            (Waiter::Task(a), Waiter::Task(b)) => {
+                a.will_notify_current() && b.will_notify_current()
+            }
+            (Waiter::Waker(a), Waiter::Waker(b)) => a.will_wake(b),
+            _ => false,
+        }
+    }
+}
+
+impl Timer {
+    fn register(self: &sync::Arc<Self>, when: time::Instant, waiter: Waiter) -> u64 {
+        let mut state = self.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.entries.insert((when, id), waiter);
+        if !state.started {
+            state.started = true;
+            let timer = self.clone();
+            thread::Builder::new()
+                .name("prost-simple-rpc-timer".to_owned())
+                .spawn(move || timer.run())
+                .expect("failed to spawn timer thread");
+        }
+        self.condvar.notify_one();
+        id
+    }
+
+    fn deregister(&self, when: time::Instant, id: u64) {
+        self.lock().entries.remove(&(when, id));
+    }
+
+    fn run(&self) {
+        let mut state = self.lock();
+        loop {
+            let now = time::Instant::now();
+            while state
+                .entries
+                .first_key_value()
+                .is_some_and(|(&(when, _), _)| when <= now)
+            {
+                if let Some((_, waiter)) = state.entries.pop_first() {
+                    waiter.notify();
+                }
+            }
+
+            state = match state
+                .entries
+                .first_key_value()
+                .map(|(&(when, _), _)| when - now)
+            {
+                Some(timeout) => {
+                    self.condvar
+                        .wait_timeout(state, timeout)
+                        .unwrap_or_else(sync::PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .condvar
+                    .wait(state)
+                    .unwrap_or_else(sync::PoisonError::into_inner),
+            };
+        }
+    }
+
+    fn lock(&self) -> sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(sync::PoisonError::into_inner)
+    }
+}
+
+fn timer() -> &'static sync::Arc<Timer> {
+    TIMER.get_or_init(|| sync::Arc::new(Timer::default()))
+}
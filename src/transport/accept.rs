@@ -0,0 +1,84 @@
+//! Accepting connections without giving up on the first one that fails.
+//!
+//! Errors that only concern a single connection, like a client that hung up before its connection
+//! was accepted, are skipped.  Running out of resources, like file descriptors, is temporary too,
+//! so accepting is retried after a short pause instead of spinning on the same error.  Only errors
+//! that mean that the listener itself is broken end the stream of connections.
+use std::io;
+use std::time;
+
+use futures;
+
+use timer;
+
+/// How long to pause accepting after an error that isn't specific to a single connection.
+const BACKOFF: time::Duration = time::Duration::from_millis(100);
+
+/// The future that results from a call to `for_each`.
+pub(crate) struct ForEach<S, F> {
+    incoming: S,
+    on_connection: F,
+    backoff: Option<timer::Delay>,
+}
+
+/// Calls `on_connection` with every connection accepted from `incoming`.
+///
+/// If `on_connection` fails, only that connection is dropped.  The returned future completes once
+/// `incoming` ends, or fails if the listener breaks.
+pub(crate) fn for_each<S, F>(incoming: S, on_connection: F) -> ForEach<S, F>
+where
+    S: futures::Stream<Error = io::Error>,
+    F: FnMut(S::Item) -> io::Result<()>,
+{
+    ForEach {
+        incoming,
+        on_connection,
+        backoff: None,
+    }
+}
+
+impl<S, F> futures::Future for ForEach<S, F>
+where
+    S: futures::Stream<Error = io::Error>,
+    F: FnMut(S::Item) -> io::Result<()>,
+{
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> futures::Poll<(), io::Error> {
+        loop {
+            if let Some(ref mut backoff) = self.backoff {
+                if let Ok(futures::Async::NotReady) = futures::Future::poll(backoff) {
+                    return Ok(futures::Async::NotReady);
+                }
+            }
+            self.backoff = None;
+
+            match self.incoming.poll() {
+                Ok(futures::Async::Ready(Some(connection))) => {
+                    // The connection is dropped, which closes it, but others are unaffected.
+                    let _ = (self.on_connection)(connection);
+                }
+                Ok(futures::Async::Ready(None)) => return Ok(futures::Async::Ready(())),
+                Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+                Err(ref error) if is_connection_error(error) => {}
+                // The socket isn't listening (any more), so accepting will never succeed again.
+                Err(error) if error.kind() == io::ErrorKind::InvalidInput => return Err(error),
+                Err(_) => self.backoff = Some(timer::Delay::after(BACKOFF)),
+            }
+        }
+    }
+}
+
+/// Whether an error only concerns the connection that was being accepted.
+fn is_connection_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::PermissionDenied
+            | io::ErrorKind::TimedOut
+    )
+}
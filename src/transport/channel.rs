@@ -0,0 +1,414 @@
+//! An in-process transport over channels.
+//!
+//! Unlike calling a generated server directly, calls made over a channel are handled by a separate
+//! server task, which can run on another executor or thread.  The number of calls that can be
+//! queued or in flight at once is bounded, so that callers wait for the server when it falls
+//! behind:
+//!
+//! ```rust,ignore
+//! let (connection, listener) = channel::channel(16);
+//! let server = schema::echo::EchoServer::new(EchoService);
+//! thread::spawn(move || channel::serve(listener, server).wait());
+//!
+//! let client = schema::echo::EchoClient::new(channel::Client::new(connection));
+//! ```
+//!
+//! Only the metadata, deadline and cancellation of a call cross the channel, just like with a
+//! network transport.  Tests can additionally make a connection delay or lose calls with
+//! `Connection::with_faults`.
+use std::fmt;
+use std::marker;
+use std::mem;
+use std::sync;
+use std::time;
+
+use bytes;
+use futures;
+use futures::sync::mpsc;
+use futures::sync::oneshot;
+
+use context;
+use descriptor;
+use handler;
+use metadata;
+use status;
+use timer;
+
+use super::Dispatch;
+use super::Error;
+
+/// The future that results from a call to `serve`.
+pub type Serve = Box<dyn futures::Future<Item = (), Error = ()> + Send>;
+
+/// The sending half of a channel, over which any number of calls can be made concurrently.
+///
+/// Connections are cheap to clone, and all clones share the same channel, which is closed once all
+/// of them have been dropped.
+#[derive(Clone)]
+pub struct Connection {
+    shared: sync::Arc<Shared>,
+    faults: Option<sync::Arc<Faults>>,
+}
+
+/// The receiving half of a channel, which can be served with `serve`.
+#[derive(Debug)]
+pub struct Listener {
+    receiver: mpsc::UnboundedReceiver<Request>,
+    capacity: usize,
+}
+
+/// A client for a specific service, making calls over a `Connection`.
+#[derive(Clone, Debug)]
+pub struct Client<D> {
+    connection: Connection,
+    descriptor: marker::PhantomData<D>,
+}
+
+/// The future that results from a call made over a `Connection`.
+///
+/// Dropping this future before it completes cancels the call on the server.
+#[derive(Debug)]
+pub struct CallFuture {
+    state: State,
+    context: context::Context,
+    remote: context::Context,
+    shared: sync::Arc<Shared>,
+    fault: Fault,
+}
+
+/// A fault to inject into a call made over a `Connection`, for testing.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Fault {
+    /// Deliver the call normally.
+    None,
+    /// Deliver the call after the specified delay.
+    Delay(time::Duration),
+    /// Lose the call before it reaches the server, so it never completes.
+    DropRequest,
+    /// Handle the call on the server, but lose the response, so it never completes.
+    DropResponse,
+}
+
+type Faults = dyn Fn(&str, &str) -> Fault + Send + Sync;
+
+#[derive(Debug)]
+enum State {
+    Delaying(timer::Delay, Call),
+    Acquiring(Call),
+    Waiting(oneshot::Receiver<Response>),
+    Lost,
+    Done,
+}
+
+#[derive(Debug)]
+struct Shared {
+    sender: mpsc::UnboundedSender<Request>,
+    limit: sync::Arc<Limit>,
+}
+
+/// Counts the calls that are queued or in flight, so that there are never more than `capacity`.
+#[derive(Debug)]
+struct Limit {
+    capacity: usize,
+    state: sync::Mutex<LimitState>,
+}
+
+#[derive(Debug, Default)]
+struct LimitState {
+    used: usize,
+    waiters: Vec<timer::Waiter>,
+}
+
+#[derive(Debug)]
+struct Permit(sync::Arc<Limit>);
+
+#[derive(Debug)]
+struct Call {
+    service: String,
+    method: String,
+    input: bytes::Bytes,
+}
+
+#[derive(Debug)]
+struct Request {
+    service: String,
+    method: String,
+    input: bytes::Bytes,
+    context: context::Context,
+    response: oneshot::Sender<Response>,
+    permit: Permit,
+}
+
+#[derive(Debug)]
+struct Response {
+    result: Result<bytes::Bytes, status::Status>,
+    trailers: metadata::Metadata,
+}
+
+/// Creates a channel that allows up to `capacity` calls to be queued or in flight at once.
+///
+/// # Panics
+///
+/// Panics if `capacity` is zero.
+pub fn channel(capacity: usize) -> (Connection, Listener) {
+    assert!(capacity > 0, "channel capacity must be positive");
+
+    let (sender, receiver) = mpsc::unbounded();
+    let connection = Connection {
+        shared: sync::Arc::new(Shared {
+            sender,
+            limit: sync::Arc::new(Limit {
+                capacity,
+                state: sync::Mutex::new(LimitState::default()),
+            }),
+        }),
+        faults: None,
+    };
+    (connection, Listener { receiver, capacity })
+}
+
+/// Serves the calls that arrive over the specified channel by dispatching them to `dispatch`.
+///
+/// The returned future can be run on any executor, or on its own thread with `Future::wait`.  It
+/// handles all calls concurrently, and completes once all connections have been dropped and all
+/// calls have been handled.
+pub fn serve<D>(listener: Listener, dispatch: D) -> Serve
+where
+    D: Dispatch,
+{
+    use futures::Future;
+    use futures::Stream;
+
+    Box::new(
+        listener
+            .receiver
+            .map(move |request| {
+                let Request {
+                    service,
+                    method,
+                    input,
+                    context,
+                    response,
+                    permit,
+                } = request;
+                let trailers = context.clone();
+                dispatch
+                    .dispatch(&service, &method, input, context)
+                    .then(move |result| {
+                        let _ = response.send(Response {
+                            result,
+                            trailers: trailers.trailers(),
+                        });
+                        drop(permit);
+                        Ok(())
+                    })
+            })
+            .buffer_unordered(listener.capacity)
+            .for_each(|()| Ok(())),
+    )
+}
+
+impl Connection {
+    /// Returns this connection with faults injected into its calls.
+    ///
+    /// The specified function is called with the service and method name of every call made over
+    /// the returned connection, and decides how the call should misbehave.
+    pub fn with_faults<F>(mut self, faults: F) -> Connection
+    where
+        F: Fn(&str, &str) -> Fault + Send + Sync + 'static,
+    {
+        self.faults = Some(sync::Arc::new(faults));
+        self
+    }
+
+    /// Performs a raw call to the specified service and method, in the specified call context.
+    pub fn call(
+        &self,
+        service: &str,
+        method: &str,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> CallFuture {
+        let fault = match self.faults {
+            Some(ref faults) => faults(service, method),
+            None => Fault::None,
+        };
+
+        // Only what a network transport would send crosses the channel.
+        let mut remote = context::Context::with_metadata(context.metadata().clone());
+        if let Some(deadline) = context.deadline() {
+            remote = remote.with_deadline(deadline);
+        }
+
+        let call = Call {
+            service: service.to_owned(),
+            method: method.to_owned(),
+            input,
+        };
+        let state = match fault {
+            Fault::None | Fault::DropResponse => State::Acquiring(call),
+            Fault::Delay(delay) => State::Delaying(timer::Delay::after(delay), call),
+            Fault::DropRequest => State::Lost,
+        };
+
+        CallFuture {
+            state,
+            context,
+            remote,
+            shared: self.shared.clone(),
+            fault,
+        }
+    }
+
+    /// Whether the server has stopped, in which case all calls fail with `Error::Closed`.
+    pub fn is_closed(&self) -> bool {
+        self.shared.sender.is_closed()
+    }
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("closed", &self.is_closed())
+            .field("faults", &self.faults.is_some())
+            .finish()
+    }
+}
+
+impl<D> Client<D>
+where
+    D: descriptor::ServiceDescriptor + 'static,
+{
+    /// Creates a new client that makes calls over the specified connection.
+    pub fn new(connection: Connection) -> Client<D> {
+        Client {
+            connection,
+            descriptor: marker::PhantomData,
+        }
+    }
+}
+
+impl<D> handler::Handler for Client<D>
+where
+    D: descriptor::ServiceDescriptor + 'static,
+{
+    type Error = Error;
+    type Descriptor = D;
+    type CallFuture = CallFuture;
+
+    fn call(
+        &self,
+        method: D::Method,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> Self::CallFuture {
+        let method = descriptor::MethodDescriptor::proto_name(&method);
+        self.connection
+            .call(&D::full_name(), method, input, context)
+    }
+}
+
+impl futures::Future for CallFuture {
+    type Item = bytes::Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match mem::replace(&mut self.state, State::Done) {
+                State::Delaying(mut delay, call) => match futures::Future::poll(&mut delay) {
+                    Ok(futures::Async::NotReady) => {
+                        self.state = State::Delaying(delay, call);
+                        return Ok(futures::Async::NotReady);
+                    }
+                    _ => State::Acquiring(call),
+                },
+                State::Acquiring(call) => {
+                    let permit = match self.shared.limit.acquire(timer::Waiter::current()) {
+                        Some(permit) => permit,
+                        None => {
+                            self.state = State::Acquiring(call);
+                            return Ok(futures::Async::NotReady);
+                        }
+                    };
+                    let (response_sender, response) = oneshot::channel();
+                    let request = Request {
+                        service: call.service,
+                        method: call.method,
+                        input: call.input,
+                        context: self.remote.clone(),
+                        response: response_sender,
+                        permit,
+                    };
+                    if self.shared.sender.unbounded_send(request).is_err() {
+                        return Err(Error::Closed);
+                    }
+                    State::Waiting(response)
+                }
+                State::Waiting(mut receiver) => match receiver.poll() {
+                    Ok(futures::Async::NotReady) => {
+                        self.state = State::Waiting(receiver);
+                        return Ok(futures::Async::NotReady);
+                    }
+                    Ok(futures::Async::Ready(_)) if self.fault == Fault::DropResponse => {
+                        State::Lost
+                    }
+                    Ok(futures::Async::Ready(response)) => {
+                        self.context.set_trailers(response.trailers);
+                        return match response.result {
+                            Ok(output) => Ok(futures::Async::Ready(output)),
+                            Err(status) => Err(Error::Status { status }),
+                        };
+                    }
+                    Err(oneshot::Canceled) => return Err(Error::Closed),
+                },
+                State::Lost => {
+                    self.state = State::Lost;
+                    return Ok(futures::Async::NotReady);
+                }
+                State::Done => panic!("cannot poll a call after it has completed"),
+            }
+        }
+    }
+}
+
+impl Drop for CallFuture {
+    fn drop(&mut self) {
+        match self.state {
+            State::Done => {}
+            _ => self.remote.cancel(),
+        }
+    }
+}
+
+impl Limit {
+    fn acquire(self: &sync::Arc<Self>, waiter: timer::Waiter) -> Option<Permit> {
+        let mut state = lock(&self.state);
+        if state.used < self.capacity {
+            state.used += 1;
+            Some(Permit(self.clone()))
+        } else {
+            // Only register once, no matter how often the task polls while it waits.
+            if !state.waiters.iter().any(|queued| queued.same_as(&waiter)) {
+                state.waiters.push(waiter);
+            }
+            None
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let waiters = {
+            let mut state = lock(&self.0.state);
+            state.used -= 1;
+            mem::take(&mut state.waiters)
+        };
+        for waiter in waiters {
+            waiter.notify();
+        }
+    }
+}
+
+fn lock<T>(mutex: &sync::Mutex<T>) -> sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(sync::PoisonError::into_inner)
+}
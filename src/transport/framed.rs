@@ -0,0 +1,510 @@
+//! A protocol of length-prefixed protobuf frames for byte stream transports.
+//!
+//! Message-oriented transports like WebSockets carry the same frames, one per message, without the
+//! length prefix.
+//!
+//! Every call is identified by a correlation ID chosen by the client, so that many calls can be in
+//! flight over the same connection at once, and responses can arrive in any order.
+use std::collections;
+use std::fmt;
+use std::io;
+use std::marker;
+use std::sync;
+use std::sync::atomic;
+use std::time;
+
+use bytes;
+use futures;
+use futures::sync::mpsc;
+use futures::sync::oneshot;
+use prost;
+use tokio;
+
+use context;
+use descriptor;
+use handler;
+use metadata;
+use status;
+
+use super::Dispatch;
+use super::Error;
+
+/// The largest frame that can be sent over a byte stream, in bytes.
+///
+/// A call whose request doesn't fit fails with `Error::TooLarge`, and a response that doesn't fit
+/// is replaced with a `ResourceExhausted` error, without affecting other calls on the connection.
+#[cfg(any(feature = "stdio", feature = "tcp", all(unix, feature = "unix")))]
+pub const MAX_MESSAGE_LEN: usize = 8 * 1024 * 1024;
+
+const MAX_ERROR_MESSAGE_LEN: usize = 1024;
+
+/// A connection to a server, over which any number of calls can be made concurrently.
+///
+/// Connections are cheap to clone, and all clones share the same underlying connection, which is
+/// closed once all of them have been dropped.
+#[derive(Clone)]
+pub struct Connection {
+    shared: sync::Arc<Shared>,
+}
+
+/// A client for a specific service, making calls over a `Connection`.
+#[derive(Clone, Debug)]
+pub struct Client<D> {
+    connection: Connection,
+    descriptor: marker::PhantomData<D>,
+}
+
+/// The future that results from a call made over a `Connection`.
+///
+/// Dropping this future before it completes tells the server to cancel the call.
+pub struct CallFuture {
+    id: u64,
+    context: context::Context,
+    response: Option<oneshot::Receiver<Frame>>,
+    error: Option<Error>,
+    shared: sync::Arc<Shared>,
+    done: bool,
+}
+
+type Pending = sync::Arc<sync::Mutex<Option<collections::HashMap<u64, oneshot::Sender<Frame>>>>>;
+
+type Calls = sync::Arc<sync::Mutex<collections::HashMap<u64, context::Context>>>;
+
+struct Shared {
+    next_id: atomic::AtomicU64,
+    pending: Pending,
+    sender: mpsc::UnboundedSender<Frame>,
+    max_len: usize,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Frame {
+    #[prost(uint64, tag = "1")]
+    id: u64,
+    #[prost(enumeration = "Kind", tag = "2")]
+    kind: i32,
+    #[prost(string, tag = "3")]
+    service: String,
+    #[prost(string, tag = "4")]
+    method: String,
+    #[prost(btree_map = "string, string", tag = "5")]
+    metadata: collections::BTreeMap<String, String>,
+    #[prost(uint64, optional, tag = "6")]
+    timeout_micros: Option<u64>,
+    #[prost(bytes, tag = "7")]
+    body: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Enumeration)]
+enum Kind {
+    Request = 0,
+    Response = 1,
+    Error = 2,
+    Cancel = 3,
+}
+
+impl Connection {
+    /// Starts driving a connection over the specified byte stream on the current tokio executor.
+    #[cfg(any(feature = "stdio", feature = "tcp", all(unix, feature = "unix")))]
+    pub(crate) fn new<T>(io: T) -> Connection
+    where
+        T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static,
+    {
+        Connection::with_messages(framed(io), MAX_MESSAGE_LEN)
+    }
+
+    /// Starts driving a connection over the specified messages on the current tokio executor.
+    ///
+    /// Every message carries exactly one frame, of at most `max_len` bytes.
+    pub(crate) fn with_messages<M>(messages: M, max_len: usize) -> Connection
+    where
+        M: futures::Stream<Item = bytes::BytesMut, Error = io::Error>
+            + futures::Sink<SinkItem = bytes::Bytes, SinkError = io::Error>
+            + Send
+            + 'static,
+    {
+        use futures::Future;
+        use futures::Sink;
+        use futures::Stream;
+
+        let (sink, stream) = messages.split();
+        let (sender, receiver) = mpsc::unbounded();
+        let pending: Pending = sync::Arc::new(sync::Mutex::new(Some(collections::HashMap::new())));
+
+        let writer = receiver
+            .map(encode)
+            .forward(sink.sink_map_err(|_| ()))
+            .map(|_| ());
+
+        // The driver must not keep the sender alive, so that it stops once all connections are
+        // dropped.
+        let reader_pending = pending.clone();
+        let reader = stream.map_err(|_| ()).for_each(move |buf| {
+            let frame: Frame = prost::Message::decode(buf.freeze()).map_err(|_| ())?;
+            if let Some(ref mut pending) = *lock(&reader_pending) {
+                if let Some(response) = pending.remove(&frame.id) {
+                    let _ = response.send(frame);
+                }
+            }
+            Ok(())
+        });
+
+        let closed = pending.clone();
+        tokio::spawn(writer.select(reader).then(move |_| {
+            // Dropping all of the response senders fails the outstanding calls.
+            lock(&closed).take();
+            Ok(())
+        }));
+
+        Connection {
+            shared: sync::Arc::new(Shared {
+                next_id: atomic::AtomicU64::new(0),
+                pending,
+                sender,
+                max_len,
+            }),
+        }
+    }
+
+    /// Performs a raw call to the specified service and method, in the specified call context.
+    pub fn call(
+        &self,
+        service: &str,
+        method: &str,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> CallFuture {
+        let id = self.shared.next_id.fetch_add(1, atomic::Ordering::Relaxed);
+        let frame = Frame {
+            id,
+            kind: Kind::Request as i32,
+            service: service.to_owned(),
+            method: method.to_owned(),
+            metadata: metadata_entries(context.metadata()),
+            timeout_micros: context.timeout().map(|timeout| {
+                let micros = timeout.as_micros();
+                if micros > u128::from(u64::MAX) {
+                    u64::MAX
+                } else {
+                    micros as u64
+                }
+            }),
+            body: input.to_vec(),
+        };
+
+        let len = prost::Message::encoded_len(&frame);
+        if len > self.shared.max_len {
+            return CallFuture {
+                id,
+                context,
+                response: None,
+                error: Some(Error::TooLarge {
+                    len,
+                    max: self.shared.max_len,
+                }),
+                shared: self.shared.clone(),
+                done: false,
+            };
+        }
+
+        let (response_sender, response) = oneshot::channel();
+        let registered = match *lock(&self.shared.pending) {
+            Some(ref mut pending) => {
+                pending.insert(id, response_sender);
+                true
+            }
+            None => false,
+        };
+        let sent = registered && self.shared.sender.unbounded_send(frame).is_ok();
+
+        CallFuture {
+            id,
+            context,
+            response: if sent { Some(response) } else { None },
+            error: None,
+            shared: self.shared.clone(),
+            done: false,
+        }
+    }
+
+    /// Whether the connection has been closed, in which case all calls fail with `Error::Closed`.
+    pub fn is_closed(&self) -> bool {
+        lock(&self.shared.pending).is_none()
+    }
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+impl<D> Client<D>
+where
+    D: descriptor::ServiceDescriptor + 'static,
+{
+    /// Creates a new client that makes calls over the specified connection.
+    pub fn new(connection: Connection) -> Client<D> {
+        Client {
+            connection,
+            descriptor: marker::PhantomData,
+        }
+    }
+}
+
+impl<D> handler::Handler for Client<D>
+where
+    D: descriptor::ServiceDescriptor + 'static,
+{
+    type Error = Error;
+    type Descriptor = D;
+    type CallFuture = CallFuture;
+
+    fn call(
+        &self,
+        method: D::Method,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> Self::CallFuture {
+        let method = descriptor::MethodDescriptor::proto_name(&method);
+        self.connection
+            .call(&D::full_name(), method, input, context)
+    }
+}
+
+impl futures::Future for CallFuture {
+    type Item = bytes::Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        if let Some(error) = self.error.take() {
+            self.done = true;
+            return Err(error);
+        }
+
+        let result = match self.response {
+            Some(ref mut response) => response.poll(),
+            None => Err(oneshot::Canceled),
+        };
+        let frame = match result {
+            Ok(futures::Async::Ready(frame)) => frame,
+            Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+            Err(oneshot::Canceled) => {
+                self.done = true;
+                return Err(Error::Closed);
+            }
+        };
+
+        self.done = true;
+        self.context
+            .set_trailers(frame.metadata.into_iter().collect());
+        if frame.kind == Kind::Response as i32 {
+            Ok(futures::Async::Ready(frame.body.into()))
+        } else {
+            let status = status::Status::decode(frame.body.into())?;
+            Err(Error::Status { status })
+        }
+    }
+}
+
+impl Drop for CallFuture {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        if let Some(ref mut pending) = *lock(&self.shared.pending) {
+            pending.remove(&self.id);
+        }
+        let _ = self.shared.sender.unbounded_send(Frame {
+            id: self.id,
+            kind: Kind::Cancel as i32,
+            ..Frame::default()
+        });
+    }
+}
+
+impl fmt::Debug for CallFuture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CallFuture").field("id", &self.id).finish()
+    }
+}
+
+/// Serves calls arriving over the specified byte stream by dispatching them to `dispatch`.
+///
+/// Each call is handled in its own task on the current tokio executor, so slow calls don't hold up
+/// other calls on the same connection.  The returned future completes when the client disconnects,
+/// cancelling all of the calls that are still in flight.
+///
+/// If the transport knows the credentials of the client, they are attached to the context of every
+/// call.
+#[cfg(any(feature = "stdio", feature = "tcp", all(unix, feature = "unix")))]
+pub(crate) fn serve_connection<T, D>(
+    io: T,
+    dispatch: D,
+    peer_credentials: Option<context::PeerCredentials>,
+) -> Box<dyn futures::Future<Item = (), Error = io::Error> + Send>
+where
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static,
+    D: Dispatch,
+{
+    serve_messages(framed(io), MAX_MESSAGE_LEN, dispatch, peer_credentials)
+}
+
+/// Serves calls arriving over the specified messages by dispatching them to `dispatch`.
+///
+/// Every message carries exactly one frame, of at most `max_len` bytes.  This otherwise behaves
+/// like `serve_connection`.
+pub(crate) fn serve_messages<M, D>(
+    messages: M,
+    max_len: usize,
+    dispatch: D,
+    peer_credentials: Option<context::PeerCredentials>,
+) -> Box<dyn futures::Future<Item = (), Error = io::Error> + Send>
+where
+    M: futures::Stream<Item = bytes::BytesMut, Error = io::Error>
+        + futures::Sink<SinkItem = bytes::Bytes, SinkError = io::Error>
+        + Send
+        + 'static,
+    D: Dispatch,
+{
+    use futures::Future;
+    use futures::Stream;
+
+    let (sink, stream) = messages.split();
+    let (sender, receiver) = mpsc::unbounded();
+    let calls: Calls = sync::Arc::new(sync::Mutex::new(collections::HashMap::new()));
+
+    let writer = receiver
+        .map(encode)
+        .map_err(|()| io::Error::other("response channel failed"))
+        .forward(sink)
+        .map(|_| ());
+
+    let reader_calls = calls.clone();
+    let reader = stream.for_each(move |buf| {
+        let frame: Frame = prost::Message::decode(buf.freeze())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let id = frame.id;
+
+        if frame.kind == Kind::Cancel as i32 {
+            if let Some(context) = lock(&reader_calls).remove(&id) {
+                context.cancel();
+            }
+            return Ok(());
+        } else if frame.kind != Kind::Request as i32 {
+            return Ok(());
+        }
+
+        let mut context = context::Context::with_metadata(frame.metadata.into_iter().collect());
+        if let Some(timeout_micros) = frame.timeout_micros {
+            context = context.with_timeout(time::Duration::from_micros(timeout_micros));
+        }
+        if let Some(peer_credentials) = peer_credentials {
+            context = context.with_peer_credentials(peer_credentials);
+        }
+        lock(&reader_calls).insert(id, context.clone());
+
+        let call = dispatch.dispatch(
+            &frame.service,
+            &frame.method,
+            frame.body.into(),
+            context.clone(),
+        );
+        let calls = reader_calls.clone();
+        let sender = sender.clone();
+        tokio::spawn(call.then(move |result| {
+            lock(&calls).remove(&id);
+            let frame = response(id, result, &context.trailers(), max_len);
+            let _ = sender.unbounded_send(frame);
+            Ok(())
+        }));
+        Ok(())
+    });
+
+    Box::new(reader.select(writer).then(move |result| {
+        for (_, context) in lock(&calls).drain() {
+            context.cancel();
+        }
+        result.map(|_| ()).map_err(|(error, _)| error)
+    }))
+}
+
+#[cfg(any(feature = "stdio", feature = "tcp", all(unix, feature = "unix")))]
+fn framed<T>(io: T) -> tokio::codec::Framed<T, tokio::codec::LengthDelimitedCodec>
+where
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite,
+{
+    let mut codec = tokio::codec::LengthDelimitedCodec::new();
+    codec.set_max_frame_length(MAX_MESSAGE_LEN);
+    tokio::codec::Framed::new(io, codec)
+}
+
+/// Builds the frame that answers a call.
+///
+/// A response that is larger than `max_len` is replaced with a `ResourceExhausted` error.  An error
+/// that is too large is sent without trailers and with a shortened message instead.
+fn response(
+    id: u64,
+    result: Result<bytes::Bytes, status::Status>,
+    trailers: &metadata::Metadata,
+    max_len: usize,
+) -> Frame {
+    let metadata = metadata_entries(trailers);
+    let status = match result {
+        Ok(body) => {
+            let frame = Frame {
+                id,
+                kind: Kind::Response as i32,
+                metadata: metadata.clone(),
+                body: body.to_vec(),
+                ..Frame::default()
+            };
+            let len = prost::Message::encoded_len(&frame);
+            if len <= max_len {
+                return frame;
+            }
+            let error = Error::TooLarge { len, max: max_len };
+            status::Status::new(status::Code::ResourceExhausted, error.to_string())
+        }
+        Err(status) => status,
+    };
+
+    let frame = Frame {
+        id,
+        kind: Kind::Error as i32,
+        metadata,
+        body: status.encode().to_vec(),
+        ..Frame::default()
+    };
+    if prost::Message::encoded_len(&frame) <= max_len {
+        return frame;
+    }
+    Frame {
+        id,
+        kind: Kind::Error as i32,
+        body: status
+            .truncated(MAX_ERROR_MESSAGE_LEN.min(max_len / 2))
+            .encode()
+            .to_vec(),
+        ..Frame::default()
+    }
+}
+
+fn encode(frame: Frame) -> bytes::Bytes {
+    let mut buf = bytes::BytesMut::with_capacity(prost::Message::encoded_len(&frame));
+    prost::Message::encode(&frame, &mut buf).expect("buffer has enough capacity");
+    buf.freeze()
+}
+
+fn lock<T>(mutex: &sync::Mutex<T>) -> sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(sync::PoisonError::into_inner)
+}
+
+fn metadata_entries(metadata: &metadata::Metadata) -> collections::BTreeMap<String, String> {
+    metadata
+        .iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect()
+}
@@ -0,0 +1,735 @@
+//! A transport that speaks the gRPC protocol over HTTP/2, for interoperating with gRPC clients and
+//! servers written in other languages.
+//!
+//! Calls are sent as `POST` requests to the path of their method, e.g. `/echo.Echo/Echo`, with the
+//! message in a length-prefixed body and the status of the call in `grpc-status` and
+//! `grpc-message` trailers:
+//!
+//! ```rust,ignore
+//! let future = grpc::connect(&addr).and_then(|connection| {
+//!     let client = schema::echo::EchoClient::new(grpc::Client::new(connection));
+//!     client.echo(schema::echo::EchoRequest { /* ... */ })
+//! });
+//!
+//! let listener = tokio::net::TcpListener::bind(&addr)?;
+//! tokio::run(grpc::serve(listener, schema::echo::EchoServer::new(EchoService)).map_err(|_| ()));
+//! ```
+//!
+//! Metadata is sent as HTTP headers, and trailers as HTTP trailers, so keys and values have to be
+//! valid header names and values; other entries are left out.  Compressed messages are not
+//! supported, and messages larger than `MAX_MESSAGE_LEN` are rejected with
+//! `Code::ResourceExhausted` before they are buffered.  Calls whose request is too large fail with
+//! `Error::TooLarge` without being sent.
+use std::fmt;
+use std::io;
+use std::marker;
+use std::mem;
+use std::net;
+use std::str;
+use std::time;
+
+use bytes;
+use futures;
+use h2;
+use http;
+use prost;
+use tokio;
+
+use context;
+use descriptor;
+use handler;
+use status;
+
+use super::accept;
+use super::headers;
+use super::Dispatch;
+use super::DispatchFuture;
+use super::Error;
+
+/// The future that results from a call to `connect`.
+pub type Connect = Box<dyn futures::Future<Item = Connection, Error = io::Error> + Send>;
+
+/// The future that results from a call to `serve`.
+pub type Serve = Box<dyn futures::Future<Item = (), Error = io::Error> + Send>;
+
+/// The largest request or response message that is accepted, in bytes.
+pub const MAX_MESSAGE_LEN: usize = 8 * 1024 * 1024;
+
+const CONTENT_TYPE: &str = "application/grpc";
+
+/// Headers that are part of the protocol, and therefore never treated as metadata.
+const RESERVED_HEADERS: &[&str] = &["content-type", "te", "user-agent", "content-length"];
+
+/// A HTTP/2 connection to a gRPC server, over which any number of calls can be made concurrently.
+///
+/// Connections are cheap to clone, and all clones share the same underlying connection, which is
+/// closed once all of them have been dropped.
+#[derive(Clone, Debug)]
+pub struct Connection {
+    send_request: h2::client::SendRequest<bytes::Bytes>,
+    authority: String,
+}
+
+/// A client for a specific service, making calls over a `Connection`.
+#[derive(Clone, Debug)]
+pub struct Client<D> {
+    connection: Connection,
+    descriptor: marker::PhantomData<D>,
+}
+
+/// The future that results from a call made over a `Connection`.
+///
+/// Dropping this future before it completes resets the HTTP/2 stream, which cancels the call on
+/// the server.
+pub struct CallFuture {
+    state: State,
+    context: context::Context,
+}
+
+enum State {
+    Failed(Error),
+    Ready(
+        h2::client::SendRequest<bytes::Bytes>,
+        Box<http::Request<()>>,
+        bytes::Bytes,
+    ),
+    Response(h2::client::ResponseFuture, h2::SendStream<bytes::Bytes>),
+    Body(
+        h2::RecvStream,
+        bytes::BytesMut,
+        h2::SendStream<bytes::Bytes>,
+    ),
+    Done,
+}
+
+struct ServerCall<D> {
+    state: ServerState,
+    respond: h2::server::SendResponse<bytes::Bytes>,
+    context: context::Context,
+    dispatch: D,
+    path: String,
+}
+
+enum ServerState {
+    Body(h2::RecvStream, bytes::BytesMut),
+    Call(DispatchFuture),
+    Done,
+}
+
+/// Connects to a gRPC server at the specified address, using HTTP/2 without TLS.
+///
+/// This must be polled on a tokio executor, which is used to drive the connection.
+pub fn connect(addr: &net::SocketAddr) -> Connect {
+    use futures::Future;
+
+    let authority = addr.to_string();
+    Box::new(
+        tokio::net::TcpStream::connect(addr)
+            .and_then(|socket| {
+                socket.set_nodelay(true)?;
+                Ok(socket)
+            })
+            .and_then(|socket| h2::client::handshake(socket).map_err(io_error))
+            .map(move |(send_request, connection)| {
+                tokio::spawn(connection.map_err(|_| ()));
+                Connection {
+                    send_request,
+                    authority,
+                }
+            }),
+    )
+}
+
+/// Serves gRPC calls from all connections accepted by the specified listener.
+///
+/// This must be polled on a tokio executor, which is used to handle connections and calls.  The
+/// returned future only completes if the listener fails; connections that fail to be set up are
+/// dropped without affecting the others.
+pub fn serve<D>(listener: tokio::net::TcpListener, dispatch: D) -> Serve
+where
+    D: Dispatch,
+{
+    use futures::Future;
+
+    Box::new(accept::for_each(listener.incoming(), move |socket| {
+        socket.set_nodelay(true)?;
+        tokio::spawn(serve_connection(socket, dispatch.clone()).map_err(|_| ()));
+        Ok(())
+    }))
+}
+
+fn serve_connection<T, D>(
+    io: T,
+    dispatch: D,
+) -> Box<dyn futures::Future<Item = (), Error = h2::Error> + Send>
+where
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static,
+    D: Dispatch,
+{
+    use futures::Future;
+    use futures::Stream;
+
+    Box::new(h2::server::handshake(io).and_then(move |connection| {
+        connection.for_each(move |(request, respond)| {
+            tokio::spawn(ServerCall::new(request, respond, dispatch.clone()));
+            Ok(())
+        })
+    }))
+}
+
+impl Connection {
+    /// Performs a raw call to the method at the specified path, e.g. `/echo.Echo/Echo`, in the
+    /// specified call context.
+    pub fn call(&self, path: &str, input: bytes::Bytes, context: context::Context) -> CallFuture {
+        let state = match self
+            .request(path, &context)
+            .and_then(|request| Ok((request, encode_message(input)?)))
+        {
+            Ok((request, input)) => {
+                State::Ready(self.send_request.clone(), Box::new(request), input)
+            }
+            Err(error) => State::Failed(error),
+        };
+        CallFuture { state, context }
+    }
+
+    fn request(&self, path: &str, context: &context::Context) -> Result<http::Request<()>, Error> {
+        let uri = format!("http://{}{}", self.authority, path)
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid method path"))?;
+
+        let mut request = http::Request::new(());
+        *request.method_mut() = http::Method::POST;
+        *request.uri_mut() = uri;
+        {
+            let headers = request.headers_mut();
+            headers.insert(
+                http::header::CONTENT_TYPE,
+                http::header::HeaderValue::from_static(CONTENT_TYPE),
+            );
+            headers.insert(
+                http::header::TE,
+                http::header::HeaderValue::from_static("trailers"),
+            );
+            if let Some(timeout) = context.timeout() {
+                if let Ok(value) = http::header::HeaderValue::from_str(&encode_timeout(timeout)) {
+                    headers.insert("grpc-timeout", value);
+                }
+            }
+            headers::insert_metadata(headers, context.metadata(), is_reserved);
+        }
+        Ok(request)
+    }
+}
+
+impl<D> Client<D>
+where
+    D: descriptor::ServiceDescriptor + 'static,
+{
+    /// Creates a new client that makes calls over the specified connection.
+    pub fn new(connection: Connection) -> Client<D> {
+        Client {
+            connection,
+            descriptor: marker::PhantomData,
+        }
+    }
+}
+
+impl<D> handler::Handler for Client<D>
+where
+    D: descriptor::ServiceDescriptor + 'static,
+{
+    type Error = Error;
+    type Descriptor = D;
+    type CallFuture = CallFuture;
+
+    fn call(
+        &self,
+        method: D::Method,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> Self::CallFuture {
+        self.connection.call(&D::path(&method), input, context)
+    }
+}
+
+impl CallFuture {
+    fn finish(
+        &self,
+        trailers: &http::HeaderMap,
+        body: bytes::BytesMut,
+    ) -> futures::Poll<bytes::Bytes, Error> {
+        self.context
+            .set_trailers(headers::extract_metadata(trailers, is_reserved));
+        match extract_status(trailers) {
+            Some(status) => Err(Error::Status { status }),
+            None => Ok(futures::Async::Ready(decode_message(body.freeze())?)),
+        }
+    }
+}
+
+impl futures::Future for CallFuture {
+    type Item = bytes::Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        use futures::Stream;
+
+        loop {
+            self.state = match mem::replace(&mut self.state, State::Done) {
+                State::Failed(error) => return Err(error),
+                State::Ready(mut send_request, request, input) => {
+                    match send_request.poll_ready().map_err(h2_error)? {
+                        futures::Async::Ready(()) => {}
+                        futures::Async::NotReady => {
+                            self.state = State::Ready(send_request, request, input);
+                            return Ok(futures::Async::NotReady);
+                        }
+                    }
+                    let (response, mut stream) = send_request
+                        .send_request(*request, false)
+                        .map_err(h2_error)?;
+                    stream.send_data(input, true).map_err(h2_error)?;
+                    State::Response(response, stream)
+                }
+                State::Response(mut response, stream) => {
+                    let response = match response.poll().map_err(h2_error)? {
+                        futures::Async::Ready(response) => response,
+                        futures::Async::NotReady => {
+                            self.state = State::Response(response, stream);
+                            return Ok(futures::Async::NotReady);
+                        }
+                    };
+                    let (parts, body) = response.into_parts();
+                    if parts.status != http::StatusCode::OK {
+                        let status = status::Status::new(
+                            http_status_code(parts.status),
+                            format!("Unexpected HTTP status {}", parts.status),
+                        );
+                        return Err(Error::Status { status });
+                    }
+                    // Failed calls may be answered with trailers only.
+                    if parts.headers.contains_key("grpc-status") {
+                        return self.finish(&parts.headers, bytes::BytesMut::new());
+                    }
+                    State::Body(body, bytes::BytesMut::new(), stream)
+                }
+                State::Body(mut body, mut buf, stream) => {
+                    loop {
+                        match body.poll().map_err(h2_error)? {
+                            futures::Async::Ready(Some(chunk)) => {
+                                let _ = body.release_capacity().release_capacity(chunk.len());
+                                if let Err(status) = buffer(&mut buf, &chunk) {
+                                    let mut stream = stream;
+                                    stream.send_reset(h2::Reason::CANCEL);
+                                    return Err(Error::Status { status });
+                                }
+                            }
+                            futures::Async::Ready(None) => break,
+                            futures::Async::NotReady => {
+                                self.state = State::Body(body, buf, stream);
+                                return Ok(futures::Async::NotReady);
+                            }
+                        }
+                    }
+                    match body.poll_trailers().map_err(h2_error)? {
+                        futures::Async::Ready(trailers) => {
+                            return self.finish(&trailers.unwrap_or_default(), buf);
+                        }
+                        futures::Async::NotReady => {
+                            self.state = State::Body(body, buf, stream);
+                            return Ok(futures::Async::NotReady);
+                        }
+                    }
+                }
+                State::Done => panic!("cannot poll a call after it has completed"),
+            }
+        }
+    }
+}
+
+impl Drop for CallFuture {
+    fn drop(&mut self) {
+        match self.state {
+            State::Response(_, ref mut stream) | State::Body(_, _, ref mut stream) => {
+                stream.send_reset(h2::Reason::CANCEL)
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Debug for CallFuture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CallFuture")
+            .field("context", &self.context)
+            .finish()
+    }
+}
+
+impl<D> ServerCall<D>
+where
+    D: Dispatch,
+{
+    fn new(
+        request: http::Request<h2::RecvStream>,
+        mut respond: h2::server::SendResponse<bytes::Bytes>,
+        dispatch: D,
+    ) -> ServerCall<D> {
+        let (parts, body) = request.into_parts();
+
+        let is_grpc = parts
+            .headers
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with(CONTENT_TYPE));
+        let state = if parts.method == http::Method::POST && is_grpc {
+            ServerState::Body(body, bytes::BytesMut::new())
+        } else {
+            let mut response = http::Response::new(());
+            *response.status_mut() = http::StatusCode::UNSUPPORTED_MEDIA_TYPE;
+            let _ = respond.send_response(response, true);
+            ServerState::Done
+        };
+
+        let mut context =
+            context::Context::with_metadata(headers::extract_metadata(&parts.headers, is_reserved));
+        let timeout = parts
+            .headers
+            .get("grpc-timeout")
+            .and_then(|value| value.to_str().ok())
+            .and_then(decode_timeout);
+        if let Some(timeout) = timeout {
+            context = context.with_timeout(timeout);
+        }
+
+        ServerCall {
+            state,
+            respond,
+            context,
+            dispatch,
+            path: parts.uri.path().to_owned(),
+        }
+    }
+
+    fn dispatch(&self, body: bytes::BytesMut) -> DispatchFuture {
+        let input = match decode_message(body.freeze()) {
+            Ok(input) => input,
+            Err(error) => {
+                let status = status::Status::new(status::Code::Internal, error.to_string());
+                return Box::new(futures::future::err(status));
+            }
+        };
+        let mut parts = self.path.splitn(3, '/');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(""), Some(service), Some(method)) => {
+                self.dispatch
+                    .dispatch(service, method, input, self.context.clone())
+            }
+            _ => {
+                let status = status::Status::new(
+                    status::Code::Unimplemented,
+                    format!("Unknown method path {:?}", self.path),
+                );
+                Box::new(futures::future::err(status))
+            }
+        }
+    }
+
+    fn respond(&mut self, result: Result<bytes::Bytes, status::Status>) {
+        use status::ToStatus;
+
+        let result = result.and_then(|output| encode_message(output).map_err(|e| e.to_status()));
+        let mut response = http::Response::new(());
+        response.headers_mut().insert(
+            http::header::CONTENT_TYPE,
+            http::header::HeaderValue::from_static(CONTENT_TYPE),
+        );
+
+        let mut trailers = http::HeaderMap::new();
+        headers::insert_metadata(&mut trailers, &self.context.trailers(), is_reserved);
+        match result {
+            Ok(output) => {
+                insert_status(&mut trailers, &status::Status::new(status::Code::Ok, ""));
+                if let Ok(mut stream) = self.respond.send_response(response, false) {
+                    let _ = stream.send_data(output, false);
+                    let _ = stream.send_trailers(trailers);
+                }
+            }
+            Err(status) => {
+                // Failed calls are answered with trailers only.
+                insert_status(&mut trailers, &status);
+                response.headers_mut().extend(trailers);
+                let _ = self.respond.send_response(response, true);
+            }
+        }
+    }
+}
+
+impl<D> futures::Future for ServerCall<D>
+where
+    D: Dispatch,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> futures::Poll<(), ()> {
+        use futures::Stream;
+
+        // The client resets the stream when it is no longer interested in the response.
+        match self.respond.poll_reset() {
+            Ok(futures::Async::NotReady) => {}
+            _ => {
+                self.context.cancel();
+                return Ok(futures::Async::Ready(()));
+            }
+        }
+
+        loop {
+            self.state = match mem::replace(&mut self.state, ServerState::Done) {
+                ServerState::Body(mut body, mut buf) => loop {
+                    match body.poll() {
+                        Ok(futures::Async::Ready(Some(chunk))) => {
+                            let _ = body.release_capacity().release_capacity(chunk.len());
+                            if let Err(status) = buffer(&mut buf, &chunk) {
+                                self.respond(Err(status));
+                                return Ok(futures::Async::Ready(()));
+                            }
+                        }
+                        Ok(futures::Async::Ready(None)) => {
+                            break ServerState::Call(self.dispatch(buf))
+                        }
+                        Ok(futures::Async::NotReady) => {
+                            self.state = ServerState::Body(body, buf);
+                            return Ok(futures::Async::NotReady);
+                        }
+                        Err(_) => {
+                            self.context.cancel();
+                            return Ok(futures::Async::Ready(()));
+                        }
+                    }
+                },
+                ServerState::Call(mut call) => {
+                    let result = match call.poll() {
+                        Ok(futures::Async::Ready(output)) => Ok(output),
+                        Ok(futures::Async::NotReady) => {
+                            self.state = ServerState::Call(call);
+                            return Ok(futures::Async::NotReady);
+                        }
+                        Err(status) => Err(status),
+                    };
+                    self.respond(result);
+                    return Ok(futures::Async::Ready(()));
+                }
+                ServerState::Done => return Ok(futures::Async::Ready(())),
+            }
+        }
+    }
+}
+
+/// Adds the length prefix to a message, failing if it is larger than `MAX_MESSAGE_LEN`.
+fn encode_message(message: bytes::Bytes) -> Result<bytes::Bytes, Error> {
+    use bytes::BufMut;
+
+    if message.len() > MAX_MESSAGE_LEN {
+        return Err(Error::TooLarge {
+            len: message.len(),
+            max: MAX_MESSAGE_LEN,
+        });
+    }
+    let mut buf = bytes::BytesMut::with_capacity(5 + message.len());
+    buf.put_u8(0);
+    buf.put_u32_be(message.len() as u32);
+    buf.put_slice(&message);
+    Ok(buf.freeze())
+}
+
+/// Adds a chunk of a body to the buffered part, failing if it carries a message that is larger than
+/// `MAX_MESSAGE_LEN`.
+fn buffer(buf: &mut bytes::BytesMut, chunk: &[u8]) -> Result<(), status::Status> {
+    let too_large = || {
+        status::Status::new(
+            status::Code::ResourceExhausted,
+            format!("message is larger than {} bytes", MAX_MESSAGE_LEN),
+        )
+    };
+    if buf.len() + chunk.len() > 5 + MAX_MESSAGE_LEN {
+        return Err(too_large());
+    }
+    buf.extend_from_slice(chunk);
+    // Look at the length prefix as soon as it is there, to fail before the message is buffered.
+    if buf.len() >= 5
+        && u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize > MAX_MESSAGE_LEN
+    {
+        return Err(too_large());
+    }
+    Ok(())
+}
+
+fn decode_message(mut buf: bytes::Bytes) -> Result<bytes::Bytes, prost::DecodeError> {
+    if buf.len() < 5 {
+        return Err(prost::DecodeError::new("truncated gRPC message"));
+    }
+    if buf[0] != 0 {
+        return Err(prost::DecodeError::new(
+            "compressed gRPC messages are not supported",
+        ));
+    }
+    let len = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+    if buf.len() != 5 + len {
+        return Err(prost::DecodeError::new("invalid gRPC message length"));
+    }
+    Ok(buf.split_off(5))
+}
+
+/// Encodes a timeout in the `grpc-timeout` format, using the most precise unit that fits in the
+/// allowed eight digits.
+fn encode_timeout(timeout: time::Duration) -> String {
+    const MAX: u128 = 99_999_999;
+
+    let micros = timeout.as_micros();
+    let units = [
+        (1, 'u'),
+        (1_000, 'm'),
+        (1_000_000, 'S'),
+        (60_000_000, 'M'),
+        (3_600_000_000, 'H'),
+    ];
+    for &(micros_per_unit, unit) in &units {
+        // Round up, so that the server never sees a later deadline than the client.
+        let value = micros.div_ceil(micros_per_unit);
+        if value <= MAX {
+            return format!("{}{}", value, unit);
+        }
+    }
+    format!("{}H", MAX)
+}
+
+fn decode_timeout(value: &str) -> Option<time::Duration> {
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+    let (digits, unit) = value.split_at(value.len() - 1);
+    let value: u64 = digits.parse().ok()?;
+    match unit {
+        "H" => Some(time::Duration::from_secs(value * 3600)),
+        "M" => Some(time::Duration::from_secs(value * 60)),
+        "S" => Some(time::Duration::from_secs(value)),
+        "m" => Some(time::Duration::from_millis(value)),
+        "u" => Some(time::Duration::from_micros(value)),
+        "n" => Some(time::Duration::from_nanos(value)),
+        _ => None,
+    }
+}
+
+fn is_reserved(name: &str) -> bool {
+    name.starts_with(':') || name.starts_with("grpc-") || RESERVED_HEADERS.contains(&name)
+}
+
+fn insert_status(headers: &mut http::HeaderMap, status: &status::Status) {
+    headers.insert("grpc-status", status.code().as_i32().into());
+    if !status.message().is_empty() {
+        let message = percent_encode(status.message());
+        if let Ok(value) = http::header::HeaderValue::from_str(&message) {
+            headers.insert("grpc-message", value);
+        }
+    }
+}
+
+/// Returns the status of a failed call, or `None` if the call succeeded.
+fn extract_status(headers: &http::HeaderMap) -> Option<status::Status> {
+    let code = match headers.get("grpc-status") {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .map_or(status::Code::Unknown, status::Code::from_i32),
+        None => {
+            return Some(status::Status::new(
+                status::Code::Unknown,
+                "Missing grpc-status",
+            ))
+        }
+    };
+    if code == status::Code::Ok {
+        return None;
+    }
+    let message = headers
+        .get("grpc-message")
+        .and_then(|value| value.to_str().ok())
+        .map(percent_decode)
+        .unwrap_or_default();
+    Some(status::Status::new(code, message))
+}
+
+/// Maps HTTP status codes of responses that didn't come from a gRPC server, as specified by gRPC.
+fn http_status_code(status: http::StatusCode) -> status::Code {
+    match status.as_u16() {
+        400 => status::Code::Internal,
+        401 => status::Code::Unauthenticated,
+        403 => status::Code::PermissionDenied,
+        404 => status::Code::Unimplemented,
+        429 | 502 | 503 | 504 => status::Code::Unavailable,
+        _ => status::Code::Unknown,
+    }
+}
+
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for &byte in message.as_bytes() {
+        if (0x20..0x7f).contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn percent_decode(message: &str) -> String {
+    let bytes = message.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn h2_error(error: h2::Error) -> Error {
+    if error.is_io() {
+        return Error::Io {
+            error: error.into_io().expect("error is an I/O error"),
+        };
+    }
+    let code = match error.reason() {
+        Some(h2::Reason::CANCEL) => status::Code::Cancelled,
+        Some(h2::Reason::REFUSED_STREAM) => status::Code::Unavailable,
+        _ => status::Code::Internal,
+    };
+    Error::Status {
+        status: status::Status::new(code, error.to_string()),
+    }
+}
+
+fn io_error(error: h2::Error) -> io::Error {
+    if error.is_io() {
+        error.into_io().expect("error is an I/O error")
+    } else {
+        io::Error::other(error)
+    }
+}
@@ -0,0 +1,35 @@
+//! Conversions between call metadata and HTTP headers, shared by the transports built on HTTP.
+use http;
+
+use metadata;
+
+/// Adds metadata entries to HTTP headers, leaving out the ones for which `reserved` returns `true`
+/// and the ones that aren't valid header names and values.
+pub(crate) fn insert_metadata(
+    headers: &mut http::HeaderMap,
+    metadata: &metadata::Metadata,
+    reserved: fn(&str) -> bool,
+) {
+    for (key, value) in metadata {
+        if reserved(key) {
+            continue;
+        }
+        let name = http::header::HeaderName::from_bytes(key.as_bytes());
+        let value = http::header::HeaderValue::from_str(value);
+        if let (Ok(name), Ok(value)) = (name, value) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+/// Collects HTTP headers into metadata, leaving out the ones for which `reserved` returns `true`.
+pub(crate) fn extract_metadata(
+    headers: &http::HeaderMap,
+    reserved: fn(&str) -> bool,
+) -> metadata::Metadata {
+    headers
+        .iter()
+        .filter(|(name, _)| !reserved(name.as_str()))
+        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+        .collect()
+}
@@ -0,0 +1,262 @@
+//! Ready-made transports for clients and servers.
+//!
+//! Each transport is enabled by a cargo feature of the same name:
+//!
+//!   - `channel`: in-process channels, handling calls on a separate server task.
+//!   - `grpc`: the gRPC protocol over HTTP/2, for talking to gRPC implementations in other
+//!     languages.
+//!   - `quic`: one QUIC stream per call, so that lost packets only hold up the call they belong to.
+//!   - `shm`: the same frames over shared-memory ring buffers, for fast calls on one Linux host.
+//!   - `stdio`: length-prefixed frames over the stdin and stdout of a child process, for plugins.
+//!   - `tcp`: length-prefixed frames over TCP, multiplexing concurrent calls over one connection.
+//!   - `twirp`: the Twirp protocol over HTTP/1.1, for Twirp clients in other languages and `curl`.
+//!   - `udp`: one datagram per message over UDP, retransmitting small calls that can tolerate
+//!     loss.
+//!   - `unix`: the same frames over Unix domain sockets, telling services who the caller is.
+//!   - `websocket`: the same frames in binary WebSocket messages, for browsers and proxies that
+//!     only let HTTP through.
+//!
+//! Stream transports share the same framing, so a single server can listen on several of them at
+//! once by serving clones of the same `Dispatch`.
+//!
+//! On the server side, transports feed incoming calls into anything that implements `Dispatch`,
+//! which includes every `Handler` (for example a generated `FooServer`) and `router::Router`.
+use std::io;
+
+use bytes;
+use futures;
+use prost;
+
+use context;
+use descriptor;
+use error;
+use handler;
+use router;
+use status;
+
+#[cfg(any(
+    feature = "grpc",
+    feature = "tcp",
+    feature = "twirp",
+    feature = "websocket",
+    all(target_os = "linux", feature = "shm"),
+    all(unix, feature = "unix")
+))]
+mod accept;
+#[cfg(feature = "channel")]
+pub mod channel;
+#[cfg(any(
+    feature = "stdio",
+    feature = "tcp",
+    feature = "websocket",
+    all(target_os = "linux", feature = "shm"),
+    all(unix, feature = "unix")
+))]
+mod framed;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(any(feature = "grpc", feature = "twirp"))]
+mod headers;
+#[cfg(feature = "quic")]
+pub mod quic;
+#[cfg(all(target_os = "linux", feature = "shm"))]
+pub mod shm;
+#[cfg(feature = "stdio")]
+pub mod stdio;
+#[cfg(feature = "tcp")]
+pub mod tcp;
+#[cfg(feature = "twirp")]
+pub mod twirp;
+#[cfg(feature = "udp")]
+pub mod udp;
+#[cfg(all(unix, feature = "unix"))]
+pub mod unix;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+#[cfg(any(
+    feature = "stdio",
+    feature = "tcp",
+    feature = "websocket",
+    all(target_os = "linux", feature = "shm"),
+    all(unix, feature = "unix")
+))]
+pub use self::framed::CallFuture;
+#[cfg(any(
+    feature = "stdio",
+    feature = "tcp",
+    feature = "websocket",
+    all(target_os = "linux", feature = "shm"),
+    all(unix, feature = "unix")
+))]
+pub use self::framed::Client;
+#[cfg(any(
+    feature = "stdio",
+    feature = "tcp",
+    feature = "websocket",
+    all(target_os = "linux", feature = "shm"),
+    all(unix, feature = "unix")
+))]
+pub use self::framed::Connection;
+
+/// The future that results from a call to `Dispatch::dispatch`.
+pub type DispatchFuture =
+    Box<dyn futures::Future<Item = bytes::Bytes, Error = status::Status> + Send>;
+
+/// Something that can handle raw calls identified by the names of their service and method.
+///
+/// Errors are described by a `status::Status`, so that they can be sent back to the client.
+pub trait Dispatch: Clone + Send + 'static {
+    /// Handles a raw call to the specified service and method, in the specified call context.
+    ///
+    /// The service name is the fully qualified protobuf name, e.g. `echo.Echo`, and the method name
+    /// is the raw protobuf name, e.g. `Echo`.
+    fn dispatch(
+        &self,
+        service: &str,
+        method: &str,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> DispatchFuture;
+
+    /// Whether calls to the specified service and method are dispatched to a handler at all.
+    ///
+    /// Transports whose protocol tells unknown routes apart from failed calls use this before
+    /// dispatching.  The default claims every route, so that unknown ones fail when dispatched.
+    fn has_method(&self, _service: &str, _method: &str) -> bool {
+        true
+    }
+}
+
+/// An error that occurred while making a call over a transport.
+#[derive(Debug, Fail)]
+pub enum Error {
+    /// The server handled the call, but it failed.
+    #[fail(display = "Remote error: {}", status)]
+    Status {
+        /// The status that the server responded with.
+        #[cause]
+        status: status::Status,
+    },
+    /// The connection was closed before a response was received.
+    #[fail(display = "Connection closed")]
+    Closed,
+    /// An I/O error occurred on the connection.
+    #[fail(display = "I/O error: {}", error)]
+    Io {
+        /// The underlying I/O error.
+        #[cause]
+        error: io::Error,
+    },
+    /// The peer sent something that could not be decoded.
+    #[fail(display = "Protocol error: {}", error)]
+    Protocol {
+        /// The underlying decode error.
+        #[cause]
+        error: prost::DecodeError,
+    },
+    /// A message was too large to be sent by a transport that limits the size of messages.
+    #[fail(
+        display = "Message of {} bytes exceeds the limit of {} bytes",
+        len, max
+    )]
+    TooLarge {
+        /// The size of the message, in bytes.
+        len: usize,
+        /// The largest size that the transport can send, in bytes.
+        max: usize,
+    },
+    /// No response was received, even after retransmitting the call as often as allowed.
+    #[fail(display = "No response received")]
+    Unanswered,
+}
+
+impl<H> Dispatch for H
+where
+    H: handler::Handler,
+    H::Error: status::ToStatus,
+    H::CallFuture: 'static,
+{
+    fn dispatch(
+        &self,
+        service: &str,
+        method: &str,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> DispatchFuture {
+        use futures::Future;
+        use status::ToStatus;
+
+        if service != <H::Descriptor as descriptor::ServiceDescriptor>::full_name() {
+            let error = error::Error::<H::Error>::unknown_service(service);
+            return Box::new(futures::future::err(error.to_status()));
+        }
+        match descriptor::MethodDescriptor::from_proto_name(method) {
+            Some(method) => Box::new(
+                handler::Handler::call(self, method, input, context).map_err(|e| e.to_status()),
+            ),
+            None => {
+                let error = error::Error::<H::Error>::unknown_method(service, method);
+                Box::new(futures::future::err(error.to_status()))
+            }
+        }
+    }
+
+    fn has_method(&self, service: &str, method: &str) -> bool {
+        use descriptor::MethodDescriptor;
+        use descriptor::ServiceDescriptor;
+
+        service == H::Descriptor::full_name()
+            && <H::Descriptor as ServiceDescriptor>::Method::from_proto_name(method).is_some()
+    }
+}
+
+impl Dispatch for router::Router {
+    fn dispatch(
+        &self,
+        service: &str,
+        method: &str,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> DispatchFuture {
+        use futures::Future;
+        use status::ToStatus;
+
+        Box::new(
+            self.call(service, method, input, context)
+                .map_err(|e| e.to_status()),
+        )
+    }
+
+    fn has_method(&self, service: &str, method: &str) -> bool {
+        router::Router::has_method(self, service, method)
+    }
+}
+
+impl status::ToStatus for Error {
+    fn to_status(&self) -> status::Status {
+        match *self {
+            Error::Status { ref status } => status.clone(),
+            Error::Closed | Error::Io { .. } => {
+                status::Status::new(status::Code::Unavailable, self.to_string())
+            }
+            Error::Protocol { .. } => status::Status::new(status::Code::Internal, self.to_string()),
+            Error::TooLarge { .. } => {
+                status::Status::new(status::Code::ResourceExhausted, self.to_string())
+            }
+            Error::Unanswered => status::Status::new(status::Code::Unavailable, self.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io { error }
+    }
+}
+
+impl From<prost::DecodeError> for Error {
+    fn from(error: prost::DecodeError) -> Self {
+        Error::Protocol { error }
+    }
+}
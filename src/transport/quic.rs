@@ -0,0 +1,957 @@
+//! A transport over QUIC, which sends every call over its own bidirectional stream.
+//!
+//! Calls on the same connection don't hold each other up: a lost packet only delays the call whose
+//! stream it belongs to, whereas with the `tcp` transport, it delays every call on the connection.
+//! QUIC connections are always encrypted, so clients and servers are configured for TLS with the
+//! configuration types of `quinn`:
+//!
+//! ```rust,ignore
+//! let config = quinn::ServerConfig::with_single_cert(vec![cert.clone()], key)?;
+//! let listener = quic::Listener::bind(&addr, config)?;
+//! tokio::spawn(quic::serve(listener, schema::echo::EchoServer::new(EchoService)).map_err(|_| ()));
+//!
+//! let mut roots = quinn::rustls::RootCertStore::empty();
+//! roots.add(cert)?;
+//! let config = quinn::ClientConfig::with_root_certificates(Arc::new(roots))?;
+//! let future = quic::connect(&addr, "localhost", config).and_then(|connection| {
+//!     let client = schema::echo::EchoClient::new(quic::Client::new(connection));
+//!     client.echo(schema::echo::EchoRequest { /* ... */ })
+//! });
+//! ```
+//!
+//! Each direction of a stream carries a single protobuf envelope, after which it is finished.
+//! Dropping a call stops its stream, which cancels the call on the server.
+//!
+//! QUIC itself is driven by a runtime on background threads, so calls can be made from any
+//! executor.  Servers still have to be polled on a tokio executor, which is used to handle calls.
+use std::collections;
+use std::fmt;
+use std::future;
+use std::io;
+use std::marker;
+use std::mem;
+use std::net;
+use std::pin;
+use std::sync;
+use std::task;
+use std::time;
+
+use bytes;
+use futures;
+use futures::sync::mpsc;
+use futures::sync::oneshot;
+use prost;
+use quinn;
+use tokio;
+use tokio1;
+
+use context;
+use descriptor;
+use handler;
+use metadata;
+use status;
+
+use super::Dispatch;
+use super::Error;
+
+/// The largest request or response that can be sent over a stream, in bytes.
+pub const MAX_MESSAGE_LEN: usize = 8 * 1024 * 1024;
+
+/// How much of the message of an error is kept if the full error is too large to be sent.
+const MAX_ERROR_MESSAGE_LEN: usize = 1024;
+
+/// The error code with which the streams of dropped calls are stopped.
+const CANCELLED: u32 = 1;
+
+/// The future that results from a call to `connect`.
+pub type Connect = Box<dyn futures::Future<Item = Connection, Error = io::Error> + Send>;
+
+/// The future that results from a call to `serve`.
+pub type Serve = Box<dyn futures::Future<Item = (), Error = io::Error> + Send>;
+
+/// A connection to a server, over which any number of calls can be made concurrently.
+///
+/// Connections are cheap to clone, and all clones share the same QUIC connection, which is closed
+/// once all of them and all of their calls have been dropped.
+#[derive(Clone)]
+pub struct Connection {
+    shared: sync::Arc<Shared>,
+}
+
+/// A client for a specific service, making calls over a `Connection`.
+#[derive(Clone, Debug)]
+pub struct Client<D> {
+    connection: Connection,
+    descriptor: marker::PhantomData<D>,
+}
+
+/// The future that results from a call made over a `Connection`.
+///
+/// Dropping this future before it completes tells the server to cancel the call.
+pub struct CallFuture {
+    context: context::Context,
+    /// Cancelled when this future is dropped, which stops the stream of the call.
+    remote: context::Context,
+    state: State,
+}
+
+/// A QUIC endpoint that accepts connections, which can be served with `serve`.
+pub struct Listener {
+    endpoint: quinn::Endpoint,
+}
+
+enum State {
+    Failed(Error),
+    Waiting(oneshot::Receiver<Result<Envelope, Error>>),
+    Done,
+}
+
+struct Shared {
+    connection: quinn::Connection,
+    /// Keeps the endpoint open for as long as the connection is in use.
+    _endpoint: quinn::Endpoint,
+    calls: tokio1::sync::mpsc::UnboundedSender<Call>,
+}
+
+/// A call that is waiting for its stream to be opened.
+struct Call {
+    request: bytes::Bytes,
+    cancelled: context::Cancelled,
+    response: oneshot::Sender<Result<Envelope, Error>>,
+}
+
+/// Opens a stream for every call made over a connection, in the order that the calls were made.
+struct OpenStreams {
+    calls: tokio1::sync::mpsc::UnboundedReceiver<Call>,
+    call: Option<Call>,
+    open: Repeat<quinn::Connection, quinn::OpenBi<'static>>,
+}
+
+/// Sends the request of a call over its stream, and reads the response.
+struct Exchange {
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+    request: bytes::Bytes,
+    finished: bool,
+    buf: Vec<u8>,
+    cancelled: context::Cancelled,
+    response: Option<oneshot::Sender<Result<Envelope, Error>>>,
+}
+
+/// Handles the calls that arrive over a listener on a tokio executor.
+struct Server<D> {
+    endpoint: quinn::Endpoint,
+    requests: mpsc::UnboundedReceiver<Request>,
+    dispatch: D,
+}
+
+/// Accepts connections from an endpoint, until the endpoint is closed.
+struct AcceptConnections {
+    accept: Repeat<quinn::Endpoint, quinn::Accept<'static>>,
+    requests: mpsc::UnboundedSender<Request>,
+}
+
+/// Accepts the streams of a connection once it has been established, until the connection is
+/// closed.
+struct AcceptStreams {
+    connecting: pin::Pin<Box<quinn::IncomingFuture>>,
+    accept: Option<Repeat<quinn::Connection, quinn::AcceptBi<'static>>>,
+    requests: mpsc::UnboundedSender<Request>,
+}
+
+/// A call that has arrived at a server, along with the stream to respond over.
+struct Request {
+    envelope: Envelope,
+    send: quinn::SendStream,
+}
+
+/// Reads the request of a call from its stream.
+struct ReadRequest {
+    send: Option<quinn::SendStream>,
+    recv: quinn::RecvStream,
+    buf: Vec<u8>,
+    requests: mpsc::UnboundedSender<Request>,
+}
+
+/// Writes the response of a call to its stream, and finishes the stream.
+struct WriteResponse {
+    send: quinn::SendStream,
+    response: bytes::Bytes,
+}
+
+/// Cancels a call once the client stops its stream, or the connection is lost.
+struct WatchStopped {
+    stopped: pin::Pin<Box<dyn future::Future<Output = Stopped> + Send>>,
+    context: context::Context,
+}
+
+type Stopped = Result<Option<quinn::VarInt>, quinn::StoppedError>;
+
+/// Runs a future on the QUIC runtime, and sends its output to the other executor.
+struct Forward<F>
+where
+    F: future::Future,
+{
+    future: pin::Pin<Box<F>>,
+    output: Option<oneshot::Sender<F::Output>>,
+}
+
+/// Repeatedly polls futures that borrow the value they were made from, like the ones returned by
+/// `quinn::Endpoint::accept`, so that they can be polled by tasks, which must own what they use.
+///
+/// Owning such futures would take `async` blocks, which this crate's edition doesn't have.
+struct Repeat<T, F>
+where
+    T: 'static,
+{
+    /// Declared before `owner`, so that it is dropped first.
+    future: Option<pin::Pin<Box<F>>>,
+    make: fn(&'static T) -> F,
+    owner: sync::Arc<T>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Envelope {
+    #[prost(enumeration = "Kind", tag = "2")]
+    kind: i32,
+    #[prost(string, tag = "3")]
+    service: String,
+    #[prost(string, tag = "4")]
+    method: String,
+    #[prost(btree_map = "string, string", tag = "5")]
+    metadata: collections::BTreeMap<String, String>,
+    #[prost(uint64, optional, tag = "6")]
+    timeout_micros: Option<u64>,
+    #[prost(bytes, tag = "7")]
+    body: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Enumeration)]
+enum Kind {
+    Request = 0,
+    Response = 1,
+    Error = 2,
+}
+
+static RUNTIME: sync::OnceLock<tokio1::runtime::Runtime> = sync::OnceLock::new();
+
+/// Connects to a server at the specified address, which must present a certificate for
+/// `server_name` that `config` trusts.
+///
+/// The returned future can be polled on any executor.
+pub fn connect(addr: &net::SocketAddr, server_name: &str, config: quinn::ClientConfig) -> Connect {
+    use futures::Future;
+
+    let connecting = {
+        let _runtime = runtime().enter();
+        let local = if addr.is_ipv4() {
+            net::SocketAddr::from(([0, 0, 0, 0], 0))
+        } else {
+            net::SocketAddr::from(([0; 16], 0))
+        };
+        quinn::Endpoint::client(local).and_then(|endpoint| {
+            endpoint
+                .connect_with(config, *addr, server_name)
+                .map(|connecting| (endpoint, connecting))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+        })
+    };
+    let (endpoint, connecting) = match connecting {
+        Ok(connecting) => connecting,
+        Err(error) => return Box::new(futures::future::err(error)),
+    };
+
+    Box::new(spawn(connecting).then(move |result| match result {
+        Ok(Ok(connection)) => Ok(Connection::new(connection, endpoint)),
+        Ok(Err(error)) => Err(io::Error::other(error)),
+        Err(oneshot::Canceled) => Err(io::Error::other("QUIC runtime stopped")),
+    }))
+}
+
+/// Serves calls from all connections accepted by the specified listener.
+///
+/// This must be polled on a tokio executor, which is used to handle calls.  The returned future
+/// only completes once the endpoint of the listener has been closed, and dropping it closes the
+/// endpoint.
+#[allow(unsafe_code)]
+pub fn serve<D>(listener: Listener, dispatch: D) -> Serve
+where
+    D: Dispatch,
+{
+    let (sender, requests) = mpsc::unbounded();
+    let endpoint = listener.endpoint.clone();
+    runtime().spawn(AcceptConnections {
+        // SAFETY: accepting only borrows the endpoint while it waits, and the `quinn::Incoming`
+        // that it outputs owns everything it uses.
+        accept: unsafe { Repeat::new(listener.endpoint, quinn::Endpoint::accept) },
+        requests: sender,
+    });
+
+    Box::new(Server {
+        endpoint,
+        requests,
+        dispatch,
+    })
+}
+
+impl Connection {
+    #[allow(unsafe_code)]
+    fn new(connection: quinn::Connection, endpoint: quinn::Endpoint) -> Connection {
+        // Opening a stream may have to wait for the server to allow more streams, which is done by
+        // a single task, so that streams are still opened in the order that calls were made.
+        let (calls, receiver) = tokio1::sync::mpsc::unbounded_channel();
+        runtime().spawn(OpenStreams {
+            calls: receiver,
+            call: None,
+            // SAFETY: opening only borrows the connection while it waits, and the streams that it
+            // outputs own everything they use.
+            open: unsafe { Repeat::new(connection.clone(), quinn::Connection::open_bi) },
+        });
+
+        Connection {
+            shared: sync::Arc::new(Shared {
+                connection,
+                _endpoint: endpoint,
+                calls,
+            }),
+        }
+    }
+
+    /// Performs a raw call to the specified service and method, in the specified call context.
+    pub fn call(
+        &self,
+        service: &str,
+        method: &str,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> CallFuture {
+        let remote = context::Context::new();
+        let request = Envelope {
+            kind: Kind::Request as i32,
+            service: service.to_owned(),
+            method: method.to_owned(),
+            metadata: metadata_entries(context.metadata()),
+            timeout_micros: context.timeout().map(|timeout| {
+                let micros = timeout.as_micros();
+                if micros > u128::from(u64::MAX) {
+                    u64::MAX
+                } else {
+                    micros as u64
+                }
+            }),
+            body: input.to_vec(),
+        };
+
+        let state = match encode(&request) {
+            Ok(request) => {
+                let (response, receiver) = oneshot::channel();
+                let call = Call {
+                    request,
+                    cancelled: remote.cancelled(),
+                    response,
+                };
+                if self.shared.calls.send(call).is_ok() {
+                    State::Waiting(receiver)
+                } else {
+                    State::Failed(Error::Closed)
+                }
+            }
+            Err(error) => State::Failed(error),
+        };
+        CallFuture {
+            context,
+            remote,
+            state,
+        }
+    }
+
+    /// Whether the connection has been closed, in which case all calls fail with `Error::Closed`.
+    pub fn is_closed(&self) -> bool {
+        self.shared.connection.close_reason().is_some()
+    }
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("remote_address", &self.shared.connection.remote_address())
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+impl<D> Client<D>
+where
+    D: descriptor::ServiceDescriptor + 'static,
+{
+    /// Creates a new client that makes calls over the specified connection.
+    pub fn new(connection: Connection) -> Client<D> {
+        Client {
+            connection,
+            descriptor: marker::PhantomData,
+        }
+    }
+}
+
+impl<D> handler::Handler for Client<D>
+where
+    D: descriptor::ServiceDescriptor + 'static,
+{
+    type Error = Error;
+    type Descriptor = D;
+    type CallFuture = CallFuture;
+
+    fn call(
+        &self,
+        method: D::Method,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> Self::CallFuture {
+        let method = descriptor::MethodDescriptor::proto_name(&method);
+        self.connection
+            .call(&D::full_name(), method, input, context)
+    }
+}
+
+impl futures::Future for CallFuture {
+    type Item = bytes::Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let result = match self.state {
+            State::Failed(_) => None,
+            State::Waiting(ref mut receiver) => match receiver.poll() {
+                Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+                Ok(futures::Async::Ready(result)) => Some(result),
+                Err(oneshot::Canceled) => Some(Err(Error::Closed)),
+            },
+            State::Done => panic!("cannot poll a call after it has completed"),
+        };
+
+        let envelope = match (mem::replace(&mut self.state, State::Done), result) {
+            (State::Failed(error), _) => return Err(error),
+            (_, Some(result)) => result?,
+            (_, None) => unreachable!(),
+        };
+        self.context
+            .set_trailers(envelope.metadata.into_iter().collect());
+        if envelope.kind == Kind::Response as i32 {
+            Ok(futures::Async::Ready(envelope.body.into()))
+        } else {
+            let status = status::Status::decode(envelope.body.into())?;
+            Err(Error::Status { status })
+        }
+    }
+}
+
+impl Drop for CallFuture {
+    fn drop(&mut self) {
+        if let State::Waiting(_) = self.state {
+            self.remote.cancel();
+        }
+    }
+}
+
+impl fmt::Debug for CallFuture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Failed(_) => "failed",
+            State::Waiting(_) => "waiting",
+            State::Done => "done",
+        };
+        f.debug_struct("CallFuture").field("state", &state).finish()
+    }
+}
+
+impl Listener {
+    /// Creates an endpoint that accepts connections on the specified address.
+    pub fn bind(addr: &net::SocketAddr, config: quinn::ServerConfig) -> io::Result<Listener> {
+        let _runtime = runtime().enter();
+        let endpoint = quinn::Endpoint::server(config, *addr)?;
+        Ok(Listener { endpoint })
+    }
+
+    /// The local address that this listener is bound to.
+    pub fn local_addr(&self) -> io::Result<net::SocketAddr> {
+        self.endpoint.local_addr()
+    }
+}
+
+impl fmt::Debug for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Listener")
+            .field("local_addr", &self.local_addr().ok())
+            .finish()
+    }
+}
+
+impl future::Future for OpenStreams {
+    type Output = ();
+
+    fn poll(self: pin::Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<()> {
+        let this = self.get_mut();
+        loop {
+            let call = match this.call.take() {
+                Some(call) => call,
+                None => match this.calls.poll_recv(cx) {
+                    task::Poll::Ready(Some(call)) => call,
+                    // Every clone of the connection has been dropped.
+                    task::Poll::Ready(None) => return task::Poll::Ready(()),
+                    task::Poll::Pending => return task::Poll::Pending,
+                },
+            };
+            if call.response.is_canceled() {
+                continue;
+            }
+            match this.open.poll_next(cx) {
+                task::Poll::Ready(Ok((send, recv))) => {
+                    runtime().spawn(Exchange {
+                        send,
+                        recv,
+                        request: call.request,
+                        finished: false,
+                        buf: Vec::new(),
+                        cancelled: call.cancelled,
+                        response: Some(call.response),
+                    });
+                }
+                task::Poll::Ready(Err(_)) => {
+                    let _ = call.response.send(Err(Error::Closed));
+                }
+                task::Poll::Pending => {
+                    this.call = Some(call);
+                    return task::Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+impl future::Future for Exchange {
+    type Output = ();
+
+    fn poll(self: pin::Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<()> {
+        let this = self.get_mut();
+        if future::Future::poll(pin::Pin::new(&mut this.cancelled), cx).is_ready() {
+            let _ = this.send.reset(quinn::VarInt::from_u32(CANCELLED));
+            let _ = this.recv.stop(quinn::VarInt::from_u32(CANCELLED));
+            return task::Poll::Ready(());
+        }
+
+        let result = match this.poll_exchange(cx) {
+            task::Poll::Ready(result) => result,
+            task::Poll::Pending => return task::Poll::Pending,
+        };
+        if let Some(response) = this.response.take() {
+            let _ = response.send(result);
+        }
+        task::Poll::Ready(())
+    }
+}
+
+impl Exchange {
+    fn poll_exchange(&mut self, cx: &mut task::Context) -> task::Poll<Result<Envelope, Error>> {
+        // The server only responds once it has read the whole request.
+        while !self.request.is_empty() {
+            match pin::Pin::new(&mut self.send).poll_write(cx, &self.request) {
+                task::Poll::Ready(Ok(written)) => {
+                    let _ = self.request.split_to(written);
+                }
+                task::Poll::Ready(Err(error)) => return task::Poll::Ready(Err(write_error(error))),
+                task::Poll::Pending => return task::Poll::Pending,
+            }
+        }
+        if !self.finished {
+            self.finished = true;
+            let _ = self.send.finish();
+        }
+
+        match poll_read_to_end(&mut self.recv, &mut self.buf, cx) {
+            task::Poll::Ready(Ok(())) => task::Poll::Ready(
+                prost::Message::decode(&self.buf[..]).map_err(|error| Error::Protocol { error }),
+            ),
+            task::Poll::Ready(Err(error)) => task::Poll::Ready(Err(error)),
+            task::Poll::Pending => task::Poll::Pending,
+        }
+    }
+}
+
+impl<D> futures::Future for Server<D>
+where
+    D: Dispatch,
+{
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> futures::Poll<(), io::Error> {
+        use futures::Stream;
+
+        loop {
+            match self.requests.poll() {
+                Ok(futures::Async::Ready(Some(request))) => self.handle(request),
+                Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+                Ok(futures::Async::Ready(None)) | Err(()) => return Ok(futures::Async::Ready(())),
+            }
+        }
+    }
+}
+
+impl<D> Server<D>
+where
+    D: Dispatch,
+{
+    fn handle(&self, request: Request) {
+        use futures::Future;
+
+        let Request { envelope, send } = request;
+        let mut context = context::Context::with_metadata(envelope.metadata.into_iter().collect());
+        if let Some(timeout_micros) = envelope.timeout_micros {
+            context = context.with_timeout(time::Duration::from_micros(timeout_micros));
+        }
+        runtime().spawn(WatchStopped {
+            stopped: Box::pin(send.stopped()),
+            context: context.clone(),
+        });
+
+        let call = self.dispatch.dispatch(
+            &envelope.service,
+            &envelope.method,
+            envelope.body.into(),
+            context.clone(),
+        );
+        tokio::spawn(call.then(move |result| {
+            let metadata = metadata_entries(&context.trailers());
+            let response = match result {
+                Ok(body) => encode(&Envelope {
+                    kind: Kind::Response as i32,
+                    metadata: metadata.clone(),
+                    body: body.to_vec(),
+                    ..Envelope::default()
+                }),
+                Err(status) => Ok(error_response(&status, metadata.clone())),
+            };
+            let response = response.unwrap_or_else(|error| {
+                let status =
+                    status::Status::new(status::Code::ResourceExhausted, error.to_string());
+                error_response(&status, metadata)
+            });
+            runtime().spawn(WriteResponse { send, response });
+            Ok(())
+        }));
+    }
+}
+
+impl<D> Drop for Server<D> {
+    fn drop(&mut self) {
+        self.endpoint.close(quinn::VarInt::from_u32(0), b"");
+    }
+}
+
+impl future::Future for AcceptConnections {
+    type Output = ();
+
+    fn poll(self: pin::Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<()> {
+        let this = self.get_mut();
+        loop {
+            match this.accept.poll_next(cx) {
+                task::Poll::Ready(Some(incoming)) => {
+                    runtime().spawn(AcceptStreams {
+                        connecting: Box::pin(future::IntoFuture::into_future(incoming)),
+                        accept: None,
+                        requests: this.requests.clone(),
+                    });
+                }
+                task::Poll::Ready(None) => return task::Poll::Ready(()),
+                task::Poll::Pending => return task::Poll::Pending,
+            }
+        }
+    }
+}
+
+#[allow(unsafe_code)]
+impl future::Future for AcceptStreams {
+    type Output = ();
+
+    fn poll(self: pin::Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<()> {
+        let this = self.get_mut();
+        if this.accept.is_none() {
+            match this.connecting.as_mut().poll(cx) {
+                task::Poll::Ready(Ok(connection)) => {
+                    // SAFETY: accepting only borrows the connection while it waits, and the streams
+                    // that it outputs own everything they use.
+                    let accept = quinn::Connection::accept_bi;
+                    this.accept = Some(unsafe { Repeat::new(connection, accept) });
+                }
+                task::Poll::Ready(Err(_)) => return task::Poll::Ready(()),
+                task::Poll::Pending => return task::Poll::Pending,
+            }
+        }
+
+        let accept = this.accept.as_mut().expect("connection is established");
+        loop {
+            match accept.poll_next(cx) {
+                task::Poll::Ready(Ok((send, recv))) => {
+                    if this.requests.is_closed() {
+                        return task::Poll::Ready(());
+                    }
+                    runtime().spawn(ReadRequest {
+                        send: Some(send),
+                        recv,
+                        buf: Vec::new(),
+                        requests: this.requests.clone(),
+                    });
+                }
+                task::Poll::Ready(Err(_)) => return task::Poll::Ready(()),
+                task::Poll::Pending => return task::Poll::Pending,
+            }
+        }
+    }
+}
+
+impl future::Future for ReadRequest {
+    type Output = ();
+
+    fn poll(self: pin::Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<()> {
+        let this = self.get_mut();
+        let result = match poll_read_to_end(&mut this.recv, &mut this.buf, cx) {
+            task::Poll::Ready(result) => result,
+            task::Poll::Pending => return task::Poll::Pending,
+        };
+        let send = this
+            .send
+            .take()
+            .expect("cannot poll a request after it has been read");
+
+        let status = match result.map(|()| prost::Message::decode(&this.buf[..])) {
+            Ok(Ok(envelope)) => {
+                let _ = this.requests.unbounded_send(Request { envelope, send });
+                return task::Poll::Ready(());
+            }
+            Ok(Err(error)) => {
+                status::Status::new(status::Code::InvalidArgument, format!("{}", error))
+            }
+            Err(Error::TooLarge { .. }) => {
+                let _ = this.recv.stop(quinn::VarInt::from_u32(0));
+                status::Status::new(status::Code::ResourceExhausted, "request is too large")
+            }
+            // The client went away, so there is nobody to respond to.
+            Err(_) => return task::Poll::Ready(()),
+        };
+        runtime().spawn(WriteResponse {
+            send,
+            response: error_response(&status, collections::BTreeMap::new()),
+        });
+        task::Poll::Ready(())
+    }
+}
+
+impl future::Future for WriteResponse {
+    type Output = ();
+
+    fn poll(self: pin::Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<()> {
+        let this = self.get_mut();
+        while !this.response.is_empty() {
+            match pin::Pin::new(&mut this.send).poll_write(cx, &this.response) {
+                task::Poll::Ready(Ok(written)) => {
+                    let _ = this.response.split_to(written);
+                }
+                // The client stopped the stream, or went away.
+                task::Poll::Ready(Err(_)) => return task::Poll::Ready(()),
+                task::Poll::Pending => return task::Poll::Pending,
+            }
+        }
+        let _ = this.send.finish();
+        task::Poll::Ready(())
+    }
+}
+
+impl future::Future for WatchStopped {
+    type Output = ();
+
+    fn poll(self: pin::Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<()> {
+        let this = self.get_mut();
+        match this.stopped.as_mut().poll(cx) {
+            // The stream was finished normally.
+            task::Poll::Ready(Ok(None)) => task::Poll::Ready(()),
+            task::Poll::Ready(_) => {
+                this.context.cancel();
+                task::Poll::Ready(())
+            }
+            task::Poll::Pending => task::Poll::Pending,
+        }
+    }
+}
+
+impl<F> future::Future for Forward<F>
+where
+    F: future::Future,
+{
+    type Output = ();
+
+    fn poll(self: pin::Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<()> {
+        let this = self.get_mut();
+        match this.future.as_mut().poll(cx) {
+            task::Poll::Ready(output) => {
+                if let Some(sender) = this.output.take() {
+                    let _ = sender.send(output);
+                }
+                task::Poll::Ready(())
+            }
+            task::Poll::Pending => task::Poll::Pending,
+        }
+    }
+}
+
+/// Reads a stream until it is finished, failing if it carries more than `MAX_MESSAGE_LEN` bytes.
+fn poll_read_to_end(
+    recv: &mut quinn::RecvStream,
+    buf: &mut Vec<u8>,
+    cx: &mut task::Context,
+) -> task::Poll<Result<(), Error>> {
+    let mut chunk = [0; 16 * 1024];
+    loop {
+        match recv.poll_read(cx, &mut chunk) {
+            task::Poll::Ready(Ok(0)) => return task::Poll::Ready(Ok(())),
+            task::Poll::Ready(Ok(read)) => {
+                if buf.len() + read > MAX_MESSAGE_LEN {
+                    return task::Poll::Ready(Err(Error::TooLarge {
+                        len: buf.len() + read,
+                        max: MAX_MESSAGE_LEN,
+                    }));
+                }
+                buf.extend_from_slice(&chunk[..read]);
+            }
+            task::Poll::Ready(Err(quinn::ReadError::ConnectionLost(_))) => {
+                return task::Poll::Ready(Err(Error::Closed));
+            }
+            task::Poll::Ready(Err(error)) => {
+                return task::Poll::Ready(Err(Error::from(io::Error::from(error))));
+            }
+            task::Poll::Pending => return task::Poll::Pending,
+        }
+    }
+}
+
+#[allow(unsafe_code)]
+impl<T, F> Repeat<T, F>
+where
+    F: future::Future,
+{
+    /// Creates a `Repeat` that makes its futures by calling `make` with `owner`.
+    ///
+    /// # Safety
+    ///
+    /// `make` is handed a reference that only lives as long as the `Repeat`, even though its type
+    /// says otherwise, so the futures it makes may only borrow `owner` in ways that end when they
+    /// are dropped, and their outputs must not borrow `owner` at all.
+    unsafe fn new(owner: T, make: fn(&'static T) -> F) -> Repeat<T, F> {
+        Repeat {
+            future: None,
+            make,
+            owner: sync::Arc::new(owner),
+        }
+    }
+
+    /// Polls the current future, and makes a new one once it has completed.
+    fn poll_next(&mut self, cx: &mut task::Context) -> task::Poll<F::Output> {
+        if self.future.is_none() {
+            // SAFETY: the reference points into the allocation of `owner`, which stays in place when
+            // `self` is moved and is never mutated.  It is only handed to `make`, and the future
+            // that borrows it is dropped before `owner`, both in `Drop` by the order of the fields
+            // and below once it has completed.  Outputs don't borrow `owner`, which the callers of
+            // `new` guarantee.
+            let owner = unsafe { &*sync::Arc::as_ptr(&self.owner) };
+            self.future = Some(Box::pin((self.make)(owner)));
+        }
+
+        let future = self.future.as_mut().expect("future was just made");
+        match future.as_mut().poll(cx) {
+            task::Poll::Ready(output) => {
+                self.future = None;
+                task::Poll::Ready(output)
+            }
+            task::Poll::Pending => task::Poll::Pending,
+        }
+    }
+}
+
+/// Runs a future on the QUIC runtime, returning a future for its output that can be polled on any
+/// executor.
+fn spawn<F>(future: F) -> oneshot::Receiver<F::Output>
+where
+    F: future::Future + Send + 'static,
+    F::Output: Send,
+{
+    let (output, receiver) = oneshot::channel();
+    runtime().spawn(Forward {
+        future: Box::pin(future),
+        output: Some(output),
+    });
+    receiver
+}
+
+fn runtime() -> &'static tokio1::runtime::Runtime {
+    RUNTIME.get_or_init(|| {
+        tokio1::runtime::Builder::new_multi_thread()
+            .thread_name("prost-simple-rpc-quic")
+            .enable_all()
+            .build()
+            .expect("failed to start QUIC runtime")
+    })
+}
+
+/// Encodes an envelope, failing if it is larger than `MAX_MESSAGE_LEN`.
+fn encode(envelope: &Envelope) -> Result<bytes::Bytes, Error> {
+    let len = prost::Message::encoded_len(envelope);
+    if len > MAX_MESSAGE_LEN {
+        return Err(Error::TooLarge {
+            len,
+            max: MAX_MESSAGE_LEN,
+        });
+    }
+    Ok(write(envelope, len))
+}
+
+/// Encodes an error response.
+///
+/// If the response is too large, it is sent without metadata and with a shortened message
+/// instead, which always fits.
+fn error_response(
+    status: &status::Status,
+    metadata: collections::BTreeMap<String, String>,
+) -> bytes::Bytes {
+    encode(&Envelope {
+        kind: Kind::Error as i32,
+        metadata,
+        body: status.encode().to_vec(),
+        ..Envelope::default()
+    })
+    .unwrap_or_else(|_| {
+        let envelope = Envelope {
+            kind: Kind::Error as i32,
+            body: status.truncated(MAX_ERROR_MESSAGE_LEN).encode().to_vec(),
+            ..Envelope::default()
+        };
+        write(&envelope, prost::Message::encoded_len(&envelope))
+    })
+}
+
+fn write(envelope: &Envelope, len: usize) -> bytes::Bytes {
+    let mut buf = bytes::BytesMut::with_capacity(len);
+    prost::Message::encode(envelope, &mut buf).expect("buffer has enough capacity");
+    buf.freeze()
+}
+
+fn write_error(error: quinn::WriteError) -> Error {
+    match error {
+        quinn::WriteError::ConnectionLost(_) => Error::Closed,
+        error => Error::from(io::Error::from(error)),
+    }
+}
+
+fn metadata_entries(metadata: &metadata::Metadata) -> collections::BTreeMap<String, String> {
+    metadata
+        .iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect()
+}
@@ -0,0 +1,697 @@
+//! A transport of frames over shared-memory ring buffers, for calls between processes on the same
+//! Linux host.
+//!
+//! Connections are set up over a Unix domain socket, after which calls no longer touch the socket:
+//! every frame is copied into a ring buffer that both processes have mapped, and the other side is
+//! only woken up with a futex if it went to sleep.  While both sides are busy, frames are exchanged
+//! without any system calls at all:
+//!
+//! ```rust,ignore
+//! let listener = tokio::net::UnixListener::bind("/run/echo.sock")?;
+//! tokio::spawn(shm::serve(listener, server).map_err(|_| ()));
+//!
+//! let future = shm::connect("/run/echo.sock").and_then(|connection| {
+//!     let client = schema::echo::EchoClient::new(transport::Client::new(connection));
+//!     client.echo(schema::echo::EchoRequest { /* ... */ })
+//! });
+//! ```
+//!
+//! Frames are read from and written to the ring buffers directly by the tokio executor.  Only when
+//! a ring buffer is empty or full does a separate thread wait for it, yielding for a little while
+//! before going to sleep.  Handing a call over to that thread costs a context switch, so an idle
+//! connection has a somewhat higher latency than with the `unix` transport, especially on hosts
+//! with few cores.  The `transports` benchmark of the example crate compares the two.
+//!
+//! The socket stays open, so that a connection is closed when the process on the other side exits.
+//! A call whose request doesn't fit into a ring buffer fails with `Error::TooLarge`, and a response
+//! that doesn't fit is replaced with a `ResourceExhausted` error, so the capacity of the ring
+//! buffers should be chosen with the largest calls in mind.
+use std::fs;
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path;
+use std::process;
+use std::ptr;
+use std::sync;
+use std::sync::atomic;
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+use bytes;
+use futures;
+use futures::sync::oneshot;
+use libc;
+use tokio;
+
+use super::accept;
+use super::framed;
+use super::Dispatch;
+
+/// The capacity of each ring buffer of a connection, unless specified otherwise, in bytes.
+pub const DEFAULT_CAPACITY: usize = 1024 * 1024;
+
+/// The future that results from a call to `connect`.
+pub type Connect = Box<dyn futures::Future<Item = framed::Connection, Error = io::Error> + Send>;
+
+/// The future that results from a call to `serve`.
+pub type Serve = Box<dyn futures::Future<Item = (), Error = io::Error> + Send>;
+
+type Accept =
+    Box<dyn futures::Future<Item = (Region, tokio::net::UnixStream), Error = io::Error> + Send>;
+
+const MAGIC: u64 = 0x7072_6f73_745f_7368;
+const SPINS: usize = 1000;
+const WAIT_TIMEOUT: time::Duration = time::Duration::from_millis(100);
+
+/// The ring buffer that carries frames from the client to the server.
+const REQUESTS: usize = 0;
+/// The ring buffer that carries frames from the server to the client.
+const RESPONSES: usize = 1;
+
+/// The start of a shared memory region, which is followed by the data of both ring buffers.
+#[repr(C)]
+struct RegionHeader {
+    magic: atomic::AtomicU64,
+    capacity: atomic::AtomicU64,
+    rings: [RingHeader; 2],
+}
+
+/// The state of a single-producer, single-consumer ring buffer of length-prefixed frames.
+///
+/// `head` and `tail` count all bytes ever written and read.  `readable` and `writable` are futex
+/// words that are bumped whenever frames are written or read, and the `*_waiting` flags tell the
+/// other side whether it needs to wake anyone up.
+#[repr(C, align(64))]
+struct RingHeader {
+    head: atomic::AtomicU64,
+    tail: atomic::AtomicU64,
+    readable: atomic::AtomicU32,
+    writable: atomic::AtomicU32,
+    reader_waiting: atomic::AtomicU32,
+    writer_waiting: atomic::AtomicU32,
+    closed: atomic::AtomicU32,
+}
+
+/// A shared memory region, mapped into this process.
+struct Region {
+    ptr: *mut u8,
+    len: usize,
+    capacity: usize,
+}
+
+struct Ring<'a> {
+    header: &'a RingHeader,
+    data: *mut u8,
+    capacity: usize,
+}
+
+/// The frames of a connection, which are read from one ring buffer and written to the other.
+struct Messages {
+    region: sync::Arc<Region>,
+    inbox: usize,
+    outbox: usize,
+    empty: Waiter,
+    full: Waiter,
+    /// Stops watching the socket once the connection is dropped.
+    _stopped: oneshot::Sender<()>,
+}
+
+/// A thread that waits for a ring buffer on behalf of a task, so that the task isn't blocked.
+struct Waiter {
+    requests: mpsc::Sender<(u64, futures::task::Task)>,
+    waiting: sync::Arc<atomic::AtomicBool>,
+}
+
+/// Connects to a server listening on the socket at the specified path.
+///
+/// This must be polled on a tokio executor, which is used to drive the connection.
+pub fn connect<P>(path: P) -> Connect
+where
+    P: AsRef<path::Path>,
+{
+    use futures::Future;
+
+    Box::new(
+        tokio::net::UnixStream::connect(path)
+            .and_then(|socket| tokio::io::read_until(io::BufReader::new(socket), b'\n', Vec::new()))
+            .and_then(|(socket, mut line)| {
+                if line.pop() != Some(b'\n') {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed before the shared memory region was set up",
+                    ));
+                }
+                let path = String::from_utf8(line)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                let region = Region::open(path::Path::new(&path))?;
+                Ok((region, socket.into_inner()))
+            })
+            .and_then(|(region, socket)| {
+                tokio::io::write_all(socket, [1]).map(move |(socket, _)| {
+                    let max_len = region.max_message_len();
+                    let messages = start(region, socket, RESPONSES, REQUESTS);
+                    framed::Connection::with_messages(messages, max_len)
+                })
+            }),
+    )
+}
+
+/// Serves calls from all connections accepted by the specified listener, with ring buffers of
+/// `DEFAULT_CAPACITY`.
+///
+/// This must be polled on a tokio executor, which is used to handle connections and calls.  The
+/// returned future only completes if the listener fails; connections that fail to be set up are
+/// dropped without affecting the others.
+pub fn serve<D>(listener: tokio::net::UnixListener, dispatch: D) -> Serve
+where
+    D: Dispatch,
+{
+    serve_with_capacity(listener, DEFAULT_CAPACITY, dispatch)
+}
+
+/// Serves calls from all connections accepted by the specified listener, with ring buffers of the
+/// specified capacity in bytes.
+///
+/// This otherwise behaves like `serve`.  Connections fail to be set up if the ring buffers are too
+/// large to be allocated.
+///
+/// # Panics
+///
+/// Panics if `capacity` is zero.
+pub fn serve_with_capacity<D>(
+    listener: tokio::net::UnixListener,
+    capacity: usize,
+    dispatch: D,
+) -> Serve
+where
+    D: Dispatch,
+{
+    use futures::Future;
+
+    assert!(capacity > 0, "ring buffer capacity must be positive");
+
+    Box::new(accept::for_each(listener.incoming(), move |socket| {
+        let dispatch = dispatch.clone();
+        tokio::spawn(
+            accept(socket, capacity)
+                .and_then(move |(region, socket)| {
+                    let max_len = region.max_message_len();
+                    let messages = start(region, socket, REQUESTS, RESPONSES);
+                    framed::serve_messages(messages, max_len, dispatch, None)
+                })
+                .map_err(|_| ()),
+        );
+        Ok(())
+    }))
+}
+
+/// Creates a shared memory region for a new connection, and waits for the client to map it.
+fn accept(socket: tokio::net::UnixStream, capacity: usize) -> Accept {
+    use futures::Future;
+
+    let (region, path) = match Region::create(capacity) {
+        Ok(created) => created,
+        Err(error) => return Box::new(futures::future::err(error)),
+    };
+    let line = format!("{}\n", path.display());
+
+    Box::new(
+        tokio::io::write_all(socket, line)
+            .and_then(|(socket, _)| tokio::io::read_exact(socket, [0]))
+            .then(move |result| {
+                // Both processes have mapped the region by now, or never will.
+                let _ = fs::remove_file(&path);
+                result.map(|(socket, _)| (region, socket))
+            }),
+    )
+}
+
+/// Starts exchanging frames over the ring buffers of a region.
+///
+/// The connection is closed when either side drops it, or when the socket to the other process is
+/// closed.
+fn start(region: Region, socket: tokio::net::UnixStream, inbox: usize, outbox: usize) -> Messages {
+    use futures::Future;
+
+    let region = sync::Arc::new(region);
+    let (stopped_sender, stopped) = oneshot::channel::<()>();
+
+    // The other process never writes to the socket again, so it only becomes readable once that
+    // process has gone away.
+    let socket_region = region.clone();
+    tokio::spawn(
+        tokio::io::read_to_end(socket, Vec::new())
+            .map(|_| ())
+            .map_err(|_| ())
+            .select(stopped.map_err(|_| ()))
+            .then(move |_| {
+                socket_region.close();
+                Ok(())
+            }),
+    );
+
+    Messages {
+        empty: Waiter::spawn(region.clone(), move |region, _| {
+            region.ring(inbox).wait_readable()
+        }),
+        full: Waiter::spawn(region.clone(), move |region, needed| {
+            region.ring(outbox).wait_writable(needed)
+        }),
+        region,
+        inbox,
+        outbox,
+        _stopped: stopped_sender,
+    }
+}
+
+#[allow(unsafe_code)]
+impl Region {
+    /// Creates a new region with ring buffers of the specified capacity, backed by a file in
+    /// `/dev/shm` that only the current user can access.
+    fn create(capacity: usize) -> io::Result<(Region, path::PathBuf)> {
+        static NEXT_ID: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+
+        let len = capacity
+            .checked_mul(2)
+            .and_then(|data| data.checked_add(data_offset()))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "shared memory ring buffer capacity is too large",
+                )
+            })?;
+        let nanos = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.subsec_nanos());
+        let path = path::PathBuf::from(format!(
+            "/dev/shm/prost-simple-rpc-{}-{}-{}",
+            process::id(),
+            NEXT_ID.fetch_add(1, atomic::Ordering::Relaxed),
+            nanos
+        ));
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+
+        let mapped = file
+            .set_len(len as u64)
+            .and_then(|()| Region::map(&file, len));
+        let region = match mapped {
+            Ok(ptr) => Region { ptr, len, capacity },
+            Err(error) => {
+                let _ = fs::remove_file(&path);
+                return Err(error);
+            }
+        };
+
+        let header = region.header();
+        header
+            .capacity
+            .store(capacity as u64, atomic::Ordering::Relaxed);
+        header.magic.store(MAGIC, atomic::Ordering::Release);
+        Ok((region, path))
+    }
+
+    /// Maps an existing region that was created by the server.
+    fn open(path: &path::Path) -> io::Result<Region> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len < data_offset() {
+            return Err(invalid_region());
+        }
+
+        let mut region = Region {
+            ptr: Region::map(&file, len)?,
+            len,
+            capacity: 0,
+        };
+        let header = region.header();
+        let capacity = header.capacity.load(atomic::Ordering::Relaxed) as usize;
+        if header.magic.load(atomic::Ordering::Acquire) != MAGIC
+            || capacity == 0
+            || Some(len)
+                != capacity
+                    .checked_mul(2)
+                    .and_then(|data| data.checked_add(data_offset()))
+        {
+            return Err(invalid_region());
+        }
+        region.capacity = capacity;
+        Ok(region)
+    }
+
+    fn map(file: &fs::File, len: usize) -> io::Result<*mut u8> {
+        // SAFETY: this maps a fresh range of memory, which doesn't alias anything in this process.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ptr.cast())
+        }
+    }
+
+    fn header(&self) -> &RegionHeader {
+        // SAFETY: the region is at least `data_offset()` bytes long and page aligned, and the
+        // header only consists of atomics, which may be modified by the other process at any time.
+        unsafe { &*self.ptr.cast::<RegionHeader>() }
+    }
+
+    fn ring(&self, index: usize) -> Ring<'_> {
+        Ring {
+            header: &self.header().rings[index],
+            // SAFETY: the data of both ring buffers follows the header within the region.
+            data: unsafe { self.ptr.add(data_offset() + index * self.capacity) },
+            capacity: self.capacity,
+        }
+    }
+
+    /// The largest frame that fits into a ring buffer along with its length prefix, in bytes.
+    fn max_message_len(&self) -> usize {
+        self.capacity.saturating_sub(4).min(u32::MAX as usize)
+    }
+
+    /// Closes both ring buffers, waking up anyone who is waiting on them.
+    fn close(&self) {
+        for ring in &self.header().rings {
+            ring.closed.store(1, atomic::Ordering::SeqCst);
+            ring.readable.fetch_add(1, atomic::Ordering::SeqCst);
+            ring.writable.fetch_add(1, atomic::Ordering::SeqCst);
+            futex_wake(&ring.readable);
+            futex_wake(&ring.writable);
+        }
+    }
+}
+
+#[allow(unsafe_code)]
+impl Drop for Region {
+    fn drop(&mut self) {
+        // SAFETY: the region was mapped with this length, and nothing refers to it anymore.
+        unsafe {
+            libc::munmap(self.ptr.cast(), self.len);
+        }
+    }
+}
+
+// SAFETY: the region is only accessed through atomics, and through ring buffers that are only ever
+// read by one thread and written by another.
+#[allow(unsafe_code)]
+unsafe impl Send for Region {}
+#[allow(unsafe_code)]
+unsafe impl Sync for Region {}
+
+#[allow(unsafe_code)]
+impl<'a> Ring<'a> {
+    /// Writes a frame if there is enough space for it, returning whether it was written.
+    fn try_write(&self, message: &[u8]) -> io::Result<bool> {
+        let needed = 4 + message.len() as u64;
+        if needed > self.capacity as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame is larger than the shared memory ring buffer",
+            ));
+        }
+        if self.header.closed.load(atomic::Ordering::SeqCst) != 0 {
+            return Err(closed());
+        }
+
+        let head = self.header.head.load(atomic::Ordering::Relaxed);
+        let tail = self.header.tail.load(atomic::Ordering::Acquire);
+        if self.capacity as u64 - self.used(head, tail)? < needed {
+            return Ok(false);
+        }
+        let next = head.checked_add(needed).ok_or_else(corrupted)?;
+
+        // The frame fits into the free space after `head`, and it's no larger than the capacity.
+        self.copy_in(head, &(message.len() as u32).to_le_bytes());
+        self.copy_in(head + 4, message);
+        self.header.head.store(next, atomic::Ordering::Release);
+        self.header.readable.fetch_add(1, atomic::Ordering::SeqCst);
+        if self.header.reader_waiting.load(atomic::Ordering::SeqCst) != 0 {
+            futex_wake(&self.header.readable);
+        }
+        Ok(true)
+    }
+
+    /// Waits until there is space for `needed` bytes, or until the ring buffer is closed.
+    fn wait_writable(&self, needed: u64) {
+        let mut spins = 0;
+        while self.header.closed.load(atomic::Ordering::SeqCst) == 0 {
+            let seq = self.header.writable.load(atomic::Ordering::SeqCst);
+            let head = self.header.head.load(atomic::Ordering::Acquire);
+            let tail = self.header.tail.load(atomic::Ordering::Acquire);
+            // A corrupted ring buffer is reported by the next attempt to write to it.
+            match self.used(head, tail) {
+                Ok(used) if self.capacity as u64 - used < needed => {}
+                _ => return,
+            }
+            wait(
+                &self.header.writable,
+                &self.header.writer_waiting,
+                seq,
+                &mut spins,
+            );
+        }
+    }
+
+    /// Reads a frame if there is one, or returns `Ready(None)` once the ring buffer is closed.
+    fn try_read(&self) -> futures::Poll<Option<bytes::BytesMut>, io::Error> {
+        let tail = self.header.tail.load(atomic::Ordering::Relaxed);
+        let available = self.used(self.header.head.load(atomic::Ordering::Acquire), tail)?;
+        if available == 0 {
+            return if self.header.closed.load(atomic::Ordering::SeqCst) != 0 {
+                Ok(futures::Async::Ready(None))
+            } else {
+                Ok(futures::Async::NotReady)
+            };
+        }
+
+        // A frame is never split, so the length prefix has been written in full.
+        if available < 4 {
+            return Err(corrupted());
+        }
+        let mut len = [0; 4];
+        self.copy_out(tail, &mut len);
+        let len = u32::from_le_bytes(len) as usize;
+        // Since `available` is at most the capacity, this also bounds the frame by the capacity.
+        if 4 + len as u64 > available {
+            return Err(corrupted());
+        }
+        let mut message = bytes::BytesMut::from(vec![0; len]);
+        self.copy_out(tail + 4, &mut message);
+
+        self.header
+            .tail
+            .store(tail + 4 + len as u64, atomic::Ordering::Release);
+        self.header.writable.fetch_add(1, atomic::Ordering::SeqCst);
+        if self.header.writer_waiting.load(atomic::Ordering::SeqCst) != 0 {
+            futex_wake(&self.header.writable);
+        }
+        Ok(futures::Async::Ready(Some(message)))
+    }
+
+    /// Waits until there is a frame to read, or until the ring buffer is closed.
+    fn wait_readable(&self) {
+        let mut spins = 0;
+        while self.header.closed.load(atomic::Ordering::SeqCst) == 0 {
+            let seq = self.header.readable.load(atomic::Ordering::SeqCst);
+            let head = self.header.head.load(atomic::Ordering::Acquire);
+            if head != self.header.tail.load(atomic::Ordering::Acquire) {
+                return;
+            }
+            wait(
+                &self.header.readable,
+                &self.header.reader_waiting,
+                seq,
+                &mut spins,
+            );
+        }
+    }
+
+    /// How many bytes are in use between `tail` and `head`.
+    ///
+    /// Both positions are written by the other process, so they can't be trusted: this fails
+    /// unless `tail <= head <= tail + capacity`.
+    fn used(&self, head: u64, tail: u64) -> io::Result<u64> {
+        head.checked_sub(tail)
+            .filter(|&used| used <= self.capacity as u64)
+            .ok_or_else(corrupted)
+    }
+
+    fn copy_in(&self, position: u64, bytes: &[u8]) {
+        assert!(bytes.len() <= self.capacity);
+        let start = (position % self.capacity as u64) as usize;
+        let first = bytes.len().min(self.capacity - start);
+        // SAFETY: `start < capacity` and `first <= capacity - start`, so the first copy stays
+        // within the data of the ring buffer.  Since `bytes.len() <= capacity`, the rest is at most
+        // `start` bytes long, so the second copy does too.  Callers only write to space that the
+        // reader has released (`head - tail` was checked to leave room for `bytes`), which it won't
+        // touch until `head` is advanced.
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), self.data.add(start), first);
+            ptr::copy_nonoverlapping(bytes[first..].as_ptr(), self.data, bytes.len() - first);
+        }
+    }
+
+    fn copy_out(&self, position: u64, bytes: &mut [u8]) {
+        assert!(bytes.len() <= self.capacity);
+        let start = (position % self.capacity as u64) as usize;
+        let first = bytes.len().min(self.capacity - start);
+        // SAFETY: `start < capacity` and `first <= capacity - start`, so the first copy stays
+        // within the data of the ring buffer.  Since `bytes.len() <= capacity`, the rest is at most
+        // `start` bytes long, so the second copy does too.  The other process may still write to
+        // this memory if it misbehaves, but then it only corrupts the bytes that are read, since
+        // these are plain bytes without any invariants.
+        unsafe {
+            ptr::copy_nonoverlapping(self.data.add(start), bytes.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(self.data, bytes[first..].as_mut_ptr(), bytes.len() - first);
+        }
+    }
+}
+
+impl Drop for Messages {
+    fn drop(&mut self) {
+        self.region.close();
+    }
+}
+
+impl futures::Stream for Messages {
+    type Item = bytes::BytesMut;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> futures::Poll<Option<Self::Item>, Self::Error> {
+        let result = self.region.ring(self.inbox).try_read()?;
+        if result.is_not_ready() {
+            self.empty.notify_when(0)?;
+        }
+        Ok(result)
+    }
+}
+
+impl futures::Sink for Messages {
+    type SinkItem = bytes::Bytes;
+    type SinkError = io::Error;
+
+    fn start_send(
+        &mut self,
+        message: bytes::Bytes,
+    ) -> futures::StartSend<Self::SinkItem, Self::SinkError> {
+        if self.region.ring(self.outbox).try_write(&message)? {
+            return Ok(futures::AsyncSink::Ready);
+        }
+        self.full.notify_when(4 + message.len() as u64)?;
+        Ok(futures::AsyncSink::NotReady(message))
+    }
+
+    fn poll_complete(&mut self) -> futures::Poll<(), Self::SinkError> {
+        Ok(futures::Async::Ready(()))
+    }
+}
+
+impl Waiter {
+    /// Spawns a thread that runs `wait` whenever a task needs to be notified.
+    ///
+    /// The thread stops once the waiter is dropped.  `wait` must return once the region is closed.
+    fn spawn<F>(region: sync::Arc<Region>, wait: F) -> Waiter
+    where
+        F: Fn(&Region, u64) + Send + 'static,
+    {
+        let (requests, receiver) = mpsc::channel::<(u64, futures::task::Task)>();
+        let waiting = sync::Arc::new(atomic::AtomicBool::new(false));
+        let thread_waiting = waiting.clone();
+        thread::spawn(move || {
+            for (needed, task) in receiver {
+                wait(&region, needed);
+                thread_waiting.store(false, atomic::Ordering::SeqCst);
+                task.notify();
+            }
+        });
+        Waiter { requests, waiting }
+    }
+
+    /// Notifies the current task once `wait` returns, unless a notification is already pending.
+    fn notify_when(&self, needed: u64) -> io::Result<()> {
+        if self.waiting.swap(true, atomic::Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.requests
+            .send((needed, futures::task::current()))
+            .map_err(|_| closed())
+    }
+}
+
+/// Waits for the futex word to change from `seq`, yielding at first, and sleeping after that.
+fn wait(word: &atomic::AtomicU32, waiting: &atomic::AtomicU32, seq: u32, spins: &mut usize) {
+    if *spins < SPINS {
+        *spins += 1;
+        thread::yield_now();
+        return;
+    }
+    waiting.store(1, atomic::Ordering::SeqCst);
+    futex_wait(word, seq);
+    waiting.store(0, atomic::Ordering::SeqCst);
+}
+
+/// Sleeps until the futex word is woken up, unless it no longer holds `seq`.
+///
+/// This gives up after a while, in case a wake-up from a process that went away was missed.
+#[allow(unsafe_code)]
+fn futex_wait(word: &atomic::AtomicU32, seq: u32) {
+    let word: *const atomic::AtomicU32 = word;
+    let timeout = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: WAIT_TIMEOUT.subsec_nanos().into(),
+    };
+    let timeout: *const libc::timespec = &timeout;
+    // SAFETY: the futex word is a live, aligned 32-bit integer in shared memory.
+    unsafe {
+        libc::syscall(libc::SYS_futex, word, libc::FUTEX_WAIT, seq, timeout);
+    }
+}
+
+/// Wakes up everyone sleeping on the futex word, in any process.
+#[allow(unsafe_code)]
+fn futex_wake(word: &atomic::AtomicU32) {
+    let word: *const atomic::AtomicU32 = word;
+    // SAFETY: the futex word is a live, aligned 32-bit integer in shared memory.
+    unsafe {
+        libc::syscall(libc::SYS_futex, word, libc::FUTEX_WAKE, i32::MAX);
+    }
+}
+
+/// The offset of the data of the first ring buffer within a region.
+fn data_offset() -> usize {
+    size_of::<RegionHeader>()
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "shared memory connection closed")
+}
+
+fn corrupted() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "shared memory ring buffer is corrupted",
+    )
+}
+
+fn invalid_region() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid shared memory region")
+}
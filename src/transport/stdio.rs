@@ -0,0 +1,185 @@
+//! A transport of length-prefixed frames over the standard input and output of a process.
+//!
+//! This suits plugins that run as child processes.  The host spawns the plugin and makes calls
+//! over its stdin and stdout, using the same framing as the `tcp` transport:
+//!
+//! ```rust,ignore
+//! let plugin = stdio::spawn(process::Command::new("echo-plugin").arg("--verbose"))?;
+//! let client = schema::echo::EchoClient::new(transport::Client::new(plugin.connection().clone()));
+//! ```
+//!
+//! The plugin serves calls until the host goes away:
+//!
+//! ```rust,ignore
+//! fn main() {
+//!     let server = schema::echo::EchoServer::new(EchoService);
+//!     tokio::run(stdio::serve_stdio(server).map_err(|e| eprintln!("plugin failed: {}", e)));
+//! }
+//! ```
+//!
+//! Since stdout carries the calls, plugins must not print anything to it.  Their stderr is passed
+//! through to the host's stderr, so it can be used for logging instead.
+use std::fmt;
+use std::io;
+use std::process;
+
+use futures;
+use tokio;
+use tokio_process;
+
+use super::framed;
+use super::Dispatch;
+
+pub use super::framed::MAX_MESSAGE_LEN;
+
+/// The future that results from a call to `serve` or `serve_stdio`.
+pub type Serve = Box<dyn futures::Future<Item = (), Error = io::Error> + Send>;
+
+/// The future that results from a call to `Child::wait`.
+pub type Wait = Box<dyn futures::Future<Item = process::ExitStatus, Error = io::Error> + Send>;
+
+/// A child process, and a connection to it over its stdin and stdout.
+///
+/// Once the process exits, all calls over the connection fail with `Error::Closed`.  Dropping a
+/// `Child` kills the process if it is still running.
+pub struct Child {
+    connection: framed::Connection,
+    process: tokio_process::Child,
+}
+
+/// Joins the separate halves of a pipe pair into a single byte stream.
+struct Pipes<R, W> {
+    reader: R,
+    writer: W,
+}
+
+/// Spawns the specified command, and connects to it over its stdin and stdout.
+///
+/// The stdin and stdout of the command are replaced with pipes, while its stderr is inherited.
+/// This must be called on a tokio executor, which is used to drive the connection and to notice
+/// when the process exits.
+pub fn spawn(command: &mut process::Command) -> io::Result<Child> {
+    use tokio_process::CommandExt;
+
+    let mut process = command
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::inherit())
+        .spawn_async()?;
+    let stdin = process.stdin().take().expect("stdin is piped");
+    let stdout = process.stdout().take().expect("stdout is piped");
+
+    Ok(Child {
+        connection: connect(stdout, stdin),
+        process,
+    })
+}
+
+/// Connects to a peer that reads calls from `writer` and writes responses to `reader`.
+///
+/// This must be called on a tokio executor, which is used to drive the connection.
+pub fn connect<R, W>(reader: R, writer: W) -> framed::Connection
+where
+    R: tokio::io::AsyncRead + Send + 'static,
+    W: tokio::io::AsyncWrite + Send + 'static,
+{
+    framed::Connection::new(Pipes { reader, writer })
+}
+
+/// Serves calls read from `reader` by dispatching them to `dispatch`, writing responses to
+/// `writer`.
+///
+/// This must be polled on a tokio executor, which is used to handle calls.  The returned future
+/// completes once `reader` is closed, cancelling all of the calls that are still in flight.
+pub fn serve<R, W, D>(reader: R, writer: W, dispatch: D) -> Serve
+where
+    R: tokio::io::AsyncRead + Send + 'static,
+    W: tokio::io::AsyncWrite + Send + 'static,
+    D: Dispatch,
+{
+    framed::serve_connection(Pipes { reader, writer }, dispatch, None)
+}
+
+/// Serves calls read from the stdin of the current process, writing responses to its stdout.
+///
+/// The returned future completes once the host closes stdin, for example by dropping its
+/// connections or by exiting, after which the plugin should exit as well.  This must be polled on
+/// the default tokio runtime, as with `tokio::run`, since reading stdin blocks.
+pub fn serve_stdio<D>(dispatch: D) -> Serve
+where
+    D: Dispatch,
+{
+    serve(tokio::io::stdin(), tokio::io::stdout(), dispatch)
+}
+
+impl Child {
+    /// The connection to the process, over which any number of calls can be made concurrently.
+    pub fn connection(&self) -> &framed::Connection {
+        &self.connection
+    }
+
+    /// The OS-assigned identifier of the process.
+    pub fn id(&self) -> u32 {
+        self.process.id()
+    }
+
+    /// Forces the process to exit.
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.process.kill()
+    }
+
+    /// Drops this connection to the process, and waits for the process to exit.
+    ///
+    /// The stdin of the process is closed once all clones of the connection have been dropped,
+    /// which tells a plugin that is served with `serve_stdio` to shut down.
+    pub fn wait(self) -> Wait {
+        let Child {
+            connection,
+            process,
+        } = self;
+        drop(connection);
+        Box::new(process)
+    }
+}
+
+impl fmt::Debug for Child {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Child")
+            .field("id", &self.id())
+            .field("connection", &self.connection)
+            .finish()
+    }
+}
+
+impl<R, W> io::Read for Pipes<R, W>
+where
+    R: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<R, W> io::Write for Pipes<R, W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<R, W> tokio::io::AsyncRead for Pipes<R, W> where R: tokio::io::AsyncRead {}
+
+impl<R, W> tokio::io::AsyncWrite for Pipes<R, W>
+where
+    W: tokio::io::AsyncWrite,
+{
+    fn shutdown(&mut self) -> futures::Poll<(), io::Error> {
+        self.writer.shutdown()
+    }
+}
@@ -0,0 +1,64 @@
+//! A transport of length-prefixed frames over TCP.
+//!
+//! Clients multiplex any number of concurrent calls over a single connection:
+//!
+//! ```rust,ignore
+//! let future = tcp::connect(&addr).and_then(|connection| {
+//!     let client = schema::echo::EchoClient::new(transport::Client::new(connection));
+//!     client.echo(schema::echo::EchoRequest { /* ... */ })
+//! });
+//! ```
+//!
+//! Servers feed the calls from every accepted connection into anything that implements `Dispatch`:
+//!
+//! ```rust,ignore
+//! let listener = tokio::net::TcpListener::bind(&addr)?;
+//! tokio::run(tcp::serve(listener, schema::echo::EchoServer::new(EchoService)).map_err(|_| ()));
+//! ```
+use std::io;
+use std::net;
+
+use futures;
+use tokio;
+
+use super::accept;
+use super::framed;
+use super::Dispatch;
+
+pub use super::framed::MAX_MESSAGE_LEN;
+
+/// The future that results from a call to `connect`.
+pub type Connect = Box<dyn futures::Future<Item = framed::Connection, Error = io::Error> + Send>;
+
+/// The future that results from a call to `serve`.
+pub type Serve = Box<dyn futures::Future<Item = (), Error = io::Error> + Send>;
+
+/// Connects to a server at the specified address.
+///
+/// This must be polled on a tokio executor, which is used to drive the connection.
+pub fn connect(addr: &net::SocketAddr) -> Connect {
+    use futures::Future;
+
+    Box::new(tokio::net::TcpStream::connect(addr).and_then(|socket| {
+        socket.set_nodelay(true)?;
+        Ok(framed::Connection::new(socket))
+    }))
+}
+
+/// Serves calls from all connections accepted by the specified listener.
+///
+/// This must be polled on a tokio executor, which is used to handle connections and calls.  The
+/// returned future only completes if the listener fails; connections that fail to be set up are
+/// dropped without affecting the others.
+pub fn serve<D>(listener: tokio::net::TcpListener, dispatch: D) -> Serve
+where
+    D: Dispatch,
+{
+    use futures::Future;
+
+    Box::new(accept::for_each(listener.incoming(), move |socket| {
+        socket.set_nodelay(true)?;
+        tokio::spawn(framed::serve_connection(socket, dispatch.clone(), None).map_err(|_| ()));
+        Ok(())
+    }))
+}
@@ -0,0 +1,789 @@
+//! A transport that speaks the Twirp protocol over HTTP/1.1, so that services can be called by
+//! existing Twirp clients, or even `curl`.
+//!
+//! Calls are sent as `POST` requests to `/twirp/package.Service/Method` with an
+//! `application/protobuf` or `application/json` body, and failed calls are answered with a Twirp
+//! JSON error object:
+//!
+//! ```rust,ignore
+//! let connection = twirp::Connection::new(format!("http://{}/twirp", addr));
+//! let client = schema::echo::EchoClient::new(twirp::Client::new(connection));
+//!
+//! let listener = tokio::net::TcpListener::bind(&addr)?;
+//! tokio::run(twirp::serve(listener, schema::echo::EchoServer::new(EchoService)).map_err(|_| ()));
+//! ```
+//!
+//! Metadata is sent as HTTP request headers, and trailers as HTTP response headers.  Bodies larger
+//! than `MAX_MESSAGE_LEN` are rejected with `Code::ResourceExhausted` instead of being buffered.
+//!
+//! Services only know the protobuf encoding of their messages, so serving JSON bodies takes a
+//! `JsonMapping` that converts between the two, which is passed to `serve_with_json`.  `SerdeJson`
+//! is such a mapping for messages that implement `serde`'s traits, which `prost_build` can derive.
+//! Twirp clients use the proto3 JSON mapping, so `bytes` fields must be encoded as base64 and
+//! 64-bit integer fields as strings, which the `proto3` module does:
+//!
+//! ```rust,ignore
+//! // In build.rs:
+//! config.type_attribute(".echo", "#[derive(serde::Serialize, serde::Deserialize)]");
+//! config.type_attribute(".echo", "#[serde(default, rename_all = \"camelCase\")]");
+//! config.field_attribute(
+//!     ".echo.EchoRequest.data",
+//!     "#[serde(with = \"prost_simple_rpc::transport::twirp::proto3::bytes\")]",
+//! );
+//!
+//! let mut json = twirp::SerdeJson::new();
+//! json.add::<EchoDescriptor, EchoRequest, EchoResponse>(EchoMethodDescriptor::Echo);
+//! let server = EchoServer::new(EchoService);
+//! tokio::run(twirp::serve_with_json(listener, server, json).map_err(|_| ()));
+//! ```
+//!
+//! Since `prost` doesn't generate the names of enum values, enum fields are encoded as numbers,
+//! which proto3 JSON parsers accept, but requests that use enum names are rejected as `malformed`.
+//!
+//! Requests with JSON bodies are answered with JSON bodies.  The client always sends
+//! `application/protobuf`.
+use std::any;
+use std::collections;
+use std::fmt;
+use std::io;
+use std::marker;
+use std::sync;
+
+use bytes;
+use futures;
+use http;
+use hyper;
+use prost;
+use serde;
+use serde_json;
+use tokio;
+
+use context;
+use descriptor;
+use handler;
+use status;
+
+use super::accept;
+use super::headers;
+use super::Dispatch;
+use super::Error;
+
+/// The future that results from a call made over a `Connection`.
+pub type CallFuture = Box<dyn futures::Future<Item = bytes::Bytes, Error = Error> + Send>;
+
+/// The future that results from a call to `serve`.
+pub type Serve = Box<dyn futures::Future<Item = (), Error = io::Error> + Send>;
+
+/// The path prefix that Twirp routes start with.
+pub const PREFIX: &str = "/twirp";
+
+/// The largest request or response body that is accepted, in bytes.
+pub const MAX_MESSAGE_LEN: usize = 8 * 1024 * 1024;
+
+const CONTENT_TYPE: &str = "application/protobuf";
+
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Headers that are part of HTTP itself, and therefore never treated as metadata.
+const RESERVED_HEADERS: &[&str] = &[
+    "accept",
+    "accept-encoding",
+    "connection",
+    "content-length",
+    "content-type",
+    "date",
+    "host",
+    "keep-alive",
+    "te",
+    "transfer-encoding",
+    "upgrade",
+    "user-agent",
+];
+
+/// A client for Twirp servers at a specific base URL, over which any number of calls can be made
+/// concurrently.
+///
+/// Connections are cheap to clone, and all clones share the same pool of HTTP connections.
+#[derive(Clone, Debug)]
+pub struct Connection {
+    client: hyper::Client<hyper::client::HttpConnector>,
+    base_url: String,
+}
+
+/// A client for a specific service, making calls over a `Connection`.
+#[derive(Clone, Debug)]
+pub struct Client<D> {
+    connection: Connection,
+    descriptor: marker::PhantomData<D>,
+}
+
+/// Converts between the JSON and protobuf encodings of the messages of methods, so that calls with
+/// JSON bodies can be served.
+pub trait JsonMapping: Send + Sync {
+    /// Converts the JSON encoding of an input of the specified method to its protobuf encoding.
+    ///
+    /// Fails with `Code::Unimplemented` if there is no JSON mapping for the method, and with
+    /// `Code::InvalidArgument` if the JSON isn't a valid input.
+    fn input_to_protobuf(
+        &self,
+        service: &str,
+        method: &str,
+        json: &[u8],
+    ) -> Result<bytes::Bytes, status::Status>;
+
+    /// Converts the protobuf encoding of an output of the specified method to its JSON encoding.
+    fn output_to_json(
+        &self,
+        service: &str,
+        method: &str,
+        protobuf: bytes::Bytes,
+    ) -> Result<bytes::Bytes, status::Status>;
+}
+
+/// A `JsonMapping` for methods whose messages implement `serde::Serialize` and
+/// `serde::Deserialize`, with the JSON encoding that those implementations define.
+///
+/// Twirp clients expect the proto3 JSON mapping, which uses `lowerCamelCase` field names, so the
+/// messages should be renamed accordingly, e.g. with `#[serde(rename_all = "camelCase")]`, and
+/// `bytes` and 64-bit integer fields should use the encodings in the `proto3` module.
+#[derive(Default)]
+pub struct SerdeJson {
+    methods: collections::HashMap<(String, String), SerdeMethod>,
+}
+
+/// The conversions of the messages of a single method in a `SerdeJson`.
+struct SerdeMethod {
+    input_to_protobuf: fn(&[u8]) -> Result<bytes::Bytes, status::Status>,
+    output_to_json: fn(bytes::Bytes) -> Result<bytes::Bytes, status::Status>,
+}
+
+/// The method that a request is for, and the JSON mapping to use if it has a JSON body.
+struct Route {
+    service: String,
+    method: String,
+    json: Option<sync::Arc<dyn JsonMapping>>,
+}
+
+/// Why a body couldn't be read.
+#[derive(Debug)]
+enum BodyError {
+    Http(hyper::Error),
+    TooLarge,
+}
+
+/// Serves Twirp calls from all connections accepted by the specified listener.
+///
+/// Routes are expected under the standard `/twirp` prefix.  This must be polled on a tokio
+/// executor, which is used to handle connections and calls.  The returned future only completes if
+/// the listener fails; connections that fail to be set up are dropped without affecting the
+/// others.
+///
+/// Requests with JSON bodies are rejected, since there is no `JsonMapping` to convert them; use
+/// `serve_with_json` to serve them as well.
+pub fn serve<D>(listener: tokio::net::TcpListener, dispatch: D) -> Serve
+where
+    D: Dispatch,
+{
+    serve_with(listener, dispatch, None)
+}
+
+/// Serves Twirp calls from all connections accepted by the specified listener, like `serve`, but
+/// also serves requests with JSON bodies by converting them with the specified `JsonMapping`.
+pub fn serve_with_json<D, J>(listener: tokio::net::TcpListener, dispatch: D, json: J) -> Serve
+where
+    D: Dispatch,
+    J: JsonMapping + 'static,
+{
+    serve_with(listener, dispatch, Some(sync::Arc::new(json)))
+}
+
+fn serve_with<D>(
+    listener: tokio::net::TcpListener,
+    dispatch: D,
+    json: Option<sync::Arc<dyn JsonMapping>>,
+) -> Serve
+where
+    D: Dispatch,
+{
+    use futures::Future;
+
+    let http = hyper::server::conn::Http::new();
+    Box::new(accept::for_each(listener.incoming(), move |socket| {
+        let dispatch = dispatch.clone();
+        let json = json.clone();
+        let service =
+            hyper::service::service_fn(move |request| handle(&dispatch, json.clone(), request));
+        tokio::spawn(http.serve_connection(socket, service).map_err(|_| ()));
+        Ok(())
+    }))
+}
+
+impl SerdeJson {
+    /// Creates a mapping that doesn't know any methods yet.
+    pub fn new() -> SerdeJson {
+        SerdeJson::default()
+    }
+
+    /// Adds the mapping of a method, whose input and output messages are `I` and `O`.
+    ///
+    /// # Panics
+    ///
+    /// If `I` or `O` aren't the input and output types of the method.
+    pub fn add<D, I, O>(&mut self, method: D::Method) -> &mut SerdeJson
+    where
+        D: descriptor::ServiceDescriptor,
+        I: prost::Message + for<'de> serde::Deserialize<'de> + 'static,
+        O: prost::Message + Default + serde::Serialize + 'static,
+    {
+        use descriptor::MethodDescriptor;
+
+        assert!(
+            method.input_type() == any::TypeId::of::<I>()
+                && method.output_type() == any::TypeId::of::<O>(),
+            "the messages of {} are not {} and {}",
+            D::path(&method),
+            any::type_name::<I>(),
+            any::type_name::<O>(),
+        );
+        self.methods.insert(
+            (D::full_name(), method.proto_name().to_owned()),
+            SerdeMethod {
+                input_to_protobuf: input_to_protobuf::<I>,
+                output_to_json: output_to_json::<O>,
+            },
+        );
+        self
+    }
+
+    fn method(&self, service: &str, method: &str) -> Result<&SerdeMethod, status::Status> {
+        self.methods
+            .get(&(service.to_owned(), method.to_owned()))
+            .ok_or_else(|| {
+                status::Status::new(
+                    status::Code::Unimplemented,
+                    format!("no JSON mapping for /{}/{}", service, method),
+                )
+            })
+    }
+}
+
+impl JsonMapping for SerdeJson {
+    fn input_to_protobuf(
+        &self,
+        service: &str,
+        method: &str,
+        json: &[u8],
+    ) -> Result<bytes::Bytes, status::Status> {
+        (self.method(service, method)?.input_to_protobuf)(json)
+    }
+
+    fn output_to_json(
+        &self,
+        service: &str,
+        method: &str,
+        protobuf: bytes::Bytes,
+    ) -> Result<bytes::Bytes, status::Status> {
+        (self.method(service, method)?.output_to_json)(protobuf)
+    }
+}
+
+impl fmt::Debug for SerdeJson {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut methods = self
+            .methods
+            .keys()
+            .map(|(service, method)| format!("/{}/{}", service, method))
+            .collect::<Vec<_>>();
+        methods.sort();
+        f.debug_struct("SerdeJson")
+            .field("methods", &methods)
+            .finish()
+    }
+}
+
+impl Connection {
+    /// Creates a connection that makes calls to routes under the specified base URL, usually ending
+    /// in the `/twirp` prefix, e.g. `http://localhost:8080/twirp`.
+    ///
+    /// HTTP connections are only opened once calls are made, which must happen on a tokio
+    /// executor.
+    pub fn new<U>(base_url: U) -> Connection
+    where
+        U: Into<String>,
+    {
+        Connection {
+            client: hyper::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_owned(),
+        }
+    }
+
+    /// Performs a raw call to the method at the specified path, e.g. `/echo.Echo/Echo`, in the
+    /// specified call context.
+    pub fn call(&self, path: &str, input: bytes::Bytes, context: context::Context) -> CallFuture {
+        use futures::Future;
+
+        let uri = match format!("{}{}", self.base_url, path).parse() {
+            Ok(uri) => uri,
+            Err(_) => {
+                let error = io::Error::new(io::ErrorKind::InvalidInput, "invalid method path");
+                return Box::new(futures::future::err(Error::from(error)));
+            }
+        };
+
+        let mut request = http::Request::new(hyper::Body::from(input));
+        *request.method_mut() = http::Method::POST;
+        *request.uri_mut() = uri;
+        headers::insert_metadata(request.headers_mut(), context.metadata(), is_reserved);
+        request.headers_mut().insert(
+            http::header::CONTENT_TYPE,
+            http::header::HeaderValue::from_static(CONTENT_TYPE),
+        );
+
+        Box::new(
+            self.client
+                .request(request)
+                .map_err(BodyError::Http)
+                .and_then(|response| {
+                    let (parts, body) = response.into_parts();
+                    read_body(body).map(move |body| (parts, body))
+                })
+                .map_err(|error| match error {
+                    BodyError::Http(error) => Error::from(io::Error::other(error)),
+                    BodyError::TooLarge => Error::Status {
+                        status: too_large("response"),
+                    },
+                })
+                .and_then(move |(parts, body)| {
+                    context.set_trailers(headers::extract_metadata(&parts.headers, is_reserved));
+                    if parts.status == http::StatusCode::OK {
+                        Ok(body.freeze())
+                    } else {
+                        let status = decode_error(parts.status, &body);
+                        Err(Error::Status { status })
+                    }
+                }),
+        )
+    }
+}
+
+impl<D> Client<D>
+where
+    D: descriptor::ServiceDescriptor + 'static,
+{
+    /// Creates a new client that makes calls over the specified connection.
+    pub fn new(connection: Connection) -> Client<D> {
+        Client {
+            connection,
+            descriptor: marker::PhantomData,
+        }
+    }
+}
+
+impl<D> handler::Handler for Client<D>
+where
+    D: descriptor::ServiceDescriptor + 'static,
+{
+    type Error = Error;
+    type Descriptor = D;
+    type CallFuture = CallFuture;
+
+    fn call(
+        &self,
+        method: D::Method,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> Self::CallFuture {
+        self.connection.call(&D::path(&method), input, context)
+    }
+}
+
+fn handle<D>(
+    dispatch: &D,
+    json: Option<sync::Arc<dyn JsonMapping>>,
+    request: http::Request<hyper::Body>,
+) -> Box<dyn futures::Future<Item = http::Response<hyper::Body>, Error = hyper::Error> + Send>
+where
+    D: Dispatch,
+{
+    use futures::Future;
+
+    let dispatch = dispatch.clone();
+    let (parts, body) = request.into_parts();
+    // The body is read even for requests that are rejected, so that the connection can be reused,
+    // unless it is too large to be worth reading.
+    Box::new(read_body(body).then(move |body| {
+        let body = match body {
+            Ok(body) => body,
+            Err(BodyError::Http(error)) => {
+                return futures::future::Either::A(futures::future::err(error));
+            }
+            Err(BodyError::TooLarge) => {
+                let status = too_large("request");
+                let response = error_response(twirp_code(status.code()), status.message());
+                return futures::future::Either::A(futures::future::ok(response));
+            }
+        };
+        let route = match route(&dispatch, &parts, json) {
+            Ok(route) => route,
+            Err(message) => {
+                let response = error_response("bad_route", &message);
+                return futures::future::Either::A(futures::future::ok(response));
+            }
+        };
+        let input = match route.json {
+            Some(ref json) => match json.input_to_protobuf(&route.service, &route.method, &body) {
+                Ok(input) => input,
+                Err(status) => {
+                    let code = match status.code() {
+                        status::Code::Unimplemented => "bad_route",
+                        status::Code::InvalidArgument => "malformed",
+                        code => twirp_code(code),
+                    };
+                    let response = error_response(code, status.message());
+                    return futures::future::Either::A(futures::future::ok(response));
+                }
+            },
+            None => body.freeze(),
+        };
+
+        let context =
+            context::Context::with_metadata(headers::extract_metadata(&parts.headers, is_reserved));
+        let trailers = context.clone();
+        futures::future::Either::B(
+            dispatch
+                .dispatch(&route.service, &route.method, input, context)
+                .then(move |result| {
+                    let mut response = output_response(&route, result);
+                    headers::insert_metadata(
+                        response.headers_mut(),
+                        &trailers.trailers(),
+                        is_reserved,
+                    );
+                    Ok(response)
+                }),
+        )
+    }))
+}
+
+/// Determines the route of a request, or why it isn't a valid Twirp request for a method that is
+/// dispatched.
+fn route<D>(
+    dispatch: &D,
+    parts: &http::request::Parts,
+    json: Option<sync::Arc<dyn JsonMapping>>,
+) -> Result<Route, String>
+where
+    D: Dispatch,
+{
+    if parts.method != http::Method::POST {
+        return Err(format!("unsupported method {}", parts.method));
+    }
+
+    let content_type = parts
+        .headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let json = if content_type.starts_with(JSON_CONTENT_TYPE) {
+        match json {
+            Some(json) => Some(json),
+            None => return Err(format!("JSON bodies are not served, use {}", CONTENT_TYPE)),
+        }
+    } else if content_type.starts_with(CONTENT_TYPE) {
+        None
+    } else {
+        return Err(format!("unsupported Content-Type {:?}", content_type));
+    };
+
+    parts
+        .uri
+        .path()
+        .strip_prefix(PREFIX)
+        .and_then(|path| path.strip_prefix('/'))
+        .and_then(|path| path.split_once('/'))
+        .filter(|&(service, method)| dispatch.has_method(service, method))
+        .map(|(service, method)| Route {
+            service: service.to_owned(),
+            method: method.to_owned(),
+            json,
+        })
+        .ok_or_else(|| format!("no route for {}", parts.uri.path()))
+}
+
+/// Builds the response to a call, in the same encoding as its request.
+fn output_response(
+    route: &Route,
+    result: Result<bytes::Bytes, status::Status>,
+) -> http::Response<hyper::Body> {
+    let result = match route.json {
+        Some(ref json) => result
+            .and_then(|output| json.output_to_json(&route.service, &route.method, output))
+            .map(|output| (output, JSON_CONTENT_TYPE)),
+        None => result.map(|output| (output, CONTENT_TYPE)),
+    };
+    match result {
+        Ok((output, content_type)) => {
+            let mut response = http::Response::new(hyper::Body::from(output));
+            response.headers_mut().insert(
+                http::header::CONTENT_TYPE,
+                http::header::HeaderValue::from_static(content_type),
+            );
+            response
+        }
+        Err(status) => error_response(twirp_code(status.code()), status.message()),
+    }
+}
+
+fn input_to_protobuf<I>(json: &[u8]) -> Result<bytes::Bytes, status::Status>
+where
+    I: prost::Message + for<'de> serde::Deserialize<'de>,
+{
+    let input = serde_json::from_slice::<I>(json)
+        .map_err(|error| status::Status::new(status::Code::InvalidArgument, error.to_string()))?;
+    let mut buf = bytes::BytesMut::with_capacity(input.encoded_len());
+    input.encode(&mut buf).expect("buffer has enough capacity");
+    Ok(buf.freeze())
+}
+
+fn output_to_json<O>(protobuf: bytes::Bytes) -> Result<bytes::Bytes, status::Status>
+where
+    O: prost::Message + Default + serde::Serialize,
+{
+    let internal =
+        |error: &dyn fmt::Display| status::Status::new(status::Code::Internal, error.to_string());
+    let output = O::decode(protobuf).map_err(|error| internal(&error))?;
+    let json = serde_json::to_vec(&output).map_err(|error| internal(&error))?;
+    Ok(json.into())
+}
+
+/// Reads a whole body, failing as soon as it turns out to be larger than `MAX_MESSAGE_LEN`.
+fn read_body(
+    body: hyper::Body,
+) -> impl futures::Future<Item = bytes::BytesMut, Error = BodyError> + Send {
+    use futures::Stream;
+
+    body.map_err(BodyError::Http)
+        .fold(bytes::BytesMut::new(), |mut buf, chunk| {
+            if buf.len() + chunk.len() > MAX_MESSAGE_LEN {
+                return Err(BodyError::TooLarge);
+            }
+            buf.extend_from_slice(&chunk);
+            Ok(buf)
+        })
+}
+
+fn too_large(what: &str) -> status::Status {
+    status::Status::new(
+        status::Code::ResourceExhausted,
+        format!("{} is larger than {} bytes", what, MAX_MESSAGE_LEN),
+    )
+}
+
+fn error_response(code: &str, message: &str) -> http::Response<hyper::Body> {
+    let mut error = serde_json::Map::new();
+    error.insert("code".to_owned(), code.into());
+    error.insert("msg".to_owned(), message.into());
+    let body = serde_json::Value::Object(error).to_string();
+
+    let mut response = http::Response::new(hyper::Body::from(body));
+    *response.status_mut() = twirp_http_status(code);
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        http::header::HeaderValue::from_static(JSON_CONTENT_TYPE),
+    );
+    response
+}
+
+/// Decodes a Twirp error object, falling back to the HTTP status for errors that didn't come from
+/// a Twirp server, as specified by Twirp.
+fn decode_error(http_status: http::StatusCode, body: &[u8]) -> status::Status {
+    let error = serde_json::from_slice::<serde_json::Value>(body).ok();
+    let field = |name: &str| {
+        error
+            .as_ref()
+            .and_then(|error| error.get(name))
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_owned)
+    };
+    match (field("code"), field("msg")) {
+        (Some(code), message) => {
+            status::Status::new(status_code(&code), message.unwrap_or_default())
+        }
+        _ => {
+            let code = match http_status.as_u16() {
+                300..=400 => status::Code::Internal,
+                401 => status::Code::Unauthenticated,
+                403 => status::Code::PermissionDenied,
+                404 => status::Code::Unimplemented,
+                429 | 502 | 503 | 504 => status::Code::Unavailable,
+                _ => status::Code::Unknown,
+            };
+            status::Status::new(code, format!("Unexpected HTTP status {}", http_status))
+        }
+    }
+}
+
+fn twirp_code(code: status::Code) -> &'static str {
+    match code {
+        status::Code::Ok => "unknown",
+        status::Code::Cancelled => "canceled",
+        status::Code::Unknown => "unknown",
+        status::Code::InvalidArgument => "invalid_argument",
+        status::Code::DeadlineExceeded => "deadline_exceeded",
+        status::Code::NotFound => "not_found",
+        status::Code::AlreadyExists => "already_exists",
+        status::Code::PermissionDenied => "permission_denied",
+        status::Code::ResourceExhausted => "resource_exhausted",
+        status::Code::FailedPrecondition => "failed_precondition",
+        status::Code::Aborted => "aborted",
+        status::Code::OutOfRange => "out_of_range",
+        status::Code::Unimplemented => "unimplemented",
+        status::Code::Internal => "internal",
+        status::Code::Unavailable => "unavailable",
+        status::Code::DataLoss => "dataloss",
+        status::Code::Unauthenticated => "unauthenticated",
+    }
+}
+
+fn status_code(code: &str) -> status::Code {
+    match code {
+        "canceled" => status::Code::Cancelled,
+        "invalid_argument" | "malformed" => status::Code::InvalidArgument,
+        "deadline_exceeded" => status::Code::DeadlineExceeded,
+        "not_found" => status::Code::NotFound,
+        "bad_route" | "unimplemented" => status::Code::Unimplemented,
+        "already_exists" => status::Code::AlreadyExists,
+        "permission_denied" => status::Code::PermissionDenied,
+        "unauthenticated" => status::Code::Unauthenticated,
+        "resource_exhausted" => status::Code::ResourceExhausted,
+        "failed_precondition" => status::Code::FailedPrecondition,
+        "aborted" => status::Code::Aborted,
+        "out_of_range" => status::Code::OutOfRange,
+        "internal" => status::Code::Internal,
+        "unavailable" => status::Code::Unavailable,
+        "dataloss" => status::Code::DataLoss,
+        _ => status::Code::Unknown,
+    }
+}
+
+fn twirp_http_status(code: &str) -> http::StatusCode {
+    match code {
+        "canceled" | "deadline_exceeded" => http::StatusCode::REQUEST_TIMEOUT,
+        "invalid_argument" | "malformed" | "out_of_range" => http::StatusCode::BAD_REQUEST,
+        "not_found" | "bad_route" => http::StatusCode::NOT_FOUND,
+        "already_exists" | "aborted" => http::StatusCode::CONFLICT,
+        "permission_denied" => http::StatusCode::FORBIDDEN,
+        "unauthenticated" => http::StatusCode::UNAUTHORIZED,
+        "resource_exhausted" => http::StatusCode::TOO_MANY_REQUESTS,
+        "failed_precondition" => http::StatusCode::PRECONDITION_FAILED,
+        "unimplemented" => http::StatusCode::NOT_IMPLEMENTED,
+        "unavailable" => http::StatusCode::SERVICE_UNAVAILABLE,
+        _ => http::StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn is_reserved(name: &str) -> bool {
+    RESERVED_HEADERS.contains(&name)
+}
+
+/// Encodings of protobuf field types for `#[serde(with = "...")]` attributes, so that messages
+/// deriving `serde`'s traits follow the proto3 JSON mapping.
+pub mod proto3 {
+    /// Encodes a `bytes` field as a base64 string.
+    ///
+    /// Both the standard and the URL-safe alphabets are accepted, with or without padding.
+    pub mod bytes {
+        use base64;
+        use serde;
+
+        /// Serializes bytes as a standard base64 string with padding.
+        pub fn serialize<S>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            serializer.serialize_str(&base64::encode(value))
+        }
+
+        /// Deserializes bytes from a base64 string.
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            use serde::Deserialize;
+
+            let value = String::deserialize(deserializer)?;
+            let value = value.trim_end_matches('=');
+            let config = if value.contains(['-', '_']) {
+                base64::URL_SAFE_NO_PAD
+            } else {
+                base64::STANDARD_NO_PAD
+            };
+            base64::decode_config(value, config).map_err(serde::de::Error::custom)
+        }
+    }
+
+    /// Encodes a 64-bit integer field, i.e. an `i64` or `u64`, as a decimal string.
+    ///
+    /// Both strings and numbers are accepted.
+    pub mod int64 {
+        use std::fmt;
+        use std::marker;
+        use std::str;
+
+        use serde;
+
+        /// Serializes an integer as a decimal string.
+        pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            T: fmt::Display,
+            S: serde::Serializer,
+        {
+            serializer.collect_str(value)
+        }
+
+        /// Deserializes an integer from a decimal string or a number.
+        pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+        where
+            T: str::FromStr,
+            T::Err: fmt::Display,
+            D: serde::Deserializer<'de>,
+        {
+            deserializer.deserialize_any(Visitor(marker::PhantomData))
+        }
+
+        struct Visitor<T>(marker::PhantomData<T>);
+
+        impl<'de, T> serde::de::Visitor<'de> for Visitor<T>
+        where
+            T: str::FromStr,
+            T::Err: fmt::Display,
+        {
+            type Value = T;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a 64-bit integer, or a string containing one")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<T, E>
+            where
+                E: serde::de::Error,
+            {
+                value.parse().map_err(E::custom)
+            }
+
+            fn visit_i64<E>(self, value: i64) -> Result<T, E>
+            where
+                E: serde::de::Error,
+            {
+                self.visit_str(&value.to_string())
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<T, E>
+            where
+                E: serde::de::Error,
+            {
+                self.visit_str(&value.to_string())
+            }
+        }
+    }
+}
@@ -0,0 +1,683 @@
+//! A transport of one datagram per message over UDP, for small calls that can tolerate loss.
+//!
+//! Every call is sent as a single datagram that carries a correlation ID chosen by the client.
+//! Clients retransmit calls that haven't been answered after a while, until their retransmission
+//! budget runs out:
+//!
+//! ```rust,ignore
+//! let future = udp::connect(&addr).and_then(|connection| {
+//!     let connection = connection.with_retransmits(3, Duration::from_millis(50));
+//!     let client = schema::echo::EchoClient::new(udp::Client::new(connection));
+//!     client.echo(schema::echo::EchoRequest { /* ... */ })
+//! });
+//! ```
+//!
+//! Servers remember the responses they sent for a while, and answer retransmitted calls with the
+//! same response instead of handling them again:
+//!
+//! ```rust,ignore
+//! let socket = tokio::net::UdpSocket::bind(&addr)?;
+//! tokio::run(udp::serve(socket, schema::echo::EchoServer::new(EchoService)).map_err(|_| ()));
+//! ```
+//!
+//! Messages are never split across datagrams.  Calls whose request doesn't fit into
+//! `MAX_DATAGRAM_LEN` bytes fail with `Error::TooLarge` without being sent, and calls whose
+//! response doesn't fit fail with a `ResourceExhausted` status.  Errors that don't fit are sent
+//! without trailers and with a shortened message.  Calls can't be cancelled once they have been
+//! sent.
+use std::collections;
+use std::fmt;
+use std::io;
+use std::marker;
+use std::mem;
+use std::net;
+use std::sync;
+use std::sync::atomic;
+use std::time;
+
+use bytes;
+use futures;
+use futures::sync::mpsc;
+use futures::sync::oneshot;
+use prost;
+use tokio;
+
+use context;
+use descriptor;
+use handler;
+use metadata;
+use status;
+use timer;
+
+use super::Dispatch;
+use super::Error;
+
+/// The largest message that fits into a single UDP datagram over IPv4, in bytes.
+pub const MAX_DATAGRAM_LEN: usize = 65_507;
+
+/// How much of the message of an error is kept if the full error doesn't fit into a datagram.
+const MAX_ERROR_MESSAGE_LEN: usize = 1024;
+
+/// How many times calls are retransmitted, unless specified otherwise.
+pub const DEFAULT_RETRANSMITS: u32 = 2;
+
+/// How long calls wait for a response before they are retransmitted, unless specified otherwise.
+pub const DEFAULT_RETRANSMIT_INTERVAL: time::Duration = time::Duration::from_millis(200);
+
+/// How long servers remember the calls they have seen, to suppress duplicates.
+const DEDUPLICATION_WINDOW: time::Duration = time::Duration::from_secs(30);
+
+/// The future that results from a call to `connect`.
+pub type Connect = Box<dyn futures::Future<Item = Connection, Error = io::Error> + Send>;
+
+/// The future that results from a call to `serve`.
+pub type Serve = Box<dyn futures::Future<Item = (), Error = io::Error> + Send>;
+
+/// A client for a server at a specific address, over which any number of calls can be made
+/// concurrently.
+///
+/// Connections are cheap to clone, and all clones share the same local socket, which is closed once
+/// all of them have been dropped.
+#[derive(Clone)]
+pub struct Connection {
+    shared: sync::Arc<Shared>,
+    retransmits: u32,
+    interval: time::Duration,
+}
+
+/// A client for a specific service, making calls over a `Connection`.
+#[derive(Clone, Debug)]
+pub struct Client<D> {
+    connection: Connection,
+    descriptor: marker::PhantomData<D>,
+}
+
+/// The future that results from a call made over a `Connection`.
+pub struct CallFuture {
+    id: u64,
+    context: context::Context,
+    state: State,
+    shared: sync::Arc<Shared>,
+}
+
+enum State {
+    Failed(Error),
+    Waiting {
+        request: bytes::Bytes,
+        response: oneshot::Receiver<Datagram>,
+        retransmit: timer::Delay,
+        retransmits: u32,
+        interval: time::Duration,
+    },
+    Done,
+}
+
+type Pending = sync::Arc<sync::Mutex<Option<collections::HashMap<u64, oneshot::Sender<Datagram>>>>>;
+
+struct Shared {
+    next_id: atomic::AtomicU64,
+    pending: Pending,
+    sender: mpsc::UnboundedSender<bytes::Bytes>,
+}
+
+/// Drives the socket of a `Connection`, sending requests and routing responses to their calls.
+struct Driver {
+    socket: tokio::net::UdpSocket,
+    outgoing: mpsc::UnboundedReceiver<bytes::Bytes>,
+    queued: Option<bytes::Bytes>,
+    pending: Pending,
+    buf: Vec<u8>,
+}
+
+/// Drives the socket of a server, handling requests and sending responses.
+struct Server<D> {
+    socket: tokio::net::UdpSocket,
+    dispatch: D,
+    outgoing: mpsc::UnboundedReceiver<(bytes::Bytes, net::SocketAddr)>,
+    sender: mpsc::UnboundedSender<(bytes::Bytes, net::SocketAddr)>,
+    queued: Option<(bytes::Bytes, net::SocketAddr)>,
+    calls: sync::Arc<sync::Mutex<Calls>>,
+    buf: Vec<u8>,
+}
+
+/// The calls that a server has seen recently, by client address and correlation ID.
+#[derive(Default)]
+struct Calls {
+    entries: collections::HashMap<(net::SocketAddr, u64), Entry>,
+    expiry: collections::VecDeque<(time::Instant, (net::SocketAddr, u64))>,
+}
+
+enum Entry {
+    InFlight,
+    Done(bytes::Bytes),
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Datagram {
+    #[prost(uint64, tag = "1")]
+    id: u64,
+    #[prost(enumeration = "Kind", tag = "2")]
+    kind: i32,
+    #[prost(string, tag = "3")]
+    service: String,
+    #[prost(string, tag = "4")]
+    method: String,
+    #[prost(btree_map = "string, string", tag = "5")]
+    metadata: collections::BTreeMap<String, String>,
+    #[prost(uint64, optional, tag = "6")]
+    timeout_micros: Option<u64>,
+    #[prost(bytes, tag = "7")]
+    body: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Enumeration)]
+enum Kind {
+    Request = 0,
+    Response = 1,
+    Error = 2,
+}
+
+/// Creates a connection to a server at the specified address, from a local socket bound to an
+/// ephemeral port.
+///
+/// This must be polled on a tokio executor, which is used to drive the connection.
+pub fn connect(addr: &net::SocketAddr) -> Connect {
+    let addr = *addr;
+    Box::new(futures::future::lazy(move || {
+        let local = if addr.is_ipv4() {
+            net::SocketAddr::from(([0, 0, 0, 0], 0))
+        } else {
+            net::SocketAddr::from(([0; 16], 0))
+        };
+        let socket = tokio::net::UdpSocket::bind(&local)?;
+        socket.connect(&addr)?;
+
+        let (sender, outgoing) = mpsc::unbounded();
+        let pending: Pending = sync::Arc::new(sync::Mutex::new(Some(collections::HashMap::new())));
+        // The driver must not keep the sender alive, so that it stops once all connections and
+        // calls are dropped.
+        tokio::spawn(Driver {
+            socket,
+            outgoing,
+            queued: None,
+            pending: pending.clone(),
+            buf: vec![0; MAX_DATAGRAM_LEN],
+        });
+
+        Ok(Connection {
+            shared: sync::Arc::new(Shared {
+                next_id: atomic::AtomicU64::new(0),
+                pending,
+                sender,
+            }),
+            retransmits: DEFAULT_RETRANSMITS,
+            interval: DEFAULT_RETRANSMIT_INTERVAL,
+        })
+    }))
+}
+
+/// Serves calls from all datagrams received by the specified socket.
+///
+/// Each call is handled in its own task on the current tokio executor.  Retransmitted calls that
+/// arrive while the original call is still being handled are ignored, and ones that arrive after
+/// it was handled are answered with the same response, for 30 seconds after the call first
+/// arrived.  The returned future only completes if receiving a datagram fails.
+pub fn serve<D>(socket: tokio::net::UdpSocket, dispatch: D) -> Serve
+where
+    D: Dispatch,
+{
+    let (sender, outgoing) = mpsc::unbounded();
+    Box::new(Server {
+        socket,
+        dispatch,
+        outgoing,
+        sender,
+        queued: None,
+        calls: sync::Arc::new(sync::Mutex::new(Calls::default())),
+        buf: vec![0; MAX_DATAGRAM_LEN],
+    })
+}
+
+impl Connection {
+    /// Returns this connection with a different retransmission budget.
+    ///
+    /// Calls are sent once, and then sent again up to `retransmits` times, whenever `interval` has
+    /// passed without a response.  A call that is still unanswered after its last retransmission
+    /// and another `interval` fails with `Error::Unanswered`.  Servers only suppress duplicates for
+    /// 30 seconds, so retransmissions shouldn't span more than that.
+    pub fn with_retransmits(mut self, retransmits: u32, interval: time::Duration) -> Connection {
+        self.retransmits = retransmits;
+        self.interval = interval;
+        self
+    }
+
+    /// Performs a raw call to the specified service and method, in the specified call context.
+    pub fn call(
+        &self,
+        service: &str,
+        method: &str,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> CallFuture {
+        let id = self.shared.next_id.fetch_add(1, atomic::Ordering::Relaxed);
+        let request = encode(&Datagram {
+            id,
+            kind: Kind::Request as i32,
+            service: service.to_owned(),
+            method: method.to_owned(),
+            metadata: metadata_entries(context.metadata()),
+            timeout_micros: context.timeout().map(|timeout| {
+                let micros = timeout.as_micros();
+                if micros > u128::from(u64::MAX) {
+                    u64::MAX
+                } else {
+                    micros as u64
+                }
+            }),
+            body: input.to_vec(),
+        });
+
+        let state = match request {
+            Ok(request) => self.send(id, request),
+            Err(error) => State::Failed(error),
+        };
+        CallFuture {
+            id,
+            context,
+            state,
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Whether the local socket has failed, in which case all calls fail with `Error::Closed`.
+    pub fn is_closed(&self) -> bool {
+        self.shared.sender.is_closed()
+    }
+
+    fn send(&self, id: u64, request: bytes::Bytes) -> State {
+        let (response_sender, response) = oneshot::channel();
+        match *lock(&self.shared.pending) {
+            Some(ref mut pending) => {
+                pending.insert(id, response_sender);
+            }
+            None => return State::Failed(Error::Closed),
+        }
+        if self.shared.sender.unbounded_send(request.clone()).is_err() {
+            return State::Failed(Error::Closed);
+        }
+        State::Waiting {
+            request,
+            response,
+            retransmit: timer::Delay::after(self.interval),
+            retransmits: self.retransmits,
+            interval: self.interval,
+        }
+    }
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("closed", &self.is_closed())
+            .field("retransmits", &self.retransmits)
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+impl<D> Client<D>
+where
+    D: descriptor::ServiceDescriptor + 'static,
+{
+    /// Creates a new client that makes calls over the specified connection.
+    pub fn new(connection: Connection) -> Client<D> {
+        Client {
+            connection,
+            descriptor: marker::PhantomData,
+        }
+    }
+}
+
+impl<D> handler::Handler for Client<D>
+where
+    D: descriptor::ServiceDescriptor + 'static,
+{
+    type Error = Error;
+    type Descriptor = D;
+    type CallFuture = CallFuture;
+
+    fn call(
+        &self,
+        method: D::Method,
+        input: bytes::Bytes,
+        context: context::Context,
+    ) -> Self::CallFuture {
+        let method = descriptor::MethodDescriptor::proto_name(&method);
+        self.connection
+            .call(&D::full_name(), method, input, context)
+    }
+}
+
+impl futures::Future for CallFuture {
+    type Item = bytes::Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let datagram = match self.state {
+            State::Failed(_) => match mem::replace(&mut self.state, State::Done) {
+                State::Failed(error) => return Err(error),
+                _ => unreachable!(),
+            },
+            State::Waiting {
+                ref request,
+                ref mut response,
+                ref mut retransmit,
+                ref mut retransmits,
+                interval,
+            } => loop {
+                match response.poll() {
+                    Ok(futures::Async::Ready(datagram)) => break datagram,
+                    Ok(futures::Async::NotReady) => {}
+                    Err(oneshot::Canceled) => {
+                        self.state = State::Done;
+                        return Err(Error::Closed);
+                    }
+                }
+                if futures::Future::poll(retransmit) != Ok(futures::Async::Ready(())) {
+                    return Ok(futures::Async::NotReady);
+                }
+                if *retransmits == 0 {
+                    self.state = State::Done;
+                    return Err(Error::Unanswered);
+                }
+                *retransmits -= 1;
+                *retransmit = timer::Delay::after(interval);
+                let _ = self.shared.sender.unbounded_send(request.clone());
+            },
+            State::Done => panic!("cannot poll a call after it has completed"),
+        };
+
+        self.state = State::Done;
+        self.context
+            .set_trailers(datagram.metadata.into_iter().collect());
+        if datagram.kind == Kind::Response as i32 {
+            Ok(futures::Async::Ready(datagram.body.into()))
+        } else {
+            let status = status::Status::decode(datagram.body.into())?;
+            Err(Error::Status { status })
+        }
+    }
+}
+
+impl Drop for CallFuture {
+    fn drop(&mut self) {
+        if let State::Waiting { .. } = self.state {
+            if let Some(ref mut pending) = *lock(&self.shared.pending) {
+                pending.remove(&self.id);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for CallFuture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CallFuture").field("id", &self.id).finish()
+    }
+}
+
+impl futures::Future for Driver {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> futures::Poll<(), ()> {
+        match self.poll_socket() {
+            Ok(futures::Async::NotReady) => Ok(futures::Async::NotReady),
+            _ => {
+                // Dropping all of the response senders fails the outstanding calls.
+                lock(&self.pending).take();
+                self.outgoing.close();
+                Ok(futures::Async::Ready(()))
+            }
+        }
+    }
+}
+
+impl Driver {
+    /// Sends and receives datagrams until the connection is dropped or the socket fails.
+    fn poll_socket(&mut self) -> futures::Poll<(), io::Error> {
+        use futures::Stream;
+
+        loop {
+            if let Some(ref request) = self.queued {
+                match self.socket.poll_send(request) {
+                    Ok(futures::Async::NotReady) => break,
+                    Ok(futures::Async::Ready(_)) => {}
+                    // Requests are lost if the server isn't listening yet, just like on the way.
+                    Err(ref error) if error.kind() == io::ErrorKind::ConnectionRefused => {}
+                    Err(error) => return Err(error),
+                }
+            }
+            self.queued = None;
+            match self.outgoing.poll() {
+                Ok(futures::Async::Ready(Some(request))) => self.queued = Some(request),
+                Ok(futures::Async::NotReady) => break,
+                Ok(futures::Async::Ready(None)) | Err(()) => {
+                    return Ok(futures::Async::Ready(()));
+                }
+            }
+        }
+
+        loop {
+            let len = match self.socket.poll_recv(&mut self.buf) {
+                Ok(futures::Async::Ready(len)) => len,
+                Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+                Err(ref error) if error.kind() == io::ErrorKind::ConnectionRefused => continue,
+                Err(error) => return Err(error),
+            };
+            // Anything that isn't a response to a pending call is ignored, since it might be a late
+            // duplicate, or not even come from this transport.
+            let datagram: Datagram = match prost::Message::decode(&self.buf[..len]) {
+                Ok(datagram) => datagram,
+                Err(_) => continue,
+            };
+            if datagram.kind == Kind::Request as i32 {
+                continue;
+            }
+            if let Some(ref mut pending) = *lock(&self.pending) {
+                if let Some(response) = pending.remove(&datagram.id) {
+                    let _ = response.send(datagram);
+                }
+            }
+        }
+    }
+}
+
+impl<D> futures::Future for Server<D>
+where
+    D: Dispatch,
+{
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> futures::Poll<(), io::Error> {
+        use futures::Stream;
+
+        loop {
+            if let Some((ref response, ref peer)) = self.queued {
+                match self.socket.poll_send_to(response, peer) {
+                    Ok(futures::Async::NotReady) => break,
+                    // The client retransmits the call if the response got lost, so one that
+                    // couldn't be sent at all is simply dropped.
+                    Ok(futures::Async::Ready(_)) | Err(_) => {}
+                }
+            }
+            self.queued = None;
+            match self.outgoing.poll() {
+                Ok(futures::Async::Ready(Some(response))) => self.queued = Some(response),
+                Ok(futures::Async::NotReady) => break,
+                Ok(futures::Async::Ready(None)) | Err(()) => {
+                    unreachable!("the server has a sender")
+                }
+            }
+        }
+
+        loop {
+            let (len, peer) = match self.socket.poll_recv_from(&mut self.buf)? {
+                futures::Async::Ready(received) => received,
+                futures::Async::NotReady => return Ok(futures::Async::NotReady),
+            };
+            let datagram: Datagram = match prost::Message::decode(&self.buf[..len]) {
+                Ok(datagram) => datagram,
+                Err(_) => continue,
+            };
+            if datagram.kind == Kind::Request as i32 {
+                self.handle(datagram, peer);
+            }
+        }
+    }
+}
+
+impl<D> Server<D>
+where
+    D: Dispatch,
+{
+    fn handle(&self, datagram: Datagram, peer: net::SocketAddr) {
+        use futures::Future;
+
+        let id = datagram.id;
+        match lock(&self.calls).start(peer, id) {
+            Some(Entry::InFlight) => return,
+            Some(Entry::Done(response)) => {
+                let _ = self.sender.unbounded_send((response, peer));
+                return;
+            }
+            None => {}
+        }
+
+        let mut context = context::Context::with_metadata(datagram.metadata.into_iter().collect());
+        if let Some(timeout_micros) = datagram.timeout_micros {
+            context = context.with_timeout(time::Duration::from_micros(timeout_micros));
+        }
+        let call = self.dispatch.dispatch(
+            &datagram.service,
+            &datagram.method,
+            datagram.body.into(),
+            context.clone(),
+        );
+
+        let calls = self.calls.clone();
+        let sender = self.sender.clone();
+        tokio::spawn(call.then(move |result| {
+            let metadata = metadata_entries(&context.trailers());
+            let response = match result {
+                Ok(body) => encode(&Datagram {
+                    id,
+                    kind: Kind::Response as i32,
+                    metadata: metadata.clone(),
+                    body: body.to_vec(),
+                    ..Datagram::default()
+                }),
+                Err(status) => Ok(error_response(id, &status, metadata.clone())),
+            };
+            let response = response.unwrap_or_else(|error| {
+                let status =
+                    status::Status::new(status::Code::ResourceExhausted, error.to_string());
+                error_response(id, &status, metadata)
+            });
+
+            lock(&calls).finish(peer, id, response.clone());
+            let _ = sender.unbounded_send((response, peer));
+            Ok(())
+        }));
+    }
+}
+
+impl Calls {
+    /// Records that a call has arrived, returning what is known about it if it arrived before.
+    fn start(&mut self, peer: net::SocketAddr, id: u64) -> Option<Entry> {
+        let now = time::Instant::now();
+        while self
+            .expiry
+            .front()
+            .is_some_and(|&(expires, _)| expires <= now)
+        {
+            if let Some((_, key)) = self.expiry.pop_front() {
+                self.entries.remove(&key);
+            }
+        }
+
+        match self.entries.get(&(peer, id)) {
+            Some(Entry::InFlight) => Some(Entry::InFlight),
+            Some(Entry::Done(response)) => Some(Entry::Done(response.clone())),
+            None => {
+                self.entries.insert((peer, id), Entry::InFlight);
+                self.expiry
+                    .push_back((now + DEDUPLICATION_WINDOW, (peer, id)));
+                None
+            }
+        }
+    }
+
+    /// Records the response to a call, unless the call has been forgotten in the meantime.
+    fn finish(&mut self, peer: net::SocketAddr, id: u64, response: bytes::Bytes) {
+        if let Some(entry) = self.entries.get_mut(&(peer, id)) {
+            *entry = Entry::Done(response);
+        }
+    }
+}
+
+/// Encodes a datagram, failing if it doesn't fit into `MAX_DATAGRAM_LEN` bytes.
+fn encode(datagram: &Datagram) -> Result<bytes::Bytes, Error> {
+    let len = prost::Message::encoded_len(datagram);
+    if len > MAX_DATAGRAM_LEN {
+        return Err(Error::TooLarge {
+            len,
+            max: MAX_DATAGRAM_LEN,
+        });
+    }
+    Ok(write(datagram, len))
+}
+
+/// Encodes an error response.
+///
+/// If the response doesn't fit into a datagram, it is sent without metadata and with a shortened
+/// message instead, which always fits.
+fn error_response(
+    id: u64,
+    status: &status::Status,
+    metadata: collections::BTreeMap<String, String>,
+) -> bytes::Bytes {
+    encode(&Datagram {
+        id,
+        kind: Kind::Error as i32,
+        metadata,
+        body: status.encode().to_vec(),
+        ..Datagram::default()
+    })
+    .unwrap_or_else(|_| {
+        let datagram = Datagram {
+            id,
+            kind: Kind::Error as i32,
+            body: status.truncated(MAX_ERROR_MESSAGE_LEN).encode().to_vec(),
+            ..Datagram::default()
+        };
+        write(&datagram, prost::Message::encoded_len(&datagram))
+    })
+}
+
+fn write(datagram: &Datagram, len: usize) -> bytes::Bytes {
+    let mut buf = bytes::BytesMut::with_capacity(len);
+    prost::Message::encode(datagram, &mut buf).expect("buffer has enough capacity");
+    buf.freeze()
+}
+
+fn lock<T>(mutex: &sync::Mutex<T>) -> sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(sync::PoisonError::into_inner)
+}
+
+fn metadata_entries(metadata: &metadata::Metadata) -> collections::BTreeMap<String, String> {
+    metadata
+        .iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect()
+}
@@ -0,0 +1,116 @@
+//! A transport of length-prefixed frames over Unix domain sockets.
+//!
+//! This uses the same framing as the `tcp` transport, and additionally tells services which process
+//! is calling them via `Context::peer_credentials()`:
+//!
+//! ```rust,ignore
+//! let listener = tokio::net::UnixListener::bind("/run/echo.sock")?;
+//! tokio::run(unix::serve(listener, router).map_err(|_| ()));
+//!
+//! // ...and in the service:
+//! let uid = Context::current().peer_credentials().map(|credentials| credentials.uid);
+//! ```
+use std::io;
+use std::path;
+
+use futures;
+use tokio;
+
+use context;
+
+use super::accept;
+use super::framed;
+use super::Dispatch;
+
+pub use super::framed::MAX_MESSAGE_LEN;
+
+/// The future that results from a call to `connect`.
+pub type Connect = Box<dyn futures::Future<Item = framed::Connection, Error = io::Error> + Send>;
+
+/// The future that results from a call to `serve`.
+pub type Serve = Box<dyn futures::Future<Item = (), Error = io::Error> + Send>;
+
+/// Connects to a server listening on the socket at the specified path.
+///
+/// This must be polled on a tokio executor, which is used to drive the connection.
+pub fn connect<P>(path: P) -> Connect
+where
+    P: AsRef<path::Path>,
+{
+    use futures::Future;
+
+    Box::new(tokio::net::UnixStream::connect(path).map(framed::Connection::new))
+}
+
+/// Serves calls from all connections accepted by the specified listener.
+///
+/// The credentials of the connecting process are attached to the context of every call made over
+/// the connection.  This must be polled on a tokio executor, which is used to handle connections
+/// and calls.  The returned future only completes if the listener fails; connections that fail to
+/// be set up are dropped without affecting the others.
+pub fn serve<D>(listener: tokio::net::UnixListener, dispatch: D) -> Serve
+where
+    D: Dispatch,
+{
+    use futures::Future;
+
+    Box::new(accept::for_each(listener.incoming(), move |socket| {
+        let peer_credentials = peer_credentials(&socket)?;
+        tokio::spawn(
+            framed::serve_connection(socket, dispatch.clone(), Some(peer_credentials))
+                .map_err(|_| ()),
+        );
+        Ok(())
+    }))
+}
+
+/// Looks up the credentials of the process on the other end of the socket via `SO_PEERCRED`.
+///
+/// `tokio` only reports the user and group IDs, so this asks the kernel directly to get the
+/// process ID as well.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[allow(unsafe_code)]
+fn peer_credentials(socket: &tokio::net::UnixStream) -> io::Result<context::PeerCredentials> {
+    use libc;
+    use std::os::unix::io::AsRawFd;
+
+    let mut ucred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = size_of::<libc::ucred>() as libc::socklen_t;
+    let ptr: *mut libc::ucred = &mut ucred;
+    // SAFETY: `ucred` and `len` describe a buffer of the size that `SO_PEERCRED` writes to.
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            ptr as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(context::PeerCredentials {
+        uid: ucred.uid,
+        gid: ucred.gid,
+        pid: Some(ucred.pid as u32),
+    })
+}
+
+/// Looks up the credentials of the process on the other end of the socket.
+///
+/// The process ID is only reported on Linux.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_credentials(socket: &tokio::net::UnixStream) -> io::Result<context::PeerCredentials> {
+    let ucred = socket.peer_cred()?;
+    Ok(context::PeerCredentials {
+        uid: ucred.uid,
+        gid: ucred.gid,
+        pid: None,
+    })
+}
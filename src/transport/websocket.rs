@@ -0,0 +1,680 @@
+//! A transport of protobuf frames over WebSockets.
+//!
+//! Every binary message carries one of the same frames as the `tcp` transport, so any number of
+//! concurrent calls can be multiplexed over a single WebSocket:
+//!
+//! ```rust,ignore
+//! let future = websocket::connect(&addr, "/rpc").and_then(|connection| {
+//!     let client = schema::echo::EchoClient::new(transport::Client::new(connection));
+//!     client.echo(schema::echo::EchoRequest { /* ... */ })
+//! });
+//! ```
+//!
+//! Servers accept WebSocket upgrades on any path, and feed the calls into anything that implements
+//! `Dispatch`:
+//!
+//! ```rust,ignore
+//! let listener = tokio::net::TcpListener::bind(&addr)?;
+//! tokio::run(websocket::serve(listener, router).map_err(|_| ()));
+//! ```
+//!
+//! Clients offer the `PROTOCOL` subprotocol, which browsers can request as well.  Pings are
+//! answered automatically.  When either side closes the WebSocket, or sends a text message, all of
+//! the calls that are still in flight fail with `Error::Closed` on the client and are cancelled on
+//! the server.
+use std::collections;
+use std::collections::hash_map;
+use std::io;
+use std::net;
+
+use base64;
+use bytes;
+use bytes::BufMut;
+use futures;
+use getrandom;
+use httparse;
+use sha1;
+use tokio;
+
+use super::accept;
+use super::framed;
+use super::Dispatch;
+
+/// The subprotocol that clients offer, and that servers select when it is offered.
+pub const PROTOCOL: &str = "prost-simple-rpc";
+
+/// The largest message that can be sent over a WebSocket, in bytes.
+///
+/// A call whose request doesn't fit fails with `Error::TooLarge`, and a response that doesn't fit
+/// is replaced with a `ResourceExhausted` error, without affecting other calls on the WebSocket.
+pub const MAX_MESSAGE_LEN: usize = 8 * 1024 * 1024;
+
+/// The future that results from a call to `connect`.
+pub type Connect = Box<dyn futures::Future<Item = framed::Connection, Error = io::Error> + Send>;
+
+/// The future that results from a call to `serve`.
+pub type Serve = Box<dyn futures::Future<Item = (), Error = io::Error> + Send>;
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_HANDSHAKE_LEN: usize = 16 * 1024;
+const MAX_HEADERS: usize = 64;
+
+const BAD_REQUEST: &str =
+    "HTTP/1.1 400 Bad Request\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+const UPGRADE_REQUIRED: &str = "HTTP/1.1 426 Upgrade Required\r\nSec-WebSocket-Version: 13\r\n\
+                                Connection: close\r\nContent-Length: 0\r\n\r\n";
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_UNSUPPORTED_DATA: u16 = 1003;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Role {
+    Client,
+    Server,
+}
+
+/// Reads the head of the HTTP request or response that opens a WebSocket, and writes raw heads.
+#[derive(Debug)]
+struct Handshake(Role);
+
+#[derive(Debug)]
+struct Head {
+    method: Option<String>,
+    code: Option<u16>,
+    headers: collections::HashMap<String, String>,
+}
+
+/// Encodes and decodes WebSocket frames, reassembling fragmented messages.
+#[derive(Debug)]
+struct Codec {
+    role: Role,
+    fragments: Option<(u8, bytes::BytesMut)>,
+}
+
+#[derive(Debug)]
+enum Message {
+    Binary(bytes::Bytes),
+    Text(bytes::Bytes),
+    Ping(bytes::Bytes),
+    Pong(bytes::Bytes),
+    Close(Option<u16>),
+}
+
+/// An open WebSocket, as a stream and sink of the payloads of binary messages.
+struct Socket<T> {
+    framed: tokio::codec::Framed<T, Codec>,
+    outgoing: collections::VecDeque<Message>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+/// Connects to a server at the specified address, and opens a WebSocket on the specified path.
+///
+/// This must be polled on a tokio executor, which is used to drive the connection.
+pub fn connect(addr: &net::SocketAddr, path: &str) -> Connect {
+    use futures::Future;
+    use futures::Sink;
+    use futures::Stream;
+
+    let mut key = [0; 16];
+    if let Err(error) = random(&mut key) {
+        return Box::new(futures::future::err(error));
+    }
+    let key = base64::encode(&key);
+    let accept = accept_key(&key);
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Protocol: {}\r\n\r\n",
+        path, addr, key, PROTOCOL
+    );
+
+    Box::new(
+        tokio::net::TcpStream::connect(addr)
+            .and_then(|socket| {
+                socket.set_nodelay(true)?;
+                Ok(tokio::codec::Framed::new(socket, Handshake(Role::Client)))
+            })
+            .and_then(move |framed| framed.send(request))
+            .and_then(|framed| framed.into_future().map_err(|(error, _)| error))
+            .and_then(move |(head, framed)| {
+                check_response(head, &accept)?;
+                Ok(framed::Connection::with_messages(
+                    Socket::new(framed, Role::Client),
+                    MAX_MESSAGE_LEN,
+                ))
+            }),
+    )
+}
+
+/// Serves calls from all WebSockets opened over connections accepted by the specified listener.
+///
+/// This must be polled on a tokio executor, which is used to handle connections and calls.  The
+/// returned future only completes if the listener fails; connections that fail to be set up are
+/// dropped without affecting the others.
+pub fn serve<D>(listener: tokio::net::TcpListener, dispatch: D) -> Serve
+where
+    D: Dispatch,
+{
+    use futures::Future;
+
+    Box::new(accept::for_each(listener.incoming(), move |socket| {
+        socket.set_nodelay(true)?;
+        let dispatch = dispatch.clone();
+        tokio::spawn(
+            accept(socket)
+                .and_then(move |socket| {
+                    framed::serve_messages(socket, MAX_MESSAGE_LEN, dispatch, None)
+                })
+                .map_err(|_| ()),
+        );
+        Ok(())
+    }))
+}
+
+/// Performs the server side of the handshake, rejecting connections that don't open a WebSocket.
+fn accept<T>(io: T) -> Box<dyn futures::Future<Item = Socket<T>, Error = io::Error> + Send>
+where
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static,
+{
+    use futures::Future;
+    use futures::Sink;
+    use futures::Stream;
+
+    Box::new(
+        tokio::codec::Framed::new(io, Handshake(Role::Server))
+            .into_future()
+            .map_err(|(error, _)| error)
+            .and_then(|(head, framed)| match head {
+                Some(head) => Ok((head, framed)),
+                None => Err(eof()),
+            })
+            .and_then(|(head, framed)| {
+                let (response, rejection) = respond(&head);
+                framed
+                    .send(response)
+                    .and_then(move |framed| match rejection {
+                        None => Ok(Socket::new(framed, Role::Server)),
+                        Some(reason) => Err(io::Error::new(io::ErrorKind::InvalidData, reason)),
+                    })
+            }),
+    )
+}
+
+/// Builds the response to a handshake request, along with the reason if it was rejected.
+fn respond(head: &Head) -> (String, Option<&'static str>) {
+    let key = match head.header("sec-websocket-key") {
+        Some(key)
+            if head.method.as_deref() == Some("GET")
+                && head.has_token("upgrade", "websocket")
+                && head.has_token("connection", "upgrade") =>
+        {
+            key
+        }
+        _ => return (BAD_REQUEST.to_owned(), Some("invalid WebSocket handshake")),
+    };
+    if head.header("sec-websocket-version") != Some("13") {
+        return (
+            UPGRADE_REQUIRED.to_owned(),
+            Some("unsupported WebSocket version"),
+        );
+    }
+
+    let mut response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n",
+        accept_key(key)
+    );
+    if head.has_token("sec-websocket-protocol", PROTOCOL) {
+        response.push_str(&format!("Sec-WebSocket-Protocol: {}\r\n", PROTOCOL));
+    }
+    response.push_str("\r\n");
+    (response, None)
+}
+
+/// Checks that the server accepted the handshake with the specified key.
+fn check_response(head: Option<Head>, accept: &str) -> io::Result<()> {
+    let head = head.ok_or_else(eof)?;
+    if head.code != Some(101) {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!(
+                "server refused to open a WebSocket with status {}",
+                head.code.unwrap_or_default()
+            ),
+        ));
+    }
+    let protocol = head.header("sec-websocket-protocol");
+    if !head.has_token("upgrade", "websocket")
+        || !head.has_token("connection", "upgrade")
+        || head.header("sec-websocket-accept") != Some(accept)
+        || protocol.is_some() && protocol != Some(PROTOCOL)
+    {
+        return Err(invalid_data("invalid WebSocket handshake"));
+    }
+    Ok(())
+}
+
+impl Head {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    fn has_token(&self, name: &str, token: &str) -> bool {
+        self.header(name).is_some_and(|value| {
+            value
+                .split(',')
+                .any(|candidate| candidate.trim().eq_ignore_ascii_case(token))
+        })
+    }
+}
+
+impl tokio::codec::Decoder for Handshake {
+    type Item = Head;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut bytes::BytesMut) -> io::Result<Option<Head>> {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let (len, head) = match self.0 {
+            Role::Server => {
+                let mut request = httparse::Request::new(&mut headers);
+                match request.parse(buf).map_err(invalid_data)? {
+                    httparse::Status::Complete(len) => (
+                        len,
+                        Head {
+                            method: request.method.map(str::to_owned),
+                            code: None,
+                            headers: collect_headers(request.headers)?,
+                        },
+                    ),
+                    httparse::Status::Partial => return partial(buf),
+                }
+            }
+            Role::Client => {
+                let mut response = httparse::Response::new(&mut headers);
+                match response.parse(buf).map_err(invalid_data)? {
+                    httparse::Status::Complete(len) => (
+                        len,
+                        Head {
+                            method: None,
+                            code: response.code,
+                            headers: collect_headers(response.headers)?,
+                        },
+                    ),
+                    httparse::Status::Partial => return partial(buf),
+                }
+            }
+        };
+        buf.split_to(len);
+        Ok(Some(head))
+    }
+}
+
+impl tokio::codec::Encoder for Handshake {
+    type Item = String;
+    type Error = io::Error;
+
+    fn encode(&mut self, head: String, buf: &mut bytes::BytesMut) -> io::Result<()> {
+        buf.extend_from_slice(head.as_bytes());
+        Ok(())
+    }
+}
+
+impl Codec {
+    fn new(role: Role) -> Codec {
+        Codec {
+            role,
+            fragments: None,
+        }
+    }
+}
+
+impl tokio::codec::Decoder for Codec {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut bytes::BytesMut) -> io::Result<Option<Message>> {
+        loop {
+            if buf.len() < 2 {
+                return Ok(None);
+            }
+            let fin = buf[0] & 0x80 != 0;
+            let opcode = buf[0] & 0x0f;
+            let masked = buf[1] & 0x80 != 0;
+
+            if buf[0] & 0x70 != 0 {
+                return Err(invalid_data("WebSocket frame uses an unknown extension"));
+            }
+            if masked != (self.role == Role::Server) {
+                return Err(invalid_data("WebSocket frame is masked incorrectly"));
+            }
+
+            let (len, mut offset) = match buf[1] & 0x7f {
+                126 if buf.len() < 4 => return Ok(None),
+                126 => (u64::from(u16::from_be_bytes([buf[2], buf[3]])), 4),
+                127 if buf.len() < 10 => return Ok(None),
+                127 => {
+                    let mut len = [0; 8];
+                    len.copy_from_slice(&buf[2..10]);
+                    (u64::from_be_bytes(len), 10)
+                }
+                len => (u64::from(len), 2),
+            };
+            if opcode >= OPCODE_CLOSE && (!fin || len > 125) {
+                return Err(invalid_data("WebSocket control frame is too large"));
+            }
+            let buffered = self
+                .fragments
+                .as_ref()
+                .map_or(0, |(_, fragments)| fragments.len());
+            if len > (MAX_MESSAGE_LEN - buffered) as u64 {
+                return Err(invalid_data("WebSocket message is too large"));
+            }
+            let len = len as usize;
+
+            let mask = if masked {
+                if buf.len() < offset + 4 {
+                    return Ok(None);
+                }
+                let mask = [
+                    buf[offset],
+                    buf[offset + 1],
+                    buf[offset + 2],
+                    buf[offset + 3],
+                ];
+                offset += 4;
+                Some(mask)
+            } else {
+                None
+            };
+            if buf.len() < offset + len {
+                buf.reserve(offset + len - buf.len());
+                return Ok(None);
+            }
+
+            buf.split_to(offset);
+            let mut payload = buf.split_to(len);
+            if let Some(mask) = mask {
+                for (i, byte) in payload.iter_mut().enumerate() {
+                    *byte ^= mask[i % 4];
+                }
+            }
+
+            match opcode {
+                OPCODE_CONTINUATION => match self.fragments.take() {
+                    Some((opcode, mut fragments)) => {
+                        fragments.extend_from_slice(&payload);
+                        if fin {
+                            return Ok(Some(Message::data(opcode, fragments)));
+                        }
+                        self.fragments = Some((opcode, fragments));
+                    }
+                    None => return Err(invalid_data("unexpected WebSocket continuation frame")),
+                },
+                OPCODE_TEXT | OPCODE_BINARY if self.fragments.is_none() => {
+                    if fin {
+                        return Ok(Some(Message::data(opcode, payload)));
+                    }
+                    self.fragments = Some((opcode, payload));
+                }
+                OPCODE_TEXT | OPCODE_BINARY => {
+                    return Err(invalid_data("expected a WebSocket continuation frame"));
+                }
+                OPCODE_CLOSE => {
+                    let code = if payload.len() >= 2 {
+                        Some(u16::from_be_bytes([payload[0], payload[1]]))
+                    } else {
+                        None
+                    };
+                    return Ok(Some(Message::Close(code)));
+                }
+                OPCODE_PING => return Ok(Some(Message::Ping(payload.freeze()))),
+                OPCODE_PONG => return Ok(Some(Message::Pong(payload.freeze()))),
+                _ => return Err(invalid_data("unknown WebSocket opcode")),
+            }
+        }
+    }
+}
+
+impl tokio::codec::Encoder for Codec {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn encode(&mut self, message: Message, buf: &mut bytes::BytesMut) -> io::Result<()> {
+        let (opcode, payload) = match message {
+            Message::Binary(payload) => (OPCODE_BINARY, payload),
+            Message::Text(payload) => (OPCODE_TEXT, payload),
+            Message::Ping(payload) => (OPCODE_PING, payload),
+            Message::Pong(payload) => (OPCODE_PONG, payload),
+            Message::Close(code) => (
+                OPCODE_CLOSE,
+                code.map_or_else(bytes::Bytes::new, |code| {
+                    bytes::Bytes::from(&code.to_be_bytes()[..])
+                }),
+            ),
+        };
+        let mask_bit = match self.role {
+            Role::Client => 0x80,
+            Role::Server => 0,
+        };
+
+        buf.reserve(14 + payload.len());
+        buf.put_u8(0x80 | opcode);
+        if payload.len() < 126 {
+            buf.put_u8(mask_bit | payload.len() as u8);
+        } else if payload.len() <= usize::from(u16::MAX) {
+            buf.put_u8(mask_bit | 126);
+            buf.put_slice(&(payload.len() as u16).to_be_bytes());
+        } else {
+            buf.put_u8(mask_bit | 127);
+            buf.put_slice(&(payload.len() as u64).to_be_bytes());
+        }
+
+        match self.role {
+            Role::Client => {
+                let mut mask = [0; 4];
+                random(&mut mask)?;
+                buf.put_slice(&mask);
+                buf.extend(
+                    payload
+                        .iter()
+                        .enumerate()
+                        .map(|(i, byte)| byte ^ mask[i % 4]),
+                );
+            }
+            Role::Server => buf.put_slice(&payload),
+        }
+        Ok(())
+    }
+}
+
+impl Message {
+    fn data(opcode: u8, payload: bytes::BytesMut) -> Message {
+        if opcode == OPCODE_TEXT {
+            Message::Text(payload.freeze())
+        } else {
+            Message::Binary(payload.freeze())
+        }
+    }
+}
+
+impl<T> Socket<T>
+where
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite,
+{
+    /// Switches a connection that has completed its handshake over to WebSocket frames.
+    fn new(framed: tokio::codec::Framed<T, Handshake>, role: Role) -> Socket<T> {
+        let handshake = framed.into_parts();
+        let mut parts = tokio::codec::FramedParts::new(handshake.io, Codec::new(role));
+        parts.read_buf = handshake.read_buf;
+        parts.write_buf = handshake.write_buf;
+
+        Socket {
+            framed: tokio::codec::Framed::from_parts(parts),
+            outgoing: collections::VecDeque::new(),
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    /// Starts sending the queued messages, returning whether all of them could be started.
+    fn start_outgoing(&mut self) -> io::Result<bool> {
+        while let Some(message) = self.outgoing.pop_front() {
+            if let futures::AsyncSink::NotReady(message) =
+                futures::Sink::start_send(&mut self.framed, message)?
+            {
+                self.outgoing.push_front(message);
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn close_with(&mut self, code: Option<u16>) {
+        if !self.close_sent {
+            self.close_sent = true;
+            self.outgoing.push_back(Message::Close(code));
+        }
+    }
+}
+
+impl<T> futures::Stream for Socket<T>
+where
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite,
+{
+    type Item = bytes::BytesMut;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> futures::Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            // Answering pings and closes is best effort, and must not hold up reading.
+            futures::Sink::poll_complete(self)?;
+            if self.close_received {
+                return Ok(futures::Async::Ready(None));
+            }
+
+            let message = match futures::Stream::poll(&mut self.framed)? {
+                futures::Async::Ready(Some(message)) => message,
+                futures::Async::Ready(None) => return Ok(futures::Async::Ready(None)),
+                futures::Async::NotReady => return Ok(futures::Async::NotReady),
+            };
+            match message {
+                Message::Binary(payload) => {
+                    return Ok(futures::Async::Ready(Some(payload.into())));
+                }
+                Message::Text(_) => {
+                    self.close_with(Some(CLOSE_UNSUPPORTED_DATA));
+                    futures::Sink::poll_complete(self)?;
+                    return Err(invalid_data("WebSocket text messages are not supported"));
+                }
+                Message::Ping(payload) => self.outgoing.push_back(Message::Pong(payload)),
+                Message::Pong(_) => {}
+                Message::Close(code) => {
+                    self.close_received = true;
+                    self.close_with(code);
+                }
+            }
+        }
+    }
+}
+
+impl<T> futures::Sink for Socket<T>
+where
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite,
+{
+    type SinkItem = bytes::Bytes;
+    type SinkError = io::Error;
+
+    fn start_send(
+        &mut self,
+        payload: bytes::Bytes,
+    ) -> futures::StartSend<Self::SinkItem, Self::SinkError> {
+        if self.close_sent {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "WebSocket is closing",
+            ));
+        }
+        if !self.start_outgoing()? {
+            return Ok(futures::AsyncSink::NotReady(payload));
+        }
+        // Messages that don't fit yet are queued, and block further messages until they do.
+        self.outgoing.push_back(Message::Binary(payload));
+        self.start_outgoing()?;
+        Ok(futures::AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> futures::Poll<(), Self::SinkError> {
+        if !self.start_outgoing()? {
+            return Ok(futures::Async::NotReady);
+        }
+        futures::Sink::poll_complete(&mut self.framed)
+    }
+
+    fn close(&mut self) -> futures::Poll<(), Self::SinkError> {
+        self.close_with(Some(CLOSE_NORMAL));
+        match self.poll_complete()? {
+            futures::Async::Ready(()) => futures::Sink::close(&mut self.framed),
+            futures::Async::NotReady => Ok(futures::Async::NotReady),
+        }
+    }
+}
+
+fn collect_headers(
+    headers: &[httparse::Header],
+) -> io::Result<collections::HashMap<String, String>> {
+    let mut collected = collections::HashMap::<String, String>::new();
+    for header in headers {
+        let value = String::from_utf8(header.value.to_vec()).map_err(invalid_data)?;
+        match collected.entry(header.name.to_ascii_lowercase()) {
+            hash_map::Entry::Occupied(mut entry) => {
+                entry.get_mut().push_str(", ");
+                entry.get_mut().push_str(&value);
+            }
+            hash_map::Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
+        }
+    }
+    Ok(collected)
+}
+
+fn partial(buf: &bytes::BytesMut) -> io::Result<Option<Head>> {
+    if buf.len() > MAX_HANDSHAKE_LEN {
+        Err(invalid_data("WebSocket handshake is too large"))
+    } else {
+        Ok(None)
+    }
+}
+
+fn accept_key(key: &str) -> String {
+    let mut sha1 = sha1::Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(ACCEPT_GUID.as_bytes());
+    base64::encode(&sha1.digest().bytes())
+}
+
+/// Fills `buf` with random bytes from the operating system, for handshake keys and frame masks.
+fn random(buf: &mut [u8]) -> io::Result<()> {
+    getrandom::getrandom(buf).map_err(io::Error::from)
+}
+
+fn eof() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "connection closed during the WebSocket handshake",
+    )
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}